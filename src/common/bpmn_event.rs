@@ -1,3 +1,5 @@
+use crate::common::text_metrics::text_size;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[allow(dead_code)]
 pub enum BpmnEvent {
@@ -42,17 +44,105 @@ pub enum BpmnEvent {
     TaskScript(String),                       // Script task with label
 }
 
+impl BpmnEvent {
+    /// Tasks, subprocesses and transactions, which are drawn as rectangles with their label inside
+    pub fn is_activity(&self) -> bool {
+        matches!(
+            self,
+            BpmnEvent::ActivityTask(_)
+                | BpmnEvent::ActivityCallActivity(_)
+                | BpmnEvent::TaskUser(_)
+                | BpmnEvent::TaskService(_)
+                | BpmnEvent::TaskBusinessRule(_)
+                | BpmnEvent::TaskScript(_)
+                | BpmnEvent::ActivitySubprocess(_)
+                | BpmnEvent::ActivityEventSubprocess(_)
+                | BpmnEvent::ActivityTransaction(_)
+        )
+    }
+
+    /// Gateways, which are drawn as diamonds
+    pub fn is_gateway(&self) -> bool {
+        matches!(
+            self,
+            BpmnEvent::GatewayExclusive
+                | BpmnEvent::GatewayInclusive
+                | BpmnEvent::GatewayParallel
+                | BpmnEvent::GatewayEvent
+                | BpmnEvent::GatewayJoin(_)
+        )
+    }
+
+    /// Returns the label of the event, if it has one
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            BpmnEvent::GatewayExclusive
+            | BpmnEvent::GatewayInclusive
+            | BpmnEvent::GatewayParallel
+            | BpmnEvent::GatewayEvent => None,
+
+            BpmnEvent::Start(label)
+            | BpmnEvent::Middle(label)
+            | BpmnEvent::End(label)
+            | BpmnEvent::GatewayJoin(label)
+            | BpmnEvent::ActivityTask(label)
+            | BpmnEvent::ActivitySubprocess(label)
+            | BpmnEvent::ActivityCallActivity(label)
+            | BpmnEvent::ActivityEventSubprocess(label)
+            | BpmnEvent::ActivityTransaction(label)
+            | BpmnEvent::StartTimerEvent(label)
+            | BpmnEvent::StartSignalEvent(label)
+            | BpmnEvent::StartMessageEvent(label)
+            | BpmnEvent::StartConditionalEvent(label)
+            | BpmnEvent::EndErrorEvent(label)
+            | BpmnEvent::EndCancelEvent(label)
+            | BpmnEvent::EndSignalEvent(label)
+            | BpmnEvent::EndMessageEvent(label)
+            | BpmnEvent::EndTerminateEvent(label)
+            | BpmnEvent::EndEscalationEvent(label)
+            | BpmnEvent::EndCompensationEvent(label)
+            | BpmnEvent::BoundaryEvent(label, _, _)
+            | BpmnEvent::BoundaryErrorEvent(label, _, _)
+            | BpmnEvent::BoundaryTimerEvent(label, _, _)
+            | BpmnEvent::BoundaryCancelEvent(label, _, _)
+            | BpmnEvent::BoundarySignalEvent(label, _, _)
+            | BpmnEvent::BoundaryMessageEvent(label, _, _)
+            | BpmnEvent::BoundaryEscalationEvent(label, _, _)
+            | BpmnEvent::BoundaryConditionalEvent(label, _, _)
+            | BpmnEvent::BoundaryCompensationEvent(label, _)
+            | BpmnEvent::DataStoreReference(label)
+            | BpmnEvent::DataObjectReference(label)
+            | BpmnEvent::TaskUser(label)
+            | BpmnEvent::TaskService(label)
+            | BpmnEvent::TaskBusinessRule(label)
+            | BpmnEvent::TaskScript(label) => Some(label),
+        }
+    }
+}
+
+// Tasks start at the default size of modelers and grow in steps until their label fits
+const TASK_WIDTH: f64 = 100.0;
+const TASK_HEIGHT: f64 = 80.0;
+const MAX_TASK_WIDTH: f64 = 200.0;
+const TASK_WIDTH_STEP: f64 = 20.0;
+/// Space between the border of a task and its label
+pub const TASK_PADDING: f64 = 5.0;
+/// Labels of events, gateways and data are wrapped to this width outside of their shape
+pub const EXTERNAL_LABEL_WIDTH: f64 = 90.0;
+/// Distance between a shape and its external label
+pub const LABEL_GAP: f64 = 7.0;
+
+/// Size of the shape of a node. Activities grow to fit their label, first wider and then taller.
 pub fn get_node_size(event: &BpmnEvent) -> (usize, usize) {
     match event {
-        // Start Events
+        // Events
         BpmnEvent::Start(_)
+        | BpmnEvent::Middle(_)
         | BpmnEvent::StartTimerEvent(_)
         | BpmnEvent::StartSignalEvent(_)
         | BpmnEvent::StartMessageEvent(_)
-        | BpmnEvent::StartConditionalEvent(_) => (36, 36),
-
-        // End Events
-        BpmnEvent::End(_)
+        | BpmnEvent::StartConditionalEvent(_)
+        | BpmnEvent::End(_)
         | BpmnEvent::EndErrorEvent(_)
         | BpmnEvent::EndCancelEvent(_)
         | BpmnEvent::EndSignalEvent(_)
@@ -69,12 +159,12 @@ pub fn get_node_size(event: &BpmnEvent) -> (usize, usize) {
         | BpmnEvent::GatewayJoin(_) => (50, 50),
 
         // Activities
-        BpmnEvent::ActivityTask(_)
-        | BpmnEvent::ActivityCallActivity(_)
-        | BpmnEvent::TaskUser(_)
-        | BpmnEvent::TaskService(_)
-        | BpmnEvent::TaskBusinessRule(_)
-        | BpmnEvent::TaskScript(_) => (100, 80),
+        BpmnEvent::ActivityTask(label)
+        | BpmnEvent::ActivityCallActivity(label)
+        | BpmnEvent::TaskUser(label)
+        | BpmnEvent::TaskService(label)
+        | BpmnEvent::TaskBusinessRule(label)
+        | BpmnEvent::TaskScript(label) => task_size(label),
 
         // Subprocesses and Transactions (expanded)
         BpmnEvent::ActivitySubprocess(_)
@@ -95,8 +185,32 @@ pub fn get_node_size(event: &BpmnEvent) -> (usize, usize) {
         // Data Objects
         BpmnEvent::DataStoreReference(_) => (50, 50),
         BpmnEvent::DataObjectReference(_) => (36, 50),
+    }
+}
+
+/// Size of the label drawn outside of the shape, for nodes other than activities that have a label.
+/// The name of a join only ties the branches together in the DSL, so it is not drawn.
+pub fn get_label_size(event: &BpmnEvent) -> Option<(usize, usize)> {
+    let no_external_label = event.is_activity() || matches!(event, BpmnEvent::GatewayJoin(_));
+    let label = event.label().filter(|label| !label.trim().is_empty())?;
+    if no_external_label {
+        return None;
+    }
+    let (width, height) = text_size(label, EXTERNAL_LABEL_WIDTH);
+    Some((width.ceil() as usize, height.ceil() as usize))
+}
 
-        // Default case for any other elements
-        _ => (100, 80),
+fn task_size(label: &str) -> (usize, usize) {
+    let mut width = TASK_WIDTH;
+    loop {
+        let (text_width, text_height) = text_size(label, width - 2.0 * TASK_PADDING);
+        let fits_width = text_width + 2.0 * TASK_PADDING <= width;
+        let fits_height = text_height + 2.0 * TASK_PADDING <= TASK_HEIGHT;
+        if (fits_width && fits_height) || width >= MAX_TASK_WIDTH {
+            let width = width.max(text_width + 2.0 * TASK_PADDING);
+            let height = TASK_HEIGHT.max(text_height + 2.0 * TASK_PADDING);
+            return (width.ceil() as usize, height.ceil() as usize);
+        }
+        width += TASK_WIDTH_STEP;
     }
 }
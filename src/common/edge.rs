@@ -4,6 +4,8 @@ pub struct Edge {
     pub to: usize,
     pub text: Option<String>,
     pub bend_points: Option<Vec<(f64, f64)>>, // Uued, lõplikud punktid, mis hõlmavad algus-, lõpp- ja painutuspunkte
+    pub is_back_edge: bool, // Points back against the flow, found when cycles are broken for the layering
+    pub label_bounds: Option<(f64, f64, f64, f64)>, // Where the text is drawn, as x, y, width and height
 }
 
 impl Edge {
@@ -13,6 +15,8 @@ impl Edge {
             to,
             text,
             bend_points: None, // Alguses tühi, määratakse assign_bend_points-s
+            is_back_edge: false,
+            label_bounds: None,
         }
     }
 
@@ -25,3 +29,27 @@ impl Edge {
     //     }
     // }
 }
+
+/// A message flow between a node and a collapsed pool, which has no nodes to connect to
+#[derive(Debug, Clone)]
+pub struct PoolFlow {
+    pub node: usize,
+    pub pool: usize,   // Index of the collapsed pool in the pools of the graph
+    pub to_pool: bool, // The message goes from the node to the pool, not the other way round
+    pub text: Option<String>,
+    pub bend_points: Option<Vec<(f64, f64)>>,
+    pub label_bounds: Option<(f64, f64, f64, f64)>,
+}
+
+impl PoolFlow {
+    pub fn new(node: usize, pool: usize, to_pool: bool, text: Option<String>) -> Self {
+        PoolFlow {
+            node,
+            pool,
+            to_pool,
+            text,
+            bend_points: None,
+            label_bounds: None,
+        }
+    }
+}
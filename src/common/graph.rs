@@ -1,15 +1,26 @@
 // graph.rs
 use crate::common::bpmn_event::BpmnEvent;
-use crate::common::edge::Edge;
+use crate::common::edge::{Edge, PoolFlow};
+use crate::common::layout_hint::LayoutHint;
 use crate::common::node::Node;
 use crate::common::pool::Pool;
+use crate::layout::Orientation;
 
 /// Represents a graph consisting of nodes and edges.
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct Graph {
     pub pools: Vec<Pool>,    // Pools
     pub edges: Vec<Edge>,    // Edges
+    pub pool_flows: Vec<PoolFlow>, // Message flows to and from collapsed pools
     pub last_node_id: usize, // Last used node ID
+    pub orientation: Orientation, // Direction the layout was made in
+    pub hints: Vec<LayoutHint>, // Layout hints from the DSL
+}
+
+impl Default for Graph {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Graph {
@@ -17,7 +28,10 @@ impl Graph {
         Graph {
             pools: Vec::new(),
             edges: Vec::new(),
+            pool_flows: Vec::new(),
             last_node_id: 0,
+            orientation: Orientation::default(),
+            hints: Vec::new(),
         }
     }
 
@@ -58,6 +72,34 @@ impl Graph {
         node_id
     }
 
+    /// Adds a lane without nodes to a pool, nested in the `parent` lane if it is given
+    pub fn add_lane(
+        &mut self,
+        pool_name: Option<String>,
+        lane_name: String,
+        parent: Option<String>,
+    ) {
+        let pool_name = pool_name.unwrap_or_default();
+        if let Some(pool) = self
+            .pools
+            .iter_mut()
+            .find(|p| p.get_pool_name() == pool_name)
+        {
+            pool.add_lane(lane_name, parent);
+        } else {
+            let mut new_pool = Pool::new(pool_name);
+            new_pool.add_lane(lane_name, parent);
+            self.pools.push(new_pool);
+        }
+    }
+
+    /// Adds an empty pool for an external participant whose process is not shown
+    pub fn add_collapsed_pool(&mut self, pool_name: String) {
+        let mut pool = Pool::new(pool_name);
+        pool.collapsed = true;
+        self.pools.push(pool);
+    }
+
     pub fn get_pools(&self) -> &Vec<Pool> {
         &self.pools
     }
@@ -87,10 +129,17 @@ impl Graph {
                 }
             }
         }
-        println!("Node with id {} not found", id);
         None
     }
 
+    /// Iterates over the nodes of all pools and lanes
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.pools
+            .iter()
+            .flat_map(|pool| pool.get_lanes())
+            .flat_map(|lane| lane.get_layers())
+    }
+
     pub fn get_nodes_by_pool_name(&self, pool_name: &str) -> Vec<&Node> {
         self.pools
             .iter()
@@ -1,6 +1,6 @@
 //lane.rs
 use crate::common::node::Node;
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct Lane {
     lane: String,
     pub layers: Vec<Node>,
@@ -8,6 +8,7 @@ pub struct Lane {
     pub y: Option<f64>,
     pub width: Option<f64>,
     pub height: Option<f64>,
+    pub parent: Option<String>, // Name of the lane this lane is nested in
 }
 
 impl Lane {
@@ -19,6 +20,7 @@ impl Lane {
             y: None,
             width: None,
             height: None,
+            parent: None,
         }
     }
 
@@ -39,7 +41,7 @@ impl Lane {
     }
 
     pub fn sort_nodes_by_layer_id(&mut self) {
-        self.layers.sort_by(|a, b| a.layer_id.cmp(&b.layer_id));
+        self.layers.sort_by_key(|a| a.layer_id);
     }
 
     pub fn get_nodes_by_layer_id_mut(&mut self, layer_id: usize) -> Vec<&mut Node> {
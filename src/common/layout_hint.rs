@@ -0,0 +1,52 @@
+// layout_hint.rs
+use crate::common::graph::Graph;
+use crate::diagnostics::Diagnostic;
+
+/// A wish from the DSL about where a node goes, written as `@...` on the line after the node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutHint {
+    pub node: usize,    // Node the hint is on
+    pub kind: HintKind, // What the hint asks for
+    pub line: usize,    // Line of the hint, starting from 1
+    pub column: usize,  // Column of the hint, starting from 1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HintKind {
+    Layer(usize), // `@layer 3`, put the node into this layer, counted from 0
+    Row(usize),   // `@row Name`, put the node on the same row as the other node
+    Above(usize), // `@above Name`, put the node before the other node across the flow
+    Below(usize), // `@below Name`, put the node after the other node across the flow
+}
+
+impl LayoutHint {
+    /// The hint as it is written in the DSL, with the name of the node it refers to
+    pub fn describe(&self, name_of: impl Fn(usize) -> String) -> String {
+        match self.kind {
+            HintKind::Layer(layer) => format!("@layer {}", layer + 1),
+            HintKind::Row(other) => format!("@row {}", name_of(other)),
+            HintKind::Above(other) => format!("@above {}", name_of(other)),
+            HintKind::Below(other) => format!("@below {}", name_of(other)),
+        }
+    }
+
+    /// Warning that the layout could not keep the hint, pointing at the hint in the DSL
+    pub fn warning(&self, graph: &Graph, reason: &str) -> Diagnostic {
+        let name_of = |id| {
+            graph
+                .get_node_by_id(id)
+                .and_then(|node| node.event.as_ref()?.label())
+                .unwrap_or_default()
+                .to_string()
+        };
+        Diagnostic {
+            line: Some(self.line),
+            column: Some(self.column),
+            ..Diagnostic::warning(format!(
+                "Layout hint '{}' cannot be kept: {}",
+                self.describe(name_of),
+                reason
+            ))
+        }
+    }
+}
@@ -2,5 +2,7 @@ pub mod bpmn_event;
 pub mod edge;
 pub mod graph;
 pub mod lane;
+pub mod layout_hint;
 pub mod node;
 pub mod pool;
+pub mod text_metrics;
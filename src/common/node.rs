@@ -1,5 +1,6 @@
 // node.rs
 use crate::common::bpmn_event::*;
+use crate::layout::Orientation;
 
 #[derive(Debug, Clone)]
 pub struct Node {
@@ -46,6 +47,32 @@ impl Node {
         self.x_offset = Some(x_offset);
         self.y_offset = Some(y_offset);
     }
+
+    /// Bounds of the external label as x, y, width and height. The label goes below the shape,
+    /// or to its right when the flow goes top to bottom so edges can still leave from the bottom.
+    pub fn label_bounds(&self, orientation: Orientation) -> Option<(f64, f64, f64, f64)> {
+        let event = self.event.as_ref()?;
+        let (label_width, label_height) = get_label_size(event)?;
+        let (label_width, label_height) = (label_width as f64, label_height as f64);
+        let (width, height) = get_node_size(event);
+        let (width, height) = (width as f64, height as f64);
+        let x = self.x? + self.x_offset.unwrap_or(0.0);
+        let y = self.y? + self.y_offset.unwrap_or(0.0);
+        Some(match orientation {
+            Orientation::LeftToRight => (
+                x + (width - label_width) / 2.0,
+                y + height + LABEL_GAP,
+                label_width,
+                label_height,
+            ),
+            Orientation::TopToBottom => (
+                x + width + LABEL_GAP,
+                y + (height - label_height) / 2.0,
+                label_width,
+                label_height,
+            ),
+        })
+    }
 }
 
 impl std::fmt::Display for Node {
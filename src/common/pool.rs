@@ -1,8 +1,8 @@
 // pool.rs
 use crate::common::lane::Lane;
 use crate::common::node::Node;
-use std::collections::HashMap;
-#[derive(Clone)]
+use std::collections::{HashMap, HashSet};
+#[derive(Debug, Clone)]
 pub struct Pool {
     pool_name: String,
     pub lanes: Vec<Lane>,
@@ -10,6 +10,7 @@ pub struct Pool {
     pub y: Option<f64>,
     pub width: Option<f64>,
     pub height: Option<f64>,
+    pub collapsed: bool, // Black box of an external participant, drawn as an empty band
 }
 
 impl Pool {
@@ -21,6 +22,7 @@ impl Pool {
             y: None,
             width: None,
             height: None,
+            collapsed: false,
         }
     }
 
@@ -38,6 +40,17 @@ impl Pool {
         }
     }
 
+    /// Adds a lane without nodes, nested in `parent` if it is given. Lanes are kept in the order
+    /// of the DSL, so a lane always comes after the lane it is nested in.
+    pub fn add_lane(&mut self, lane_name: String, parent: Option<String>) {
+        if self.lanes.iter().any(|lane| lane.get_lane() == &lane_name) {
+            return;
+        }
+        let mut lane = Lane::new(lane_name);
+        lane.parent = parent;
+        self.lanes.push(lane);
+    }
+
     pub fn get_lanes(&self) -> &Vec<Lane> {
         &self.lanes
     }
@@ -73,9 +86,64 @@ impl Pool {
         self.y = Some(y);
     }
 
-    pub fn set_lane_width(&mut self, width: f64) {
-        for lane in &mut self.lanes {
-            lane.set_width(width);
+    /// Number of lanes a lane is nested in, 0 for the lanes directly in the pool
+    pub fn lane_depth(&self, lane: &Lane) -> usize {
+        let mut depth = 0;
+        let mut parent = lane.parent.as_ref();
+        while let Some(name) = parent {
+            depth += 1;
+            parent = self
+                .lanes
+                .iter()
+                .find(|lane| lane.get_lane() == name)
+                .and_then(|lane| lane.parent.as_ref());
+        }
+        depth
+    }
+
+    /// Names of the lanes that have other lanes nested in them. They only hold their child lanes,
+    /// the nodes are in the innermost lanes.
+    pub fn parent_lanes(&self) -> HashSet<String> {
+        self.lanes
+            .iter()
+            .filter_map(|lane| lane.parent.clone())
+            .collect()
+    }
+
+    /// Sets the width of the lanes from the left edge of the pool header. Nested lanes start after
+    /// the headers of the lanes around them, so they are narrower by the width of those headers.
+    pub fn set_lane_width(&mut self, width: f64, header_width: f64) {
+        let depths: Vec<usize> = self
+            .lanes
+            .iter()
+            .map(|lane| self.lane_depth(lane))
+            .collect();
+        let lane_x = self.x.unwrap_or(0.0) + header_width;
+        for (lane, depth) in self.lanes.iter_mut().zip(depths) {
+            let indent = depth as f64 * header_width;
+            lane.x = Some(lane_x + indent);
+            lane.set_width(width - indent);
+        }
+    }
+
+    /// Stretches every lane that has lanes nested in it over the lanes nested in it
+    pub fn enclose_child_lanes(&mut self) {
+        // A lane comes before the lanes nested in it, so going backwards they are done first
+        for index in (0..self.lanes.len()).rev() {
+            let name = self.lanes[index].get_lane().clone();
+            let (top, bottom) = self
+                .lanes
+                .iter()
+                .filter(|lane| lane.parent.as_ref() == Some(&name))
+                .filter_map(|lane| Some((lane.y?, lane.y? + lane.height?)))
+                .fold((f64::MAX, f64::MIN), |(top, bottom), (y, end)| {
+                    (top.min(y), bottom.max(end))
+                });
+            if top <= bottom {
+                let lane = &mut self.lanes[index];
+                lane.y = Some(top);
+                lane.set_height(bottom - top);
+            }
         }
     }
 }
@@ -0,0 +1,61 @@
+// text_metrics.rs
+
+/// Font size of labels, modelers draw them in 12px Arial
+pub const FONT_SIZE: f64 = 12.0;
+/// Distance between the baselines of two lines of a label
+pub const LINE_HEIGHT: f64 = 14.0;
+
+// Advance widths of the printable ASCII characters from space to tilde in Helvetica,
+// in thousandths of the font size. Arial uses the same widths.
+const CHAR_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, // ' ' to '/'
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, // '0' to '?'
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778, // '@' to 'O'
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556, // 'P' to '_'
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556, // '`' to 'o'
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584, // 'p' to '~'
+];
+// Width used for characters outside of ASCII, about the width of a lowercase letter
+const DEFAULT_CHAR_WIDTH: u16 = 556;
+
+/// Width of a single line of text in pixels
+pub fn text_width(text: &str) -> f64 {
+    let thousandths: u32 = text
+        .chars()
+        .map(|c| match c {
+            ' '..='~' => CHAR_WIDTHS[c as usize - ' ' as usize],
+            _ => DEFAULT_CHAR_WIDTH,
+        } as u32)
+        .sum();
+    thousandths as f64 * FONT_SIZE / 1000.0
+}
+
+/// Breaks text into lines at spaces so that each line fits in `max_width`.
+/// A word that is wider than `max_width` on its own gets a line of its own.
+pub fn wrap_text(text: &str, max_width: f64) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        if !current.is_empty() && text_width(&format!("{} {}", current, word)) > max_width {
+            lines.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
+
+/// Width of the widest line and height of the text once wrapped to `max_width`
+pub fn text_size(text: &str, max_width: f64) -> (f64, f64) {
+    let lines = wrap_text(text, max_width);
+    let width = lines
+        .iter()
+        .map(|line| text_width(line))
+        .fold(0.0, f64::max);
+    (width, lines.len() as f64 * LINE_HEIGHT)
+}
@@ -0,0 +1,555 @@
+// from_xml.rs
+use crate::common::bpmn_event::BpmnEvent;
+use crate::common::edge::{Edge, PoolFlow};
+use crate::common::graph::Graph;
+use crate::common::node::Node;
+use crate::common::pool::Pool;
+use crate::diagnostics::Diagnostic;
+use crate::layout::Orientation;
+use roxmltree::{Document, Node as XmlNode};
+use std::collections::HashMap;
+
+// x, y, width and height
+pub(crate) type Rect = (f64, f64, f64, f64);
+// Waypoints and label bounds of an edge
+type EdgeDi = (Vec<(f64, f64)>, Option<Rect>);
+
+const UNSUPPORTED: &str = "is not supported and was skipped";
+const MODEL_NAMESPACE: &str = "http://www.omg.org/spec/BPMN/20100524/MODEL";
+
+// Elements that only describe other elements or the process itself, they are skipped silently
+const IGNORED: &[&str] = &[
+    "laneSet",
+    "sequenceFlow",
+    "extensionElements",
+    "documentation",
+    "dataObject",
+    "ioSpecification",
+    "property",
+    "incoming",
+    "outgoing",
+];
+
+/// XML ids of the imported elements, so a new diagram can refer to them
+#[derive(Debug, Default)]
+pub(crate) struct XmlIds {
+    pub plane: String,              // Collaboration, or the process if there is none
+    pub pools: Vec<Option<String>>, // Participant of every pool, None for a bare process
+    pub lanes: Vec<HashMap<String, String>>, // Lane ids of every pool by lane name
+    pub nodes: HashMap<usize, String>, // Flow node ids by node id
+    pub flows: Vec<String>,         // Sequence and message flow ids in the order of the edges
+    pub pool_flows: Vec<String>,    // Message flow ids of collapsed pools in the same order
+}
+
+/// Reads BPMN 2.0 XML, as written by this crate or drawn in a modeler, into a graph. Pools, lanes,
+/// nodes and edges get the bounds and waypoints of the diagram where it has them. Elements the
+/// graph has no place for are skipped with a warning, only XML that is not BPMN is an error.
+pub fn read_bpmn(xml: &str) -> Result<(Graph, Vec<Diagnostic>), Diagnostic> {
+    read_bpmn_with_ids(xml).map(|(graph, warnings, _)| (graph, warnings))
+}
+
+/// Reads BPMN 2.0 XML like [`read_bpmn`] and also returns the XML ids of what was imported
+pub(crate) fn read_bpmn_with_ids(
+    xml: &str,
+) -> Result<(Graph, Vec<Diagnostic>, XmlIds), Diagnostic> {
+    let document = Document::parse(xml).map_err(|error| {
+        let position = error.pos();
+        Diagnostic {
+            line: Some(position.row as usize),
+            column: Some(position.col as usize),
+            ..Diagnostic::error(format!("Invalid BPMN file: {}", error))
+        }
+    })?;
+    let definitions = document.root_element();
+    if !is(&definitions, "definitions") {
+        return Err(Diagnostic::error(
+            "Invalid BPMN file: the root element is not bpmn:definitions",
+        ));
+    }
+
+    let mut reader = Reader {
+        document: &document,
+        graph: Graph::new(),
+        warnings: Vec::new(),
+        node_ids: HashMap::new(),
+        shapes: HashMap::new(),
+        edges: HashMap::new(),
+        ids: XmlIds::default(),
+    };
+    reader.read_diagram();
+
+    // Participants become pools, processes without a participant get a pool of their own
+    let processes: Vec<XmlNode> = definitions
+        .children()
+        .filter(|node| is(node, "process"))
+        .collect();
+    let mut pools: Vec<(XmlNode, String, Option<XmlNode>)> = Vec::new();
+    let mut message_flows: Vec<XmlNode> = Vec::new();
+    for collaboration in definitions
+        .children()
+        .filter(|node| is(node, "collaboration"))
+    {
+        if reader.ids.plane.is_empty() {
+            reader.ids.plane = collaboration
+                .attribute("id")
+                .unwrap_or_default()
+                .to_string();
+        }
+        for element in collaboration.children().filter(is_model) {
+            if is(&element, "messageFlow") {
+                message_flows.push(element);
+                continue;
+            }
+            if !is(&element, "participant") {
+                reader.warn(&element, UNSUPPORTED);
+                continue;
+            }
+            let process = element.attribute("processRef").and_then(|id| {
+                processes
+                    .iter()
+                    .find(|process| process.attribute("id") == Some(id))
+            });
+            pools.push((element, name_or_id(&element), process.copied()));
+        }
+    }
+    let without_participant: Vec<XmlNode> = processes
+        .iter()
+        .filter(|process| !pools.iter().any(|(_, _, other)| other == &Some(**process)))
+        .copied()
+        .collect();
+    for process in &without_participant {
+        // A single process without a collaboration is what the DSL writes without a pool
+        let name = match process.attribute("name") {
+            Some(name) => name.to_string(),
+            None if without_participant.len() == 1 && pools.is_empty() => String::new(),
+            None => name_or_id(process),
+        };
+        pools.push((*process, name, Some(*process)));
+    }
+
+    if reader.ids.plane.is_empty() {
+        if let Some(process) = processes.first() {
+            reader.ids.plane = process.attribute("id").unwrap_or_default().to_string();
+        }
+    }
+
+    for (element, name, process) in &pools {
+        let participant = Some(*element)
+            .filter(|element| is(element, "participant"))
+            .and_then(|element| element.attribute("id"));
+        reader.ids.pools.push(participant.map(str::to_string));
+        reader.ids.lanes.push(HashMap::new());
+        let mut pool = Pool::new(name.clone());
+        pool.collapsed = process.is_none();
+        if let Some((x, y, width, height)) = reader.shape_of(element) {
+            pool.set_position(x, y);
+            pool.set_width(width);
+            pool.set_height(height);
+        }
+        reader.graph.pools.push(pool);
+        if let Some(process) = process {
+            reader.read_process(process);
+        }
+    }
+    for (_, _, process) in &pools {
+        if let Some(process) = process {
+            for flow in process.children().filter(|node| is(node, "sequenceFlow")) {
+                reader.read_flow(&flow);
+            }
+        }
+    }
+    // Message flows between flow nodes are edges between pools, like the jumps of the DSL, and
+    // the ones of collapsed pools start or end at the pool itself
+    let participants: HashMap<&str, (usize, bool)> = pools
+        .iter()
+        .enumerate()
+        .filter(|(_, (element, _, _))| is(element, "participant"))
+        .filter_map(|(index, (element, _, process))| {
+            Some((element.attribute("id")?, (index, process.is_none())))
+        })
+        .collect();
+    for flow in &message_flows {
+        let source = flow.attribute("sourceRef").unwrap_or_default();
+        let target = flow.attribute("targetRef").unwrap_or_default();
+        match (participants.get(source), participants.get(target)) {
+            (None, None) => reader.read_flow(flow),
+            (Some(&(pool, true)), None) => reader.read_pool_flow(flow, target, pool, false),
+            (None, Some(&(pool, true))) => reader.read_pool_flow(flow, source, pool, true),
+            _ => reader.warn(
+                flow,
+                "connects a pool with a process instead of a flow node and was skipped",
+            ),
+        }
+    }
+    reader.graph.last_node_id = reader.node_ids.len();
+    for (id, node_id) in &reader.node_ids {
+        reader.ids.nodes.insert(*node_id, id.clone());
+    }
+
+    Ok((reader.graph, reader.warnings, reader.ids))
+}
+
+struct Reader<'a, 'input> {
+    document: &'a Document<'input>,
+    graph: Graph,
+    warnings: Vec<Diagnostic>,
+    node_ids: HashMap<String, usize>, // Node id of every imported flow node by its XML id
+    shapes: HashMap<String, Rect>,    // Bounds of the shape of every element by its id
+    edges: HashMap<String, EdgeDi>,   // Waypoints and label bounds of every flow by its id
+    ids: XmlIds,
+}
+
+impl<'a, 'input> Reader<'a, 'input> {
+    /// Collects the shapes and edges of the diagram. Pools and lanes drawn as columns mean the flow
+    /// goes top to bottom.
+    fn read_diagram(&mut self) {
+        for shape in self
+            .document
+            .descendants()
+            .filter(|node| is(node, "BPMNShape"))
+        {
+            let (Some(id), Some(rect)) = (shape.attribute("bpmnElement"), bounds(&shape)) else {
+                continue;
+            };
+            if shape.attribute("isHorizontal") == Some("false") {
+                self.graph.orientation = Orientation::TopToBottom;
+            }
+            self.shapes.insert(id.to_string(), rect);
+        }
+        for edge in self
+            .document
+            .descendants()
+            .filter(|node| is(node, "BPMNEdge"))
+        {
+            if let Some(id) = edge.attribute("bpmnElement") {
+                let di = (waypoints(&edge), label_bounds(&edge));
+                self.edges.insert(id.to_string(), di);
+            }
+        }
+    }
+
+    fn shape_of(&self, element: &XmlNode) -> Option<Rect> {
+        let id = element.attribute("id")?;
+        self.shapes.get(id).copied()
+    }
+
+    /// Reads the lanes and flow nodes of a process into the last pool
+    fn read_process(&mut self, process: &XmlNode) {
+        let mut lane_of: HashMap<String, String> = HashMap::new();
+        for lane_set in process.children().filter(|node| is(node, "laneSet")) {
+            self.read_lanes(&lane_set, None, &mut lane_of);
+        }
+
+        // Boundary events refer to the activity they are attached to, so they come last
+        let (boundary_events, elements): (Vec<XmlNode>, Vec<XmlNode>) = process
+            .children()
+            .filter(XmlNode::is_element)
+            .partition(|node| is(node, "boundaryEvent"));
+        for element in elements.iter().chain(&boundary_events) {
+            if IGNORED.contains(&element.tag_name().name()) || !is_model(element) {
+                continue;
+            }
+            match self.flow_node_event(element) {
+                Ok(event) => self.add_node(element, event, &lane_of),
+                Err(reason) => self.warn(element, reason),
+            }
+        }
+    }
+
+    /// Adds the lanes of a lane set to the last pool, nested in `parent`. Nodes are remembered in
+    /// the innermost lane that refers to them.
+    fn read_lanes(
+        &mut self,
+        lane_set: &XmlNode,
+        parent: Option<&String>,
+        lane_of: &mut HashMap<String, String>,
+    ) {
+        for lane in lane_set.children().filter(|node| is(node, "lane")) {
+            let name = name_or_id(&lane);
+            let shape = self.shape_of(&lane);
+            let Some(pool) = self.graph.pools.last_mut() else {
+                return;
+            };
+            pool.add_lane(name.clone(), parent.cloned());
+            if let (Some(id), Some(lanes)) = (lane.attribute("id"), self.ids.lanes.last_mut()) {
+                lanes.entry(name.clone()).or_insert_with(|| id.to_string());
+            }
+            let added = pool.lanes.iter_mut().find(|lane| lane.get_lane() == &name);
+            if let (Some((x, y, width, height)), Some(added)) = (shape, added) {
+                added.set_position(x, y);
+                added.set_width(width);
+                added.set_height(height);
+            }
+            for node_ref in lane.children().filter(|node| is(node, "flowNodeRef")) {
+                if let Some(id) = node_ref.text() {
+                    lane_of.insert(id.trim().to_string(), name.clone());
+                }
+            }
+            for child_lane_set in lane.children().filter(|node| is(node, "childLaneSet")) {
+                self.read_lanes(&child_lane_set, Some(&name), lane_of);
+            }
+        }
+    }
+
+    fn add_node(&mut self, element: &XmlNode, event: BpmnEvent, lane_of: &HashMap<String, String>) {
+        let id = element.attribute("id").unwrap_or_default().to_string();
+        let node_id = self.node_ids.len() + 1;
+        let Some(pool) = self.graph.pools.last_mut() else {
+            return;
+        };
+        let mut node = Node::new(
+            node_id,
+            None,
+            None,
+            Some(event),
+            Some(pool.get_pool_name()),
+            lane_of.get(&id).cloned(),
+        );
+        if let Some((x, y, _, _)) = self.shapes.get(&id) {
+            node.set_position(*x, *y, 0.0, 0.0);
+        }
+        pool.add_node(node);
+        self.node_ids.insert(id, node_id);
+    }
+
+    /// The event of a flow node, or why the graph cannot hold the element
+    fn flow_node_event(&mut self, element: &XmlNode) -> Result<BpmnEvent, &'static str> {
+        let label = element.attribute("name").unwrap_or_default().to_string();
+        let definition = element
+            .children()
+            .find(|node| node.is_element() && node.tag_name().name().ends_with("EventDefinition"))
+            .map(|node| node.tag_name().name());
+        let event = match (element.tag_name().name(), definition) {
+            ("startEvent", None) => BpmnEvent::Start(label),
+            ("startEvent", Some("timerEventDefinition")) => BpmnEvent::StartTimerEvent(label),
+            ("startEvent", Some("signalEventDefinition")) => BpmnEvent::StartSignalEvent(label),
+            ("startEvent", Some("messageEventDefinition")) => BpmnEvent::StartMessageEvent(label),
+            ("startEvent", Some("conditionalEventDefinition")) => {
+                BpmnEvent::StartConditionalEvent(label)
+            }
+            ("endEvent", None) => BpmnEvent::End(label),
+            ("endEvent", Some("errorEventDefinition")) => BpmnEvent::EndErrorEvent(label),
+            ("endEvent", Some("cancelEventDefinition")) => BpmnEvent::EndCancelEvent(label),
+            ("endEvent", Some("signalEventDefinition")) => BpmnEvent::EndSignalEvent(label),
+            ("endEvent", Some("messageEventDefinition")) => BpmnEvent::EndMessageEvent(label),
+            ("endEvent", Some("terminateEventDefinition")) => BpmnEvent::EndTerminateEvent(label),
+            ("endEvent", Some("escalationEventDefinition")) => BpmnEvent::EndEscalationEvent(label),
+            ("endEvent", Some("compensateEventDefinition")) => {
+                BpmnEvent::EndCompensationEvent(label)
+            }
+            ("intermediateCatchEvent" | "intermediateThrowEvent", None) => BpmnEvent::Middle(label),
+            ("task", _) => BpmnEvent::ActivityTask(label),
+            ("userTask", _) => BpmnEvent::TaskUser(label),
+            ("serviceTask", _) => BpmnEvent::TaskService(label),
+            ("businessRuleTask", _) => BpmnEvent::TaskBusinessRule(label),
+            ("scriptTask", _) => BpmnEvent::TaskScript(label),
+            ("callActivity", _) => BpmnEvent::ActivityCallActivity(label),
+            ("subProcess", _) if element.attribute("triggeredByEvent") == Some("true") => {
+                BpmnEvent::ActivityEventSubprocess(label)
+            }
+            ("subProcess", _) => BpmnEvent::ActivitySubprocess(label),
+            ("transaction", _) => BpmnEvent::ActivityTransaction(label),
+            ("exclusiveGateway", _) => BpmnEvent::GatewayExclusive,
+            ("inclusiveGateway", _) => BpmnEvent::GatewayInclusive,
+            ("parallelGateway", _) => BpmnEvent::GatewayParallel,
+            ("eventBasedGateway", _) => BpmnEvent::GatewayEvent,
+            ("dataStoreReference", _) => BpmnEvent::DataStoreReference(label),
+            ("dataObjectReference", _) => BpmnEvent::DataObjectReference(label),
+            ("boundaryEvent", definition) => {
+                return self.boundary_event(element, label, definition)
+            }
+            // Close enough to keep the flow, but what makes them special is lost
+            ("sendTask" | "receiveTask" | "manualTask", _) => {
+                self.warn(element, "was imported as a plain task");
+                BpmnEvent::ActivityTask(label)
+            }
+            ("intermediateCatchEvent" | "intermediateThrowEvent", Some(_)) => {
+                self.warn(element, "was imported as a plain intermediate event");
+                BpmnEvent::Middle(label)
+            }
+            ("startEvent", Some(_)) => {
+                self.warn(element, "was imported as a plain start event");
+                BpmnEvent::Start(label)
+            }
+            _ => return Err(UNSUPPORTED),
+        };
+        if event.is_activity() && element.children().any(|node| is_flow_element(&node)) {
+            self.warn(element, "was imported without the elements inside of it");
+        }
+        Ok(event)
+    }
+
+    fn boundary_event(
+        &self,
+        element: &XmlNode,
+        label: String,
+        definition: Option<&str>,
+    ) -> Result<BpmnEvent, &'static str> {
+        let Some(&attached) = element
+            .attribute("attachedToRef")
+            .and_then(|id| self.node_ids.get(id))
+        else {
+            return Err("is attached to an element that was not imported, so it was skipped");
+        };
+        let cancel = element.attribute("cancelActivity") != Some("false");
+        Ok(match definition {
+            Some("errorEventDefinition") => BpmnEvent::BoundaryErrorEvent(label, attached, cancel),
+            Some("timerEventDefinition") => BpmnEvent::BoundaryTimerEvent(label, attached, cancel),
+            Some("cancelEventDefinition") => {
+                BpmnEvent::BoundaryCancelEvent(label, attached, cancel)
+            }
+            Some("signalEventDefinition") => {
+                BpmnEvent::BoundarySignalEvent(label, attached, cancel)
+            }
+            Some("messageEventDefinition") => {
+                BpmnEvent::BoundaryMessageEvent(label, attached, cancel)
+            }
+            Some("escalationEventDefinition") => {
+                BpmnEvent::BoundaryEscalationEvent(label, attached, cancel)
+            }
+            Some("conditionalEventDefinition") => {
+                BpmnEvent::BoundaryConditionalEvent(label, attached, cancel)
+            }
+            Some("compensateEventDefinition") => {
+                BpmnEvent::BoundaryCompensationEvent(label, attached)
+            }
+            _ => BpmnEvent::BoundaryEvent(label, attached, cancel),
+        })
+    }
+
+    /// Reads a sequence or message flow as an edge, with its waypoints and label bounds
+    fn read_flow(&mut self, flow: &XmlNode) {
+        let ends = (
+            flow.attribute("sourceRef")
+                .and_then(|id| self.node_ids.get(id)),
+            flow.attribute("targetRef")
+                .and_then(|id| self.node_ids.get(id)),
+        );
+        let (Some(&from), Some(&to)) = ends else {
+            self.warn(
+                flow,
+                "connects an element that was not imported, so it was skipped",
+            );
+            return;
+        };
+        let text = flow
+            .attribute("name")
+            .filter(|name| !name.is_empty())
+            .map(str::to_string);
+        let mut edge = Edge::new(from, to, text);
+        let id = flow.attribute("id").unwrap_or_default();
+        if let Some((points, label)) = self.edges.get(id) {
+            edge.bend_points = Some(points.clone());
+            edge.label_bounds = *label;
+        }
+        self.graph.add_edge(edge);
+        self.ids.flows.push(id.to_string());
+    }
+
+    /// Reads a message flow between a collapsed pool and a flow node, with its waypoints and label
+    fn read_pool_flow(&mut self, flow: &XmlNode, node: &str, pool: usize, to_pool: bool) {
+        let Some(&node) = self.node_ids.get(node) else {
+            self.warn(
+                flow,
+                "connects an element that was not imported, so it was skipped",
+            );
+            return;
+        };
+        let text = flow
+            .attribute("name")
+            .filter(|name| !name.is_empty())
+            .map(str::to_string);
+        let mut pool_flow = PoolFlow::new(node, pool, to_pool, text);
+        let id = flow.attribute("id").unwrap_or_default();
+        if let Some((points, label)) = self.edges.get(id) {
+            pool_flow.bend_points = Some(points.clone());
+            pool_flow.label_bounds = *label;
+        }
+        self.graph.pool_flows.push(pool_flow);
+        self.ids.pool_flows.push(id.to_string());
+    }
+
+    /// Warns about an element, pointing at where it starts in the XML
+    fn warn(&mut self, element: &XmlNode, what: &str) {
+        let position = self.document.text_pos_at(element.range().start);
+        let id = element.attribute("id").unwrap_or_default();
+        self.warnings.push(Diagnostic {
+            line: Some(position.row as usize),
+            column: Some(position.col as usize),
+            ..Diagnostic::warning(format!(
+                "The {} '{}' {}",
+                element.tag_name().name(),
+                id,
+                what
+            ))
+        });
+    }
+}
+
+/// The name of an element, or its id if it has no name
+fn name_or_id(element: &XmlNode) -> String {
+    element
+        .attribute("name")
+        .filter(|name| !name.is_empty())
+        .or_else(|| element.attribute("id"))
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Flow nodes and sequence flows, the elements a sub-process can hold
+fn is_flow_element(node: &XmlNode) -> bool {
+    let name = node.tag_name().name();
+    is_model(node)
+        && (name.ends_with("Event")
+            || name.ends_with("Task")
+            || name.ends_with("Gateway")
+            || [
+                "task",
+                "subProcess",
+                "callActivity",
+                "transaction",
+                "sequenceFlow",
+            ]
+            .contains(&name))
+}
+
+/// Elements of the BPMN model, the ones of diagrams and modeler extensions are in other namespaces
+fn is_model(node: &XmlNode) -> bool {
+    node.is_element()
+        && node
+            .tag_name()
+            .namespace()
+            .is_none_or(|namespace| namespace == MODEL_NAMESPACE)
+}
+
+pub(crate) fn is(node: &XmlNode, name: &str) -> bool {
+    node.is_element() && node.tag_name().name() == name
+}
+
+fn number(node: &XmlNode, attribute: &str) -> Option<f64> {
+    node.attribute(attribute)?.parse().ok()
+}
+
+/// The `dc:Bounds` of a shape
+pub(crate) fn bounds(shape: &XmlNode) -> Option<Rect> {
+    let bounds = shape.children().find(|node| is(node, "Bounds"))?;
+    Some((
+        number(&bounds, "x")?,
+        number(&bounds, "y")?,
+        number(&bounds, "width")?,
+        number(&bounds, "height")?,
+    ))
+}
+
+/// The `dc:Bounds` of the label of a shape or edge
+fn label_bounds(shape: &XmlNode) -> Option<Rect> {
+    bounds(&shape.children().find(|node| is(node, "BPMNLabel"))?)
+}
+
+/// The `di:waypoint`s of an edge
+pub(crate) fn waypoints(edge: &XmlNode) -> Vec<(f64, f64)> {
+    edge.children()
+        .filter(|node| is(node, "waypoint"))
+        .filter_map(|point| Some((number(&point, "x")?, number(&point, "y")?)))
+        .collect()
+}
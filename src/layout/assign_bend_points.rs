@@ -1,215 +1,762 @@
-use crate::common::bpmn_event::get_node_size;
+use crate::common::bpmn_event::{get_node_size, BpmnEvent};
+use crate::common::edge::PoolFlow;
 use crate::common::graph::Graph;
-use std::collections::BinaryHeap;
-use std::collections::HashMap;
+use crate::layout::{LayoutOptions, Orientation};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::ops::Range;
 
-const STEP_SIZE: usize = 10;
-const NODE_MARGIN: isize = 20;
+// Sides of a node, also used as the direction a route moves in
+const TOP: usize = 0;
+const RIGHT: usize = 1;
+const BOTTOM: usize = 2;
+const LEFT: usize = 3;
+// Routes are made as if the flow went left to right. Edges leave forwards or to the side and
+// enter from behind or from the side, never against the flow. The first side is the preferred one.
+const EXITS: [usize; 3] = [RIGHT, TOP, BOTTOM];
+const ENTRIES: [usize; 3] = [LEFT, TOP, BOTTOM];
 
-pub fn assign_bend_points(graph: &mut Graph) {
-    let mut matrix_width = 0;
-    let mut matrix_height = 0;
+// Costs of a route, in pixels of length
+const BEND_COST: f64 = 30.0; // Each change of direction
+const CROSSING_COST: f64 = 60.0; // Each crossing with an earlier route
+const OVERLAP_COST: f64 = 2.0; // Extra for each pixel run on top of an earlier route
+const SIDE_COST: f64 = 50.0; // Each end that is not on the preferred side
 
-    // HashMap to store coordinates of obstacles with node id as key
-    // HashMap stores tuples of top left and bottom right coordinates of obstacles
-    let mut matrix: HashMap<usize, (usize, usize, usize, usize)> = HashMap::new();
+// x1, y1, x2, y2
+type Rect = (f64, f64, f64, f64);
+// Position of the other end along the side, index of the edge and whether it starts there
+type SharedEnd = (f64, usize, bool);
 
-    for pool in graph.pools.iter() {
-        for lane in pool.lanes.iter() {
+/// Routes every edge orthogonally around nodes and labels. The routes run on a sparse grid made of
+/// the borders of the obstacles, the channels between them and the ports of the nodes, and the
+/// cheapest route wins: long routes, bends, crossings and running along earlier routes all cost.
+/// A first pass picks the sides edges leave and enter by, a second pass spreads the edges that
+/// share a side of a task over that side and routes them again.
+pub fn assign_bend_points(graph: &mut Graph, options: &LayoutOptions) {
+    let margin = options.edge_margin;
+
+    // Exact node bounds and the lanes they are in. They are kept as if the flow went left to
+    // right, so top to bottom layouts are transposed and their routes mirrored back at the end.
+    let transposed = options.orientation == Orientation::TopToBottom;
+    let mut node_boxes: HashMap<usize, NodeBox> = HashMap::new();
+    let mut lane_bounds: HashMap<(usize, usize), (f64, f64)> = HashMap::new();
+    // Edges must not run through nodes or the labels of events and gateways
+    let mut obstacles: Vec<Rect> = Vec::new();
+
+    for (pool_index, pool) in graph.pools.iter().enumerate() {
+        for (lane_index, lane) in pool.lanes.iter().enumerate() {
+            let (lane_y, lane_height) = if transposed {
+                (lane.x, lane.width)
+            } else {
+                (lane.y, lane.height)
+            };
+            if let (Some(lane_y), Some(lane_height)) = (lane_y, lane_height) {
+                lane_bounds.insert((pool_index, lane_index), (lane_y, lane_y + lane_height));
+            }
             for node in lane.layers.iter() {
-                if let (Some(x), Some(y), Some(x_offset), Some(y_offset)) =
-                    (node.x, node.y, node.x_offset, node.y_offset)
-                {
-                    let (width, height) = get_node_size(node.event.as_ref().unwrap());
-                    let x2 = x as usize + width as usize + x_offset as usize;
-                    let y2 = y as usize + height as usize + y_offset as usize;
-                    if x2 > matrix_width {
-                        matrix_width = x2 + 50;
-                    }
-                    if y2 > matrix_height {
-                        matrix_height = y2 + 50;
-                    }
-                    matrix.insert(
-                        node.id.clone(),
-                        (
-                            x as usize + x_offset as usize - NODE_MARGIN as usize,
-                            y as usize + y_offset as usize - NODE_MARGIN as usize,
-                            x2 + NODE_MARGIN as usize,
-                            y2 + NODE_MARGIN as usize,
-                        ),
-                    );
+                let Some(event) = node.event.as_ref() else {
+                    continue;
+                };
+                if let (Some(x), Some(y)) = (node.x, node.y) {
+                    let (width, height) = get_node_size(event);
+                    let x1 = x + node.x_offset.unwrap_or(0.0);
+                    let y1 = y + node.y_offset.unwrap_or(0.0);
+                    let node_box = NodeBox {
+                        x1,
+                        y1,
+                        x2: x1 + width as f64,
+                        y2: y1 + height as f64,
+                        lane: (pool_index, lane_index),
+                        shape: Shape::of(event),
+                    };
+                    let node_box = if transposed {
+                        node_box.transposed()
+                    } else {
+                        node_box
+                    };
+                    obstacles.push((
+                        node_box.x1 - margin,
+                        node_box.y1 - margin,
+                        node_box.x2 + margin,
+                        node_box.y2 + margin,
+                    ));
+                    node_boxes.insert(node.id, node_box);
+                }
+                if let Some((x, y, width, height)) = node.label_bounds(options.orientation) {
+                    obstacles.push(if transposed {
+                        (y, x, y + height, x + width)
+                    } else {
+                        (x, y, x + width, y + height)
+                    });
                 }
             }
         }
     }
 
-    for edge in graph.edges.iter_mut() {
-        let (from_x, from_y, from_x2, from_y2) = matrix.get(&edge.from).unwrap();
-        let (to_x, to_y, to_x2, to_y2) = matrix.get(&edge.to).unwrap();
-
-        // Vector to store different paths
-        let mut choices: HashMap<usize, Vec<(usize, usize)>> = HashMap::new();
-
-        // Add first bend points to choices
-        // There must be a margin before any bend points
-        // So for the top path, the bend point is at (top_start_x, top_start_y - NODE-MARGIN)
-        let start_points_with_margins = vec![
-            ((from_x + (from_x2 - from_x) / 2, *from_y), (0, NODE_MARGIN)), // Top exit
-            (
-                (*from_x2, from_y + (from_y2 - from_y) / 2),
-                (-NODE_MARGIN, 0),
-            ), // Right exit
-            (
-                (from_x + (from_x2 - from_x) / 2, *from_y2),
-                (0, -NODE_MARGIN),
-            ), // Bottom exit
-        ];
-
-        let end_points_with_margins = vec![
-            ((to_x + (to_x2 - to_x) / 2, *to_y), (0, NODE_MARGIN)), // Top entry
-            ((*to_x, to_y + (to_y2 - to_y) / 2), (NODE_MARGIN, 0)), // Left entry
-            ((to_x + (to_x2 - to_x) / 2, *to_y2), (0, -NODE_MARGIN)), // Bottom entry
-        ];
-
-        for (start_point, margin_start) in start_points_with_margins {
-            for (end_point, margin_end) in &end_points_with_margins {
-                let path = find_path(
-                    start_point.0,
-                    start_point.1,
-                    end_point.0,
-                    end_point.1,
-                    matrix_width,
-                    matrix_height,
-                    &matrix,
-                );
-                if path.len() > 0 {
-                    let length = path.len();
-                    let mut bend_points = vec![];
-                    for (x, y) in path {
-                        bend_points.push((x, y));
-                    }
-
-                    // Add the correct start and end coordinates
-                    bend_points.insert(
-                        0,
-                        (
-                            (start_point.0 as isize + margin_start.0) as usize,
-                            (start_point.1 as isize + margin_start.1) as usize,
-                        ),
-                    );
-                    bend_points.push((
-                        (end_point.0 as isize + margin_end.0) as usize,
-                        (end_point.1 as isize + margin_end.1) as usize,
-                    ));
-                    choices.insert(length, bend_points);
-                }
+    // Back edges within a lane loop around it, everything else goes to the router
+    let mut routes: Vec<Option<Vec<(f64, f64)>>> = vec![None; graph.edges.len()];
+    let mut routed: Vec<(usize, usize, usize)> = Vec::new(); // Edge index, from and to
+    for (index, edge) in graph.edges.iter().enumerate() {
+        if !node_boxes.contains_key(&edge.from) || !node_boxes.contains_key(&edge.to) {
+            continue;
+        }
+        if edge.is_back_edge {
+            if let Some(points) = route_back_edge(edge.from, edge.to, &node_boxes, &lane_bounds) {
+                routes[index] = Some(points);
+                continue;
             }
         }
+        routed.push((index, edge.from, edge.to));
+    }
 
-        // Add shortest path to edge
-        if choices.len() > 0 {
-            let mut min = usize::MAX;
-            let mut min_path = vec![];
-            for (length, path) in choices.iter() {
-                if *length < min {
-                    min = *length;
-                    min_path = path.clone();
-                }
+    // First pass, every end may take any of its sides unless it is at a gateway with several edges
+    let gateway_sides = assign_gateway_ports(&routed, &node_boxes);
+    let mut port_xs: Vec<f64> = node_boxes.values().map(NodeBox::center_x).collect();
+    let mut port_ys: Vec<f64> = node_boxes.values().map(NodeBox::center_y).collect();
+    let grid = Grid::new(&obstacles, &port_xs, &port_ys, margin);
+    let mut usage = Usage::new(&grid);
+    let mut sides: HashMap<usize, (usize, usize)> = HashMap::new();
+    for &(index, from, to) in &routed {
+        let (from_box, to_box) = (&node_boxes[&from], &node_boxes[&to]);
+        let starts: Vec<Port> = match gateway_sides.get(&(index, true)) {
+            Some(&side) => vec![from_box.port(side, None, margin, 0.0)],
+            None => EXITS
+                .iter()
+                .enumerate()
+                .map(|(rank, &side)| from_box.port(side, None, margin, side_cost(rank)))
+                .collect(),
+        };
+        let ends: Vec<Port> = match gateway_sides.get(&(index, false)) {
+            Some(&side) => vec![to_box.port(side, None, margin, 0.0)],
+            None => ENTRIES
+                .iter()
+                .enumerate()
+                .map(|(rank, &side)| to_box.port(side, None, margin, side_cost(rank)))
+                .collect(),
+        };
+        if let Some((path, start, end)) = grid.route(&starts, &ends, &usage) {
+            usage.add(&path);
+            sides.insert(index, (starts[start].side, ends[end].side));
+            routes[index] = Some(finish_route(&grid, &path, &starts[start], &ends[end]));
+        }
+    }
+
+    // Second pass, edges that share a side of a task leave or enter it side by side,
+    // ordered by where their other end is
+    let mut shared: HashMap<(usize, usize), Vec<SharedEnd>> = HashMap::new();
+    for &(index, from, to) in &routed {
+        if let Some(&(exit, entry)) = sides.get(&index) {
+            let (from_box, to_box) = (&node_boxes[&from], &node_boxes[&to]);
+            if from_box.shape == Shape::Rectangle {
+                let order = to_box.along_side(exit);
+                shared
+                    .entry((from, exit))
+                    .or_default()
+                    .push((order, index, true));
             }
-            edge.bend_points = Some(
-                min_path
-                    .iter()
-                    .map(|&(x, y)| (x as f64, y as f64))
-                    .collect(),
-            );
+            if to_box.shape == Shape::Rectangle {
+                let order = from_box.along_side(entry);
+                shared
+                    .entry((to, entry))
+                    .or_default()
+                    .push((order, index, false));
+            }
+        }
+    }
+    // Offsets from the middle of the side, by edge index and whether the edge starts there
+    let mut offsets: HashMap<(usize, bool), f64> = HashMap::new();
+    for ((node, side), mut ends) in shared {
+        if ends.len() < 2 {
+            continue;
         }
-        println!("Edge from {} to {} routed", edge.from, edge.to);
-    }
-}
-
-fn find_path(
-    cur_x: usize,
-    cur_y: usize,
-    end_x: usize,
-    end_y: usize,
-    matrix_width: usize,
-    matrix_height: usize,
-    matrix: &HashMap<usize, (usize, usize, usize, usize)>,
-) -> Vec<(usize, usize)> {
-    let mut open_set = BinaryHeap::new();
-    let mut came_from = HashMap::new();
-    let mut g_score = HashMap::new();
-    let mut f_score = HashMap::new();
-
-    open_set.push((0, cur_x, cur_y));
-    g_score.insert((cur_x, cur_y), 0);
-
-    while let Some((_, mut x, mut y)) = open_set.pop() {
-        if ((x as isize - end_x as isize).abs() <= STEP_SIZE as isize)
-            && ((y as isize - end_y as isize).abs() <= STEP_SIZE as isize)
-        {
-            let mut path = vec![(x, y)];
-            while let Some(&(px, py)) = came_from.get(&(x, y)) {
-                path.push((px, py));
-                x = px;
-                y = py;
-            }
-            path.reverse();
-            return path;
-        }
-
-        for (dx, dy) in [
-            (0, STEP_SIZE as isize),
-            (STEP_SIZE as isize, 0),
-            (0, -(STEP_SIZE as isize)),
-            (-(STEP_SIZE as isize), 0),
-        ] {
-            let nx = x as isize + dx;
-            let ny = y as isize + dy;
-
-            if nx < 0 || ny < 0 {
+        ends.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        let node_box = &node_boxes[&node];
+        let length = match side {
+            TOP | BOTTOM => node_box.x2 - node_box.x1,
+            _ => node_box.y2 - node_box.y1,
+        };
+        let spacing = options.edge_spacing.min(length / ends.len() as f64);
+        let middle = (ends.len() - 1) as f64 / 2.0;
+        for (position, (_, index, is_start)) in ends.into_iter().enumerate() {
+            let offset = (position as f64 - middle) * spacing;
+            offsets.insert((index, is_start), offset);
+            match side {
+                TOP | BOTTOM => port_xs.push(node_box.center_x() + offset),
+                _ => port_ys.push(node_box.center_y() + offset),
+            }
+        }
+    }
+    if !offsets.is_empty() {
+        let grid = Grid::new(&obstacles, &port_xs, &port_ys, margin);
+        let mut usage = Usage::new(&grid);
+        for &(index, from, to) in &routed {
+            let Some(&(exit, entry)) = sides.get(&index) else {
                 continue;
+            };
+            let (from_box, to_box) = (&node_boxes[&from], &node_boxes[&to]);
+            let start = from_box.port(exit, offsets.get(&(index, true)).copied(), margin, 0.0);
+            let end = to_box.port(entry, offsets.get(&(index, false)).copied(), margin, 0.0);
+            let starts = [start];
+            let ends = [end];
+            if let Some((path, _, _)) = grid.route(&starts, &ends, &usage) {
+                usage.add(&path);
+                routes[index] = Some(finish_route(&grid, &path, &starts[0], &ends[0]));
             }
+        }
+    }
 
-            let nx = nx as usize;
-            let ny = ny as usize;
+    for (edge, route) in graph.edges.iter_mut().zip(routes) {
+        edge.bend_points = route.map(|mut points| {
+            if transposed {
+                points.iter_mut().for_each(|(x, y)| std::mem::swap(x, y));
+            }
+            points
+        });
+    }
+    route_pool_flows(graph);
+}
 
-            if !is_in_grid(nx, ny, matrix_width, matrix_height) || is_in_obstacle(nx, ny, &matrix) {
-                continue;
+/// Draws the message flows of collapsed pools as a straight line from the middle of the side of
+/// the node that faces the band of the pool to the band
+pub(crate) fn route_pool_flows(graph: &mut Graph) {
+    let routes: Vec<_> = graph
+        .pool_flows
+        .iter()
+        .map(|flow| pool_flow_route(graph, flow))
+        .collect();
+    for (flow, route) in graph.pool_flows.iter_mut().zip(routes) {
+        flow.bend_points = route;
+    }
+}
+
+fn pool_flow_route(graph: &Graph, flow: &PoolFlow) -> Option<Vec<(f64, f64)>> {
+    let node = graph.get_node_by_id(flow.node)?;
+    let pool = graph.pools.get(flow.pool)?;
+    let (width, height) = get_node_size(node.event.as_ref()?);
+    let x1 = node.x? + node.x_offset.unwrap_or(0.0);
+    let y1 = node.y? + node.y_offset.unwrap_or(0.0);
+    let (x2, y2) = (x1 + width as f64, y1 + height as f64);
+    let (band_x1, band_y1) = (pool.x?, pool.y?);
+    let (band_x2, band_y2) = (band_x1 + pool.width?, band_y1 + pool.height?);
+
+    let (center_x, center_y) = ((x1 + x2) / 2.0, (y1 + y2) / 2.0);
+    let across = center_x.clamp(band_x1, band_x2);
+    let along = center_y.clamp(band_y1, band_y2);
+    let (at_node, at_band) = if band_y2 <= y1 {
+        ((center_x, y1), (across, band_y2))
+    } else if band_y1 >= y2 {
+        ((center_x, y2), (across, band_y1))
+    } else if band_x2 <= x1 {
+        ((x1, center_y), (band_x2, along))
+    } else {
+        ((x2, center_y), (band_x1, along))
+    };
+    Some(if flow.to_pool {
+        vec![at_node, at_band]
+    } else {
+        vec![at_band, at_node]
+    })
+}
+
+/// Spreads the edges at a gateway that has several of them on one end over its corners, in the
+/// order of the nodes at their other end. An edge to a node above the gateway leaves from the top
+/// corner, to a node in line from the front corner and to a node below from the bottom corner, and
+/// up to three edges each get a corner of their own. Edges into a gateway use the top, back and
+/// bottom corners the same way.
+fn assign_gateway_ports(
+    routed: &[(usize, usize, usize)],
+    node_boxes: &HashMap<usize, NodeBox>,
+) -> HashMap<(usize, bool), usize> {
+    // Position of the other end across the flow and the edge index, by gateway and direction
+    let mut ends: HashMap<(usize, bool), Vec<(f64, usize)>> = HashMap::new();
+    for &(index, from, to) in routed {
+        let (from_box, to_box) = (&node_boxes[&from], &node_boxes[&to]);
+        if from_box.shape == Shape::Diamond {
+            ends.entry((from, true))
+                .or_default()
+                .push((to_box.center_y(), index));
+        }
+        if to_box.shape == Shape::Diamond {
+            ends.entry((to, false))
+                .or_default()
+                .push((from_box.center_y(), index));
+        }
+    }
+
+    let mut sides = HashMap::new();
+    for ((gateway, outgoing), mut edges) in ends {
+        if edges.len() < 2 {
+            continue;
+        }
+        edges.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        let center = node_boxes[&gateway].center_y();
+        let corners = [TOP, if outgoing { RIGHT } else { LEFT }, BOTTOM];
+        let wanted: Vec<usize> = edges
+            .iter()
+            .map(|&(y, _)| match y - center {
+                offset if offset < -1.0 => 0,
+                offset if offset > 1.0 => 2,
+                _ => 1,
+            })
+            .collect();
+        // Corners in order, as close to the wanted ones as possible
+        let chosen = if edges.len() <= corners.len() {
+            (0..8u32)
+                .filter(|mask| mask.count_ones() as usize == edges.len())
+                .map(|mask| (0..3).filter(|bit| mask & (1 << bit) != 0).collect())
+                .min_by_key(|chosen: &Vec<usize>| {
+                    chosen
+                        .iter()
+                        .zip(&wanted)
+                        .map(|(corner, wanted)| corner.abs_diff(*wanted))
+                        .sum::<usize>()
+                })
+                .unwrap_or(wanted)
+        } else {
+            wanted
+        };
+        for ((_, index), corner) in edges.into_iter().zip(chosen) {
+            sides.insert((index, outgoing), corners[corner]);
+        }
+    }
+    sides
+}
+
+fn side_cost(rank: usize) -> f64 {
+    rank.min(1) as f64 * SIDE_COST
+}
+
+/// Drops repeated points and the points in the middle of straight runs, so every segment ends at a
+/// bend
+pub(crate) fn straighten(points: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    let mut route: Vec<(f64, f64)> = Vec::with_capacity(points.len());
+    for &point in points {
+        if route.last() == Some(&point) {
+            continue;
+        }
+        if let [.., (x1, y1), (x2, y2)] = route[..] {
+            if (x2 - x1) * (point.1 - y2) == (y2 - y1) * (point.0 - x2) {
+                route.pop();
+            }
+        }
+        route.push(point);
+    }
+    route
+}
+
+/// The whole route from the border of one node to the border of the other
+fn finish_route(grid: &Grid, path: &[usize], start: &Port, end: &Port) -> Vec<(f64, f64)> {
+    let mut points = vec![start.point];
+    points.extend(path.iter().map(|&index| grid.point(index)));
+    points.push(end.point);
+    straighten(&points)
+}
+
+/// Where an edge meets a node: the point on its border and the point `margin` away from it,
+/// where the route on the grid starts or ends
+struct Port {
+    point: (f64, f64),
+    stub: (f64, f64),
+    side: usize,
+    cost: f64,
+}
+
+struct NodeBox {
+    x1: f64,
+    y1: f64,
+    x2: f64,
+    y2: f64,
+    lane: (usize, usize), // Pool and lane index
+    shape: Shape,
+}
+
+/// Outline of a node, which decides where edges can meet it
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Shape {
+    Rectangle, // Anywhere along a side
+    Diamond,   // Only at the corners
+    Round,     // Only at the middle of a side
+}
+
+impl Shape {
+    fn of(event: &BpmnEvent) -> Shape {
+        if event.is_activity() {
+            Shape::Rectangle
+        } else if event.is_gateway() {
+            Shape::Diamond
+        } else {
+            Shape::Round
+        }
+    }
+}
+
+impl NodeBox {
+    fn transposed(&self) -> NodeBox {
+        NodeBox {
+            x1: self.y1,
+            y1: self.x1,
+            x2: self.y2,
+            y2: self.x2,
+            lane: self.lane,
+            shape: self.shape,
+        }
+    }
+
+    fn center_x(&self) -> f64 {
+        (self.x1 + self.x2) / 2.0
+    }
+
+    fn center_y(&self) -> f64 {
+        (self.y1 + self.y2) / 2.0
+    }
+
+    /// Position of the middle of the node along the given side
+    fn along_side(&self, side: usize) -> f64 {
+        match side {
+            TOP | BOTTOM => self.center_x(),
+            _ => self.center_y(),
+        }
+    }
+
+    /// Port on a side, moved `offset` from its middle along the side
+    fn port(&self, side: usize, offset: Option<f64>, margin: f64, cost: f64) -> Port {
+        let along = self.along_side(side) + offset.unwrap_or(0.0);
+        let (point, stub) = match side {
+            TOP => ((along, self.y1), (along, self.y1 - margin)),
+            RIGHT => ((self.x2, along), (self.x2 + margin, along)),
+            BOTTOM => ((along, self.y2), (along, self.y2 + margin)),
+            _ => ((self.x1, along), (self.x1 - margin, along)),
+        };
+        Port {
+            point,
+            stub,
+            side,
+            cost,
+        }
+    }
+}
+
+/// Routes an edge that points back against the flow as a loop over or under the nodes of its lane,
+/// whichever side is closer to both ends. Edges between lanes are left to the regular router.
+fn route_back_edge(
+    from: usize,
+    to: usize,
+    node_boxes: &HashMap<usize, NodeBox>,
+    lane_bounds: &HashMap<(usize, usize), (f64, f64)>,
+) -> Option<Vec<(f64, f64)>> {
+    let from_box = node_boxes.get(&from)?;
+    let to_box = node_boxes.get(&to)?;
+    if from_box.lane != to_box.lane {
+        return None;
+    }
+    let (lane_top, lane_bottom) = *lane_bounds.get(&from_box.lane)?;
+
+    let (mut start_x, mut end_x) = (from_box.center_x(), to_box.center_x());
+    if from == to {
+        // A loop on a single node leaves and enters it side by side
+        let quarter = (from_box.x2 - from_box.x1) / 4.0;
+        start_x += quarter;
+        end_x -= quarter;
+    }
+
+    // The loop has to clear every node of the lane it passes
+    let (left, right) = (start_x.min(end_x), start_x.max(end_x));
+    let passed = node_boxes
+        .values()
+        .filter(|node| node.lane == from_box.lane && node.x2 >= left && node.x1 <= right);
+    let top = passed
+        .clone()
+        .map(|node| node.y1)
+        .fold(f64::INFINITY, f64::min);
+    let bottom = passed.map(|node| node.y2).fold(f64::NEG_INFINITY, f64::max);
+
+    let over_y = (lane_top + top) / 2.0;
+    let under_y = (bottom + lane_bottom) / 2.0;
+    let over_length = (from_box.y1 - over_y) + (to_box.y1 - over_y);
+    let under_length = (under_y - from_box.y2) + (under_y - to_box.y2);
+
+    let (loop_y, start_y, end_y) = if over_length <= under_length {
+        (over_y, from_box.y1, to_box.y1)
+    } else {
+        (under_y, from_box.y2, to_box.y2)
+    };
+    Some(vec![
+        (start_x, start_y),
+        (start_x, loop_y),
+        (end_x, loop_y),
+        (end_x, end_y),
+    ])
+}
+
+/// Sparse orthogonal grid through the borders of the obstacles, the middles of the channels
+/// between them and the ports of the nodes. Points inside obstacles and lines through them are
+/// left out.
+struct Grid {
+    xs: Vec<f64>,
+    ys: Vec<f64>,
+    blocked: Vec<bool>,       // The point is inside an obstacle
+    blocked_right: Vec<bool>, // The line to the next point to the right runs through an obstacle
+    blocked_down: Vec<bool>,  // The line to the next point below runs through an obstacle
+}
+
+impl Grid {
+    fn new(obstacles: &[Rect], port_xs: &[f64], port_ys: &[f64], margin: f64) -> Self {
+        let xs = grid_lines(obstacles.iter().flat_map(|o| [o.0, o.2]), port_xs, margin);
+        let ys = grid_lines(obstacles.iter().flat_map(|o| [o.1, o.3]), port_ys, margin);
+        let count = xs.len() * ys.len();
+        let mut grid = Grid {
+            xs,
+            ys,
+            blocked: vec![false; count],
+            blocked_right: vec![false; count],
+            blocked_down: vec![false; count],
+        };
+
+        for &(x1, y1, x2, y2) in obstacles {
+            // Lines strictly inside the obstacle, and the lines just before them
+            let (inside_x, inside_y) = (
+                lines_between(&grid.xs, x1, x2),
+                lines_between(&grid.ys, y1, y2),
+            );
+            let crossing_x = inside_x.start.saturating_sub(1)..inside_x.end.min(grid.xs.len() - 1);
+            let crossing_y = inside_y.start.saturating_sub(1)..inside_y.end.min(grid.ys.len() - 1);
+            for i in inside_x.clone() {
+                for j in inside_y.clone() {
+                    let index = grid.index(i, j);
+                    grid.blocked[index] = true;
+                }
             }
+            for i in crossing_x {
+                for j in inside_y.clone() {
+                    let index = grid.index(i, j);
+                    grid.blocked_right[index] = true;
+                }
+            }
+            for i in inside_x.clone() {
+                for j in crossing_y.clone() {
+                    let index = grid.index(i, j);
+                    grid.blocked_down[index] = true;
+                }
+            }
+        }
+        grid
+    }
+
+    fn index(&self, i: usize, j: usize) -> usize {
+        i * self.ys.len() + j
+    }
+
+    fn point(&self, index: usize) -> (f64, f64) {
+        (
+            self.xs[index / self.ys.len()],
+            self.ys[index % self.ys.len()],
+        )
+    }
 
-            let tentative_g_score = g_score.get(&(x, y)).unwrap_or(&usize::MAX) + 1;
+    fn find(&self, (x, y): (f64, f64)) -> Option<usize> {
+        let find_line = |lines: &[f64], value: f64| {
+            let index = lines.partition_point(|&line| line < value - 1e-6);
+            (index < lines.len() && (lines[index] - value).abs() < 1e-6).then_some(index)
+        };
+        let index = self.index(find_line(&self.xs, x)?, find_line(&self.ys, y)?);
+        (!self.blocked[index]).then_some(index)
+    }
 
-            if tentative_g_score < *g_score.get(&(nx, ny)).unwrap_or(&usize::MAX) {
-                came_from.insert((nx, ny), (x, y));
-                g_score.insert((nx, ny), tentative_g_score);
-                let h_score = find_distance_to_end(nx, ny, end_x, end_y);
-                f_score.insert((nx, ny), tentative_g_score + h_score);
-                open_set.push((-(tentative_g_score as isize + h_score as isize), nx, ny));
+    /// The next point in a direction, if the line to it is free
+    fn neighbour(&self, index: usize, direction: usize) -> Option<usize> {
+        let height = self.ys.len();
+        let (i, j) = (index / height, index % height);
+        let next = match direction {
+            TOP if j > 0 && !self.blocked_down[index - 1] => index - 1,
+            BOTTOM if j + 1 < height && !self.blocked_down[index] => index + 1,
+            LEFT if i > 0 && !self.blocked_right[index - height] => index - height,
+            RIGHT if i + 1 < self.xs.len() && !self.blocked_right[index] => index + height,
+            _ => return None,
+        };
+        (!self.blocked[next]).then_some(next)
+    }
+
+    /// Cheapest route from the stub of one of the starts to the stub of one of the ends, as grid
+    /// points, with the indices of the start and end it connects
+    fn route(
+        &self,
+        starts: &[Port],
+        ends: &[Port],
+        usage: &Usage,
+    ) -> Option<(Vec<usize>, usize, usize)> {
+        // A state is a point and the direction the route moved in to reach it
+        let mut costs = vec![f64::INFINITY; self.blocked.len() * 4];
+        let mut previous = vec![usize::MAX; costs.len()];
+        let mut queue = BinaryHeap::new();
+        for start in starts {
+            if let Some(index) = self.find(start.stub) {
+                let state = index * 4 + start.side;
+                if start.cost < costs[state] {
+                    costs[state] = start.cost;
+                    queue.push(Step(start.cost, state));
+                }
             }
         }
+        let targets: Vec<(usize, usize)> = ends
+            .iter()
+            .enumerate()
+            .filter_map(|(end, port)| Some((self.find(port.stub)?, end)))
+            .collect();
+
+        let mut best: Option<(f64, usize, usize)> = None; // Cost, last state and end
+        while let Some(Step(cost, state)) = queue.pop() {
+            if cost > costs[state] {
+                continue;
+            }
+            if best.is_some_and(|(best_cost, _, _)| cost >= best_cost) {
+                break;
+            }
+            let (index, direction) = (state / 4, state % 4);
+            for &(_, end) in targets.iter().filter(|(target, _)| *target == index) {
+                // The route has to turn unless it already moves into the node
+                let inward = (ends[end].side + 2) % 4;
+                let bend = if direction == inward { 0.0 } else { BEND_COST };
+                let total = cost + bend + ends[end].cost;
+                if best.is_none_or(|(best_cost, _, _)| total < best_cost) {
+                    best = Some((total, state, end));
+                }
+            }
+
+            for next_direction in [TOP, RIGHT, BOTTOM, LEFT] {
+                if next_direction == (direction + 2) % 4 {
+                    continue;
+                }
+                let Some(next) = self.neighbour(index, next_direction) else {
+                    continue;
+                };
+                let (x, y) = self.point(index);
+                let (next_x, next_y) = self.point(next);
+                let length = (next_x - x).abs() + (next_y - y).abs();
+                let mut step = length * (1.0 + OVERLAP_COST * usage.along(index, next) as f64);
+                if next_direction != direction {
+                    step += BEND_COST;
+                }
+                if usage.crossed(next, next_direction) {
+                    step += CROSSING_COST;
+                }
+                let next_state = next * 4 + next_direction;
+                if cost + step < costs[next_state] {
+                    costs[next_state] = cost + step;
+                    previous[next_state] = state;
+                    queue.push(Step(cost + step, next_state));
+                }
+            }
+        }
+
+        let (_, mut state, end) = best?;
+        let mut path = vec![state / 4];
+        while previous[state] != usize::MAX {
+            state = previous[state];
+            path.push(state / 4);
+        }
+        path.reverse();
+        let start = starts
+            .iter()
+            .position(|port| self.find(port.stub) == Some(path[0]) && port.side == state % 4)?;
+        Some((path, start, end))
     }
+}
 
-    vec![]
+/// Sorted grid lines through the borders of the obstacles, the middles between them, the ports and
+/// a ring around everything
+fn grid_lines(borders: impl Iterator<Item = f64>, ports: &[f64], margin: f64) -> Vec<f64> {
+    let mut borders: Vec<f64> = borders.collect();
+    sort_lines(&mut borders);
+    let mut lines: Vec<f64> = borders
+        .windows(2)
+        .map(|pair| (pair[0] + pair[1]) / 2.0)
+        .chain(ports.iter().copied())
+        .collect();
+    if let (Some(first), Some(last)) = (borders.first(), borders.last()) {
+        lines.extend([first - margin, last + margin]);
+    }
+    lines.extend(borders);
+    sort_lines(&mut lines);
+    lines
 }
 
-fn is_in_grid(x: usize, y: usize, matrix_width: usize, matrix_height: usize) -> bool {
-    x < matrix_width && y < matrix_height
+/// Indices of the lines strictly between `from` and `to`
+fn lines_between(lines: &[f64], from: f64, to: f64) -> Range<usize> {
+    lines.partition_point(|&line| line <= from)..lines.partition_point(|&line| line < to)
 }
 
-fn is_in_obstacle(
-    x: usize,
-    y: usize,
-    matrix: &HashMap<usize, (usize, usize, usize, usize)>,
-) -> bool {
-    for (_, (x1, y1, x2, y2)) in matrix.iter() {
-        if x >= *x1 && x <= *x2 && y >= *y1 && y <= *y2 {
-            return true;
+fn sort_lines(lines: &mut Vec<f64>) {
+    lines.sort_by(f64::total_cmp);
+    lines.dedup_by(|a, b| (*a - *b).abs() < 1e-6);
+}
+
+/// Routes found so far, as soft obstacles for the next ones
+struct Usage {
+    height: usize,
+    segments: HashMap<(usize, usize), usize>, // Routes on the line between two neighbouring points
+    through: Vec<[usize; 2]>,                 // Routes at each point, horizontally and vertically
+}
+
+impl Usage {
+    fn new(grid: &Grid) -> Self {
+        Usage {
+            height: grid.ys.len(),
+            segments: HashMap::new(),
+            through: vec![[0, 0]; grid.blocked.len()],
         }
     }
-    false
+
+    fn add(&mut self, path: &[usize]) {
+        for pair in path.windows(2) {
+            let (from, to) = (pair[0].min(pair[1]), pair[0].max(pair[1]));
+            *self.segments.entry((from, to)).or_default() += 1;
+            let axis = self.axis(from, to);
+            self.through[from][axis] += 1;
+            self.through[to][axis] += 1;
+        }
+    }
+
+    fn along(&self, a: usize, b: usize) -> usize {
+        self.segments
+            .get(&(a.min(b), a.max(b)))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Whether an earlier route passes the point across the given direction
+    fn crossed(&self, index: usize, direction: usize) -> bool {
+        let across = match direction {
+            LEFT | RIGHT => 1,
+            _ => 0,
+        };
+        self.through[index][across] > 0
+    }
+
+    // 0 for horizontal lines, 1 for vertical ones
+    fn axis(&self, from: usize, to: usize) -> usize {
+        usize::from(to - from < self.height)
+    }
 }
 
-fn find_distance_to_end(x: usize, y: usize, end_x: usize, end_y: usize) -> usize {
-    ((x as isize - end_x as isize).abs() + (y as isize - end_y as isize).abs()) as usize
+/// Entry of the search queue, the cheapest comes out first
+struct Step(f64, usize);
+
+impl PartialEq for Step {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Step {}
+
+impl PartialOrd for Step {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Step {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0).then(other.1.cmp(&self.1))
+    }
 }
@@ -0,0 +1,93 @@
+// boundary_events.rs
+use crate::common::bpmn_event::{get_node_size, BpmnEvent};
+use crate::common::graph::Graph;
+use std::collections::{HashMap, HashSet};
+
+/// Moves every boundary event onto the bottom border of the activity it is attached to. Events on
+/// the same activity are spread evenly along the border.
+pub fn attach_boundary_events(graph: &mut Graph) {
+    let mut attached: HashMap<usize, Vec<usize>> = HashMap::new();
+    for node in graph.nodes() {
+        if let Some(activity) = node.event.as_ref().and_then(attached_to) {
+            attached.entry(activity).or_default().push(node.id);
+        }
+    }
+    let mut positions: HashMap<usize, (f64, f64)> = HashMap::new();
+    for (activity, events) in &attached {
+        let Some(node) = graph.get_node_by_id(*activity) else {
+            continue;
+        };
+        let (Some(event), Some(x), Some(y)) = (node.event.as_ref(), node.x, node.y) else {
+            continue;
+        };
+        let (width, height) = get_node_size(event);
+        let (width, height) = (width as f64, height as f64);
+        let x = x + node.x_offset.unwrap_or(0.0);
+        let y = y + node.y_offset.unwrap_or(0.0);
+        for (index, id) in events.iter().enumerate() {
+            let center = x + width * (index + 1) as f64 / (events.len() + 1) as f64;
+            positions.insert(*id, (center - 18.0, y + height - 18.0));
+        }
+    }
+    for pool in graph.pools.iter_mut() {
+        for lane in pool.lanes.iter_mut() {
+            for node in lane.get_layers_mut() {
+                if let Some((x, y)) = positions.get(&node.id) {
+                    node.set_position(*x, *y, 0.0, 0.0);
+                }
+            }
+        }
+    }
+}
+
+/// Routes the flows leaving boundary events that the router found no way for, since they start
+/// right at the border of the activity. They go down and then across to the target, or straight
+/// down when it is right below.
+pub fn route_boundary_flows(graph: &mut Graph) {
+    let bounds: HashMap<usize, (f64, f64, f64, f64)> = graph
+        .nodes()
+        .filter_map(|node| {
+            let (width, height) = get_node_size(node.event.as_ref()?);
+            let x = node.x? + node.x_offset.unwrap_or(0.0);
+            let y = node.y? + node.y_offset.unwrap_or(0.0);
+            Some((node.id, (x, y, width as f64, height as f64)))
+        })
+        .collect();
+    let boundary_events: HashSet<usize> = graph
+        .nodes()
+        .filter(|node| node.event.as_ref().and_then(attached_to).is_some())
+        .map(|node| node.id)
+        .collect();
+    for edge in graph.edges.iter_mut() {
+        if edge.bend_points.is_some() || !boundary_events.contains(&edge.from) {
+            continue;
+        }
+        let (Some(from), Some(to)) = (bounds.get(&edge.from), bounds.get(&edge.to)) else {
+            continue;
+        };
+        let x = from.0 + from.2 / 2.0;
+        let start = (x, from.1 + from.3);
+        let y = to.1 + to.3 / 2.0;
+        edge.bend_points = Some(if (to.0..=to.0 + to.2).contains(&x) {
+            vec![start, (x, if to.1 >= start.1 { to.1 } else { to.1 + to.3 })]
+        } else {
+            let end = if to.0 > x { to.0 } else { to.0 + to.2 };
+            vec![start, (x, y), (end, y)]
+        });
+    }
+}
+
+fn attached_to(event: &BpmnEvent) -> Option<usize> {
+    match event {
+        BpmnEvent::BoundaryEvent(_, activity, _)
+        | BpmnEvent::BoundaryErrorEvent(_, activity, _)
+        | BpmnEvent::BoundaryTimerEvent(_, activity, _)
+        | BpmnEvent::BoundaryCancelEvent(_, activity, _)
+        | BpmnEvent::BoundarySignalEvent(_, activity, _)
+        | BpmnEvent::BoundaryMessageEvent(_, activity, _)
+        | BpmnEvent::BoundaryEscalationEvent(_, activity, _)
+        | BpmnEvent::BoundaryConditionalEvent(_, activity, _)
+        | BpmnEvent::BoundaryCompensationEvent(_, activity) => Some(*activity),
+        _ => None,
+    }
+}
@@ -0,0 +1,331 @@
+// brandes_koepf.rs
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Places the nodes of each layer along the layer with the method of Brandes and Köpf.
+/// Nodes are aligned with the median of their neighbours in four directions, each alignment is
+/// packed as tightly as `sizes` and `gap` allow, and the result is the balanced median of the four,
+/// so chains end up on one line and branches spread evenly around the node they leave from.
+/// `layers` holds node ids in their order within each layer, `sizes` how far a node reaches along
+/// the layer before and after its center. Returns the center of every node, with the edge of the
+/// first node at 0.
+pub fn brandes_koepf_positions(
+    layers: &[Vec<usize>],
+    edges: &[(usize, usize)],
+    sizes: &HashMap<usize, (f64, f64)>,
+    gap: f64,
+) -> HashMap<usize, f64> {
+    let graph = SegmentGraph::new(layers, edges, sizes);
+    let conflicts = graph.type_1_conflicts();
+
+    let mut alignments: Vec<(Vec<f64>, bool)> = Vec::new();
+    for upward in [true, false] {
+        for from_end in [false, true] {
+            let mut ordered: Vec<Vec<usize>> = graph.layers.clone();
+            if !upward {
+                ordered.reverse();
+            }
+            if from_end {
+                for layer in ordered.iter_mut() {
+                    layer.reverse();
+                }
+            }
+            let neighbours = if upward { &graph.above } else { &graph.below };
+
+            let root = graph.vertical_alignment(&ordered, neighbours, &conflicts);
+            let mut xs = graph.compact(&ordered, &root, gap, from_end);
+            if from_end {
+                xs.iter_mut().for_each(|x| *x = -*x);
+            }
+            alignments.push((xs, from_end));
+        }
+    }
+
+    // Shift every alignment onto the narrowest one, by its first edge or last edge
+    let extent = |xs: &[f64]| {
+        let start = (0..xs.len())
+            .map(|v| xs[v] - graph.sizes[v].0)
+            .fold(f64::INFINITY, f64::min);
+        let end = (0..xs.len())
+            .map(|v| xs[v] + graph.sizes[v].1)
+            .fold(f64::NEG_INFINITY, f64::max);
+        (start, end)
+    };
+    let narrowest = alignments
+        .iter()
+        .map(|(xs, _)| extent(xs))
+        .min_by(|a, b| (a.1 - a.0).total_cmp(&(b.1 - b.0)))
+        .unwrap_or((0.0, 0.0));
+    for (xs, from_end) in alignments.iter_mut() {
+        let (start, end) = extent(xs);
+        let delta = if *from_end {
+            narrowest.1 - end
+        } else {
+            narrowest.0 - start
+        };
+        xs.iter_mut().for_each(|x| *x += delta);
+    }
+
+    let mut balanced: Vec<f64> = (0..graph.sizes.len())
+        .map(|v| {
+            let mut values: Vec<f64> = alignments.iter().map(|(xs, _)| xs[v]).collect();
+            values.sort_by(f64::total_cmp);
+            (values[1] + values[2]) / 2.0
+        })
+        .collect();
+
+    let (start, _) = extent(&balanced);
+    if start.is_finite() {
+        balanced.iter_mut().for_each(|x| *x -= start);
+    }
+    graph
+        .ids
+        .iter()
+        .enumerate()
+        .map(|(index, id)| (*id, balanced[index]))
+        .collect()
+}
+
+/// Layers where every edge connects neighbouring layers, long edges are split with dummy nodes.
+/// Real nodes come first in numbering, dummies after them.
+struct SegmentGraph {
+    ids: Vec<usize>,         // Node id of every real node
+    layers: Vec<Vec<usize>>, // Nodes of each layer in order
+    above: Vec<Vec<usize>>,  // Neighbours of each node in the previous layer
+    below: Vec<Vec<usize>>,  // Neighbours of each node in the next layer
+    sizes: Vec<(f64, f64)>,  // Reach of each node before and after its center, 0 for dummies
+}
+
+impl SegmentGraph {
+    fn new(
+        layers: &[Vec<usize>],
+        edges: &[(usize, usize)],
+        sizes: &HashMap<usize, (f64, f64)>,
+    ) -> Self {
+        let ids: Vec<usize> = layers.iter().flatten().copied().collect();
+        let mut index_of: HashMap<usize, usize> = HashMap::new();
+        let mut layer_of: Vec<usize> = Vec::new();
+        let mut node_layers: Vec<Vec<usize>> = vec![Vec::new(); layers.len()];
+        for (layer_index, layer) in layers.iter().enumerate() {
+            for id in layer {
+                let index = index_of.len();
+                index_of.insert(*id, index);
+                layer_of.push(layer_index);
+                node_layers[layer_index].push(index);
+            }
+        }
+
+        let mut graph = SegmentGraph {
+            sizes: ids
+                .iter()
+                .map(|id| sizes.get(id).copied().unwrap_or((0.0, 0.0)))
+                .collect(),
+            ids,
+            layers: node_layers,
+            above: vec![Vec::new(); index_of.len()],
+            below: vec![Vec::new(); index_of.len()],
+        };
+
+        for (from, to) in edges {
+            let (Some(&from), Some(&to)) = (index_of.get(from), index_of.get(to)) else {
+                continue;
+            };
+            let (upper, lower) = if layer_of[from] <= layer_of[to] {
+                (from, to)
+            } else {
+                (to, from)
+            };
+            if layer_of[upper] == layer_of[lower] {
+                continue;
+            }
+
+            let mut previous = upper;
+            for layer in layer_of[upper] + 1..layer_of[lower] {
+                let dummy = graph.sizes.len();
+                graph.sizes.push((0.0, 0.0));
+                graph.above.push(Vec::new());
+                graph.below.push(Vec::new());
+                graph.layers[layer].push(dummy);
+                graph.link(previous, dummy);
+                previous = dummy;
+            }
+            graph.link(previous, lower);
+        }
+        graph
+    }
+
+    fn link(&mut self, upper: usize, lower: usize) {
+        self.below[upper].push(lower);
+        self.above[lower].push(upper);
+    }
+
+    fn is_dummy(&self, node: usize) -> bool {
+        node >= self.ids.len()
+    }
+
+    /// Segments between real nodes that cross a segment between two dummies.
+    /// Long edges are kept straight, so these segments may not be aligned.
+    fn type_1_conflicts(&self) -> HashSet<(usize, usize)> {
+        let mut conflicts = HashSet::new();
+        let position = positions(&self.layers, self.sizes.len());
+
+        for pair in self.layers.windows(2) {
+            let (upper, lower) = (&pair[0], &pair[1]);
+            let mut start = 0;
+            let mut scan = 0;
+            for (index, &node) in lower.iter().enumerate() {
+                let inner = if self.is_dummy(node) {
+                    self.above[node]
+                        .iter()
+                        .copied()
+                        .find(|&upper_node| self.is_dummy(upper_node))
+                } else {
+                    None
+                };
+                let end = inner.map_or(upper.len(), |upper_node| position[upper_node]);
+
+                if inner.is_some() || index + 1 == lower.len() {
+                    for &scan_node in &lower[scan..=index] {
+                        for &upper_node in &self.above[scan_node] {
+                            let upper_position = position[upper_node];
+                            if (upper_position < start || end < upper_position)
+                                && !(self.is_dummy(upper_node) && self.is_dummy(scan_node))
+                            {
+                                conflicts.insert(conflict_key(upper_node, scan_node));
+                            }
+                        }
+                    }
+                    scan = index + 1;
+                    start = end;
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Builds blocks of nodes aligned with the median of their neighbours in the previous layer
+    /// of `layers`. Returns the root of every node's block.
+    fn vertical_alignment(
+        &self,
+        layers: &[Vec<usize>],
+        neighbours: &[Vec<usize>],
+        conflicts: &HashSet<(usize, usize)>,
+    ) -> Vec<usize> {
+        let count = self.sizes.len();
+        let mut root: Vec<usize> = (0..count).collect();
+        let mut align: Vec<usize> = (0..count).collect();
+        let position = positions(layers, count);
+
+        for layer in layers {
+            let mut previous: Option<usize> = None;
+            for &node in layer {
+                let mut adjacent = neighbours[node].clone();
+                if adjacent.is_empty() {
+                    continue;
+                }
+                adjacent.sort_by_key(|&n| position[n]);
+                let last = adjacent.len() - 1;
+                for &median in &adjacent[last / 2..=last.div_ceil(2)] {
+                    if align[node] == node
+                        && previous.is_none_or(|p| p < position[median])
+                        && !conflicts.contains(&conflict_key(node, median))
+                    {
+                        align[median] = node;
+                        root[node] = root[median];
+                        align[node] = root[node];
+                        previous = Some(position[median]);
+                    }
+                }
+            }
+        }
+        root
+    }
+
+    /// Gives every block a coordinate as small as possible while keeping `gap` between
+    /// neighbouring nodes, then moves blocks towards their successors where there is room.
+    /// `reversed` layers run from the end, so nodes reach after their center what they
+    /// normally reach before it.
+    fn compact(&self, layers: &[Vec<usize>], root: &[usize], gap: f64, reversed: bool) -> Vec<f64> {
+        let count = self.sizes.len();
+        let mut successors: HashMap<usize, Vec<(usize, f64)>> = HashMap::new();
+        let mut predecessors: HashMap<usize, Vec<(usize, f64)>> = HashMap::new();
+        for layer in layers {
+            for pair in layer.windows(2) {
+                let (before, after) = (pair[0], pair[1]);
+                let separation = if reversed {
+                    self.sizes[before].0 + self.sizes[after].1 + gap
+                } else {
+                    self.sizes[before].1 + self.sizes[after].0 + gap
+                };
+                successors
+                    .entry(root[before])
+                    .or_default()
+                    .push((root[after], separation));
+                predecessors
+                    .entry(root[after])
+                    .or_default()
+                    .push((root[before], separation));
+            }
+        }
+
+        // The block graph has no cycles, because blocks never cross each other
+        let roots: Vec<usize> = (0..count).filter(|&v| root[v] == v).collect();
+        let mut in_degree: HashMap<usize, usize> = roots
+            .iter()
+            .map(|&r| (r, predecessors.get(&r).map_or(0, Vec::len)))
+            .collect();
+        let mut queue: VecDeque<usize> = roots
+            .iter()
+            .copied()
+            .filter(|r| in_degree[r] == 0)
+            .collect();
+        let mut order = Vec::new();
+        while let Some(block) = queue.pop_front() {
+            order.push(block);
+            for (next, _) in successors.get(&block).into_iter().flatten() {
+                let degree = in_degree.get_mut(next).unwrap();
+                *degree -= 1;
+                if *degree == 0 {
+                    queue.push_back(*next);
+                }
+            }
+        }
+
+        let mut xs = vec![0.0; count];
+        for &block in &order {
+            xs[block] = predecessors
+                .get(&block)
+                .into_iter()
+                .flatten()
+                .map(|(before, separation)| xs[*before] + separation)
+                .fold(0.0, f64::max);
+        }
+        for &block in order.iter().rev() {
+            let limit = successors
+                .get(&block)
+                .into_iter()
+                .flatten()
+                .map(|(after, separation)| xs[*after] - separation)
+                .fold(f64::INFINITY, f64::min);
+            if limit.is_finite() {
+                xs[block] = xs[block].max(limit);
+            }
+        }
+
+        (0..count).map(|v| xs[root[v]]).collect()
+    }
+}
+
+fn positions(layers: &[Vec<usize>], count: usize) -> Vec<usize> {
+    let mut position = vec![0; count];
+    for layer in layers {
+        for (index, &node) in layer.iter().enumerate() {
+            position[node] = index;
+        }
+    }
+    position
+}
+
+fn conflict_key(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
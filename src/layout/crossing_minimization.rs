@@ -1,66 +1,289 @@
 use crate::common::graph::Graph;
-use crate::common::node::Node;
-use crate::common::pool::Pool;
 use std::collections::HashMap;
 
-#[allow(dead_code)]
-/// Reduces crossings in the graph by rearranging nodes within each layer.
-pub fn reduce_crossings<'a>(
-    pools_lanes_layers: &'a mut Vec<Pool>,
-    graph: &'a Graph,
-) -> &'a Vec<Pool> {
-    for pool in &mut *pools_lanes_layers {
+// Sweeps up and down the layers, Graphviz uses the same number of iterations
+const MAX_SWEEPS: usize = 24;
+// Upper bound for the transposition rounds after each sweep
+const MAX_TRANSPOSE_ROUNDS: usize = 100;
+
+/// Number of edge crossings in a layout before and after the nodes were reordered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CrossingCounts {
+    pub before: usize,
+    pub after: usize,
+}
+
+/// Reorders the nodes within each layer of every lane so fewer edges cross.
+/// Works on the layering from `solve_layer_assignment`; edges between lanes are not counted.
+pub fn reduce_crossings(graph: &mut Graph) -> CrossingCounts {
+    let edges: Vec<(usize, usize)> = graph
+        .edges
+        .iter()
+        .map(|edge| (edge.from, edge.to))
+        .collect();
+    let mut total = CrossingCounts::default();
+
+    for pool in graph.get_pools_mut() {
         for lane in pool.get_lanes_mut() {
-            align_connected_nodes(lane.get_layers_mut(), graph);
+            let layer_count = lane
+                .get_layers()
+                .iter()
+                .map(|node| node.layer_id.unwrap_or(0) + 1)
+                .max()
+                .unwrap_or(0);
+            let mut layers: Vec<Vec<usize>> = vec![Vec::new(); layer_count];
+            for node in lane.get_layers() {
+                layers[node.layer_id.unwrap_or(0)].push(node.id);
+            }
+
+            let (ordered, counts) = order_layers(&layers, &edges);
+            total.before += counts.before;
+            total.after += counts.after;
+
+            let position: HashMap<usize, usize> = ordered
+                .iter()
+                .flat_map(|layer| layer.iter().enumerate().map(|(i, id)| (*id, i)))
+                .collect();
+            lane.get_layers_mut()
+                .sort_by_key(|node| (node.layer_id.unwrap_or(0), position[&node.id]));
         }
     }
-    pools_lanes_layers
+    total
 }
 
-/// Align nodes that are connected and share the same layer by their layer ID
-fn align_connected_nodes(nodes: &mut Vec<Node>, graph: &Graph) {
-    let mut x_position_map: HashMap<usize, f64> = HashMap::new();
-    let mut x_position = 0.0;
-
-    // Group nodes by layer
-    let mut layer_groups: HashMap<usize, Vec<usize>> = HashMap::new();
-    for (idx, node) in nodes.iter().enumerate() {
-        layer_groups
-            .entry(node.layer_id.unwrap_or(0))
-            .or_default()
-            .push(idx);
+/// Orders the nodes of every layer with the barycenter heuristic and transposition of neighbours.
+/// `layers` holds node ids in their current order, edges to unknown nodes or within a layer are ignored.
+pub fn order_layers(
+    layers: &[Vec<usize>],
+    edges: &[(usize, usize)],
+) -> (Vec<Vec<usize>>, CrossingCounts) {
+    let mut layer_graph = LayerGraph::new(layers, edges);
+    let before = layer_graph.crossings();
+
+    let mut best = layer_graph.layers.clone();
+    let mut best_crossings = before;
+    for sweep in 0..MAX_SWEEPS {
+        if best_crossings == 0 {
+            break;
+        }
+        if sweep % 2 == 0 {
+            for layer in 1..layer_graph.layers.len() {
+                layer_graph.reorder(layer, true);
+            }
+        } else {
+            for layer in (0..layer_graph.layers.len().saturating_sub(1)).rev() {
+                layer_graph.reorder(layer, false);
+            }
+        }
+        layer_graph.transpose();
+
+        let crossings = layer_graph.crossings();
+        if crossings < best_crossings {
+            best_crossings = crossings;
+            best = layer_graph.layers.clone();
+        }
+    }
+
+    let ordered = best
+        .iter()
+        .map(|layer| {
+            layer
+                .iter()
+                .filter(|&&node| node < layer_graph.ids.len())
+                .map(|&node| layer_graph.ids[node])
+                .collect()
+        })
+        .collect();
+    (
+        ordered,
+        CrossingCounts {
+            before,
+            after: best_crossings,
+        },
+    )
+}
+
+/// Counts the edge crossings of the given layer order like `order_layers` does before reordering.
+/// Edges spanning several layers pass the layers in between after their last node.
+pub fn count_crossings(layers: &[Vec<usize>], edges: &[(usize, usize)]) -> usize {
+    LayerGraph::new(layers, edges).crossings()
+}
+
+/// Layers where every edge connects neighbouring layers.
+/// Longer edges are split with dummy nodes, which come after the real nodes in numbering.
+struct LayerGraph {
+    ids: Vec<usize>,         // Node id of every real node
+    layers: Vec<Vec<usize>>, // Nodes of each layer in order
+    above: Vec<Vec<usize>>,  // Neighbours of each node in the previous layer
+    below: Vec<Vec<usize>>,  // Neighbours of each node in the next layer
+    position: Vec<usize>,    // Index of each node within its layer
+}
+
+impl LayerGraph {
+    fn new(layers: &[Vec<usize>], edges: &[(usize, usize)]) -> Self {
+        let ids: Vec<usize> = layers.iter().flatten().copied().collect();
+        let mut layer_of: HashMap<usize, usize> = HashMap::new();
+        let mut index_of: HashMap<usize, usize> = HashMap::new();
+        let mut node_layers: Vec<Vec<usize>> = vec![Vec::new(); layers.len()];
+        for (layer_index, layer) in layers.iter().enumerate() {
+            for id in layer {
+                let index = index_of.len();
+                index_of.insert(*id, index);
+                layer_of.insert(index, layer_index);
+                node_layers[layer_index].push(index);
+            }
+        }
+
+        let mut layer_graph = LayerGraph {
+            ids,
+            layers: node_layers,
+            above: vec![Vec::new(); index_of.len()],
+            below: vec![Vec::new(); index_of.len()],
+            position: Vec::new(),
+        };
+
+        for (from, to) in edges {
+            let (Some(&from), Some(&to)) = (index_of.get(from), index_of.get(to)) else {
+                continue;
+            };
+            // Back edges are counted like the forward edge they were layered as
+            let (upper, lower) = if layer_of[&from] <= layer_of[&to] {
+                (from, to)
+            } else {
+                (to, from)
+            };
+            let (upper_layer, lower_layer) = (layer_of[&upper], layer_of[&lower]);
+            if upper_layer == lower_layer {
+                continue;
+            }
+
+            let mut previous = upper;
+            for layer in upper_layer + 1..lower_layer {
+                let dummy = layer_graph.above.len();
+                layer_graph.above.push(Vec::new());
+                layer_graph.below.push(Vec::new());
+                layer_graph.layers[layer].push(dummy);
+                layer_graph.link(previous, dummy);
+                previous = dummy;
+            }
+            layer_graph.link(previous, lower);
+        }
+
+        layer_graph.position = vec![0; layer_graph.above.len()];
+        for layer in 0..layer_graph.layers.len() {
+            layer_graph.update_positions(layer);
+        }
+        layer_graph
     }
 
-    // Process each layer group
-    for (_layer_id, indices) in layer_groups {
-        for &i in &indices {
-            let node_a = nodes[i].id;
+    fn link(&mut self, upper: usize, lower: usize) {
+        self.below[upper].push(lower);
+        self.above[lower].push(upper);
+    }
 
-            let x_a = *x_position_map.entry(node_a).or_insert_with(|| {
-                let pos = x_position;
-                x_position += 50.0;
-                pos
-            });
+    fn update_positions(&mut self, layer: usize) {
+        for (index, &node) in self.layers[layer].iter().enumerate() {
+            self.position[node] = index;
+        }
+    }
 
-            for &j in indices.iter().filter(|&&j| j > i) {
-                let node_b = nodes[j].id;
+    /// Sorts a layer by the average position of each node's neighbours in the layer above or below.
+    /// Nodes without such neighbours keep their place.
+    fn reorder(&mut self, layer: usize, use_above: bool) {
+        let neighbours = if use_above { &self.above } else { &self.below };
+        let mut movable: Vec<(f64, usize)> = Vec::new();
+        let mut fixed: Vec<Option<usize>> = Vec::new();
+        for &node in &self.layers[layer] {
+            let adjacent = &neighbours[node];
+            if adjacent.is_empty() {
+                fixed.push(Some(node));
+            } else {
+                let sum: usize = adjacent.iter().map(|&n| self.position[n]).sum();
+                movable.push((sum as f64 / adjacent.len() as f64, node));
+                fixed.push(None);
+            }
+        }
+        movable.sort_by(|a, b| a.0.total_cmp(&b.0));
 
-                let has_edge = graph.edges.iter().any(|edge| {
-                    (edge.from == node_a && edge.to == node_b)
-                        || (edge.from == node_b && edge.to == node_a)
-                });
+        let mut movable = movable.into_iter().map(|(_, node)| node);
+        self.layers[layer] = fixed
+            .into_iter()
+            .map(|slot| slot.unwrap_or_else(|| movable.next().unwrap()))
+            .collect();
+        self.update_positions(layer);
+    }
 
-                if has_edge {
-                    x_position_map.insert(node_b, x_a);
+    /// Swaps neighbouring nodes while that removes crossings
+    fn transpose(&mut self) {
+        for _ in 0..MAX_TRANSPOSE_ROUNDS {
+            let mut improved = false;
+            for layer in 0..self.layers.len() {
+                for index in 1..self.layers[layer].len() {
+                    let (left, right) = (self.layers[layer][index - 1], self.layers[layer][index]);
+                    if self.pair_crossings(right, left) < self.pair_crossings(left, right) {
+                        self.layers[layer].swap(index - 1, index);
+                        self.position[left] = index;
+                        self.position[right] = index - 1;
+                        improved = true;
+                    }
+                }
+            }
+            if !improved {
+                break;
+            }
+        }
+    }
+
+    /// Crossings between the edges of two nodes of a layer when `left` comes before `right`
+    fn pair_crossings(&self, left: usize, right: usize) -> usize {
+        let mut crossings = 0;
+        for neighbours in [&self.above, &self.below] {
+            for &left_neighbour in &neighbours[left] {
+                for &right_neighbour in &neighbours[right] {
+                    if self.position[left_neighbour] > self.position[right_neighbour] {
+                        crossings += 1;
+                    }
                 }
             }
         }
+        crossings
     }
 
-    // Update node positions
-    for node in nodes {
-        if let Some(&x) = x_position_map.get(&node.id) {
-            node.x = Some(x);
+    fn crossings(&self) -> usize {
+        (1..self.layers.len())
+            .map(|layer| self.crossings_above(layer))
+            .sum()
+    }
+
+    /// Counts crossing edges between a layer and the one above it as inversions
+    /// of the lower ends when the edges are sorted by their upper ends
+    fn crossings_above(&self, layer: usize) -> usize {
+        let mut lower_ends: Vec<(usize, usize)> = self.layers[layer]
+            .iter()
+            .flat_map(|&node| self.above[node].iter().map(move |&upper| (upper, node)))
+            .map(|(upper, lower)| (self.position[upper], self.position[lower]))
+            .collect();
+        lower_ends.sort();
+
+        // Fenwick tree over the positions in the lower layer
+        let size = self.layers[layer].len();
+        let mut tree = vec![0; size + 1];
+        let mut crossings = 0;
+        for (seen, (_, lower)) in lower_ends.into_iter().enumerate() {
+            let mut at_most = 0;
+            let mut i = lower + 1;
+            while i > 0 {
+                at_most += tree[i];
+                i -= i & i.wrapping_neg();
+            }
+            crossings += seen - at_most;
+
+            let mut i = lower + 1;
+            while i <= size {
+                tree[i] += 1;
+                i += i & i.wrapping_neg();
+            }
         }
+        crossings
     }
 }
@@ -1,56 +1,79 @@
 use crate::common::graph::Graph;
-use crate::common::edge::Edge;
-use std::collections::HashSet;
+use std::collections::HashMap;
 
+/// Reverses the edges that close a cycle, so the layering only sees an acyclic graph.
+/// Back edges are found with a depth first search in the order the nodes were defined,
+/// so a jump back to an earlier node is the edge that gets reversed.
 pub fn eliminate_back_edges(graph: &mut Graph) {
-    let mut visited = HashSet::new();
-    let mut stack = HashSet::new();
-    let mut back_edges = Vec::new();
-
-    // Abifunktsioon DFS-i jaoks
-    fn dfs(
-        graph: &Graph,
-        node_id: usize,
-        visited: &mut HashSet<usize>,
-        stack: &mut HashSet<usize>,
-        back_edges: &mut Vec<Edge>,
-    ) {
-        visited.insert(node_id);
-        stack.insert(node_id);
+    let mut node_ids: Vec<usize> = graph.nodes().map(|node| node.id).collect();
+    node_ids.sort();
+    let edges: Vec<(usize, usize)> = graph
+        .edges
+        .iter()
+        .map(|edge| (edge.from, edge.to))
+        .collect();
 
-        for edge in graph.edges.iter().filter(|e| e.from == node_id) {
-            if stack.contains(&edge.to) {
-                // Leitud tagasiserv, lisa see tagasiservade nimekirja
-                back_edges.push(Edge::new(edge.from, edge.to, None));
-            } else if !visited.contains(&edge.to) {
-                dfs(graph, edge.to, visited, stack, back_edges);
-            }
-        }
-        stack.remove(&node_id);
+    for edge in graph.edges.iter_mut() {
+        edge.is_back_edge = false;
     }
-
-    // Käivitame DFS-i iga sõlme jaoks
-    for node in &graph.nodes {
-        if !visited.contains(&node.id) {
-            dfs(graph, node.id, &mut visited, &mut stack, &mut back_edges);
-        }
+    for index in find_back_edges(&node_ids, &edges) {
+        let edge = &mut graph.edges[index];
+        std::mem::swap(&mut edge.from, &mut edge.to);
+        edge.is_back_edge = true;
     }
+}
 
-    // Pöörame või eemaldame tagasiservad, et graaf muutuks atsükliliseks
-    for edge in back_edges {
-        reverse_or_remove_edge(graph, edge);
+/// Turns the reversed back edges around again once the layers are assigned
+pub fn restore_back_edges(graph: &mut Graph) {
+    for edge in graph.edges.iter_mut().filter(|edge| edge.is_back_edge) {
+        std::mem::swap(&mut edge.from, &mut edge.to);
     }
 }
 
-/// Abifunktsioon serva ümberpööramiseks või eemaldamiseks graafis.
-fn reverse_or_remove_edge(graph: &mut Graph, edge: Edge) {
-    // Leiame ja eemaldame originaalse serva
-    if let Some(index) = graph.edges.iter().position(|e| e.from == edge.from && e.to == edge.to) {
-        graph.edges.remove(index);
+/// Returns the indices of the edges that lead back to a node on the current search path.
+/// Reversing all of them leaves no cycles, a loop from a node to itself counts as a back edge.
+pub fn find_back_edges(node_ids: &[usize], edges: &[(usize, usize)]) -> Vec<usize> {
+    const NEW: u8 = 0;
+    const ON_PATH: u8 = 1;
+    const DONE: u8 = 2;
+
+    let index: HashMap<usize, usize> = node_ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (*id, i))
+        .collect();
+    let mut outgoing: Vec<Vec<(usize, usize)>> = vec![Vec::new(); node_ids.len()];
+    for (edge_index, (from, to)) in edges.iter().enumerate() {
+        if let (Some(&from), Some(&to)) = (index.get(from), index.get(to)) {
+            outgoing[from].push((edge_index, to));
+        }
     }
 
-    // Lisame vastupidise serva ainult siis kui see veel ei eksisteeri
-    if !graph.edges.iter().any(|e| e.from == edge.to && e.to == edge.from) {
-        graph.edges.push(Edge::new(edge.to, edge.from, None));
+    let mut state = vec![NEW; node_ids.len()];
+    let mut back_edges = Vec::new();
+    for start in 0..node_ids.len() {
+        if state[start] != NEW {
+            continue;
+        }
+        state[start] = ON_PATH;
+        let mut stack = vec![(start, 0)];
+        while let Some(&(node, position)) = stack.last() {
+            if let Some(&(edge_index, next)) = outgoing[node].get(position) {
+                stack.last_mut().unwrap().1 += 1;
+                match state[next] {
+                    NEW => {
+                        state[next] = ON_PATH;
+                        stack.push((next, 0));
+                    }
+                    ON_PATH => back_edges.push(edge_index),
+                    _ => {}
+                }
+            } else {
+                state[node] = DONE;
+                stack.pop();
+            }
+        }
     }
+    back_edges.sort();
+    back_edges
 }
@@ -0,0 +1,458 @@
+use crate::common::bpmn_event::get_node_size;
+use crate::common::graph::Graph;
+use crate::common::node::Node;
+use crate::from_xml::{bounds, is, waypoints, Rect};
+use crate::layout::node_positioning::transpose;
+use crate::layout::{LayoutOptions, Orientation};
+use crate::to_xml::{generate_bpmn, get_node_bpmn_id};
+use roxmltree::{Document, Node as XmlNode};
+use std::collections::{HashMap, HashSet};
+
+/// Shapes and routes of an earlier layout, read from the BPMN file it was written to.
+/// Numeric ids shift whenever a node is inserted, so elements are matched by a stable key made of
+/// their pool, lane, type and name, and elements that agree on all of these by their order.
+#[derive(Debug, Default)]
+pub struct PreviousLayout {
+    nodes: HashMap<String, Rect>,
+    edges: HashMap<(String, String), Vec<(f64, f64)>>, // Waypoints by the keys of both ends
+    pools: HashMap<String, Rect>,
+    lanes: HashMap<(String, String), Rect>, // By pool and lane name
+}
+
+impl PreviousLayout {
+    /// Reads the `dc:Bounds` of pools, lanes and nodes and the waypoints of sequence flows
+    pub fn from_bpmn(xml: &str) -> Result<Self, String> {
+        Ok(read_diagram(xml)?.0)
+    }
+}
+
+/// Puts every node that is unchanged since the previous layout back where it was. New and changed
+/// nodes move along with the nearest placed node before them, or after them, and then along the
+/// flow until they are clear of the others. Lanes and pools keep their previous bounds where they
+/// can and grow where new content needs room. Returns the stable keys of the nodes that are
+/// exactly where they were.
+pub fn keep_previous_positions(
+    graph: &mut Graph,
+    options: &LayoutOptions,
+    previous: &PreviousLayout,
+) -> HashMap<usize, String> {
+    let keys = node_keys(graph);
+    // The work is done as if the flow went left to right
+    let transposed = options.orientation == Orientation::TopToBottom;
+    let flow = |rect: Rect| flip(rect, transposed);
+    if transposed {
+        transpose(graph);
+    }
+
+    let fresh: HashMap<usize, Rect> = graph
+        .nodes()
+        .map(|node| (node.id, shape(node, transposed)))
+        .collect();
+    let mut placed: HashMap<usize, Rect> = HashMap::new();
+    for (id, rect) in &fresh {
+        let old = keys
+            .get(id)
+            .and_then(|key| previous.nodes.get(key))
+            .copied()
+            .map(flow);
+        if let Some(old) = old.filter(|old| same_size(old, rect)) {
+            placed.insert(*id, old);
+        }
+    }
+
+    let mut pending: Vec<(usize, usize)> = graph
+        .nodes()
+        .filter(|node| !placed.contains_key(&node.id))
+        .map(|node| (node.layer_id.unwrap_or(0), node.id))
+        .collect();
+    pending.sort();
+    for (_, id) in pending {
+        let forward = || graph.edges.iter().filter(|edge| !edge.is_back_edge);
+        let neighbour = forward()
+            .find(|edge| edge.to == id && placed.contains_key(&edge.from))
+            .map(|edge| edge.from)
+            .or_else(|| {
+                forward()
+                    .find(|edge| edge.from == id && placed.contains_key(&edge.to))
+                    .map(|edge| edge.to)
+            });
+        let (dx, dy) = match neighbour {
+            Some(other) => (
+                placed[&other].0 - fresh[&other].0,
+                placed[&other].1 - fresh[&other].1,
+            ),
+            None => lane_shift(graph, id, previous, transposed),
+        };
+        // Everything after the new node in the flow moves along until its successors are a layer
+        // gap away, other pools and branches stay where they are
+        let mut after = HashSet::new();
+        let mut stack = vec![id];
+        while let Some(current) = stack.pop() {
+            for edge in forward().filter(|edge| edge.from == current) {
+                if after.insert(edge.to) {
+                    stack.push(edge.to);
+                }
+            }
+        }
+        let (x, y, width, height) = fresh[&id];
+        let mut rect = (x + dx, y + dy, width, height);
+        while let Some((_, blocker)) = placed.iter().find(|(other, other_rect)| {
+            !after.contains(*other) && overlaps(&rect, other_rect, options.node_gap)
+        }) {
+            rect.0 = blocker.0 + blocker.2 + options.node_gap;
+        }
+        let room = forward()
+            .filter(|edge| edge.from == id)
+            .filter_map(|edge| placed.get(&edge.to))
+            .map(|next| next.0 - (rect.0 + rect.2))
+            .fold(f64::INFINITY, f64::min);
+        if room < options.layer_gap {
+            for (other, other_rect) in placed.iter_mut() {
+                if after.contains(other) {
+                    other_rect.0 += options.layer_gap - room;
+                }
+            }
+        }
+        placed.insert(id, rect);
+    }
+
+    for pool in graph.get_pools_mut() {
+        for lane in pool.get_lanes_mut() {
+            for node in lane.get_layers_mut() {
+                let (x, y, _, _) = placed[&node.id];
+                node.x = Some(x - node.x_offset.unwrap_or(0.0));
+                node.y = Some(y - node.y_offset.unwrap_or(0.0));
+            }
+        }
+    }
+    fit_pools_and_lanes(graph, options, previous, transposed);
+
+    let mut kept = HashMap::new();
+    for node in graph.nodes() {
+        let old = keys
+            .get(&node.id)
+            .and_then(|key| previous.nodes.get(key))
+            .copied()
+            .map(flow);
+        if old.is_some_and(|old| old == shape(node, transposed)) {
+            kept.insert(node.id, keys[&node.id].clone());
+        }
+    }
+    if transposed {
+        transpose(graph);
+    }
+    kept
+}
+
+/// Gives edges between two kept nodes their previous route back, unless a node now stands in
+/// its way. Routes from the file that do not go anywhere or do not start and end at the shapes of
+/// their nodes are left to the router.
+pub fn keep_previous_routes(
+    graph: &mut Graph,
+    previous: &PreviousLayout,
+    kept: &HashMap<usize, String>,
+) {
+    let shapes: Vec<(usize, Rect)> = graph
+        .nodes()
+        .map(|node| (node.id, shape(node, false)))
+        .collect();
+    for edge in graph.edges.iter_mut() {
+        let (Some(from), Some(to)) = (kept.get(&edge.from), kept.get(&edge.to)) else {
+            continue;
+        };
+        let Some(points) = previous.edges.get(&(from.clone(), to.clone())) else {
+            continue;
+        };
+        let shape_of = |id| {
+            shapes
+                .iter()
+                .find(|(node, _)| *node == id)
+                .map(|(_, rect)| rect)
+        };
+        let (Some(first), Some(last)) = (points.first(), points.last()) else {
+            continue;
+        };
+        let moves = points.iter().any(|point| point != first);
+        let attached = shape_of(edge.from).is_some_and(|rect| touches(*first, rect))
+            && shape_of(edge.to).is_some_and(|rect| touches(*last, rect));
+        if !moves || !attached {
+            continue;
+        }
+        let blocked = points.windows(2).any(|pair| {
+            let (x1, y1) = (pair[0].0.min(pair[1].0), pair[0].1.min(pair[1].1));
+            let segment = (
+                x1,
+                y1,
+                (pair[0].0 - pair[1].0).abs(),
+                (pair[0].1 - pair[1].1).abs(),
+            );
+            shapes.iter().any(|(id, rect)| {
+                *id != edge.from && *id != edge.to && overlaps(&segment, rect, 0.0)
+            })
+        });
+        if !blocked {
+            edge.bend_points = Some(points.clone());
+        }
+    }
+}
+
+/// Whether a point is on or inside a rectangle, give or take a pixel of rounding
+fn touches((x, y): (f64, f64), rect: &Rect) -> bool {
+    x >= rect.0 - 1.0
+        && x <= rect.0 + rect.2 + 1.0
+        && y >= rect.1 - 1.0
+        && y <= rect.1 + rect.3 + 1.0
+}
+
+/// Bounds of the shape of a node, mirrored along the diagonal if `transposed`
+fn shape(node: &Node, transposed: bool) -> Rect {
+    let (width, height) = node.event.as_ref().map_or((0, 0), get_node_size);
+    let (width, height) = if transposed {
+        (height as f64, width as f64)
+    } else {
+        (width as f64, height as f64)
+    };
+    (
+        node.x.unwrap_or(0.0) + node.x_offset.unwrap_or(0.0),
+        node.y.unwrap_or(0.0) + node.y_offset.unwrap_or(0.0),
+        width,
+        height,
+    )
+}
+
+/// Mirrors a rectangle along the diagonal if `transposed`
+fn flip((x, y, width, height): Rect, transposed: bool) -> Rect {
+    if transposed {
+        (y, x, height, width)
+    } else {
+        (x, y, width, height)
+    }
+}
+
+fn same_size(a: &Rect, b: &Rect) -> bool {
+    (a.2 - b.2).abs() < 0.5 && (a.3 - b.3).abs() < 0.5
+}
+
+/// Whether two rectangles come closer than `gap`
+fn overlaps(a: &Rect, b: &Rect, gap: f64) -> bool {
+    a.0 < b.0 + b.2 + gap && b.0 < a.0 + a.2 + gap && a.1 < b.1 + b.3 + gap && b.1 < a.1 + a.3 + gap
+}
+
+/// How far the lane of a node moved since the previous layout
+fn lane_shift(graph: &Graph, id: usize, previous: &PreviousLayout, transposed: bool) -> (f64, f64) {
+    for pool in graph.get_pools() {
+        for lane in pool.get_lanes() {
+            if !lane.get_layers().iter().any(|node| node.id == id) {
+                continue;
+            }
+            let old = previous
+                .lanes
+                .get(&(pool.get_pool_name(), lane.get_lane().clone()));
+            if let (Some(old), Some(x), Some(y)) = (old, lane.x, lane.y) {
+                let old = flip(*old, transposed);
+                return (old.0 - x, old.1 - y);
+            }
+        }
+    }
+    (0.0, 0.0)
+}
+
+/// Lanes keep their previous bounds, or the new ones if they are new, but follow each other without
+/// overlapping and grow to hold their nodes. Nodes move along with their lane. All pools end up
+/// with the left edge and width of the pools reaching furthest.
+fn fit_pools_and_lanes(
+    graph: &mut Graph,
+    options: &LayoutOptions,
+    previous: &PreviousLayout,
+    transposed: bool,
+) {
+    let flow = |rect: Rect| flip(rect, transposed);
+    let padding = options.lane_padding;
+    let header = options.pool_header_width;
+    let mut bottom: Option<f64> = None;
+
+    for pool in graph.get_pools_mut() {
+        let pool_name = pool.get_pool_name();
+        let fresh = (
+            pool.x.unwrap_or(0.0),
+            pool.y.unwrap_or(0.0),
+            pool.width.unwrap_or(0.0),
+            pool.height.unwrap_or(0.0),
+        );
+        let old = previous.pools.get(&pool_name).copied().map(&flow);
+        let (pool_x, pool_y, pool_width, pool_height) = old.unwrap_or(fresh);
+        let pool_y = bottom.map_or(pool_y, |bottom| pool_y.max(bottom));
+        if pool.collapsed {
+            pool.set_position(pool_x, pool_y);
+            pool.set_width(pool_width.max(fresh.2));
+            pool.set_height(pool_height);
+            bottom = Some(pool_y + pool_height);
+            continue;
+        }
+        let lane_x = pool_x + header;
+        let mut lane_width = pool_width.max(fresh.2) - header;
+        let mut lane_y = pool_y;
+        let parent_lanes = pool.parent_lanes();
+
+        for lane in pool.get_lanes_mut() {
+            if parent_lanes.contains(lane.get_lane()) {
+                continue;
+            }
+            let old = previous
+                .lanes
+                .get(&(pool_name.clone(), lane.get_lane().clone()))
+                .copied()
+                .map(&flow);
+            let (_, top, width, height) = old.unwrap_or((
+                lane.x.unwrap_or(0.0),
+                lane.y.unwrap_or(0.0),
+                lane.width.unwrap_or(0.0),
+                lane.height.unwrap_or(0.0),
+            ));
+            let shift = lane_y.max(top) - top;
+            let mut content_bottom: f64 = 0.0;
+            for node in lane.get_layers_mut() {
+                node.y = node.y.map(|y| y + shift);
+                let (x, y, width, height) = shape(node, transposed);
+                content_bottom = content_bottom.max(y + height);
+                lane_width = lane_width.max(x + width + padding - lane_x);
+            }
+            let top = top + shift;
+            let height = height.max(content_bottom + padding - top);
+            lane.set_position(lane_x, top);
+            lane.set_height(height);
+            lane_y = top + height;
+            lane_width = lane_width.max(width);
+        }
+
+        pool.set_position(pool_x, pool_y);
+        pool.set_width(lane_width + header);
+        pool.set_height(lane_y - pool_y);
+        pool.enclose_child_lanes();
+        bottom = Some(lane_y);
+    }
+
+    // Pools only grow to line up, so their nodes stay inside of them
+    let pools = graph.get_pools_mut();
+    let left = pools
+        .iter()
+        .filter_map(|pool| pool.x)
+        .fold(f64::MAX, f64::min);
+    let right = pools
+        .iter()
+        .filter_map(|pool| Some(pool.x? + pool.width?))
+        .fold(f64::MIN, f64::max);
+    for pool in pools.iter_mut().filter(|_| left <= right) {
+        let y = pool.y.unwrap_or(0.0);
+        pool.set_position(left, y);
+        pool.set_width(right - left);
+        pool.set_lane_width(right - left - header, header);
+    }
+}
+
+/// Stable keys of the nodes of a laid out graph. The graph is written out and read back, so the
+/// keys are made exactly like the ones of a previous file.
+fn node_keys(graph: &Graph) -> HashMap<usize, String> {
+    let Ok((_, keys)) = read_diagram(&generate_bpmn(graph)) else {
+        return HashMap::new();
+    };
+    graph
+        .nodes()
+        .filter_map(|node| Some((node.id, keys.get(&get_node_bpmn_id(node))?.clone())))
+        .collect()
+}
+
+/// Reads the layout of a BPMN file, together with the stable key of every node by its id
+fn read_diagram(xml: &str) -> Result<(PreviousLayout, HashMap<String, String>), String> {
+    let document = Document::parse(xml).map_err(|e| format!("Invalid BPMN file: {}", e))?;
+    let elements: HashMap<&str, XmlNode> = document
+        .descendants()
+        .filter_map(|node| Some((node.attribute("id")?, node)))
+        .collect();
+    let name = |id: &str| {
+        elements
+            .get(id)
+            .and_then(|element| element.attribute("name"))
+            .unwrap_or("")
+            .to_string()
+    };
+
+    let mut layout = PreviousLayout::default();
+    let mut pools: Vec<(String, Rect)> = Vec::new();
+    let mut lanes: Vec<(String, Rect)> = Vec::new();
+    let mut shapes: Vec<(&str, Rect)> = Vec::new();
+    for shape in document.descendants().filter(|node| is(node, "BPMNShape")) {
+        let (Some(id), Some(rect)) = (shape.attribute("bpmnElement"), bounds(&shape)) else {
+            continue;
+        };
+        match elements.get(id) {
+            Some(element) if is(element, "participant") => pools.push((name(id), rect)),
+            Some(element) if is(element, "lane") => lanes.push((name(id), rect)),
+            _ => shapes.push((id, rect)),
+        }
+    }
+
+    // Lanes can be nested, the innermost one is the smallest
+    let containing = |areas: &[(String, Rect)], (x, y, width, height): Rect| {
+        let (center_x, center_y) = (x + width / 2.0, y + height / 2.0);
+        areas
+            .iter()
+            .filter(|(_, (x, y, width, height))| {
+                center_x >= *x && center_x <= x + width && center_y >= *y && center_y <= y + height
+            })
+            .min_by(|(_, a), (_, b)| (a.2 * a.3).total_cmp(&(b.2 * b.3)))
+            .map(|(name, _)| name.clone())
+            .unwrap_or_default()
+    };
+    for (lane, rect) in &lanes {
+        layout
+            .lanes
+            .insert((containing(&pools, *rect), lane.clone()), *rect);
+    }
+    layout.pools.extend(pools.iter().cloned());
+
+    // Nodes are counted in the order of the numbers in their ids, which is the order of the input
+    shapes.sort_by_key(|(id, _)| {
+        id.rsplit_once('_')
+            .and_then(|(_, number)| number.parse::<usize>().ok())
+            .unwrap_or(usize::MAX)
+    });
+    let mut keys: HashMap<String, String> = HashMap::new();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for (id, rect) in shapes {
+        let kind = id.rsplit_once('_').map_or(id, |(kind, _)| kind);
+        let base = format!(
+            "{}/{}/{}/{}",
+            containing(&pools, rect),
+            containing(&lanes, rect),
+            kind,
+            name(id)
+        );
+        let count = counts.entry(base.clone()).or_default();
+        *count += 1;
+        let key = format!("{}#{}", base, count);
+        keys.insert(id.to_string(), key.clone());
+        layout.nodes.insert(key, rect);
+    }
+
+    for edge in document.descendants().filter(|node| is(node, "BPMNEdge")) {
+        let Some(flow) = edge
+            .attribute("bpmnElement")
+            .and_then(|id| elements.get(id))
+        else {
+            continue;
+        };
+        let ends = (
+            flow.attribute("sourceRef").and_then(|id| keys.get(id)),
+            flow.attribute("targetRef").and_then(|id| keys.get(id)),
+        );
+        let (Some(from), Some(to)) = ends else {
+            continue;
+        };
+        layout
+            .edges
+            .insert((from.clone(), to.clone()), waypoints(&edge));
+    }
+
+    Ok((layout, keys))
+}
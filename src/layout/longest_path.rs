@@ -0,0 +1,55 @@
+// longest_path.rs
+
+use std::collections::{HashMap, VecDeque};
+
+/// Puts every node one layer after its furthest predecessor, nodes without predecessors go to layer 0.
+/// When the remaining nodes only form cycles, the first of them is taken as if it had no predecessors,
+/// so edges that close a cycle are ignored.
+pub fn longest_path_layers(node_ids: &[usize], edges: &[(usize, usize)]) -> HashMap<usize, usize> {
+    let index: HashMap<usize, usize> = node_ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (*id, i))
+        .collect();
+    let mut successors: Vec<Vec<usize>> = vec![Vec::new(); node_ids.len()];
+    let mut in_degree = vec![0; node_ids.len()];
+    for (from, to) in edges {
+        if let (Some(&from), Some(&to)) = (index.get(from), index.get(to)) {
+            if from != to {
+                successors[from].push(to);
+                in_degree[to] += 1;
+            }
+        }
+    }
+
+    let mut layers = vec![0; node_ids.len()];
+    let mut done = vec![false; node_ids.len()];
+    let mut queue: VecDeque<usize> = (0..node_ids.len()).filter(|&i| in_degree[i] == 0).collect();
+    let mut remaining = node_ids.len();
+
+    while remaining > 0 {
+        let node = match queue.pop_front() {
+            Some(node) => node,
+            // Only cycles are left, break one open at the first node in input order
+            None => (0..node_ids.len()).find(|&i| !done[i]).unwrap(),
+        };
+        if done[node] {
+            continue;
+        }
+        done[node] = true;
+        remaining -= 1;
+
+        for &next in &successors[node] {
+            if done[next] {
+                continue;
+            }
+            layers[next] = layers[next].max(layers[node] + 1);
+            in_degree[next] -= 1;
+            if in_degree[next] == 0 {
+                queue.push_back(next);
+            }
+        }
+    }
+
+    node_ids.iter().copied().zip(layers).collect()
+}
@@ -2,6 +2,213 @@ pub mod solve_layer_assignment;
 pub mod crossing_minimization;
 pub mod node_positioning;
 pub mod assign_bend_points;
+pub mod place_labels;
+pub mod boundary_events;
+pub mod incremental;
+pub mod eliminate_back_edges;
+pub mod longest_path;
+pub mod network_simplex;
+pub mod brandes_koepf;
 
 // pub mod testlayout;
-// pub mod eliminate_back_edges;
\ No newline at end of file
+
+use std::str::FromStr;
+
+/// Options that control how a graph is laid out, all distances are in pixels
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct LayoutOptions {
+    pub layering: Layering,
+    pub layer_across_pools: bool, // Layer all pools together instead of each pool on its own
+    pub orientation: Orientation,
+    pub layer_gap: f64,    // Space between the widest nodes of neighbouring layers
+    pub node_gap: f64,     // Space between neighbouring nodes of a layer
+    pub lane_padding: f64, // Space between the border of a lane and its nodes
+    pub pool_header_width: f64, // Width of the band with the pool name
+    pub edge_margin: f64,  // Distance edges keep from nodes they pass
+    pub edge_spacing: f64, // Space between edges that share a side of a task
+}
+
+impl Default for LayoutOptions {
+    fn default() -> Self {
+        LayoutOptions {
+            layering: Layering::default(),
+            layer_across_pools: false,
+            orientation: Orientation::default(),
+            layer_gap: 50.0,
+            node_gap: 20.0,
+            lane_padding: 50.0,
+            pool_header_width: 30.0,
+            edge_margin: 20.0,
+            edge_spacing: 10.0,
+        }
+    }
+}
+
+type Setter = fn(&mut LayoutOptions, &str) -> Result<(), String>;
+
+// Every option `set` takes, by name with underscores
+const OPTIONS: &[(&str, Setter)] = &[
+    ("layering", |options, value| {
+        options.layering = value.parse()?;
+        Ok(())
+    }),
+    ("layer_across_pools", |options, value| {
+        options.layer_across_pools = value
+            .parse()
+            .map_err(|_| format!("layer_across_pools must be true or false, not '{}'", value))?;
+        Ok(())
+    }),
+    ("orientation", |options, value| {
+        options.orientation = value.parse()?;
+        Ok(())
+    }),
+    ("layer_gap", |options, value| {
+        options.layer_gap = parse_distance("layer_gap", value)?;
+        Ok(())
+    }),
+    ("node_gap", |options, value| {
+        options.node_gap = parse_distance("node_gap", value)?;
+        Ok(())
+    }),
+    ("lane_padding", |options, value| {
+        options.lane_padding = parse_distance("lane_padding", value)?;
+        Ok(())
+    }),
+    ("pool_header_width", |options, value| {
+        options.pool_header_width = parse_distance("pool_header_width", value)?;
+        Ok(())
+    }),
+    ("edge_margin", |options, value| {
+        options.edge_margin = parse_distance("edge_margin", value)?;
+        Ok(())
+    }),
+    ("edge_spacing", set_edge_spacing),
+    // What the option was called before edges were routed on a sparse grid
+    ("grid_size", set_edge_spacing),
+];
+
+fn set_edge_spacing(options: &mut LayoutOptions, value: &str) -> Result<(), String> {
+    let edge_spacing = parse_distance("edge_spacing", value)?;
+    if edge_spacing < 1.0 {
+        return Err("edge_spacing must be at least 1".to_string());
+    }
+    options.edge_spacing = edge_spacing;
+    Ok(())
+}
+
+impl LayoutOptions {
+    /// Whether `set` knows an option of this name, dashes work like underscores here as well
+    pub fn has_option(name: &str) -> bool {
+        setter(name).is_some()
+    }
+
+    /// Sets an option by the name of its field, e.g. `set("layer_gap", "30")`.
+    /// Dashes in the name work like underscores, so command line flags can be passed on as they are.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let set = setter(name)
+            .ok_or_else(|| format!("Unknown layout option '{}'", name.trim().replace('-', "_")))?;
+        set(self, value.trim())
+    }
+
+    /// Reads options from a config file with one `name = value` pair per line.
+    /// Options that are not in the file keep their default. A `#` at the start of a line or after
+    /// a space starts a comment, unless it is inside quotes.
+    pub fn from_config(config: &str) -> Result<Self, String> {
+        let mut options = LayoutOptions::default();
+        for (index, line) in config.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Line {}: expected 'name = value'", index + 1))?;
+            options
+                .set(name, value.trim().trim_matches('"'))
+                .map_err(|e| format!("Line {}: {}", index + 1, e))?;
+        }
+        Ok(options)
+    }
+}
+
+fn setter(name: &str) -> Option<Setter> {
+    let name = name.trim().replace('-', "_");
+    OPTIONS
+        .iter()
+        .find(|(option, _)| *option == name)
+        .map(|(_, setter)| *setter)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut after_space = true;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted && after_space => return &line[..index],
+            _ => {}
+        }
+        after_space = c.is_whitespace();
+    }
+    line
+}
+
+fn parse_distance(name: &str, value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(distance) if distance.is_finite() && distance >= 0.0 => Ok(distance),
+        _ => Err(format!(
+            "{} must be a number of pixels, not '{}'",
+            name, value
+        )),
+    }
+}
+
+/// How nodes are assigned to layers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layering {
+    /// Every node goes one layer after its furthest predecessor, fast but edges can get long
+    LongestPath,
+    /// Shortest total edge length, found with the network simplex method
+    #[default]
+    NetworkSimplex,
+    /// Shortest total edge length, found as an integer program with the CBC solver
+    #[cfg(feature = "cbc")]
+    Ilp,
+}
+
+impl FromStr for Layering {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "longest-path" => Ok(Layering::LongestPath),
+            "network-simplex" => Ok(Layering::NetworkSimplex),
+            #[cfg(feature = "cbc")]
+            "ilp" => Ok(Layering::Ilp),
+            _ => Err(format!("Unknown layering '{}'", value)),
+        }
+    }
+}
+
+/// Direction the flow goes in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Orientation {
+    /// Layers are columns and pools and lanes are horizontal bands
+    #[default]
+    LeftToRight,
+    /// Layers are rows and pools and lanes are vertical bands
+    TopToBottom,
+}
+
+impl FromStr for Orientation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "left-to-right" => Ok(Orientation::LeftToRight),
+            "top-to-bottom" => Ok(Orientation::TopToBottom),
+            _ => Err(format!("Unknown orientation '{}'", value)),
+        }
+    }
+}
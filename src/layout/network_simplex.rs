@@ -0,0 +1,305 @@
+// network_simplex.rs
+
+use crate::layout::longest_path::longest_path_layers;
+use std::collections::{HashMap, HashSet};
+
+/// Assigns layers with the smallest total edge length where every edge spans at least one layer,
+/// using the network simplex method of Gansner et al. Every connected part of the graph starts at layer 0.
+/// Edges that close a cycle are ignored, like in the longest path layering it starts from.
+pub fn network_simplex_layers(
+    node_ids: &[usize],
+    edges: &[(usize, usize)],
+) -> HashMap<usize, usize> {
+    let initial = longest_path_layers(node_ids, edges);
+
+    // Only edges the initial layering satisfies are kept, these can not form a cycle.
+    // Parallel edges become one edge with a higher weight.
+    let mut merged: Vec<(usize, usize, i64)> = Vec::new();
+    let mut merged_index: HashMap<(usize, usize), usize> = HashMap::new();
+    for &(from, to) in edges {
+        if let (Some(from_layer), Some(to_layer)) = (initial.get(&from), initial.get(&to)) {
+            if from_layer < to_layer {
+                let index = *merged_index.entry((from, to)).or_insert_with(|| {
+                    merged.push((from, to, 0));
+                    merged.len() - 1
+                });
+                merged[index].2 += 1;
+            }
+        }
+    }
+
+    let mut layers = HashMap::new();
+    for component in connected_components(node_ids, &merged) {
+        let local: HashMap<usize, usize> = component
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (*id, i))
+            .collect();
+        let component_edges: Vec<(usize, usize, i64)> = merged
+            .iter()
+            .filter(|(from, _, _)| local.contains_key(from))
+            .map(|&(from, to, weight)| (local[&from], local[&to], weight))
+            .collect();
+        let ranks: Vec<i64> = component.iter().map(|id| initial[id] as i64).collect();
+
+        let mut simplex = Simplex::new(ranks, component_edges);
+        simplex.solve();
+
+        let min_rank = simplex.rank.iter().copied().min().unwrap_or(0);
+        for (id, rank) in component.iter().zip(&simplex.rank) {
+            layers.insert(*id, (rank - min_rank) as usize);
+        }
+    }
+    layers
+}
+
+/// Groups the nodes that are connected by edges, in input order
+fn connected_components(node_ids: &[usize], edges: &[(usize, usize, i64)]) -> Vec<Vec<usize>> {
+    let mut neighbours: HashMap<usize, Vec<usize>> = HashMap::new();
+    for &(from, to, _) in edges {
+        neighbours.entry(from).or_default().push(to);
+        neighbours.entry(to).or_default().push(from);
+    }
+
+    let mut visited: HashSet<usize> = HashSet::new();
+    let mut components: Vec<Vec<usize>> = Vec::new();
+    for &start in node_ids {
+        if !visited.insert(start) {
+            continue;
+        }
+        let mut component = Vec::new();
+        let mut stack = vec![start];
+        while let Some(node) = stack.pop() {
+            component.push(node);
+            for &next in neighbours.get(&node).into_iter().flatten() {
+                if visited.insert(next) {
+                    stack.push(next);
+                }
+            }
+        }
+        component.sort_by_key(|id| node_ids.iter().position(|n| n == id));
+        components.push(component);
+    }
+    components
+}
+
+/// Network simplex over one connected, acyclic graph where every edge has a minimum length of 1
+struct Simplex {
+    tails: Vec<usize>,
+    heads: Vec<usize>,
+    weights: Vec<i64>,
+    incident: Vec<Vec<usize>>, // Edges touching each node
+    rank: Vec<i64>,
+    in_tree: Vec<bool>,
+    cut_values: Vec<i64>,
+    parent_edge: Vec<Option<usize>>,
+    low: Vec<usize>, // Smallest postorder number in the subtree of a node
+    lim: Vec<usize>, // Postorder number of a node in the spanning tree
+}
+
+impl Simplex {
+    fn new(rank: Vec<i64>, edges: Vec<(usize, usize, i64)>) -> Self {
+        let node_count = rank.len();
+        let mut incident = vec![Vec::new(); node_count];
+        for (index, &(from, to, _)) in edges.iter().enumerate() {
+            incident[from].push(index);
+            incident[to].push(index);
+        }
+        Simplex {
+            tails: edges.iter().map(|e| e.0).collect(),
+            heads: edges.iter().map(|e| e.1).collect(),
+            weights: edges.iter().map(|e| e.2).collect(),
+            incident,
+            rank,
+            in_tree: vec![false; edges.len()],
+            cut_values: vec![0; edges.len()],
+            parent_edge: vec![None; node_count],
+            low: vec![0; node_count],
+            lim: vec![0; node_count],
+        }
+    }
+
+    fn solve(&mut self) {
+        if self.rank.len() < 2 {
+            return;
+        }
+        self.feasible_tree();
+        self.init_low_lim();
+        self.init_cut_values();
+
+        // Degenerate pivots could in theory repeat forever, the bound is far above what real graphs need
+        let max_iterations = self.rank.len() * self.tails.len() + 1;
+        for _ in 0..max_iterations {
+            let Some(leaving) =
+                (0..self.tails.len()).find(|&e| self.in_tree[e] && self.cut_values[e] < 0)
+            else {
+                break;
+            };
+            let Some(entering) = self.enter_edge(leaving) else {
+                break;
+            };
+            self.in_tree[leaving] = false;
+            self.in_tree[entering] = true;
+            self.init_low_lim();
+            self.init_cut_values();
+            self.update_ranks();
+        }
+    }
+
+    fn slack(&self, edge: usize) -> i64 {
+        self.rank[self.heads[edge]] - self.rank[self.tails[edge]] - 1
+    }
+
+    fn other_end(&self, edge: usize, node: usize) -> usize {
+        if self.tails[edge] == node {
+            self.heads[edge]
+        } else {
+            self.tails[edge]
+        }
+    }
+
+    /// Builds a spanning tree of tight edges, moving the ranks of the tree until it reaches every node
+    fn feasible_tree(&mut self) {
+        let node_count = self.rank.len();
+        let mut tree_nodes = vec![false; node_count];
+        tree_nodes[0] = true;
+
+        loop {
+            // Grow the tree along edges without slack
+            let mut stack: Vec<usize> = (0..node_count).filter(|&n| tree_nodes[n]).collect();
+            while let Some(node) = stack.pop() {
+                for &edge in &self.incident[node] {
+                    let other = self.other_end(edge, node);
+                    if !tree_nodes[other] && self.slack(edge) == 0 {
+                        tree_nodes[other] = true;
+                        self.in_tree[edge] = true;
+                        stack.push(other);
+                    }
+                }
+            }
+            if tree_nodes.iter().all(|&in_tree| in_tree) {
+                return;
+            }
+
+            // Pull the tree towards the closest node outside of it, which makes that edge tight
+            let Some(edge) = (0..self.tails.len())
+                .filter(|&e| tree_nodes[self.tails[e]] != tree_nodes[self.heads[e]])
+                .min_by_key(|&e| self.slack(e))
+            else {
+                return;
+            };
+            let delta = if tree_nodes[self.tails[edge]] {
+                self.slack(edge)
+            } else {
+                -self.slack(edge)
+            };
+            for (rank, _) in self
+                .rank
+                .iter_mut()
+                .zip(&tree_nodes)
+                .filter(|(_, &in_tree)| in_tree)
+            {
+                *rank += delta;
+            }
+        }
+    }
+
+    /// Numbers the tree in postorder from node 0, so subtree membership is a range check
+    fn init_low_lim(&mut self) {
+        let mut next_lim = 1;
+        self.parent_edge[0] = None;
+        self.low[0] = next_lim;
+        let mut stack = vec![(0, 0)];
+        while let Some(&(node, position)) = stack.last() {
+            if position < self.incident[node].len() {
+                stack.last_mut().unwrap().1 += 1;
+                let edge = self.incident[node][position];
+                if !self.in_tree[edge] || Some(edge) == self.parent_edge[node] {
+                    continue;
+                }
+                let child = self.other_end(edge, node);
+                self.parent_edge[child] = Some(edge);
+                self.low[child] = next_lim;
+                stack.push((child, 0));
+            } else {
+                self.lim[node] = next_lim;
+                next_lim += 1;
+                stack.pop();
+            }
+        }
+    }
+
+    fn is_descendant(&self, node: usize, root: usize) -> bool {
+        self.low[root] <= self.lim[node] && self.lim[node] <= self.lim[root]
+    }
+
+    /// Children come before their parents in postorder, so their cut values are known
+    /// when the cut value of the edge above a node is calculated
+    fn init_cut_values(&mut self) {
+        let mut order: Vec<usize> = (0..self.rank.len()).collect();
+        order.sort_by_key(|&node| self.lim[node]);
+
+        for child in order {
+            let Some(parent_edge) = self.parent_edge[child] else {
+                continue;
+            };
+            let child_is_tail = self.tails[parent_edge] == child;
+            let mut cut_value = self.weights[parent_edge];
+
+            for &edge in &self.incident[child] {
+                if edge == parent_edge {
+                    continue;
+                }
+                let is_out_edge = self.tails[edge] == child;
+                let points_to_head = is_out_edge == child_is_tail;
+                let weight = self.weights[edge];
+                cut_value += if points_to_head { weight } else { -weight };
+                if self.in_tree[edge] {
+                    let other_cut_value = self.cut_values[edge];
+                    cut_value += if points_to_head {
+                        -other_cut_value
+                    } else {
+                        other_cut_value
+                    };
+                }
+            }
+            self.cut_values[parent_edge] = cut_value;
+        }
+    }
+
+    /// Finds the non tree edge with the least slack that reconnects the two parts
+    /// of the tree once `leaving` is removed, in the opposite direction of `leaving`
+    fn enter_edge(&self, leaving: usize) -> Option<usize> {
+        let (tail, head) = (self.tails[leaving], self.heads[leaving]);
+        // The subtree below `leaving` holds either its tail or its head
+        let (subtree_root, flip) = if self.lim[tail] > self.lim[head] {
+            (head, true)
+        } else {
+            (tail, false)
+        };
+
+        (0..self.tails.len())
+            .filter(|&edge| {
+                flip == self.is_descendant(self.tails[edge], subtree_root)
+                    && flip != self.is_descendant(self.heads[edge], subtree_root)
+            })
+            .min_by_key(|&edge| self.slack(edge))
+    }
+
+    /// Makes every tree edge tight again, parents have a higher postorder number than their children
+    fn update_ranks(&mut self) {
+        let mut order: Vec<usize> = (0..self.rank.len()).collect();
+        order.sort_by_key(|&node| std::cmp::Reverse(self.lim[node]));
+
+        for node in order {
+            if let Some(edge) = self.parent_edge[node] {
+                let parent = self.other_end(edge, node);
+                self.rank[node] = if self.tails[edge] == node {
+                    self.rank[parent] - 1
+                } else {
+                    self.rank[parent] + 1
+                };
+            }
+        }
+    }
+}
@@ -1,191 +1,373 @@
-use crate::common::{bpmn_event::get_node_size, graph::Graph, lane::Lane, node::Node};
+use crate::common::bpmn_event::{get_label_size, get_node_size, BpmnEvent, LABEL_GAP};
+use crate::common::layout_hint::{HintKind, LayoutHint};
+use crate::common::{graph::Graph, lane::Lane};
+use crate::diagnostics::Diagnostic;
+use crate::layout::brandes_koepf::brandes_koepf_positions;
+use crate::layout::{LayoutOptions, Orientation};
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::mem::swap;
 
-pub fn assign_xy_to_nodes(graph: &mut Graph) {
+// Every node gets a cell at least this large, smaller shapes are centered in it
+const MIN_CELL_WIDTH: f64 = 100.0;
+const MIN_CELL_HEIGHT: f64 = 80.0;
+/// Height of the band a collapsed pool is drawn as
+pub const COLLAPSED_POOL_HEIGHT: f64 = 60.0;
+/// Lanes without nodes keep this height, so they are still drawn
+pub const MIN_LANE_HEIGHT: f64 = 100.0;
+// Lanes are at least this wide, even if their nodes need less room
+const MIN_LANE_WIDTH: f64 = 350.0;
+
+/// Places pools, lanes and nodes. The layout is made for left to right flow, for top to bottom
+/// it is made with the node sizes swapped and then mirrored along the diagonal.
+/// `@row`, `@above` and `@below` hints move nodes across the flow where there is room, a warning
+/// is returned for every hint that could not be kept.
+pub fn assign_xy_to_nodes(graph: &mut Graph, options: &LayoutOptions) -> Vec<Diagnostic> {
+    let orientation = options.orientation;
     let pool_position_x = 100.0;
     let mut pool_position_y = 100.0;
-    let mut node_position_y = 150.0;
-    let layer_width = 150.0;
-    let lane_x_offset = 30.0;
+    let layer_gap = options.layer_gap;
+    let node_gap = options.node_gap;
+    let lane_x_offset = options.pool_header_width;
+    let lane_padding = options.lane_padding;
     let lane_position_x = pool_position_x + lane_x_offset;
-    let mut lane_position_y = 100.0;
-    let node_x_start = lane_position_x + 50.0;
-
-    let mut original_positions: HashMap<usize, f64> = HashMap::new();
-
-    {
-        let pools = graph.get_pools_mut();
-        for pool in pools {
-            let mut pool_height = 0.0;
-            let mut lane_width = 0.0;
-            for lane in pool.get_lanes_mut() {
-                lane.sort_nodes_by_layer_id();
-                let max_height = find_max_nodes_in_layer(lane.get_layers()) * 100 + 80;
-                pool_height += max_height as f64;
-                lane.set_height(max_height as f64);
-                let new_lane_width = get_lane_width(lane);
-                if new_lane_width > lane_width {
-                    lane_width = new_lane_width;
-                }
-
-                for layer_index in 0..lane.get_layers().len() {
-                    let x = node_x_start + (layer_index as f64 * layer_width);
-                    let mut y_layer_position = node_position_y;
-                    {
-                        let nodes_for_this_layer = lane.get_nodes_by_layer_id_mut(layer_index);
-                        for node in nodes_for_this_layer {
-                            let (node_size_x, node_size_y) =
-                                get_node_size(node.event.as_ref().unwrap());
-                            let y_offset = if node_size_y < 80 {
-                                (80 - node_size_y) as f64 / 2.0
-                            } else {
-                                0.0
-                            };
-                            let x_offset = if node_size_x < 100 {
-                                (100 - node_size_x) as f64 / 2.0
-                            } else {
-                                0.0
-                            };
-                            let old_y = node.y.unwrap_or(y_layer_position);
-                            node.set_position(x, old_y, x_offset, y_offset);
-                            original_positions.insert(node.id, x);
-                            y_layer_position += 100.0;
-                        }
-                    }
-                }
-
-                node_position_y += max_height as f64;
-                lane.set_position(lane_position_x, lane_position_y);
-                lane_position_y += max_height as f64;
+    // Nodes start after the headers of all lanes they can be nested in
+    let nesting = graph
+        .get_pools()
+        .iter()
+        .flat_map(|pool| pool.get_lanes().iter().map(|lane| pool.lane_depth(lane)))
+        .max()
+        .unwrap_or(0);
+    let node_x_start = lane_position_x + nesting as f64 * lane_x_offset + lane_padding;
+
+    // Back edges are drawn around the lane, so they should not pull nodes out of line
+    let edges: Vec<(usize, usize)> = graph
+        .edges
+        .iter()
+        .filter(|edge| !edge.is_back_edge)
+        .map(|edge| (edge.from, edge.to))
+        .collect();
+
+    // Each column is as wide as its widest node, so the same layer lines up in every lane and pool.
+    // Shapes are centered on the middle line of their column, their labels may reach further.
+    let mut column_reach: Vec<(f64, f64)> = Vec::new();
+    for node in graph.nodes() {
+        let layer = node.layer_id.unwrap_or(0);
+        if column_reach.len() <= layer {
+            column_reach.resize(layer + 1, (0.0, 0.0));
+        }
+        let (before, after) = extents(node.event.as_ref(), orientation).along;
+        column_reach[layer].0 = column_reach[layer].0.max(before);
+        column_reach[layer].1 = column_reach[layer].1.max(after);
+    }
+    let mut column_x = Vec::with_capacity(column_reach.len());
+    let mut x = node_x_start;
+    for (before, after) in &column_reach {
+        column_x.push(x);
+        x += before + after + layer_gap;
+    }
+
+    // Hints between nodes of one lane are kept within the lane, for nodes in different lanes
+    // the order of the lanes decides
+    let mut broken: Vec<(LayoutHint, &str)> = Vec::new();
+    let lane_of: HashMap<usize, (usize, usize)> = graph
+        .get_pools()
+        .iter()
+        .enumerate()
+        .flat_map(|(pool_index, pool)| {
+            pool.get_lanes()
+                .iter()
+                .enumerate()
+                .flat_map(move |(lane_index, lane)| {
+                    lane.get_layers()
+                        .iter()
+                        .map(move |node| (node.id, (pool_index, lane_index)))
+                })
+        })
+        .collect();
+    let mut lane_hints: HashMap<(usize, usize), Vec<LayoutHint>> = HashMap::new();
+    for hint in &graph.hints {
+        let (other, wanted) = match hint.kind {
+            HintKind::Row(other) => (other, Ordering::Equal),
+            HintKind::Above(other) => (other, Ordering::Less),
+            HintKind::Below(other) => (other, Ordering::Greater),
+            HintKind::Layer(_) => continue,
+        };
+        let (Some(lane), Some(other_lane)) = (lane_of.get(&hint.node), lane_of.get(&other)) else {
+            continue;
+        };
+        if lane == other_lane {
+            lane_hints.entry(*lane).or_default().push(hint.clone());
+        } else if wanted == Ordering::Equal {
+            broken.push((hint.clone(), "the nodes are in different lanes"));
+        } else if lane.cmp(other_lane) != wanted {
+            broken.push((
+                hint.clone(),
+                "the lanes of the nodes are in the other order",
+            ));
+        }
+    }
+
+    // Collapsed pools are bands above or below the other pools: the ones written before the first
+    // expanded pool go above them, all others below
+    let first_expanded = graph
+        .get_pools()
+        .iter()
+        .position(|pool| !pool.collapsed)
+        .unwrap_or(0);
+    let mut stack: Vec<usize> = (0..graph.get_pools().len()).collect();
+    stack.sort_by_key(|&index| {
+        let pool = &graph.get_pools()[index];
+        match (pool.collapsed, index < first_expanded) {
+            (true, true) => 0,
+            (false, _) => 1,
+            (true, false) => 2,
+        }
+    });
+
+    for pool_index in stack {
+        let pool = &mut graph.get_pools_mut()[pool_index];
+        if pool.collapsed {
+            pool.set_position(pool_position_x, pool_position_y);
+            pool.set_height(COLLAPSED_POOL_HEIGHT);
+            pool_position_y += COLLAPSED_POOL_HEIGHT;
+            continue;
+        }
+        let mut pool_height = 0.0;
+        let mut lane_width: f64 = 0.0;
+        let mut lane_position_y = pool_position_y;
+        // Lanes with nested lanes are stretched over them afterwards
+        let parent_lanes = pool.parent_lanes();
+
+        for (lane_index, lane) in pool.get_lanes_mut().iter_mut().enumerate() {
+            if parent_lanes.contains(lane.get_lane()) {
+                continue;
             }
+            let hints = lane_hints
+                .remove(&(pool_index, lane_index))
+                .unwrap_or_default();
+            let offsets = lane_offsets(lane, &edges, node_gap, orientation, &hints, &mut broken);
+            let mut content_height: f64 = 0.0;
+            let mut content_right = node_x_start;
 
-            if lane_width > pool.width.unwrap_or(0.0) {
-                pool.set_width(lane_width + lane_x_offset);
+            for node in lane.get_layers_mut() {
+                let layer = node.layer_id.unwrap_or(0);
+                let extents = extents(node.event.as_ref(), orientation);
+                let (shape_along, shape_across) = extents.shape;
+                let (above, below) = extents.across;
+                let (column_before, column_after) = column_reach[layer];
+                let center = offsets.get(&node.id).copied().unwrap_or(0.0);
+
+                let x = column_x[layer];
+                let y = lane_position_y + lane_padding + center - above;
+                let x_offset = column_before - shape_along / 2.0;
+                let y_offset = above - shape_across / 2.0;
+                node.set_position(x, y, x_offset, y_offset);
+
+                content_height = content_height.max(center + below);
+                content_right = content_right.max(x + column_before + column_after);
             }
-            pool.set_height(pool_height);
-            pool.set_position(pool_position_x, pool_position_y);
-            pool_position_y += pool_height;
-            pool.set_lane_width(lane_width);
+
+            // Lanes are as high as the nodes placed in them reach
+            let lane_height = (content_height + 2.0 * lane_padding).max(MIN_LANE_HEIGHT);
+            lane.set_height(lane_height);
+            lane.set_position(lane_position_x, lane_position_y);
+            lane_position_y += lane_height;
+            pool_height += lane_height;
+            lane_width = lane_width
+                .max(content_right + lane_padding - lane_position_x)
+                .max(MIN_LANE_WIDTH);
         }
 
-        //     let mut lane_change_new_x: HashMap<usize, f64> = HashMap::new();
-        //     {
-        //         let edges = &graph.edges;
-        //         for edge in edges {
-        //             if let (Some(to_node), Some(from_node)) = (
-        //                 graph.get_node_by_id(edge.to),
-        //                 graph.get_node_by_id(edge.from),
-        //             ) {
-        //                 if from_node.lane != to_node.lane {
-        //                     if let Some(fx) = from_node.x {
-        //                         lane_change_new_x.insert(to_node.id, fx);
-        //                     }
-        //                 }
-        //             }
-        //         }
-        //     }
-
-        //     let mut lane_shifts: HashMap<(String, String), Vec<(usize, usize, f64)>> = HashMap::new();
-
-        //     {
-        //         let pools = graph.get_pools();
-        //         for pool in pools {
-        //             for lane in pool.get_lanes() {
-        //                 let layer_nodes = lane.get_layers();
-        //                 for (index, node) in layer_nodes.iter().enumerate() {
-        //                     if let Some(&new_x) = lane_change_new_x.get(&node.id) {
-        //                         if let Some(&old_x) = original_positions.get(&node.id) {
-        //                             let dx = new_x - old_x;
-        //                             if dx.abs() > f64::EPSILON {
-        //                                 let key = (pool.get_pool_name(), lane.get_lane().clone());
-        //                                 lane_shifts
-        //                                     .entry(key)
-        //                                     .or_default()
-        //                                     .push((index, node.id, dx));
-        //                             }
-        //                         }
-        //                     }
-        //                 }
-        //             }
-        //         }
-        //     }
-
-        //     for changes_key in lane_shifts.keys() {
-        //         let mut changes = lane_shifts.get(changes_key).unwrap().clone();
-        //         changes.sort_by_key(|c| c.0);
-        //     }
-
-        //     {
-        //         let pools = graph.get_pools_mut();
-        //         for pool in pools {
-        //             let pool_name = pool.get_pool_name().to_string(); // Salvestame pooli nime ette
-        //             for lane in pool.get_lanes_mut() {
-        //                 let lane_name = lane.get_lane().clone(); // Salvestame lane'i nime ette
-        //                 let key = (pool_name.clone(), lane_name);
-        //                 if let Some(mut changes) = lane_shifts.get(&key).cloned() {
-        //                     changes.sort_by_key(|c| c.0);
-
-        //                     let layer_nodes = lane.get_layers();
-        //                     let len = layer_nodes.len();
-        //                     let mut dx_map: Vec<f64> = vec![0.0; len];
-
-        //                     for (node_index, _node_id, dx) in changes {
-        //                         for i in node_index..len {
-        //                             dx_map[i] += dx;
-        //                         }
-        //                     }
-
-        //                     for i in 1..len {
-        //                         dx_map[i] += dx_map[i - 1];
-        //                     }
-
-        //                     let lane_nodes_mut = lane.get_layers_mut();
-        //                     for i in 0..len {
-        //                         if dx_map[i].abs() > f64::EPSILON {
-        //                             let node = &mut lane_nodes_mut[i];
-        //                             let old_x = node.x.unwrap_or(0.0);
-        //                             let old_y = node.y.unwrap_or(0.0);
-        //                             let old_y_off = node.y_offset.unwrap_or(0.0);
-        //                             let old_x_off = node.x_offset.unwrap_or(0.0);
-        //                             node.set_position(old_x + dx_map[i], old_y, old_x_off, old_y_off);
-        //                         }
-        //                     }
-        //                 }
-        //             }
-        //         }
-        //     }
+        pool.set_width(lane_width + lane_x_offset);
+        pool.set_height(pool_height);
+        pool.set_position(pool_position_x, pool_position_y);
+        pool_position_y += pool_height;
+        pool.enclose_child_lanes();
+    }
+
+    // All pools of the collaboration share their left edge and are as wide as the widest one
+    let widest = graph
+        .get_pools()
+        .iter()
+        .filter_map(|pool| pool.width)
+        .fold(MIN_LANE_WIDTH + lane_x_offset, f64::max);
+    for pool in graph.get_pools_mut() {
+        pool.set_width(widest);
+        pool.set_lane_width(widest - lane_x_offset, lane_x_offset);
+    }
+
+    if orientation == Orientation::TopToBottom {
+        transpose(graph);
     }
+    graph.orientation = orientation;
+
+    broken
+        .into_iter()
+        .map(|(hint, reason)| hint.warning(graph, reason))
+        .collect()
 }
 
-fn find_max_nodes_in_layer(nodes: &Vec<Node>) -> usize {
-    let mut max = 0;
-    let mut cur_max = 0;
-    let mut current_layer_id = 0;
-
-    for node in nodes {
-        println!("Node max: {}", node.layer_id.as_ref().unwrap());
-        if node.layer_id.unwrap_or(0) != current_layer_id {
-            current_layer_id = node.layer_id.unwrap_or(0);
-            if cur_max > max {
-                max = cur_max;
+/// Center of every node of the lane measured from the top of its content area.
+/// `hints` are the hints between nodes of this lane, the ones that cannot be kept go to `broken`.
+fn lane_offsets(
+    lane: &Lane,
+    edges: &[(usize, usize)],
+    node_gap: f64,
+    orientation: Orientation,
+    hints: &[LayoutHint],
+    broken: &mut Vec<(LayoutHint, &str)>,
+) -> HashMap<usize, f64> {
+    let layer_count = lane
+        .get_layers()
+        .iter()
+        .map(|node| node.layer_id.unwrap_or(0) + 1)
+        .max()
+        .unwrap_or(0);
+    // Layers are shared by the whole pool, so a lane can have empty layers
+    let mut layers: Vec<Vec<usize>> = vec![Vec::new(); layer_count];
+    let mut sizes: HashMap<usize, (f64, f64)> = HashMap::new();
+    for node in lane.get_layers() {
+        layers[node.layer_id.unwrap_or(0)].push(node.id);
+        sizes.insert(node.id, extents(node.event.as_ref(), orientation).across);
+    }
+
+    // `@above` and `@below` between nodes of one layer only change their order
+    for hint in hints {
+        let (other, before) = match hint.kind {
+            HintKind::Above(other) => (other, true),
+            HintKind::Below(other) => (other, false),
+            _ => continue,
+        };
+        let Some(layer) = layers
+            .iter_mut()
+            .find(|layer| layer.contains(&hint.node) && layer.contains(&other))
+        else {
+            continue;
+        };
+        layer.retain(|id| *id != hint.node);
+        let index = layer.iter().position(|id| *id == other).unwrap_or(0);
+        layer.insert(if before { index } else { index + 1 }, hint.node);
+    }
+    let mut centers = brandes_koepf_positions(&layers, edges, &sizes, node_gap);
+
+    // Otherwise the node moves across the flow, as far as its neighbours in its layer allow
+    for hint in hints {
+        let (HintKind::Row(other) | HintKind::Above(other) | HintKind::Below(other)) = hint.kind
+        else {
+            continue;
+        };
+        let Some(layer) = layers.iter().find(|layer| layer.contains(&hint.node)) else {
+            continue;
+        };
+        if layer.contains(&other) {
+            if matches!(hint.kind, HintKind::Row(_)) {
+                broken.push((hint.clone(), "both nodes are in the same layer"));
             }
+            continue;
+        }
+        let (before, after) = sizes[&hint.node];
+        let (other_before, other_after) = sizes[&other];
+        let (center, other_center) = (centers[&hint.node], centers[&other]);
+        let wanted = match hint.kind {
+            HintKind::Row(_) => other_center,
+            HintKind::Above(_) => center.min(other_center - other_before - node_gap - after),
+            _ => center.max(other_center + other_after + node_gap + before),
+        };
+        let index = layer.iter().position(|id| *id == hint.node).unwrap_or(0);
+        let free_before = index == 0 || {
+            let previous = layer[index - 1];
+            centers[&previous] + sizes[&previous].1 + node_gap <= wanted - before + 1e-6
+        };
+        let free_after = index + 1 == layer.len() || {
+            let next = layer[index + 1];
+            wanted + after + node_gap <= centers[&next] - sizes[&next].0 + 1e-6
+        };
+        if free_before && free_after {
+            centers.insert(hint.node, wanted);
+        } else {
+            broken.push((hint.clone(), "other nodes of its layer are in the way"));
+        }
+    }
+
+    // A node moved up can stick out of the content area, which then starts at its top
+    let top = layers
+        .iter()
+        .flatten()
+        .map(|id| centers[id] - sizes[id].0)
+        .fold(0.0, f64::min);
+    for center in centers.values_mut() {
+        *center -= top;
+    }
+    centers
+}
 
-            cur_max = 1;
-        } else if node.layer_id.unwrap_or(0) == current_layer_id {
-            cur_max += 1;
+/// Space a node takes in flow terms, measured from the center of its shape
+struct Extents {
+    shape: (f64, f64),  // Size of the shape along and across the flow
+    along: (f64, f64),  // Reach before and after the center along the flow
+    across: (f64, f64), // Reach before and after the center across the flow
+}
+
+/// Extents of the cell a node is centered in, grown to hold its external label
+fn extents(event: Option<&BpmnEvent>, orientation: Orientation) -> Extents {
+    let (width, height) = event.map_or((0, 0), get_node_size);
+    let (width, height) = (width as f64, height as f64);
+    let half_width = width.max(MIN_CELL_WIDTH) / 2.0;
+    let half_height = height.max(MIN_CELL_HEIGHT) / 2.0;
+    let (mut left, mut right, mut top, mut bottom) =
+        (half_width, half_width, half_height, half_height);
+
+    if let Some((label_width, label_height)) = event.and_then(get_label_size) {
+        let (label_width, label_height) = (label_width as f64, label_height as f64);
+        match orientation {
+            Orientation::LeftToRight => {
+                bottom = bottom.max(height / 2.0 + LABEL_GAP + label_height);
+                left = left.max(label_width / 2.0);
+                right = right.max(label_width / 2.0);
+            }
+            Orientation::TopToBottom => {
+                right = right.max(width / 2.0 + LABEL_GAP + label_width);
+                top = top.max(label_height / 2.0);
+                bottom = bottom.max(label_height / 2.0);
+            }
         }
     }
-    println!("Max nodes found: {}", max);
-    max
+
+    let shape = along_flow((width, height), orientation);
+    match orientation {
+        Orientation::LeftToRight => Extents {
+            shape,
+            along: (left, right),
+            across: (top, bottom),
+        },
+        Orientation::TopToBottom => Extents {
+            shape,
+            along: (top, bottom),
+            across: (left, right),
+        },
+    }
 }
 
-fn get_lane_width(lane: &Lane) -> f64 {
-    let last_node = lane.get_layers().last().unwrap();
-    let last_layer = last_node.layer_id.unwrap_or(0);
-    println!("last_layer: {}", last_layer);
-    if last_layer == 0 || last_layer == 1 {
-        return 350.0;
-    } else {
-        return (last_layer) as f64 * 200.0;
+fn along_flow((width, height): (f64, f64), orientation: Orientation) -> (f64, f64) {
+    match orientation {
+        Orientation::LeftToRight => (width, height),
+        Orientation::TopToBottom => (height, width),
+    }
+}
+
+/// Mirrors every position and size along the diagonal, so columns become rows
+pub(crate) fn transpose(graph: &mut Graph) {
+    for pool in graph.get_pools_mut() {
+        swap(&mut pool.x, &mut pool.y);
+        swap(&mut pool.width, &mut pool.height);
+        for lane in pool.get_lanes_mut() {
+            swap(&mut lane.x, &mut lane.y);
+            swap(&mut lane.width, &mut lane.height);
+            for node in lane.get_layers_mut() {
+                swap(&mut node.x, &mut node.y);
+                swap(&mut node.x_offset, &mut node.y_offset);
+            }
+        }
     }
 }
@@ -0,0 +1,156 @@
+use crate::common::bpmn_event::{get_node_size, EXTERNAL_LABEL_WIDTH};
+use crate::common::graph::Graph;
+use crate::common::text_metrics::text_size;
+use crate::layout::assign_bend_points::straighten;
+use crate::layout::LayoutOptions;
+
+// x, y, width and height
+type Rect = (f64, f64, f64, f64);
+type Segment = ((f64, f64), (f64, f64));
+
+// Distance between an edge label and the line it belongs to
+const LABEL_OFFSET: f64 = 5.0;
+// Distance between the places tried along a segment
+const SLIDE_STEP: f64 = 10.0;
+
+/// Places the text of every edge, and of every message flow of a collapsed pool, next to the first
+/// segment of its route. The label may not cover a node, the label of a node or the label of an
+/// earlier edge. Of the free places the one crossing the fewest edges wins, then the one closest
+/// to the start of the edge. When the first segment has no free place, the following segments are
+/// tried in turn.
+pub fn place_edge_labels(graph: &mut Graph, options: &LayoutOptions) {
+    let mut obstacles: Vec<Rect> = Vec::new();
+    for node in graph.nodes() {
+        if let (Some(event), Some(x), Some(y)) = (node.event.as_ref(), node.x, node.y) {
+            let (width, height) = get_node_size(event);
+            obstacles.push((
+                x + node.x_offset.unwrap_or(0.0),
+                y + node.y_offset.unwrap_or(0.0),
+                width as f64,
+                height as f64,
+            ));
+        }
+        if let Some(bounds) = node.label_bounds(options.orientation) {
+            obstacles.push(bounds);
+        }
+    }
+
+    let segments: Vec<Segment> = graph
+        .edges
+        .iter()
+        .filter_map(|edge| edge.bend_points.as_ref())
+        .chain(
+            graph
+                .pool_flows
+                .iter()
+                .filter_map(|flow| flow.bend_points.as_ref()),
+        )
+        .flat_map(|points| points.windows(2).map(|pair| (pair[0], pair[1])))
+        .collect();
+
+    for edge in graph.edges.iter_mut() {
+        edge.label_bounds = place_label(
+            edge.text.as_deref(),
+            &edge.bend_points,
+            &mut obstacles,
+            &segments,
+        );
+    }
+    for flow in graph.pool_flows.iter_mut() {
+        flow.label_bounds = place_label(
+            flow.text.as_deref(),
+            &flow.bend_points,
+            &mut obstacles,
+            &segments,
+        );
+    }
+}
+
+/// Bounds for the text of an edge along its route, which are added to the obstacles
+fn place_label(
+    text: Option<&str>,
+    points: &Option<Vec<(f64, f64)>>,
+    obstacles: &mut Vec<Rect>,
+    segments: &[Segment],
+) -> Option<Rect> {
+    let text = text.filter(|text| !text.trim().is_empty())?;
+    let points = points.as_ref().filter(|points| points.len() >= 2)?;
+
+    let (width, height) = text_size(text, EXTERNAL_LABEL_WIDTH);
+    let (width, height) = (width.ceil(), height.ceil());
+    let route = straighten(points);
+    // A route of repeated points has no segment to put the label on
+    if route.len() < 2 {
+        return None;
+    }
+    let bounds = route
+        .windows(2)
+        .find_map(|pair| {
+            candidates((pair[0], pair[1]), width, height)
+                .into_iter()
+                .enumerate()
+                .filter(|(_, rect)| !obstacles.iter().any(|obstacle| overlaps(*rect, *obstacle)))
+                .min_by_key(|(index, rect)| (crossings(rect, segments), *index))
+                .map(|(_, rect)| rect)
+        })
+        .unwrap_or_else(|| candidates((route[0], route[1]), width, height)[0]);
+
+    obstacles.push(bounds);
+    Some(bounds)
+}
+
+/// Places for a label along a segment, nearest to its start first. Labels of horizontal segments
+/// go above or below the line, labels of vertical segments to the right or left of it.
+fn candidates(((x1, y1), (x2, y2)): Segment, width: f64, height: f64) -> Vec<Rect> {
+    let horizontal = (x2 - x1).abs() >= (y2 - y1).abs();
+    let (length, extent) = if horizontal {
+        ((x2 - x1).abs(), width)
+    } else {
+        ((y2 - y1).abs(), height)
+    };
+    let room = (length - extent - 2.0 * LABEL_OFFSET).max(0.0);
+    let steps = (room / SLIDE_STEP).floor() as usize;
+
+    let mut candidates = Vec::with_capacity(2 * (steps + 1));
+    for step in 0..=steps {
+        let slide = LABEL_OFFSET + step as f64 * SLIDE_STEP;
+        if horizontal {
+            let x = if x2 >= x1 {
+                x1 + slide
+            } else {
+                x1 - slide - width
+            };
+            candidates.push((x, y1 - LABEL_OFFSET - height, width, height));
+            candidates.push((x, y1 + LABEL_OFFSET, width, height));
+        } else {
+            let y = if y2 >= y1 {
+                y1 + slide
+            } else {
+                y1 - slide - height
+            };
+            candidates.push((x1 + LABEL_OFFSET, y, width, height));
+            candidates.push((x1 - LABEL_OFFSET - width, y, width, height));
+        }
+    }
+    candidates
+}
+
+fn overlaps((ax, ay, aw, ah): Rect, (bx, by, bw, bh): Rect) -> bool {
+    ax < bx + bw && bx < ax + aw && ay < by + bh && by < ay + ah
+}
+
+/// Number of segments running through the rectangle. Segments are compared by their bounding box,
+/// which is exact for the horizontal and vertical segments routes are made of.
+fn crossings(rect: &Rect, segments: &[Segment]) -> usize {
+    segments
+        .iter()
+        .filter(|((x1, y1), (x2, y2))| {
+            let bounds = (x1.min(*x2), y1.min(*y2), (x2 - x1).abs(), (y2 - y1).abs());
+            let (x, y, width, height) = *rect;
+            bounds.0 <= x + width
+                && x <= bounds.0 + bounds.2
+                && bounds.1 <= y + height
+                && y <= bounds.1 + bounds.3
+        })
+        .count()
+}
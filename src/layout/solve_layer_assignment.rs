@@ -1,73 +1,241 @@
 use crate::common::edge::Edge;
 use crate::common::graph::Graph;
-use crate::common::lane::Lane;
+use crate::common::layout_hint::HintKind;
 use crate::common::node::Node;
 use crate::common::pool::Pool;
+use crate::diagnostics::Diagnostic;
+use crate::layout::longest_path::longest_path_layers;
+use crate::layout::network_simplex::network_simplex_layers;
+use crate::layout::{Layering, LayoutOptions};
+#[cfg(feature = "cbc")]
 use good_lp::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
-pub fn solve_layer_assignment(graph: &mut Graph) {
+/// Assigns layers to all nodes of a pool at once, so edges between lanes point forward too.
+/// With `layer_across_pools` the whole graph is one problem, which also orders flows between pools.
+/// Nodes with an `@layer` hint go into that layer where the edges allow it, a warning is returned
+/// for every hint that could not be kept.
+pub fn solve_layer_assignment(graph: &mut Graph, options: &LayoutOptions) -> Vec<Diagnostic> {
     find_crossings(&graph.edges, &mut graph.pools);
 
+    let pins: HashMap<usize, usize> = graph
+        .hints
+        .iter()
+        .filter_map(|hint| match hint.kind {
+            HintKind::Layer(layer) => Some((hint.node, layer)),
+            _ => None,
+        })
+        .collect();
+    let mut layers = HashMap::new();
+    if options.layer_across_pools {
+        let node_ids: Vec<usize> = graph.nodes().map(|node| node.id).collect();
+        layers = solve_layers(&graph.edges, &node_ids, options.layering, &pins);
+    } else {
+        for pool in graph.pools.iter() {
+            let node_ids: Vec<usize> = pool
+                .get_lanes()
+                .iter()
+                .flat_map(|lane| lane.get_layers())
+                .map(|node| node.id)
+                .collect();
+            layers.extend(solve_layers(
+                &graph.edges,
+                &node_ids,
+                options.layering,
+                &pins,
+            ));
+        }
+    }
     for pool in graph.pools.iter_mut() {
-        for lane in pool.get_lanes_mut() {
-            solve_layers(&graph.edges, lane);
+        apply_layers(pool, &layers);
+    }
+
+    let node_count = graph.nodes().count();
+    graph
+        .hints
+        .iter()
+        .filter_map(|hint| match hint.kind {
+            HintKind::Layer(layer) if layers.get(&hint.node) != Some(&layer) => {
+                let placed = layers.get(&hint.node).map_or(0, |layer| layer + 1);
+                let reason = if layer >= node_count {
+                    format!("there are only {} nodes, it is put into layer {}", node_count, placed)
+                } else {
+                    format!("the flow around the node puts it into layer {}", placed)
+                };
+                Some(hint.warning(graph, &reason))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn solve_layers(
+    edges: &[Edge],
+    node_ids: &[usize],
+    layering: Layering,
+    pins: &HashMap<usize, usize>,
+) -> HashMap<usize, usize> {
+    let inner_edges: Vec<(usize, usize)> = edges
+        .iter()
+        .filter(|edge| edge.from != edge.to)
+        .filter(|edge| node_ids.contains(&edge.from) && node_ids.contains(&edge.to))
+        .map(|edge| (edge.from, edge.to))
+        .collect();
+
+    // A layering never needs more layers than nodes, pins beyond that go into the last one
+    let last = node_ids.len().saturating_sub(1);
+    let pins: HashMap<usize, usize> = pins
+        .iter()
+        .filter(|(node, _)| node_ids.contains(node))
+        .map(|(&node, &layer)| (node, layer.min(last)))
+        .collect();
+    let pins = &pins;
+
+    let mut layers = match layering {
+        Layering::LongestPath => longest_path_layers(node_ids, &inner_edges),
+        Layering::NetworkSimplex => network_simplex_layers(node_ids, &inner_edges),
+        // The solver fails when the edges form a cycle or the pins contradict each other,
+        // network simplex handles those cases
+        #[cfg(feature = "cbc")]
+        Layering::Ilp => solve_ilp_layers(node_ids, &inner_edges, pins)
+            .unwrap_or_else(|| network_simplex_layers(node_ids, &inner_edges)),
+    };
+    pin_layers(node_ids, &inner_edges, &mut layers, pins);
+    layers
+}
+
+/// Moves pinned nodes into their layer, and the nodes before and after them as far as the edges need.
+/// Other nodes stay where they are when they can. A pinned node that comes after a node pinned to a
+/// later layer ends up in the first layer the edges allow.
+fn pin_layers(
+    node_ids: &[usize],
+    edges: &[(usize, usize)],
+    layers: &mut HashMap<usize, usize>,
+    pins: &HashMap<usize, usize>,
+) {
+    if !node_ids.iter().any(|id| pins.contains_key(id)) {
+        return;
+    }
+    let mut successors: HashMap<usize, Vec<usize>> = HashMap::new();
+    let mut predecessors: HashMap<usize, Vec<usize>> = HashMap::new();
+    for &(from, to) in edges {
+        successors.entry(from).or_default().push(to);
+        predecessors.entry(to).or_default().push(from);
+    }
+    let order = topological_order(node_ids, &successors, &predecessors);
+
+    // The last layer each node can take, so the pinned nodes after it still fit
+    let mut latest: HashMap<usize, i64> = HashMap::new();
+    for &node in order.iter().rev() {
+        let pinned = pins.get(&node).map_or(i64::MAX, |&layer| layer as i64);
+        let bound = successors
+            .get(&node)
+            .into_iter()
+            .flatten()
+            .filter_map(|next| latest.get(next))
+            .fold(pinned, |bound, &next| bound.min(next.saturating_sub(1)));
+        latest.insert(node, bound);
+    }
+
+    for &node in &order {
+        let earliest = predecessors
+            .get(&node)
+            .into_iter()
+            .flatten()
+            .filter_map(|previous| layers.get(previous))
+            .map(|&layer| layer as i64 + 1)
+            .max()
+            .unwrap_or(0);
+        let wanted = match pins.get(&node) {
+            Some(&layer) => layer as i64,
+            None => (layers.get(&node).copied().unwrap_or(0) as i64).min(latest[&node]),
+        };
+        layers.insert(node, wanted.max(earliest) as usize);
+    }
+}
+
+/// Nodes in an order where every edge points forward, nodes on a cycle come in input order
+fn topological_order(
+    node_ids: &[usize],
+    successors: &HashMap<usize, Vec<usize>>,
+    predecessors: &HashMap<usize, Vec<usize>>,
+) -> Vec<usize> {
+    let mut in_degree: HashMap<usize, usize> = node_ids
+        .iter()
+        .map(|id| (*id, predecessors.get(id).map_or(0, Vec::len)))
+        .collect();
+    let mut queue: VecDeque<usize> = node_ids
+        .iter()
+        .copied()
+        .filter(|id| in_degree[id] == 0)
+        .collect();
+    let mut order = Vec::with_capacity(node_ids.len());
+    while let Some(node) = queue.pop_front() {
+        order.push(node);
+        for next in successors.get(&node).into_iter().flatten() {
+            if let Some(degree) = in_degree.get_mut(next) {
+                *degree -= 1;
+                if *degree == 0 {
+                    queue.push_back(*next);
+                }
+            }
+        }
+    }
+    for id in node_ids {
+        if !order.contains(id) {
+            order.push(*id);
+        }
+    }
+    order
+}
+
+fn apply_layers(pool: &mut Pool, layers: &HashMap<usize, usize>) {
+    for lane in pool.get_lanes_mut() {
+        for node in lane.get_layers_mut() {
+            node.layer_id = layers.get(&node.id).copied();
         }
+        lane.sort_nodes_by_layer_id();
     }
 }
 
-fn solve_layers(edges: &Vec<Edge>, lane: &mut Lane) {
+#[cfg(feature = "cbc")]
+fn solve_ilp_layers(
+    node_ids: &[usize],
+    edges: &[(usize, usize)],
+    pins: &HashMap<usize, usize>,
+) -> Option<HashMap<usize, usize>> {
     let mut vars = variables!();
-    let mut layer_vars = Vec::new();
+    let mut layer_vars = HashMap::new();
 
-    for node in lane.get_layers() {
+    for node_id in node_ids {
         let layer_var = vars.add(variable().integer().min(0));
-        layer_vars.push((node.id, layer_var));
+        layer_vars.insert(*node_id, layer_var);
     }
 
     let mut objective = Expression::from(0.0);
-    for edge in edges {
-        let from_var = layer_vars
-            .iter()
-            .find(|(id, _)| *id == edge.from)
-            .map(|(_, v)| *v);
-        let to_var = layer_vars
-            .iter()
-            .find(|(id, _)| *id == edge.to)
-            .map(|(_, v)| *v);
-
-        if let (Some(from_var), Some(to_var)) = (from_var, to_var) {
-            objective = objective + (to_var - from_var);
-        }
+    for (from, to) in edges {
+        objective += layer_vars[to] - layer_vars[from];
     }
 
     let mut problem = vars.minimise(objective).using(coin_cbc);
     problem.set_parameter("logLevel", "0");
 
-    for edge in edges {
-        let from_var = layer_vars
-            .iter()
-            .find(|(id, _)| *id == edge.from)
-            .map(|(_, v)| *v);
-        let to_var = layer_vars
-            .iter()
-            .find(|(id, _)| *id == edge.to)
-            .map(|(_, v)| *v);
-
-        if let (Some(from_var), Some(to_var)) = (from_var, to_var) {
-            problem = problem.with((to_var - from_var).geq(1));
-        }
+    for (from, to) in edges {
+        problem = problem.with((layer_vars[to] - layer_vars[from]).geq(1));
     }
-
-    let solution = problem.solve().unwrap();
-    for (node_id, layer_var) in &layer_vars {
-        let layer_value = solution.value(*layer_var) as usize;
-        if let Some(node) = lane.get_layers_mut().iter_mut().find(|n| n.id == *node_id) {
-            node.layer_id = Some(layer_value);
+    for (node_id, layer) in pins {
+        if let Some(layer_var) = layer_vars.get(node_id) {
+            problem = problem.with(constraint::eq(*layer_var, *layer as f64));
         }
     }
 
-    lane.sort_nodes_by_layer_id();
+    let solution = problem.solve().ok()?;
+    Some(
+        layer_vars
+            .iter()
+            .map(|(node_id, layer_var)| (*node_id, solution.value(*layer_var).round() as usize))
+            .collect(),
+    )
 }
 
 fn find_crossings(edges: &Vec<Edge>, pools: &mut Vec<Pool>) {
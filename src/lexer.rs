@@ -3,7 +3,8 @@
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Pool(String),                 // `=` for pool
-    Lane(String),                 // `==` for lane
+    CollapsedPool(String),        // `= [Name]` for a collapsed pool of an external participant
+    Lane(usize, String),          // `==` for lane, `===` and deeper for a lane in the lane above, with its depth
     EventStart(String),           // `#` for start event
     EventMiddle(String),          // `#` for middle event (detected by context)
     EventEnd(String),             // `.` for end event
@@ -17,6 +18,7 @@ pub enum Token {
     Label(String),                // `:` for branch label
     Branch(String, String),       // `->` Branch label and text
     JoinLabel(String),            // `<-` for join gateway
+    Hint(String),                 // `@` for a layout hint on the previous node
     Text(String),                 // Any freeform text
     Eof,                          // End of file/input
 }
@@ -48,6 +50,8 @@ pub struct Lexer<'a> {
     pub line: usize,                // Current line number
     pub column: usize,                  // Current column number
     pub seen_start: bool,               // State flag for distinguishing event start/middle
+    pub token_line: usize,              // Line where the last token started
+    pub token_column: usize,            // Column where the last token started
 }
 
 impl<'a> Lexer<'a> {
@@ -60,6 +64,8 @@ impl<'a> Lexer<'a> {
             line: 1,
             column: 0,
             seen_start: false,    // Initially, no start event has been seen
+            token_line: 1,
+            token_column: 1,
         };
         lexer.advance(); // Load the first character
         lexer
@@ -74,11 +80,10 @@ impl<'a> Lexer<'a> {
             self.column += 1;        // Move to the next column
         }
 
-        if self.position < self.input.len() {
-            self.current_char = Some(self.input.chars().nth(self.position).unwrap());
+        // `position` counts characters, so multi-byte characters run out before `input.len()`
+        self.current_char = self.input.chars().nth(self.position);
+        if self.current_char.is_some() {
             self.position += 1;
-        } else {
-            self.current_char = None; // End of input
         }
     }
 
@@ -88,6 +93,9 @@ impl<'a> Lexer<'a> {
         let saved_char = self.current_char;
         let saved_line = self.line;
         let saved_column = self.column;
+        let saved_token_line = self.token_line;
+        let saved_token_column = self.token_column;
+        let saved_seen_start = self.seen_start;
     
         // Get the next token
         let token = self.next_token();
@@ -97,6 +105,9 @@ impl<'a> Lexer<'a> {
         self.current_char = saved_char;
         self.line = saved_line;
         self.column = saved_column;
+        self.token_line = saved_token_line;
+        self.token_column = saved_token_column;
+        self.seen_start = saved_seen_start;
     
         token
     }
@@ -104,12 +115,14 @@ impl<'a> Lexer<'a> {
     // Get the next token from the input
     pub fn next_token(&mut self) -> Result<Token, LexerError> {
         self.skip_whitespace(); // Skip any unnecessary whitespace
+        self.token_line = self.line;
+        self.token_column = self.column;
 
         match self.current_char {
             Some('/') => {
                 self.advance(); // Skip '/'
                 if self.current_char == Some('/') {
-                    while self.current_char != Some('\n') {
+                    while self.current_char.is_some() && self.current_char != Some('\n') {
                         self.advance(); // Skip the comment
                     }
                     self.advance(); // Skip the newline
@@ -122,11 +135,20 @@ impl<'a> Lexer<'a> {
                 self.advance(); // Skip '='
                 if self.current_char == Some('=') {
                     self.advance(); // Skip second '=' for lanes
+                    let mut depth = 0;
+                    while self.current_char == Some('=') {
+                        self.advance(); // Every further '=' nests the lane one deeper
+                        depth += 1;
+                    }
                     let lane_name = self.read_text();
-                    Ok(Token::Lane(lane_name))
+                    Ok(Token::Lane(depth, lane_name))
                 } else {
                     let pool_name = self.read_text();
-                    Ok(Token::Pool(pool_name))
+                    // A name in brackets is a black box, the participant has no process of its own
+                    match pool_name.strip_prefix('[').and_then(|name| name.strip_suffix(']')) {
+                        Some(name) => Ok(Token::CollapsedPool(name.trim().to_string())),
+                        None => Ok(Token::Pool(pool_name)),
+                    }
                 }
             },
             Some('#') => {
@@ -184,6 +206,11 @@ impl<'a> Lexer<'a> {
                 self.advance(); // Skip '*'
                 Ok(Token::GatewayEvent)
             },
+            Some('@') => {
+                self.advance(); // Skip '@'
+                let text: String = self.read_text(); // Read the hint and what it refers to
+                Ok(Token::Hint(text))
+            },
             Some('G') => {
                 self.advance(); // Skip 'G'
                 Ok(Token::Go)
@@ -259,7 +286,7 @@ impl<'a> Lexer<'a> {
         let current_line = lines[self.line - 1];
     
         // Create the error highlight
-        let highlight = " ".repeat(self.column - 2) + "^";
+        let highlight = " ".repeat(self.column.saturating_sub(2)) + "^";
     
         format!("{}\n{}", current_line, highlight)
     }
@@ -0,0 +1,176 @@
+// src/lib.rs
+
+//! Converts the BPMN DSL to BPMN 2.0 XML.
+//!
+//! ```no_run
+//! let mut graph = bpmn_parser::parse("# Start\n- Task\n. End").unwrap();
+//! bpmn_parser::layout(&mut graph, &bpmn_parser::LayoutOptions::default());
+//! let xml = bpmn_parser::to_bpmn_xml(&graph);
+//! ```
+
+pub mod common;
+pub mod diagnostics;
+pub mod from_xml;
+pub mod layout;
+pub mod lexer;
+pub mod lsp;
+pub mod parser;
+pub mod read_input;
+pub mod relayout;
+pub mod serve;
+mod test;
+pub mod to_dsl;
+pub mod to_svg;
+pub mod to_xml;
+pub mod validate;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod xml_writer;
+
+pub use common::bpmn_event::BpmnEvent;
+pub use common::edge::{Edge, PoolFlow};
+pub use common::graph::Graph;
+pub use common::lane::Lane;
+pub use common::node::Node;
+pub use common::pool::Pool;
+pub use diagnostics::{Diagnostic, Diagnostics, Severity};
+pub use layout::incremental::PreviousLayout;
+pub use layout::{Layering, LayoutOptions, Orientation};
+
+use layout::assign_bend_points::assign_bend_points;
+use layout::boundary_events::{attach_boundary_events, route_boundary_flows};
+use layout::crossing_minimization::reduce_crossings;
+use layout::eliminate_back_edges::{eliminate_back_edges, restore_back_edges};
+use layout::incremental::{keep_previous_positions, keep_previous_routes};
+use layout::node_positioning::assign_xy_to_nodes;
+use layout::place_labels::place_edge_labels;
+use layout::solve_layer_assignment::solve_layer_assignment;
+use lexer::Lexer;
+use parser::Parser;
+
+/// Parses DSL input into a graph without positions
+pub fn parse(input: &str) -> Result<Graph, Diagnostics> {
+    let lexer = Lexer::new(input);
+    let graph = Parser::new(lexer)?.parse()?;
+    Ok(graph)
+}
+
+/// Assigns positions to all pools, lanes, nodes and edges of the graph.
+/// Returns a warning for every layout hint in the DSL that could not be kept.
+pub fn layout(graph: &mut Graph, options: &LayoutOptions) -> Diagnostics {
+    run_layout(graph, options, None)
+}
+
+/// Lays out the graph like [`layout`], but keeps pools, lanes, nodes and edges that are unchanged
+/// since a previous layout where they were, so small edits to the DSL give small diffs
+pub fn layout_incremental(
+    graph: &mut Graph,
+    options: &LayoutOptions,
+    previous: &PreviousLayout,
+) -> Diagnostics {
+    run_layout(graph, options, Some(previous))
+}
+
+fn run_layout(
+    graph: &mut Graph,
+    options: &LayoutOptions,
+    previous: Option<&PreviousLayout>,
+) -> Diagnostics {
+    eliminate_back_edges(graph);
+    let mut warnings = solve_layer_assignment(graph, options);
+    restore_back_edges(graph);
+    reduce_crossings(graph);
+    warnings.extend(assign_xy_to_nodes(graph, options));
+    let kept = previous.map(|previous| keep_previous_positions(graph, options, previous));
+    attach_boundary_events(graph);
+    assign_bend_points(graph, options);
+    if let (Some(previous), Some(kept)) = (previous, &kept) {
+        keep_previous_routes(graph, previous, kept);
+    }
+    route_boundary_flows(graph);
+    place_edge_labels(graph, options);
+    warnings.into_iter().collect()
+}
+
+/// Writes a laid out graph as BPMN 2.0 XML
+pub fn to_bpmn_xml(graph: &Graph) -> String {
+    to_xml::generate_bpmn(graph)
+}
+
+/// Writes a laid out graph as BPMN 2.0 XML to a file, socket or any other writer as it goes
+pub fn write_bpmn_xml<W: std::io::Write>(graph: &Graph, out: W) -> std::io::Result<()> {
+    to_xml::write_bpmn(graph, out)
+}
+
+/// Reads BPMN 2.0 XML, e.g. drawn in a modeler, into a graph with the positions of its diagram.
+/// Elements the graph cannot hold are skipped and come back as warnings next to the graph.
+pub fn from_bpmn_xml(xml: &str) -> Result<(Graph, Diagnostics), Diagnostics> {
+    let (graph, warnings) =
+        from_xml::read_bpmn(xml).map_err(|error| Diagnostics::from_iter([error]))?;
+    Ok((graph, warnings.into_iter().collect()))
+}
+
+/// Lays out BPMN 2.0 XML, e.g. from a tool that writes no or a broken diagram, and writes it back
+/// with a new diagram. Everything but the diagram is kept as it was, elements the layout cannot
+/// draw come back as warnings.
+pub fn relayout_bpmn_xml(
+    xml: &str,
+    options: &LayoutOptions,
+) -> Result<(String, Diagnostics), Diagnostics> {
+    let (xml, warnings) = relayout::relayout_bpmn(xml, options)
+        .map_err(|error| Diagnostics::from_iter([error]))?;
+    Ok((xml, warnings.into_iter().collect()))
+}
+
+/// Checks BPMN 2.0 XML, e.g. written by [`to_bpmn_xml`], for broken references, duplicate or
+/// invalid ids, flow lists that do not match the sequence flows, sequence flows between processes
+/// and elements missing from the diagram. Returns an error for each problem, none if it is valid.
+pub fn validate_bpmn_xml(xml: &str) -> Diagnostics {
+    validate::validate_bpmn(xml).into_iter().collect()
+}
+
+/// Writes a graph as DSL text. What the DSL cannot hold, like user tasks or boundary events, is
+/// written as the closest DSL element or left out, and comes back as warnings next to the text.
+pub fn to_dsl(graph: &Graph) -> (String, Diagnostics) {
+    let (dsl, warnings) = to_dsl::write_dsl(graph);
+    (dsl, warnings.into_iter().collect())
+}
+
+/// Reads BPMN 2.0 XML and writes it as DSL text, with the warnings of both steps
+pub fn bpmn_to_dsl(xml: &str) -> Result<(String, Diagnostics), Diagnostics> {
+    let (graph, warnings, ids) =
+        from_xml::read_bpmn_with_ids(xml).map_err(|error| Diagnostics::from_iter([error]))?;
+    let (dsl, more) = to_dsl::write_dsl_with_ids(&graph, &ids.nodes);
+    Ok((dsl, warnings.into_iter().chain(more).collect()))
+}
+
+/// Renders a laid out graph as an SVG image
+pub fn to_svg(graph: &Graph) -> String {
+    to_svg::generate_svg(graph)
+}
+
+/// Parses and lays out DSL input and writes it as BPMN 2.0 XML
+pub fn dsl_to_bpmn(input: &str, options: &LayoutOptions) -> Result<String, Diagnostics> {
+    let mut graph = parse(input)?;
+    layout(&mut graph, options);
+    Ok(to_bpmn_xml(&graph))
+}
+
+/// Parses DSL input and lays it out again against the BPMN XML of a previous layout, keeping
+/// whatever is unchanged where it was
+pub fn dsl_to_bpmn_incremental(
+    input: &str,
+    options: &LayoutOptions,
+    previous: &PreviousLayout,
+) -> Result<String, Diagnostics> {
+    let mut graph = parse(input)?;
+    layout_incremental(&mut graph, options, previous);
+    Ok(to_bpmn_xml(&graph))
+}
+
+/// Parses and lays out DSL input and renders it as SVG
+pub fn dsl_to_svg(input: &str, options: &LayoutOptions) -> Result<String, Diagnostics> {
+    let mut graph = parse(input)?;
+    layout(&mut graph, options);
+    Ok(to_svg(&graph))
+}
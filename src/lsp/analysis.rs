@@ -0,0 +1,277 @@
+// analysis.rs
+
+use crate::lexer::{Lexer, Token};
+use crate::parser::{ParseError, Parser};
+
+/// A span on one line of the document. Lines and columns start from 0 and count characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn contains(&self, line: usize, column: usize) -> bool {
+        self.line == line && column >= self.start && column <= self.end
+    }
+}
+
+/// How a label name is used at one place in the document
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelUse {
+    Definition,  // `name:` starts a branch
+    Branch,      // `X ->name` branches to a label
+    Join,        // `J name` ends a branch
+    JoinGateway, // `X <-name` joins the branches ending with `J name`
+    GoFrom,      // `G ->name` starts a jump
+    GoTo,        // `G <-name` ends a jump
+}
+
+impl LabelUse {
+    /// Uses that share the same label names
+    fn namespace(&self) -> [LabelUse; 2] {
+        match self {
+            LabelUse::Definition | LabelUse::Branch => [LabelUse::Definition, LabelUse::Branch],
+            LabelUse::Join | LabelUse::JoinGateway => [LabelUse::Join, LabelUse::JoinGateway],
+            LabelUse::GoFrom | LabelUse::GoTo => [LabelUse::GoFrom, LabelUse::GoTo],
+        }
+    }
+
+    /// The use that defines the label this use refers to
+    fn definition(&self) -> LabelUse {
+        match self {
+            LabelUse::Definition | LabelUse::Branch => LabelUse::Definition,
+            LabelUse::Join => LabelUse::JoinGateway,
+            LabelUse::JoinGateway => LabelUse::Join,
+            LabelUse::GoFrom => LabelUse::GoTo,
+            LabelUse::GoTo => LabelUse::GoFrom,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LabelOccurrence {
+    pub name: String,
+    pub usage: LabelUse,
+    pub span: Span,
+}
+
+/// A DSL element, used for hovers and document symbols
+#[derive(Debug, Clone)]
+pub struct Element {
+    pub kind: &'static str,
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+}
+
+/// Everything the language server knows about one document
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub elements: Vec<Element>,
+    pub labels: Vec<LabelOccurrence>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Analysis {
+    pub fn new(text: &str) -> Self {
+        let mut analysis = Analysis::default();
+        analysis.collect_tokens(text);
+        analysis.collect_diagnostics(text);
+        analysis
+    }
+
+    fn collect_tokens(&mut self, text: &str) {
+        let lines: Vec<&str> = text.split('\n').collect();
+        let mut lexer = Lexer::new(text);
+        // Branches and joins belong to the gateway or go token before them
+        let mut after_go = false;
+
+        // Stop at the first lexer error, the parser reports it as a diagnostic
+        while let Ok(token) = lexer.next_token() {
+            let line = lexer.token_line - 1;
+            let column = lexer.token_column - 1;
+            let line_text = lines.get(line).copied().unwrap_or("");
+            let span_of = |name: &str| find_span(line_text, line, column, name);
+
+            match &token {
+                Token::Eof => break,
+                Token::Go => after_go = true,
+                Token::GatewayExclusive
+                | Token::GatewayParallel
+                | Token::GatewayInclusive
+                | Token::GatewayEvent => after_go = false,
+                _ => {}
+            }
+
+            let (kind, name, usage) = match &token {
+                Token::Pool(name) => ("Pool", name.clone(), None),
+                Token::CollapsedPool(name) => ("Collapsed pool", name.clone(), None),
+                Token::Lane(_, name) => ("Lane", name.clone(), None),
+                Token::EventStart(name) => ("Start event", name.clone(), None),
+                Token::EventMiddle(name) => ("Intermediate event", name.clone(), None),
+                Token::EventEnd(name) => ("End event", name.clone(), None),
+                Token::ActivityTask(name) => ("Task", name.clone(), None),
+                Token::GatewayExclusive => ("Exclusive gateway", "X".to_string(), None),
+                Token::GatewayParallel => ("Parallel gateway", "+".to_string(), None),
+                Token::GatewayInclusive => ("Inclusive gateway", "O".to_string(), None),
+                Token::GatewayEvent => ("Event gateway", "*".to_string(), None),
+                Token::Go => ("Go", "G".to_string(), None),
+                Token::Label(name) => ("Label", name.clone(), Some(LabelUse::Definition)),
+                Token::Join(name, _) => ("Join", name.clone(), Some(LabelUse::Join)),
+                Token::Branch(name, _) if after_go => {
+                    ("Go from", name.clone(), Some(LabelUse::GoFrom))
+                }
+                Token::Branch(name, _) => ("Branch", name.clone(), Some(LabelUse::Branch)),
+                Token::JoinLabel(name) if after_go => ("Go to", name.clone(), Some(LabelUse::GoTo)),
+                Token::JoinLabel(name) => {
+                    ("Join gateway", name.clone(), Some(LabelUse::JoinGateway))
+                }
+                Token::Hint(text) => ("Layout hint", text.clone(), None),
+                Token::Text(name) => ("Text", name.clone(), None),
+                Token::Eof => unreachable!(),
+            };
+
+            let span = span_of(&name);
+            if let Some(usage) = usage {
+                self.labels.push(LabelOccurrence {
+                    name: name.clone(),
+                    usage,
+                    span,
+                });
+            }
+            self.elements.push(Element { kind, name, span });
+        }
+    }
+
+    fn collect_diagnostics(&mut self, text: &str) {
+        let result = std::panic::catch_unwind(|| Parser::new(Lexer::new(text))?.parse());
+        let error = match result {
+            Ok(Ok(_)) => return,
+            Ok(Err(error)) => error,
+            Err(_) => {
+                self.diagnostics.push(Diagnostic {
+                    span: Span { line: 0, start: 0, end: 0 },
+                    message: "The parser failed on this document".to_string(),
+                });
+                return;
+            }
+        };
+
+        // The rest of the error message is a text highlight, editors show the span instead
+        let message = error.to_string().lines().next().unwrap_or("").to_string();
+        let span = match (&error, error.position()) {
+            (_, Some((line, column))) => {
+                let line_length = text.split('\n').nth(line - 1).map_or(0, |l| l.chars().count());
+                Span {
+                    line: line - 1,
+                    start: (column - 1).min(line_length),
+                    end: line_length,
+                }
+            }
+            (ParseError::UndefinedLabelError(name), None) => self
+                .labels
+                .iter()
+                .find(|label| label.usage == LabelUse::Branch && &label.name == name)
+                .map(|label| label.span)
+                .unwrap_or(Span { line: 0, start: 0, end: 0 }),
+            _ => Span { line: 0, start: 0, end: 0 },
+        };
+        self.diagnostics.push(Diagnostic { span, message });
+    }
+
+    pub fn label_at(&self, line: usize, column: usize) -> Option<&LabelOccurrence> {
+        self.labels.iter().find(|label| label.span.contains(line, column))
+    }
+
+    pub fn element_at(&self, line: usize, column: usize) -> Option<&Element> {
+        self.elements
+            .iter()
+            .find(|element| element.span.contains(line, column))
+            .or_else(|| self.elements.iter().find(|element| element.span.line == line))
+    }
+
+    /// Finds where the label under the cursor is defined
+    pub fn definitions(&self, line: usize, column: usize) -> Vec<Span> {
+        let Some(label) = self.label_at(line, column) else {
+            return Vec::new();
+        };
+        let definition = label.usage.definition();
+        self.labels
+            .iter()
+            .filter(|other| other.usage == definition && other.name == label.name)
+            .map(|other| other.span)
+            .collect()
+    }
+
+    /// Finds every use of the label under the cursor
+    pub fn references(&self, line: usize, column: usize) -> Vec<Span> {
+        let Some(label) = self.label_at(line, column) else {
+            return Vec::new();
+        };
+        let namespace = label.usage.namespace();
+        self.labels
+            .iter()
+            .filter(|other| namespace.contains(&other.usage) && other.name == label.name)
+            .map(|other| other.span)
+            .collect()
+    }
+
+    /// Suggests label names for a cursor after `->`, `<-` or `J`
+    pub fn completions(&self, line_text: &str, column: usize) -> Vec<String> {
+        let before: String = line_text.chars().take(column).collect();
+        let statement = before.trim_start();
+        let is_go = statement.starts_with('G');
+
+        let suggested_uses: &[LabelUse] = if let Some(index) = before.rfind("->") {
+            if before[index..].contains('"') {
+                return Vec::new();
+            }
+            if is_go {
+                &[LabelUse::GoTo]
+            } else {
+                &[LabelUse::Definition]
+            }
+        } else if before.rfind("<-").is_some() {
+            if is_go {
+                &[LabelUse::GoFrom]
+            } else {
+                &[LabelUse::Join]
+            }
+        } else if statement.starts_with('J') && !statement.contains('"') {
+            &[LabelUse::JoinGateway, LabelUse::Join]
+        } else {
+            return Vec::new();
+        };
+
+        let mut names: Vec<String> = self
+            .labels
+            .iter()
+            .filter(|label| suggested_uses.contains(&label.usage) && !label.name.is_empty())
+            .map(|label| label.name.clone())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+/// Finds `name` on the line at or after the column where its token started
+fn find_span(line_text: &str, line: usize, column: usize, name: &str) -> Span {
+    let rest: String = line_text.chars().skip(column).collect();
+    let start = match rest.find(name) {
+        Some(byte_index) if !name.is_empty() => column + rest[..byte_index].chars().count(),
+        _ => column,
+    };
+    Span {
+        line,
+        start,
+        end: start + name.chars().count().max(1),
+    }
+}
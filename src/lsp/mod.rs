@@ -0,0 +1,350 @@
+// lsp/mod.rs
+
+pub mod analysis;
+
+use analysis::{Analysis, Span};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+// LSP symbol kinds
+const SYMBOL_KIND_NAMESPACE: u32 = 3;
+const SYMBOL_KIND_CLASS: u32 = 5;
+const SYMBOL_KIND_KEY: u32 = 20;
+
+// LSP completion item kinds
+const COMPLETION_KIND_REFERENCE: u32 = 18;
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Runs the language server over stdin and stdout until the client asks it to exit
+pub fn run_stdio() -> io::Result<i32> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    run(stdin.lock(), stdout.lock())
+}
+
+/// Runs the language server until an `exit` notification or the end of input.
+/// Returns the process exit code the protocol asks for.
+pub fn run<R: BufRead, W: Write>(mut reader: R, mut writer: W) -> io::Result<i32> {
+    let mut documents: HashMap<String, String> = HashMap::new();
+    let mut shutdown_requested = false;
+
+    while let Some(message) = read_message(&mut reader)? {
+        let message = match message {
+            Ok(message) => message,
+            Err(error) => {
+                // Without a readable message there is no id to answer to, JSON-RPC uses null
+                let message = format!("Parse error: {}", error);
+                respond_error(&mut writer, Value::Null, PARSE_ERROR, &message)?;
+                continue;
+            }
+        };
+        let method = message["method"].as_str().unwrap_or("");
+        let id = message.get("id").cloned();
+        let params = &message["params"];
+
+        match method {
+            "initialize" => {
+                let result = json!({
+                    "capabilities": {
+                        "textDocumentSync": 1,
+                        "definitionProvider": true,
+                        "referencesProvider": true,
+                        "hoverProvider": true,
+                        "documentSymbolProvider": true,
+                        "completionProvider": { "triggerCharacters": [">", "-", " "] }
+                    },
+                    "serverInfo": { "name": "bpmn-parser" }
+                });
+                respond(&mut writer, id, result)?;
+            }
+            "shutdown" => {
+                shutdown_requested = true;
+                respond(&mut writer, id, Value::Null)?;
+            }
+            "exit" => return Ok(if shutdown_requested { 0 } else { 1 }),
+            "textDocument/didOpen" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+                let text = params["textDocument"]["text"].as_str().unwrap_or("").to_string();
+                publish_diagnostics(&mut writer, &uri, &text)?;
+                documents.insert(uri, text);
+            }
+            "textDocument/didChange" => {
+                // Only full document sync is advertised, so the last change holds the whole text
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+                if let Some(text) = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                {
+                    publish_diagnostics(&mut writer, &uri, text)?;
+                    documents.insert(uri, text.to_string());
+                }
+            }
+            "textDocument/didClose" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                documents.remove(uri);
+                notify(
+                    &mut writer,
+                    "textDocument/publishDiagnostics",
+                    json!({ "uri": uri, "diagnostics": [] }),
+                )?;
+            }
+            "textDocument/definition"
+            | "textDocument/references"
+            | "textDocument/hover"
+            | "textDocument/completion"
+            | "textDocument/documentSymbol" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                let text = documents.get(uri).map(String::as_str).unwrap_or("");
+                let result = handle_document_request(method, uri, text, params);
+                respond(&mut writer, id, result)?;
+            }
+            _ => {
+                // Requests have an id and need an answer, unknown notifications are ignored
+                if let Some(id) = id {
+                    let message = format!("Unknown method {}", method);
+                    respond_error(&mut writer, id, METHOD_NOT_FOUND, &message)?;
+                }
+            }
+        }
+    }
+    Ok(1)
+}
+
+fn handle_document_request(method: &str, uri: &str, text: &str, params: &Value) -> Value {
+    let analysis = Analysis::new(text);
+    let lines: Vec<&str> = text.split('\n').collect();
+    let line = params["position"]["line"].as_u64().unwrap_or(0) as usize;
+    let line_text = lines.get(line).copied().unwrap_or("");
+    let column = from_utf16_column(
+        line_text,
+        params["position"]["character"].as_u64().unwrap_or(0) as usize,
+    );
+
+    match method {
+        "textDocument/definition" => Value::Array(
+            analysis
+                .definitions(line, column)
+                .into_iter()
+                .map(|span| json!({ "uri": uri, "range": to_range(&lines, span) }))
+                .collect(),
+        ),
+        "textDocument/references" => Value::Array(
+            analysis
+                .references(line, column)
+                .into_iter()
+                .map(|span| json!({ "uri": uri, "range": to_range(&lines, span) }))
+                .collect(),
+        ),
+        "textDocument/hover" => match analysis.element_at(line, column) {
+            Some(element) => json!({
+                "contents": { "kind": "markdown", "value": format!("**{}** `{}`", element.kind, element.name) },
+                "range": to_range(&lines, element.span)
+            }),
+            None => Value::Null,
+        },
+        "textDocument/completion" => Value::Array(
+            analysis
+                .completions(line_text, column)
+                .into_iter()
+                .map(|name| json!({ "label": name, "kind": COMPLETION_KIND_REFERENCE }))
+                .collect(),
+        ),
+        "textDocument/documentSymbol" => document_symbols(&analysis, &lines),
+        _ => Value::Null,
+    }
+}
+
+// (span, name, children) of a lane or pool while document symbols are collected
+type LaneSymbol = (Span, String, Vec<Value>);
+type PoolSymbol = (Span, String, Vec<LaneSymbol>);
+
+/// Pools contain their lanes and lanes contain the labels defined in them
+fn document_symbols(analysis: &Analysis, lines: &[&str]) -> Value {
+    let last_line = lines.len().saturating_sub(1);
+    let mut pools: Vec<PoolSymbol> = Vec::new();
+
+    for element in &analysis.elements {
+        match element.kind {
+            "Pool" | "Collapsed pool" => {
+                pools.push((element.span, element.name.clone(), Vec::new()))
+            }
+            "Lane" => {
+                if pools.is_empty() {
+                    pools.push((element.span, String::new(), Vec::new()));
+                }
+                let lanes = &mut pools.last_mut().unwrap().2;
+                lanes.push((element.span, element.name.clone(), Vec::new()));
+            }
+            "Label" => {
+                if pools.is_empty() {
+                    pools.push((element.span, String::new(), Vec::new()));
+                }
+                let lanes = &mut pools.last_mut().unwrap().2;
+                if lanes.is_empty() {
+                    lanes.push((element.span, String::new(), Vec::new()));
+                }
+                let range = to_range(lines, element.span);
+                lanes.last_mut().unwrap().2.push(json!({
+                    "name": element.name,
+                    "kind": SYMBOL_KIND_KEY,
+                    "range": range,
+                    "selectionRange": range
+                }));
+            }
+            _ => {}
+        }
+    }
+
+    // A pool or lane reaches until the next one starts, unnamed ones only hold their children
+    let mut symbols = Vec::new();
+    let pool_starts: Vec<usize> = pools.iter().map(|pool| pool.0.line).collect();
+    for (pool_index, (pool_span, pool_name, lanes)) in pools.into_iter().enumerate() {
+        let pool_end = pool_starts
+            .get(pool_index + 1)
+            .map_or(last_line, |next| next.saturating_sub(1));
+        let lane_starts: Vec<usize> = lanes.iter().map(|lane| lane.0.line).collect();
+        let mut lane_symbols = Vec::new();
+        for (lane_index, (lane_span, lane_name, labels)) in lanes.into_iter().enumerate() {
+            if lane_name.is_empty() {
+                lane_symbols.extend(labels);
+                continue;
+            }
+            let lane_end = lane_starts
+                .get(lane_index + 1)
+                .map_or(pool_end, |next| next.saturating_sub(1));
+            lane_symbols.push(json!({
+                "name": lane_name,
+                "kind": SYMBOL_KIND_CLASS,
+                "range": block_range(lines, lane_span.line, lane_end),
+                "selectionRange": to_range(lines, lane_span),
+                "children": labels
+            }));
+        }
+        if pool_name.is_empty() {
+            symbols.extend(lane_symbols);
+            continue;
+        }
+        symbols.push(json!({
+            "name": pool_name,
+            "kind": SYMBOL_KIND_NAMESPACE,
+            "range": block_range(lines, pool_span.line, pool_end),
+            "selectionRange": to_range(lines, pool_span),
+            "children": lane_symbols
+        }));
+    }
+    Value::Array(symbols)
+}
+
+fn publish_diagnostics<W: Write>(writer: &mut W, uri: &str, text: &str) -> io::Result<()> {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let diagnostics: Vec<Value> = Analysis::new(text)
+        .diagnostics
+        .into_iter()
+        .map(|diagnostic| {
+            json!({
+                "range": to_range(&lines, diagnostic.span),
+                "severity": 1,
+                "source": "bpmn-parser",
+                "message": diagnostic.message
+            })
+        })
+        .collect();
+    notify(
+        writer,
+        "textDocument/publishDiagnostics",
+        json!({ "uri": uri, "diagnostics": diagnostics }),
+    )
+}
+
+/// Converts a span counted in characters to an LSP range counted in UTF-16 code units
+fn to_range(lines: &[&str], span: Span) -> Value {
+    let line_text = lines.get(span.line).copied().unwrap_or("");
+    json!({
+        "start": { "line": span.line, "character": to_utf16_column(line_text, span.start) },
+        "end": { "line": span.line, "character": to_utf16_column(line_text, span.end) }
+    })
+}
+
+fn block_range(lines: &[&str], start_line: usize, end_line: usize) -> Value {
+    let end_line = end_line.max(start_line);
+    let end_text = lines.get(end_line).copied().unwrap_or("");
+    json!({
+        "start": { "line": start_line, "character": 0 },
+        "end": { "line": end_line, "character": to_utf16_column(end_text, end_text.chars().count()) }
+    })
+}
+
+fn to_utf16_column(line_text: &str, column: usize) -> usize {
+    line_text.chars().take(column).map(char::len_utf16).sum()
+}
+
+fn from_utf16_column(line_text: &str, utf16_column: usize) -> usize {
+    let mut units = 0;
+    for (index, c) in line_text.chars().enumerate() {
+        if units >= utf16_column {
+            return index;
+        }
+        units += c.len_utf16();
+    }
+    line_text.chars().count()
+}
+
+/// Reads one `Content-Length` framed JSON-RPC message, or `None` at the end of input. A body that
+/// is not JSON comes back as the error of the JSON parser, the next message can still be read.
+fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<serde_json::Result<Value>>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            if content_length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let mut body = vec![0; content_length.unwrap_or(0)];
+    reader.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)))
+}
+
+fn write_message<W: Write>(writer: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+fn respond<W: Write>(writer: &mut W, id: Option<Value>, result: Value) -> io::Result<()> {
+    write_message(
+        writer,
+        &json!({ "jsonrpc": "2.0", "id": id.unwrap_or(Value::Null), "result": result }),
+    )
+}
+
+fn respond_error<W: Write>(writer: &mut W, id: Value, code: i64, message: &str) -> io::Result<()> {
+    write_message(
+        writer,
+        &json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } }),
+    )
+}
+
+fn notify<W: Write>(writer: &mut W, method: &str, params: Value) -> io::Result<()> {
+    write_message(
+        writer,
+        &json!({ "jsonrpc": "2.0", "method": method, "params": params }),
+    )
+}
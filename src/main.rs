@@ -1,25 +1,153 @@
 // src/main.rs
 
-mod common;
-mod layout;
-mod lexer;
-mod parser;
-mod read_input;
-mod test;
-mod to_xml;
-use crate::read_input::read_lines;
-use crate::to_xml::generate_bpmn;
-use layout::assign_bend_points::assign_bend_points;
-use layout::node_positioning::assign_xy_to_nodes;
-use layout::solve_layer_assignment::solve_layer_assignment;
-use lexer::Lexer;
-use parser::Parser;
+use bpmn_parser::read_input::read_lines;
+use bpmn_parser::{
+    bpmn_to_dsl, layout, layout_incremental, lsp, parse, relayout_bpmn_xml, serve,
+    validate_bpmn_xml, write_bpmn_xml, Diagnostics, Graph, LayoutOptions, PreviousLayout,
+};
 
 use std::env;
+use std::fs::File;
+use std::io::BufWriter;
 use std::process::Command;
 
+const DEFAULT_SERVE_PORT: u16 = 8000;
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+
+    let previous = match take_previous_layout(&mut args) {
+        Ok(previous) => previous,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let options = match take_layout_options(&mut args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // `lsp` speaks the language server protocol over stdin and stdout
+    if args.len() > 1 && args[1] == "lsp" {
+        match lsp::run_stdio() {
+            Ok(code) => std::process::exit(code),
+            Err(e) => {
+                eprintln!("Language server error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // `serve <file> [port]` starts the live preview server instead of converting the file
+    if args.len() > 1 && args[1] == "serve" {
+        if args.len() < 3 {
+            eprintln!("Usage: bpmn-parser serve <file> [port]");
+            std::process::exit(1);
+        }
+        let port = match args.get(3).map(|port| port.parse::<u16>()) {
+            None => DEFAULT_SERVE_PORT,
+            Some(Ok(port)) => port,
+            Some(Err(_)) => {
+                eprintln!("Error: Port must be a number between 0 and 65535");
+                std::process::exit(1);
+            }
+        };
+        println!("Serving {} at http://127.0.0.1:{}/", args[2], port);
+        if let Err(e) = serve::serve(&args[2], port, &options) {
+            eprintln!("Error starting server: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // `to-dsl <file.bpmn>` prints BPMN XML as DSL text
+    if args.len() > 1 && args[1] == "to-dsl" {
+        if args.len() < 3 {
+            eprintln!("Usage: bpmn-parser to-dsl <file.bpmn>");
+            std::process::exit(1);
+        }
+        let xml = match std::fs::read_to_string(&args[2]) {
+            Ok(xml) => xml,
+            Err(e) => {
+                eprintln!("Error reading file {}: {}", args[2], e);
+                std::process::exit(1);
+            }
+        };
+        match bpmn_to_dsl(&xml) {
+            Ok((dsl, warnings)) => {
+                for warning in &warnings {
+                    eprintln!("Warning: {}", warning);
+                }
+                print!("{}", dsl);
+            }
+            Err(diagnostics) => {
+                eprintln!("Failed to read BPMN:\n{}", diagnostics);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // `relayout <file.bpmn>` prints the file with a new diagram, the process itself is kept as it is
+    if args.len() > 1 && args[1] == "relayout" {
+        if args.len() < 3 {
+            eprintln!("Usage: bpmn-parser relayout <file.bpmn>");
+            std::process::exit(1);
+        }
+        let xml = match std::fs::read_to_string(&args[2]) {
+            Ok(xml) => xml,
+            Err(e) => {
+                eprintln!("Error reading file {}: {}", args[2], e);
+                std::process::exit(1);
+            }
+        };
+        match relayout_bpmn_xml(&xml, &options) {
+            Ok((xml, warnings)) => {
+                for warning in &warnings {
+                    eprintln!("Warning: {}", warning);
+                }
+                print!("{}", xml);
+            }
+            Err(diagnostics) => {
+                eprintln!("Failed to read BPMN:\n{}", diagnostics);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // `validate <file.bpmn>` prints every problem of a BPMN file and fails if there is one
+    if args.len() > 1 && args[1] == "validate" {
+        if args.len() < 3 {
+            eprintln!("Usage: bpmn-parser validate <file.bpmn>");
+            std::process::exit(1);
+        }
+        let xml = match std::fs::read_to_string(&args[2]) {
+            Ok(xml) => xml,
+            Err(e) => {
+                eprintln!("Error reading file {}: {}", args[2], e);
+                std::process::exit(1);
+            }
+        };
+        let problems = validate_bpmn_xml(&xml);
+        if problems.is_empty() {
+            println!("{} is valid", args[2]);
+            return;
+        }
+        for problem in &problems {
+            match (problem.line, problem.column) {
+                (Some(line), Some(column)) => {
+                    eprintln!("{}:{}:{}: {}", args[2], line, column, problem)
+                }
+                _ => eprintln!("{}: {}", args[2], problem),
+            }
+        }
+        std::process::exit(1);
+    }
 
     // Use "input.txt" if no argument is provided
     let input_data = if args.len() < 2 {
@@ -35,7 +163,7 @@ fn main() {
     };
 
     // Check if the output format is valid
-    if output_data != "pdf" && output_data != "svg" && output_data != "png" && output_data != "" {
+    if output_data != "pdf" && output_data != "svg" && output_data != "png" && !output_data.is_empty() {
         eprintln!("Error: Output format must be pdf, svg, png or left blank for xml");
         std::process::exit(1);
     }
@@ -49,9 +177,12 @@ fn main() {
         }
     };
 
-    let bpmn = run_parser(&input);
+    let graph = match run_parser(&input, &options, previous.as_ref()) {
+        Ok(graph) => graph,
+        Err(_) => std::process::exit(1),
+    };
 
-    to_xml::export_to_xml(&bpmn);
+    export_to_xml(&graph);
 
     if !output_data.is_empty() {
         match convert_bpmn_to_image(output_data) {
@@ -61,196 +192,105 @@ fn main() {
     }
 }
 
-pub fn run_parser(input: &str) -> String {
-    // Initialize the lexer with the input
-    let lexer = Lexer::new(input);
-
-    // Initialize the parser with the lexer
-    let parser = Parser::new(lexer);
-
-    // Parse the input and handle the result
-    match parser.expect("REASON").parse() {
-        Ok(mut graph) => {
-            println!("Parsed BPMN Graph:");
-
-            solve_layer_assignment(&mut graph);
-            // reduce_crossings(&mut graph, &layers);
-            assign_xy_to_nodes(&mut graph);
-            assign_bend_points(&mut graph);
-            graph.print_graph();
-
-            // for node in &graph.nodes {
-            //     if let Some(event) = &node.event {
-            //         match event {
-            //             BpmnEvent::Start(label) => {
-            //                 println!("  Start Event: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::Middle(label) => {
-            //                 println!("  Middle Event: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::End(label) => {
-            //                 println!("  End Event: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::GatewayExclusive => {
-            //                 println!("  Gateway Exclusive Event (ID: {})", node.id);
-            //             }
-            //             BpmnEvent::GatewayJoin(label) => {
-            //                 println!("  Gateway Join Event: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::ActivityTask(label) => {
-            //                 println!("  Activity Task: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::GatewayInclusive => {
-            //                 println!("  Gateway Inclusive Event (ID: {})", node.id);
-            //             }
-
-            //             BpmnEvent::ActivitySubprocess(label) => {
-            //                 println!("  Activity Subprocess: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::ActivityCallActivity(label) => {
-            //                 println!("  Activity Call Activity: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::ActivityEventSubprocess(label) => {
-            //                 println!("  Activity Event Subprocess: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::ActivityTransaction(label) => {
-            //                 println!("  Activity Transaction: {} (ID: {})", label, node.id);
-            //             }
-
-            //             BpmnEvent::StartTimerEvent(label) => {
-            //                 println!("  Start Timer Event: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::StartSignalEvent(label) => {
-            //                 println!("  Start Signal Event: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::StartMessageEvent(label) => {
-            //                 println!("  Start Message Event: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::StartConditionalEvent(label) => {
-            //                 println!("  Start Conditional Event: {} (ID: {})", label, node.id);
-            //             }
-
-            //             BpmnEvent::EndErrorEvent(label) => {
-            //                 println!("  End Error Event: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::EndCancelEvent(label) => {
-            //                 println!("  End Cancel Event: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::EndSignalEvent(label) => {
-            //                 println!("  End Signal Event: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::EndMessageEvent(label) => {
-            //                 println!("  End Message Event: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::EndTerminateEvent(label) => {
-            //                 println!("  End Terminate Event: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::EndEscalationEvent(label) => {
-            //                 println!("  End Escalation Event: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::EndCompensationEvent(label) => {
-            //                 println!("  End Compensation Event: {} (ID: {})", label, node.id);
-            //             }
-
-            //             BpmnEvent::BoundaryEvent(label, attached_to, cancel_activity) => {
-            //                 println!(
-            //                     "  Boundary Event: {} (ID: {}), attached to {}, cancel_activity: {}",
-            //                     label, node.id, attached_to, cancel_activity
-            //                 );
-            //             }
-            //             BpmnEvent::BoundaryErrorEvent(label, attached_to, cancel_activity) => {
-            //                 println!(
-            //                     "  Boundary Error Event: {} (ID: {}), attached to {}, cancel_activity: {}",
-            //                     label, node.id, attached_to, cancel_activity
-            //                 );
-            //             }
-            //             BpmnEvent::BoundaryTimerEvent(label, attached_to, cancel_activity) => {
-            //                 println!(
-            //                     "  Boundary Timer Event: {} (ID: {}), attached to {}, cancel_activity: {}",
-            //                     label, node.id, attached_to, cancel_activity
-            //                 );
-            //             }
-            //             BpmnEvent::BoundaryCancelEvent(label, attached_to, cancel_activity) => {
-            //                 println!(
-            //                     "  Boundary Cancel Event: {} (ID: {}), attached to {}, cancel_activity: {}",
-            //                     label, node.id, attached_to, cancel_activity
-            //                 );
-            //             }
-            //             BpmnEvent::BoundarySignalEvent(label, attached_to, cancel_activity) => {
-            //                 println!(
-            //                     "  Boundary Signal Event: {} (ID: {}), attached to {}, cancel_activity: {}",
-            //                     label, node.id, attached_to, cancel_activity
-            //                 );
-            //             }
-            //             BpmnEvent::BoundaryMessageEvent(label, attached_to, cancel_activity) => {
-            //                 println!(
-            //                     "  Boundary Message Event: {} (ID: {}), attached to {}, cancel_activity: {}",
-            //                     label, node.id, attached_to, cancel_activity
-            //                 );
-            //             }
-            //             BpmnEvent::BoundaryEscalationEvent(label, attached_to, cancel_activity) => {
-            //                 println!(
-            //                     "  Boundary Escalation Event: {} (ID: {}), attached to {}, cancel_activity: {}",
-            //                     label, node.id, attached_to, cancel_activity
-            //                 );
-            //             }
-            //             BpmnEvent::BoundaryConditionalEvent(
-            //                 label,
-            //                 attached_to,
-            //                 cancel_activity,
-            //             ) => {
-            //                 println!(
-            //                     "  Boundary Conditional Event: {} (ID: {}), attached to {}, cancel_activity: {}",
-            //                     label, node.id, attached_to, cancel_activity
-            //                 );
-            //             }
-            //             BpmnEvent::BoundaryCompensationEvent(label, attached_to) => {
-            //                 println!(
-            //                     "  Boundary Compensation Event: {} (ID: {}), attached to {}",
-            //                     label, node.id, attached_to
-            //                 );
-            //             }
-
-            //             BpmnEvent::DataStoreReference(label) => {
-            //                 println!("  Data Store Reference: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::DataObjectReference(label) => {
-            //                 println!("  Data Object Reference: {} (ID: {})", label, node.id);
-            //             }
+/// Parses and lays out the input, printing any errors and warnings.
+/// With a previous layout, unchanged elements are kept where they were.
+pub fn run_parser(
+    input: &str,
+    options: &LayoutOptions,
+    previous: Option<&PreviousLayout>,
+) -> Result<Graph, Diagnostics> {
+    let mut graph = match parse(input) {
+        Ok(graph) => graph,
+        Err(diagnostics) => {
+            eprintln!("Failed to parse input:\n{}", diagnostics);
+            return Err(diagnostics);
+        }
+    };
+    let warnings = match previous {
+        Some(previous) => layout_incremental(&mut graph, options, previous),
+        None => layout(&mut graph, options),
+    };
+    for warning in &warnings {
+        match warning.line {
+            Some(line) => eprintln!("Warning at line {}: {}", line, warning),
+            None => eprintln!("Warning: {}", warning),
+        }
+    }
+    Ok(graph)
+}
 
-            //             BpmnEvent::TaskUser(label) => {
-            //                 println!("  User Task: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::TaskService(label) => {
-            //                 println!("  Service Task: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::TaskBusinessRule(label) => {
-            //                 println!("  Business Rule Task: {} (ID: {})", label, node.id);
-            //             }
-            //             BpmnEvent::TaskScript(label) => {
-            //                 println!("  Script Task: {} (ID: {})", label, node.id);
-            //             }
-            //         }
-            //     } else {
-            //         println!("  No Event (ID: {})", node.id);
-            //     }
-            // }
+/// Removes `--previous <file>` from the arguments and reads the layout of that BPMN file
+fn take_previous_layout(args: &mut Vec<String>) -> Result<Option<PreviousLayout>, String> {
+    let Some(index) = args.iter().position(|arg| arg == "--previous") else {
+        return Ok(None);
+    };
+    if index + 1 >= args.len() {
+        return Err("Missing value for --previous".to_string());
+    }
+    let path = args.remove(index + 1);
+    args.remove(index);
+    let xml = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Could not read previous layout {}: {}", path, e))?;
+    PreviousLayout::from_bpmn(&xml).map(Some)
+}
 
-            println!("Edges:");
-            for edge in &graph.edges {
-                if let Some(text) = &edge.text {
-                    println!("  From Node {} to Node {}: '{}'", edge.from, edge.to, text);
-                } else {
-                    println!("  From Node {} to Node {}", edge.from, edge.to);
-                }
-            }
-            return generate_bpmn(&graph);
+/// Removes `--config <file>` and `--<option> <value>` or `--<option>=<value>` flags from the
+/// arguments and builds the layout options from them. Flags override the config file wherever
+/// they are given, flags that are not layout options are left in the arguments.
+fn take_layout_options(args: &mut Vec<String>) -> Result<LayoutOptions, String> {
+    let mut config_path = None;
+    let mut flags = Vec::new();
+    let mut rest = Vec::new();
+    let mut iter = args.drain(..);
+    while let Some(arg) = iter.next() {
+        let Some(flag) = arg.strip_prefix("--") else {
+            rest.push(arg);
+            continue;
+        };
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (flag.to_string(), None),
+        };
+        if name != "config" && !LayoutOptions::has_option(&name) {
+            rest.push(arg);
+            continue;
         }
-        Err(e) => {
-            eprintln!("Failed to initialize parser:\n{}", e);
-            e.to_string()
+        let value = match value {
+            Some(value) => value,
+            None => iter
+                .next()
+                .ok_or_else(|| format!("Missing value for --{}", name))?,
+        };
+        if name == "config" {
+            config_path = Some(value);
+        } else {
+            flags.push((name, value));
         }
     }
+    drop(iter);
+    *args = rest;
+
+    let mut options = match config_path {
+        Some(path) => {
+            let config = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Could not read config file {}: {}", path, e))?;
+            LayoutOptions::from_config(&config).map_err(|e| format!("{}: {}", path, e))?
+        }
+        None => LayoutOptions::default(),
+    };
+    for (name, value) in flags {
+        options.set(&name, &value)?;
+    }
+    Ok(options)
+}
+
+/// Writes the graph as BPMN XML to `generated_bpmn.bpmn`
+fn export_to_xml(graph: &Graph) {
+    let file_path = "generated_bpmn.bpmn";
+    let file = File::create(file_path).expect("Unable to create file");
+    write_bpmn_xml(graph, BufWriter::new(file)).expect("Unable to write data");
+
+    println!("BPMN file generated at: {}", file_path);
 }
 
 fn convert_bpmn_to_image(output_type: String) -> Result<(), String> {
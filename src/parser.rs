@@ -1,6 +1,8 @@
 use crate::common::bpmn_event::BpmnEvent;
-use crate::common::edge::Edge;
+use crate::common::edge::{Edge, PoolFlow};
 use crate::common::graph::Graph;
+use crate::common::lane::Lane;
+use crate::common::layout_hint::{HintKind, LayoutHint};
 use crate::lexer::{Lexer, LexerError, Token};
 use std::collections::HashMap;
 
@@ -8,11 +10,16 @@ struct ParseContext {
     last_node_id: Option<usize>,
     current_pool: Option<String>,
     current_lane: Option<String>,
+    outer_lanes: Vec<String>, // Lanes the current lane is nested in, outermost first
     current_token: Token,
 }
 
+type LabelEvent = (BpmnEvent, usize, Option<String>, Option<String>); // (event, node id, pool, lane)
+type PendingHint = (usize, String, usize, usize, String); // (node id, hint text, line, column, highlight)
+type PoolGo = (usize, String, Option<String>, bool); // (pool index, label, optional text, receives)
+
 struct ParseBranching {
-    label_map: HashMap<String, Vec<(BpmnEvent, usize, Option<String>, Option<String>)>>, // Remember the events for each label <label name, (event, node id, pool, lane)>
+    label_map: HashMap<String, Vec<LabelEvent>>, // Remember the events for each label <label name, (event, node id, pool, lane)>
     label_end_map: HashMap<String, (String, Option<String>)>, // Remember the join label for each branch label <label name, (join label name, optional text)>
     gateway_map: HashMap<usize, Vec<(String, Option<String>)>>, // Remember the branches for each gateway <node id, (label, optional text)>
     gateway_end_map: HashMap<usize, Vec<String>>, // Remember the join labels for each gateway <node id, <join label names>>
@@ -31,6 +38,10 @@ pub enum ParseError {
     DefineNodesAfterGoError(usize, String),        // Errors related to Go nodes
     GoFromError(usize, String), // Error when a node is expected before a 'Go' token
     GoToError(usize, String),   // Error when a 'Go' token has no node to join
+    UndefinedLabelError(String), // Error when a gateway branches to a label that is never defined
+    HintError(String, usize, String), // Error in a layout hint, with message, line and highlight
+    CollapsedPoolError(String, usize, String), // Error when a collapsed pool is given lanes or nodes
+    LaneError(String, usize, String), // Error in nesting lanes, with message, line and highlight
     GenericError(String),       // Generic error
 }
 
@@ -47,11 +58,48 @@ impl std::fmt::Display for ParseError {
             ParseError::DefineNodesAfterGoError(line, highlight) => write!(f, "Incoming 'G' token must be used before defining nodes at line {}\n{}", line, highlight),
             ParseError::GoFromError(line, highlight) => write!(f, "Node must be defined before outgoing 'G' token at line {}\n{}", line, highlight),
             ParseError::GoToError(line, highlight) => write!(f, "Node must be defined after incoming 'G' token at line {}\n{}", line, highlight),
+            ParseError::UndefinedLabelError(label) => write!(f, "Label '{}' is used in a gateway but never defined", label),
+            ParseError::HintError(message, line, highlight) => write!(f, "{} at line {}\n{}", message, line, highlight),
+            ParseError::CollapsedPoolError(pool, line, highlight) => write!(f, "Collapsed pool '{}' cannot contain lanes or nodes at line {}\n{}", pool, line, highlight),
+            ParseError::LaneError(message, line, highlight) => write!(f, "{} at line {}\n{}", message, line, highlight),
             ParseError::GenericError(err) => write!(f, "{}", err),
         }
     }
 }
 
+impl ParseError {
+    /// Returns the line and column (both starting from 1) the error points at, if known
+    pub fn position(&self) -> Option<(usize, usize)> {
+        match self {
+            ParseError::LexerError(LexerError::UnexpectedCharacter(_, line, column, _))
+            | ParseError::LexerError(LexerError::UnterminatedString(line, column, _)) => {
+                Some((*line, *column))
+            }
+            ParseError::UnexpectedToken(_, _, line, highlight)
+            | ParseError::ExpectedJoinLabelError(_, line, highlight)
+            | ParseError::BranchingError(_, line, highlight)
+            | ParseError::GatewayMatchingError((line, _), highlight)
+            | ParseError::GatewayJoinMissingError(line, highlight)
+            | ParseError::UnexpectedTokenAfterGoError(_, line, highlight)
+            | ParseError::DefineNodesAfterGoError(line, highlight)
+            | ParseError::GoFromError(line, highlight)
+            | ParseError::GoToError(line, highlight)
+            | ParseError::HintError(_, line, highlight)
+            | ParseError::CollapsedPoolError(_, line, highlight)
+            | ParseError::LaneError(_, line, highlight) => {
+                // The highlight is the offending line followed by a line with a caret under the error
+                let column = highlight
+                    .lines()
+                    .skip(1)
+                    .find_map(|line| line.find('^'))
+                    .map_or(1, |index| index + 1);
+                Some((*line, column))
+            }
+            ParseError::UndefinedLabelError(_) | ParseError::GenericError(_) => None,
+        }
+    }
+}
+
 pub struct Parser<'a> {
     graph: Graph,
     lexer: Lexer<'a>,
@@ -63,7 +111,7 @@ impl<'a> Parser<'a> {
     pub fn new(mut lexer: Lexer<'a>) -> Result<Self, ParseError> {
         let current_token = lexer
             .next_token()
-            .map_err(|err| ParseError::LexerError(err))?;
+            .map_err(ParseError::LexerError)?;
         Ok(Parser {
             graph: Graph::new(),
             lexer,
@@ -71,6 +119,7 @@ impl<'a> Parser<'a> {
                 last_node_id: None,
                 current_pool: None,
                 current_lane: None,
+                outer_lanes: Vec::new(),
                 current_token,
             },
         })
@@ -107,6 +156,9 @@ impl<'a> Parser<'a> {
         let mut go_from_map: HashMap<usize, Vec<(String, Option<String>)>> = HashMap::new(); // (node id, <(labels, optional texts)>)
         let mut go_to_map: HashMap<String, Vec<usize>> = HashMap::new(); // (label, node ids)
         let mut go_active = false; // Flag to indicate if a go is active (outgoing)
+        let mut hints: Vec<PendingHint> = Vec::new(); // Layout hints, resolved once all nodes exist
+        let mut collapsed_pool: Option<String> = None; // Name of the collapsed pool being parsed
+        let mut pool_gos: Vec<PoolGo> = Vec::new(); // Jumps of collapsed pools
 
         // Parse the input
         while self.context.current_token != Token::Eof {
@@ -117,11 +169,37 @@ impl<'a> Parser<'a> {
                     self.lexer.highlight_error(),
                 ));
             }
+            // A collapsed pool ends at the next pool, only the jumps of its messages can go into it
+            if let Some(pool) = &collapsed_pool {
+                if self.context.current_token == Token::Go {
+                    self.parse_collapsed_go(&mut pool_gos)?;
+                    continue;
+                }
+                if !matches!(
+                    self.context.current_token,
+                    Token::Pool(_) | Token::CollapsedPool(_)
+                ) {
+                    return Err(ParseError::CollapsedPoolError(
+                        pool.clone(),
+                        self.lexer.line,
+                        self.lexer.highlight_error(),
+                    ));
+                }
+            }
+            self.check_lane_takes_nodes()?;
             // Match the current token and parse accordingly
             let current_token = self.context.current_token.clone();
             match current_token {
-                Token::Pool(label) => self.parse_pool(&label, &mut go_active),
-                Token::Lane(label) => self.parse_lane(&label, &mut go_active),
+                Token::Pool(label) => {
+                    collapsed_pool = None;
+                    self.parse_pool(&label, &mut go_active);
+                }
+                Token::CollapsedPool(label) => {
+                    self.parse_pool(&label, &mut go_active);
+                    self.graph.add_collapsed_pool(label.clone());
+                    collapsed_pool = Some(label);
+                }
+                Token::Lane(depth, label) => self.parse_lane(depth, &label, &mut go_active)?,
                 Token::Go => {
                     self.parse_go(
                         self.context.last_node_id,
@@ -151,9 +229,14 @@ impl<'a> Parser<'a> {
                     self.parse_gateway(BpmnEvent::GatewayEvent, &mut branching)?;
                     continue;
                 }
-                Token::Label(label) => {
-                    self.parse_label(&mut branching, &label, &mut go_from_map, &mut go_to_map)?
-                }
+                Token::Label(label) => self.parse_label(
+                    &mut branching,
+                    &label,
+                    &mut go_from_map,
+                    &mut go_to_map,
+                    &mut hints,
+                )?,
+                Token::Hint(text) => self.parse_hint(self.context.last_node_id, &text, &mut hints)?,
                 _ => {
                     return Err(ParseError::UnexpectedToken(
                         String::new(),
@@ -171,12 +254,17 @@ impl<'a> Parser<'a> {
             // Loop through all branches in the gateway
             for (label, text) in labels {
                 // Check if the label defined in the gateway exists in the label_map
-                let events = branching.label_map.get(&label).expect("Label not found!");
+                let events = branching
+                    .label_map
+                    .get(&label)
+                    .ok_or_else(|| ParseError::UndefinedLabelError(label.clone()))?;
                 // Use the first event to create the edge to the gateway node
-                let first_event = events.get(0).expect("No events found for label");
+                let first_event = events.first().ok_or_else(|| {
+                    ParseError::GenericError(format!("Label '{}' has no nodes", label))
+                })?;
                 let node_id = self.graph.add_node(
                     first_event.0.clone(),
-                    Some(first_event.1.clone()),
+                    Some(first_event.1),
                     first_event.2.clone(),
                     first_event.3.clone(),
                 );
@@ -187,7 +275,7 @@ impl<'a> Parser<'a> {
                 for event in &events[1..] {
                     let node_id = self.graph.add_node(
                         event.0.clone(),
-                        Some(first_event.1.clone()),
+                        Some(event.1),
                         event.2.clone(),
                         event.3.clone(),
                     );
@@ -255,6 +343,24 @@ impl<'a> Parser<'a> {
             }
         }
 
+        // Jumps of collapsed pools are message flows between the pool and the nodes jumping there
+        for (pool, label, text, receives) in pool_gos {
+            if receives {
+                for (node, labels) in &go_from_map {
+                    for (_, text) in labels.iter().filter(|(other, _)| *other == label) {
+                        let flow = PoolFlow::new(*node, pool, true, text.clone());
+                        self.graph.pool_flows.push(flow);
+                    }
+                }
+            } else {
+                for node in go_to_map.get(&label).into_iter().flatten() {
+                    let flow = PoolFlow::new(*node, pool, false, text.clone());
+                    self.graph.pool_flows.push(flow);
+                }
+            }
+        }
+        self.graph.pool_flows.sort_by_key(|flow| (flow.pool, flow.node, flow.to_pool));
+
         // Loop through all go_from_map entries `G -> label "Optional text"`
         for (from_node_id, labels) in go_from_map {
             // Loop through all outgoing labels from the same node
@@ -271,6 +377,19 @@ impl<'a> Parser<'a> {
             }
         }
 
+        // Hints refer to nodes by name, so they can point at nodes defined after them
+        for (node_id, text, line, column, highlight) in hints {
+            let kind = self
+                .resolve_hint(node_id, &text)
+                .map_err(|message| ParseError::HintError(message, line, highlight))?;
+            self.graph.hints.push(LayoutHint {
+                node: node_id,
+                kind,
+                line,
+                column,
+            });
+        }
+
         Ok(self.graph.clone())
     }
 
@@ -278,17 +397,110 @@ impl<'a> Parser<'a> {
     fn parse_pool(&mut self, label: &str, go_active: &mut bool) {
         self.context.current_pool = Some(label.to_string());
         self.context.current_lane = None;
+        self.context.outer_lanes.clear();
         self.context.last_node_id = None;
         self.lexer.seen_start = false;
         *go_active = false;
     }
 
-    /// Set the current lane
-    fn parse_lane(&mut self, label: &str, go_active: &mut bool) {
+    /// Set the current lane. A lane with a depth above 0 is nested in the lane one level up.
+    fn parse_lane(
+        &mut self,
+        depth: usize,
+        label: &str,
+        go_active: &mut bool,
+    ) -> Result<(), ParseError> {
+        let mut path = self.context.outer_lanes.clone();
+        path.extend(self.context.current_lane.clone());
+        if depth > path.len() {
+            return Err(ParseError::LaneError(
+                format!("Lane '{}' is nested deeper than the lane above it", label),
+                self.lexer.line,
+                self.lexer.highlight_error(),
+            ));
+        }
+        path.truncate(depth);
+
+        let pool = self.context.current_pool.clone();
+        // Nodes refer to their lane by name, so a name can only be used once in a pool
+        if let Some(lane) = self.lanes_of_current_pool().find(|lane| lane.get_lane() == label) {
+            if lane.parent.as_ref() != path.last() {
+                let place = match &lane.parent {
+                    Some(parent) => format!("nested in lane '{}'", parent),
+                    None => "directly in the pool".to_string(),
+                };
+                return Err(ParseError::LaneError(
+                    format!(
+                        "Lane '{}' is already {}, lane names have to be unique in a pool",
+                        label, place
+                    ),
+                    self.lexer.line,
+                    self.lexer.highlight_error(),
+                ));
+            }
+        }
+        if let Some(parent) = path.last() {
+            let has_nodes = self
+                .lanes_of_current_pool()
+                .any(|lane| lane.get_lane() == parent && !lane.get_layers().is_empty());
+            if has_nodes {
+                return Err(ParseError::LaneError(
+                    format!(
+                        "Lane '{}' cannot be nested in lane '{}', which already has nodes",
+                        label, parent
+                    ),
+                    self.lexer.line,
+                    self.lexer.highlight_error(),
+                ));
+            }
+        }
+        // Lanes are added right away, so they keep the order of the DSL even if they stay empty
+        self.graph.add_lane(pool, label.to_string(), path.last().cloned());
+
+        self.context.outer_lanes = path;
         self.context.current_lane = Some(label.to_string());
         self.context.last_node_id = None;
         self.lexer.seen_start = false;
         *go_active = false;
+        Ok(())
+    }
+
+    /// Lanes of the pool being parsed
+    fn lanes_of_current_pool(&self) -> impl Iterator<Item = &Lane> {
+        let pool_name = self.context.current_pool.clone().unwrap_or_default();
+        self.graph
+            .get_pools()
+            .iter()
+            .filter(move |pool| pool.get_pool_name() == pool_name)
+            .flat_map(|pool| pool.get_lanes())
+    }
+
+    /// Fails if the current token adds a node to a lane that has lanes nested in it, the nodes go
+    /// into the innermost lanes
+    fn check_lane_takes_nodes(&self) -> Result<(), ParseError> {
+        let Some(current) = &self.context.current_lane else {
+            return Ok(());
+        };
+        let adds_node = self.is_token_a_node(&self.context.current_token)
+            || matches!(
+                self.context.current_token,
+                Token::GatewayParallel | Token::GatewayInclusive | Token::GatewayEvent
+            );
+        if adds_node
+            && self
+                .lanes_of_current_pool()
+                .any(|lane| lane.parent.as_ref() == Some(current))
+        {
+            return Err(ParseError::LaneError(
+                format!(
+                    "Lane '{}' has lanes nested in it, its nodes have to go into one of them",
+                    current
+                ),
+                self.lexer.line,
+                self.lexer.highlight_error(),
+            ));
+        }
+        Ok(())
     }
 
     /// Parse a gateway
@@ -306,7 +518,7 @@ impl<'a> Parser<'a> {
         );
 
         // Call the common gateway parsing logic
-        return self.parse_gateway_common(node_id, branching, false);
+        self.parse_gateway_common(node_id, branching, false)
     }
 
     /// Common logic for parsing gateways (branch or join)
@@ -317,7 +529,7 @@ impl<'a> Parser<'a> {
         inside_label: bool,
     ) -> Result<(), ParseError> {
         // Save the current line and error message in case of an error
-        let line = self.lexer.line.clone();
+        let line = self.lexer.line;
         let highlighted_line = self.lexer.highlight_error();
         branching.gateway_types.insert(
             node_id,
@@ -366,7 +578,7 @@ impl<'a> Parser<'a> {
             branching
                 .gateway_map
                 .entry(node_id)
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push((label.clone(), branch_text));
             self.advance()?;
         }
@@ -387,7 +599,7 @@ impl<'a> Parser<'a> {
             branching
                 .gateway_end_map
                 .entry(node_id)
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push(label.clone());
             self.advance()?;
         }
@@ -401,6 +613,7 @@ impl<'a> Parser<'a> {
         label: &str,
         go_from_map: &mut HashMap<usize, Vec<(String, Option<String>)>>,
         go_to_map: &mut HashMap<String, Vec<usize>>,
+        hints: &mut Vec<PendingHint>,
     ) -> Result<(), ParseError> {
         let mut go_active_in_label = false;
         // Save the current line and error message in case of an error
@@ -408,7 +621,7 @@ impl<'a> Parser<'a> {
         let highlighted_line = self.lexer.highlight_error();
 
         // Save all events for this label
-        let mut events: Vec<(BpmnEvent, usize, Option<String>, Option<String>)> = vec![]; // (event, node_id, pool, lane)
+        let mut events: Vec<LabelEvent> = vec![]; // (event, node_id, pool, lane)
 
         // Parse all events until a join label is found
         self.advance()?;
@@ -423,10 +636,15 @@ impl<'a> Parser<'a> {
                     self.lexer.highlight_error(),
                 ));
             }
+            self.check_lane_takes_nodes()?;
             match &current_token {
                 // If the current token is a label, parse it recursively
                 Token::Label(inner_label) => {
-                    self.parse_label(branching, &inner_label, go_from_map, go_to_map)?;
+                    self.parse_label(branching, inner_label, go_from_map, go_to_map, hints)?;
+                }
+                Token::Hint(text) => {
+                    let node_id = events.last().map(|event| event.1);
+                    self.parse_hint(node_id, text, hints)?;
                 }
                 Token::EventStart(label) => {
                     events.push(self.create_event_node(BpmnEvent::Start(label.clone()))?);
@@ -507,11 +725,72 @@ impl<'a> Parser<'a> {
         Ok(())
     }
 
+    /// Remember a layout hint for the node before it
+    fn parse_hint(
+        &mut self,
+        node_id: Option<usize>,
+        text: &str,
+        hints: &mut Vec<PendingHint>,
+    ) -> Result<(), ParseError> {
+        let line = self.lexer.token_line;
+        let column = self.lexer.token_column;
+        let highlight = self
+            .lexer
+            .highlight_line_error(line, column.saturating_sub(1));
+        let node_id = node_id.ok_or_else(|| {
+            ParseError::HintError(
+                "Layout hint must come after a node".to_string(),
+                line,
+                highlight.clone(),
+            )
+        })?;
+        hints.push((node_id, text.to_string(), line, column, highlight));
+        Ok(())
+    }
+
+    /// Turn the text of a hint into what it asks for, with the nodes it names looked up
+    fn resolve_hint(&self, node_id: usize, text: &str) -> Result<HintKind, String> {
+        let (keyword, argument) = text
+            .split_once(char::is_whitespace)
+            .map_or((text, ""), |(keyword, argument)| (keyword, argument.trim()));
+        if keyword == "layer" {
+            return match argument.parse::<usize>() {
+                Ok(layer) if layer >= 1 => Ok(HintKind::Layer(layer - 1)),
+                _ => Err(format!(
+                    "'@layer' needs a layer number from 1, not '{}'",
+                    argument
+                )),
+            };
+        }
+        if !matches!(keyword, "row" | "above" | "below") {
+            return Err(format!(
+                "Unknown layout hint '@{}', expected @layer, @row, @above or @below",
+                keyword
+            ));
+        }
+        // The first node with the name is meant when several share it
+        let other = self
+            .graph
+            .nodes()
+            .filter(|node| node.event.as_ref().and_then(|event| event.label()) == Some(argument))
+            .map(|node| node.id)
+            .min()
+            .ok_or_else(|| format!("Layout hint refers to unknown node '{}'", argument))?;
+        if other == node_id {
+            return Err("Layout hint refers to the node it is on".to_string());
+        }
+        Ok(match keyword {
+            "row" => HintKind::Row(other),
+            "above" => HintKind::Above(other),
+            _ => HintKind::Below(other),
+        })
+    }
+
     /// Create an event node
     fn create_event_node(
         &mut self,
         event: BpmnEvent,
-    ) -> Result<(BpmnEvent, usize, Option<String>, Option<String>), ParseError> {
+    ) -> Result<LabelEvent, ParseError> {
         let node_id = self.graph.next_node_id();
         Ok((
             event,
@@ -525,7 +804,7 @@ impl<'a> Parser<'a> {
     fn handle_gateway_in_label(
         &mut self,
         branching: &mut ParseBranching,
-        events: &mut Vec<(BpmnEvent, usize, Option<String>, Option<String>)>,
+        events: &mut Vec<LabelEvent>,
         event: BpmnEvent,
     ) -> Result<(), ParseError> {
         // Assign a unique node ID to this gateway
@@ -540,7 +819,7 @@ impl<'a> Parser<'a> {
         ));
 
         // Store the gateway_id and parse branches without advancing
-        return self.parse_gateway_common(gateway_id, branching, true);
+        self.parse_gateway_common(gateway_id, branching, true)
     }
 
     /// Connect two nodes with an edge if needed
@@ -572,7 +851,7 @@ impl<'a> Parser<'a> {
         go_active: &mut bool,
     ) -> Result<(), ParseError> {
         // Save the current line and error message in case of an error
-        let line = self.lexer.line.clone();
+        let line = self.lexer.line;
         let highlighted_line = self.lexer.highlight_error();
 
         // Check if this go is a branching go or a join go
@@ -598,6 +877,38 @@ impl<'a> Parser<'a> {
         Ok(())
     }
 
+    /// Parses a jump in a collapsed pool. `G ->label` sends a message to the nodes after
+    /// `G <-label`, `G <-label` receives the messages of the nodes before `G ->label`.
+    fn parse_collapsed_go(&mut self, pool_gos: &mut Vec<PoolGo>) -> Result<(), ParseError> {
+        let line = self.lexer.line;
+        let highlighted_line = self.lexer.highlight_error();
+        let pool = self.graph.pools.len() - 1;
+        self.advance()?;
+        match &self.context.current_token {
+            Token::Branch(_, _) => {
+                while let Token::Branch(label, text) = &self.context.current_token {
+                    let text = Some(text.clone()).filter(|text| !text.is_empty());
+                    pool_gos.push((pool, label.clone(), text, false));
+                    self.advance()?;
+                }
+            }
+            Token::JoinLabel(_) => {
+                while let Token::JoinLabel(label) = &self.context.current_token {
+                    pool_gos.push((pool, label.clone(), None, true));
+                    self.advance()?;
+                }
+            }
+            _ => {
+                return Err(ParseError::UnexpectedTokenAfterGoError(
+                    self.context.current_token.clone(),
+                    line,
+                    highlighted_line,
+                ));
+            }
+        }
+        Ok(())
+    }
+
     fn handle_go_from(
         &mut self,
         from_id: Option<usize>,
@@ -616,7 +927,7 @@ impl<'a> Parser<'a> {
             };
             go_from_map
                 .entry(last_node_id)
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push((label.clone(), edge_text));
 
             self.advance()?;
@@ -632,7 +943,7 @@ impl<'a> Parser<'a> {
         go_to_map: &mut HashMap<String, Vec<usize>>,
     ) -> Result<(), ParseError> {
         // Check that a valid node type follows
-        let next_token = self.peek().unwrap();
+        let next_token = self.peek().map_err(ParseError::LexerError)?;
         if !self.is_token_a_node(&next_token) {
             return Err(ParseError::GoToError(
                 self.lexer.line,
@@ -644,7 +955,7 @@ impl<'a> Parser<'a> {
         while let Token::JoinLabel(label) = &self.context.current_token {
             go_to_map
                 .entry(label.clone())
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push(next_node_id);
             self.advance()?;
         }
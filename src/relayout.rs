@@ -0,0 +1,416 @@
+// relayout.rs
+use crate::common::bpmn_event::{get_node_size, BpmnEvent};
+use crate::common::graph::Graph;
+use crate::diagnostics::Diagnostic;
+use crate::from_xml::{is, read_bpmn_with_ids, XmlIds};
+use crate::layout::{LayoutOptions, Orientation};
+use crate::to_xml::number;
+use crate::xml_writer::XmlWriter;
+use roxmltree::{Document, Node as XmlNode};
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+const DI_NAMESPACE: &str = "http://www.omg.org/spec/BPMN/20100524/DI";
+const DC_NAMESPACE: &str = "http://www.omg.org/spec/DD/20100524/DC";
+const DD_NAMESPACE: &str = "http://www.omg.org/spec/DD/20100524/DI";
+
+/// Lays out BPMN 2.0 XML again and replaces its diagram with the new layout. Everything outside
+/// of the `bpmndi:BPMNDiagram` elements of the collaboration or processes stays as it was, byte
+/// for byte, including the diagrams modelers draw for the inside of collapsed sub-processes.
+/// Elements the graph cannot hold are not drawn in the new diagram and come back as warnings.
+pub fn relayout_bpmn(
+    xml: &str,
+    options: &LayoutOptions,
+) -> Result<(String, Vec<Diagnostic>), Diagnostic> {
+    let (mut graph, mut warnings, ids) = read_bpmn_with_ids(xml)?;
+    forget_positions(&mut graph);
+    let collapsed = collapse_sub_processes(&mut graph);
+    warnings.extend(crate::layout(&mut graph, options));
+
+    // Reading the graph already checked that this parses
+    let document = Document::parse(xml).map_err(|error| Diagnostic::error(error.to_string()))?;
+    let definitions = document.root_element();
+    let (diagrams, kept): (Vec<XmlNode>, Vec<XmlNode>) = definitions
+        .children()
+        .filter(|node| is(node, "BPMNDiagram"))
+        .partition(|diagram| !draws_sub_process(&document, diagram));
+
+    let used: HashSet<&str> = document
+        .descendants()
+        .filter(|node| !diagrams.iter().any(|diagram| is_within(node, diagram)))
+        .filter_map(|node| node.attribute("id"))
+        .collect();
+    let mut taken = HashSet::new();
+    let mut unique = |id: String| -> String {
+        let mut candidate = id.clone();
+        let mut suffix = 2;
+        while used.contains(candidate.as_str()) || taken.contains(&candidate) {
+            candidate = format!("{}_{}", id, suffix);
+            suffix += 1;
+        }
+        taken.insert(candidate.clone());
+        candidate
+    };
+
+    let diagram_id = diagrams
+        .first()
+        .and_then(|diagram| diagram.attribute("id"))
+        .map(str::to_string)
+        .unwrap_or_else(|| unique("BPMNDiagram_1".to_string()));
+    let plane_id = diagrams
+        .first()
+        .and_then(|diagram| diagram.children().find(|node| is(node, "BPMNPlane")))
+        .and_then(|plane| plane.attribute("id"))
+        .map(str::to_string)
+        .unwrap_or_else(|| unique("BPMNPlane_1".to_string()));
+
+    let prefixes = Prefixes::of(&definitions);
+    let indent = indent_of(xml, &definitions, &diagrams, &kept);
+    let declared: Vec<&str> = prefixes.declared.iter().map(String::as_str).collect();
+    let mut writer = XmlWriter::fragment(Vec::new(), &indent, &declared);
+    let diagram = write_diagram(
+        &mut writer,
+        &graph,
+        &ids,
+        &prefixes,
+        (&diagram_id, &plane_id),
+        &mut unique,
+        &collapsed,
+    )
+    .and_then(|_| writer.finish())
+    .map_err(|error| Diagnostic::error(error.to_string()))?;
+    let diagram = String::from_utf8(diagram).expect("The XML should be UTF-8");
+
+    // The new diagram takes the place of the first old one, or goes in front of the diagrams of the
+    // sub-processes or at the end of the definitions
+    let mut result = String::with_capacity(xml.len() + diagram.len());
+    let mut position = 0;
+    for (index, old) in diagrams.iter().enumerate() {
+        result.push_str(&xml[position..line_start(xml, old.range().start)]);
+        if index == 0 {
+            result.push_str(&diagram);
+        }
+        position = line_end(xml, old.range().end);
+    }
+    if let (true, Some(first)) = (diagrams.is_empty(), kept.first()) {
+        position = line_start(xml, first.range().start);
+        result.push_str(&xml[..position]);
+        result.push_str(&diagram);
+    } else if diagrams.is_empty() {
+        let end = definitions.range().end;
+        let closing = line_start(xml, xml[..end].rfind("</").unwrap_or(end));
+        result.push_str(&xml[..closing]);
+        if !result.ends_with('\n') {
+            result.push('\n');
+        }
+        result.push_str(&diagram);
+        position = closing;
+    }
+    result.push_str(&xml[position..]);
+
+    Ok((result, warnings))
+}
+
+/// Drops the positions that came from the old diagram, the layout places everything anew
+fn forget_positions(graph: &mut Graph) {
+    for pool in graph.pools.iter_mut() {
+        pool.x = None;
+        pool.y = None;
+        pool.width = None;
+        pool.height = None;
+        for lane in pool.lanes.iter_mut() {
+            lane.x = None;
+            lane.y = None;
+            lane.width = None;
+            lane.height = None;
+            for node in lane.get_layers_mut() {
+                node.x = None;
+                node.y = None;
+                node.x_offset = Some(0.0);
+                node.y_offset = Some(0.0);
+            }
+        }
+    }
+    for edge in graph.edges.iter_mut() {
+        edge.bend_points = None;
+        edge.label_bounds = None;
+    }
+}
+
+/// Turns sub-processes into tasks of the same name, so the layout gives them the size of a task.
+/// They are drawn collapsed, the elements inside of them are not laid out. Returns the nodes that
+/// were sub-processes.
+fn collapse_sub_processes(graph: &mut Graph) -> HashSet<usize> {
+    let mut collapsed = HashSet::new();
+    for pool in graph.pools.iter_mut() {
+        for lane in pool.lanes.iter_mut() {
+            for node in lane.get_layers_mut() {
+                if let Some(
+                    BpmnEvent::ActivitySubprocess(label)
+                    | BpmnEvent::ActivityEventSubprocess(label)
+                    | BpmnEvent::ActivityTransaction(label),
+                ) = &node.event
+                {
+                    node.event = Some(BpmnEvent::ActivityTask(label.clone()));
+                    collapsed.insert(node.id);
+                }
+            }
+        }
+    }
+    collapsed
+}
+
+/// Prefixes of the diagram namespaces, with the namespaces the diagram has to declare itself when
+/// the definitions do not
+struct Prefixes {
+    bpmndi: String,
+    dc: String,
+    di: String,
+    declared: Vec<String>, // Prefixes the definitions declare already
+    missing: Vec<(String, &'static str)>, // Prefix and URI of the namespaces to declare
+}
+
+impl Prefixes {
+    fn of(definitions: &XmlNode) -> Self {
+        let mut declared = Vec::new();
+        let mut missing = Vec::new();
+        let mut prefix = |namespace: &'static str, preferred: &str| -> String {
+            match definitions.lookup_prefix(namespace) {
+                Some(prefix) if !prefix.is_empty() => {
+                    declared.push(prefix.to_string());
+                    prefix.to_string()
+                }
+                _ => {
+                    missing.push((preferred.to_string(), namespace));
+                    preferred.to_string()
+                }
+            }
+        };
+        let bpmndi = prefix(DI_NAMESPACE, "bpmndi");
+        let dc = prefix(DC_NAMESPACE, "dc");
+        let di = prefix(DD_NAMESPACE, "di");
+        Prefixes {
+            bpmndi,
+            dc,
+            di,
+            declared,
+            missing,
+        }
+    }
+
+    fn bpmndi(&self, name: &str) -> String {
+        format!("{}:{}", self.bpmndi, name)
+    }
+}
+
+/// Writes the diagram of a laid out graph, referring to the elements by their XML ids
+fn write_diagram<W: Write>(
+    xml: &mut XmlWriter<W>,
+    graph: &Graph,
+    ids: &XmlIds,
+    prefixes: &Prefixes,
+    (diagram_id, plane_id): (&str, &str),
+    unique: &mut impl FnMut(String) -> String,
+    collapsed: &HashSet<usize>,
+) -> io::Result<()> {
+    let mut diagram = xml
+        .element(&prefixes.bpmndi("BPMNDiagram"))
+        .attribute("id", diagram_id);
+    for (prefix, uri) in &prefixes.missing {
+        diagram = diagram.namespace(prefix, uri);
+    }
+    diagram.start()?;
+    xml.element(&prefixes.bpmndi("BPMNPlane"))
+        .attribute("id", plane_id)
+        .attribute("bpmnElement", &ids.plane)
+        .start()?;
+
+    let horizontal = Some((
+        "isHorizontal",
+        graph.orientation != Orientation::TopToBottom,
+    ));
+    for (index, pool) in graph.pools.iter().enumerate() {
+        if let Some(participant) = ids.pools.get(index).and_then(Option::as_ref) {
+            let bounds = (
+                pool.x.unwrap_or(0.0),
+                pool.y.unwrap_or(0.0),
+                pool.width.unwrap_or(0.0),
+                pool.height.unwrap_or(0.0),
+            );
+            let id = unique(format!("{}_di", participant));
+            write_shape(xml, prefixes, (&id, participant), horizontal, bounds, None)?;
+        }
+        for lane in pool.get_lanes() {
+            let Some(lane_id) = ids
+                .lanes
+                .get(index)
+                .and_then(|lanes| lanes.get(lane.get_lane()))
+            else {
+                continue;
+            };
+            let bounds = (
+                lane.x.unwrap_or(0.0),
+                lane.y.unwrap_or(0.0),
+                lane.width.unwrap_or(0.0),
+                lane.height.unwrap_or(0.0),
+            );
+            let id = unique(format!("{}_di", lane_id));
+            write_shape(xml, prefixes, (&id, lane_id), horizontal, bounds, None)?;
+        }
+    }
+
+    for node in graph.nodes() {
+        let (Some(element), Some(event)) = (ids.nodes.get(&node.id), node.event.as_ref()) else {
+            continue;
+        };
+        let (width, height) = get_node_size(event);
+        let bounds = (
+            node.x.unwrap_or(0.0) + node.x_offset.unwrap_or(0.0),
+            node.y.unwrap_or(0.0) + node.y_offset.unwrap_or(0.0),
+            width as f64,
+            height as f64,
+        );
+        let expanded = collapsed
+            .contains(&node.id)
+            .then_some(("isExpanded", false));
+        let id = unique(format!("{}_di", element));
+        let label = node.label_bounds(graph.orientation);
+        write_shape(xml, prefixes, (&id, element), expanded, bounds, label)?;
+    }
+
+    let edges = graph
+        .edges
+        .iter()
+        .map(|edge| (&edge.bend_points, edge.label_bounds))
+        .zip(&ids.flows);
+    let pool_flows = graph
+        .pool_flows
+        .iter()
+        .map(|flow| (&flow.bend_points, flow.label_bounds))
+        .zip(&ids.pool_flows);
+    for ((points, label), flow) in edges.chain(pool_flows) {
+        xml.element(&prefixes.bpmndi("BPMNEdge"))
+            .attribute("id", unique(format!("{}_di", flow)))
+            .attribute("bpmnElement", flow)
+            .start()?;
+        for (x, y) in points.iter().flatten() {
+            xml.element(&format!("{}:waypoint", prefixes.di))
+                .attribute("x", number(*x))
+                .attribute("y", number(*y))
+                .empty()?;
+        }
+        write_label(xml, prefixes, label)?;
+        xml.end()?;
+    }
+
+    xml.end()?;
+    xml.end()
+}
+
+/// Shape of an element, given by the id of the shape and the id of the element
+fn write_shape<W: Write>(
+    xml: &mut XmlWriter<W>,
+    prefixes: &Prefixes,
+    (id, element): (&str, &str),
+    flag: Option<(&str, bool)>,
+    bounds: (f64, f64, f64, f64),
+    label: Option<(f64, f64, f64, f64)>,
+) -> io::Result<()> {
+    let mut shape = xml
+        .element(&prefixes.bpmndi("BPMNShape"))
+        .attribute("id", id)
+        .attribute("bpmnElement", element);
+    if let Some((name, value)) = flag {
+        shape = shape.attribute(name, value);
+    }
+    shape.start()?;
+    write_bounds(xml, prefixes, bounds)?;
+    write_label(xml, prefixes, label)?;
+    xml.end()
+}
+
+fn write_label<W: Write>(
+    xml: &mut XmlWriter<W>,
+    prefixes: &Prefixes,
+    bounds: Option<(f64, f64, f64, f64)>,
+) -> io::Result<()> {
+    let Some(bounds) = bounds else {
+        return Ok(());
+    };
+    xml.element(&prefixes.bpmndi("BPMNLabel")).start()?;
+    write_bounds(xml, prefixes, bounds)?;
+    xml.end()
+}
+
+fn write_bounds<W: Write>(
+    xml: &mut XmlWriter<W>,
+    prefixes: &Prefixes,
+    (x, y, width, height): (f64, f64, f64, f64),
+) -> io::Result<()> {
+    xml.element(&format!("{}:Bounds", prefixes.dc))
+        .attribute("x", number(x))
+        .attribute("y", number(y))
+        .attribute("width", number(width))
+        .attribute("height", number(height))
+        .empty()
+}
+
+/// Whether the plane of a diagram draws the inside of a sub-process rather than the collaboration
+/// or a process
+fn draws_sub_process(document: &Document, diagram: &XmlNode) -> bool {
+    let element = diagram
+        .children()
+        .find(|node| is(node, "BPMNPlane"))
+        .and_then(|plane| plane.attribute("bpmnElement"))
+        .and_then(|id| {
+            document
+                .descendants()
+                .find(|node| node.attribute("id") == Some(id))
+        });
+    element.is_some_and(|element| {
+        ["subProcess", "transaction", "adHocSubProcess"].contains(&element.tag_name().name())
+    })
+}
+
+/// The indentation of the old diagram, or of the first element in the definitions
+fn indent_of(xml: &str, definitions: &XmlNode, diagrams: &[XmlNode], kept: &[XmlNode]) -> String {
+    let element = diagrams
+        .first()
+        .or(kept.first())
+        .copied()
+        .or_else(|| definitions.children().find(XmlNode::is_element));
+    let Some(element) = element else {
+        return "  ".to_string();
+    };
+    let start = element.range().start;
+    let line = &xml[line_start(xml, start)..start];
+    if line.trim().is_empty() {
+        line.to_string()
+    } else {
+        String::new()
+    }
+}
+
+/// Start of the line of `position` if only whitespace comes before it on the line
+fn line_start(xml: &str, position: usize) -> usize {
+    let start = xml[..position].rfind('\n').map_or(0, |index| index + 1);
+    if xml[start..position].trim().is_empty() {
+        start
+    } else {
+        position
+    }
+}
+
+/// End of the line of `position`, after the line break, if only whitespace follows it on the line
+fn line_end(xml: &str, position: usize) -> usize {
+    let rest = &xml[position..];
+    match rest.find('\n') {
+        Some(index) if rest[..index].trim().is_empty() => position + index + 1,
+        None if rest.trim().is_empty() => xml.len(),
+        _ => position,
+    }
+}
+
+fn is_within(node: &XmlNode, ancestor: &XmlNode) -> bool {
+    node.ancestors().any(|other| other == *ancestor)
+}
@@ -0,0 +1,178 @@
+// serve.rs
+
+use crate::read_input::read_lines;
+use crate::xml_writer::escape;
+use crate::{dsl_to_svg, LayoutOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+const PAGE_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title} - bpmn-parser</title>
+<style>
+  body { margin: 0; font-family: Arial, sans-serif; }
+  #diagram { padding: 16px; }
+  .error { margin: 16px; padding: 12px; color: #a00; background: #fee; border: 1px solid #a00; }
+</style>
+</head>
+<body>
+<div id="diagram">{diagram}</div>
+<script>
+  const events = new EventSource("/events");
+  events.addEventListener("reload", () => {
+    fetch("/diagram")
+      .then((response) => response.text())
+      .then((html) => { document.getElementById("diagram").innerHTML = html; });
+  });
+</script>
+</body>
+</html>
+"#;
+
+/// Serves a live preview of the diagram in `file_path` on localhost.
+/// Open browsers are told to reload the diagram whenever the file changes.
+pub fn serve(file_path: &str, port: u16, options: &LayoutOptions) -> std::io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+
+    // Failed connections only affect that browser tab, so their errors are dropped
+    for stream in listener.incoming().flatten() {
+        let file_path = file_path.to_string();
+        let options = options.clone();
+        // Every connection gets its own thread, event streams stay open until the browser leaves
+        thread::spawn(move || {
+            let _ = handle_connection(stream, &file_path, &options);
+        });
+    }
+    Ok(())
+}
+
+fn handle_connection(
+    mut stream: TcpStream,
+    file_path: &str,
+    options: &LayoutOptions,
+) -> std::io::Result<()> {
+    let mut request_line = String::new();
+    let mut reader = BufReader::new(stream.try_clone()?);
+    reader.read_line(&mut request_line)?;
+
+    // Skip the headers, none of them are needed
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("");
+
+    match (method, path) {
+        ("GET", "/") => {
+            let page = render_page(file_path, &render_diagram(file_path, options));
+            write_response(&mut stream, "200 OK", "text/html; charset=utf-8", &page)
+        }
+        ("GET", "/diagram") => write_response(
+            &mut stream,
+            "200 OK",
+            "text/html; charset=utf-8",
+            &render_diagram(file_path, options),
+        ),
+        ("GET", "/events") => stream_events(&mut stream, file_path),
+        _ => write_response(
+            &mut stream,
+            "404 Not Found",
+            "text/plain; charset=utf-8",
+            "Not found",
+        ),
+    }
+}
+
+fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Keeps a server-sent events stream open and sends a `reload` event whenever the file changes
+fn stream_events(stream: &mut TcpStream, file_path: &str) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n"
+    )?;
+    stream.flush()?;
+
+    let mut last_modified = modified_time(file_path);
+    let mut since_keep_alive = Duration::ZERO;
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        since_keep_alive += POLL_INTERVAL;
+
+        let modified = modified_time(file_path);
+        if modified != last_modified {
+            last_modified = modified;
+            write!(stream, "event: reload\ndata: {}\n\n", file_path)?;
+            stream.flush()?;
+            since_keep_alive = Duration::ZERO;
+        } else if since_keep_alive >= KEEP_ALIVE_INTERVAL {
+            // Comments keep proxies from closing the stream and tell us when the browser is gone
+            write!(stream, ": keep-alive\n\n")?;
+            stream.flush()?;
+            since_keep_alive = Duration::ZERO;
+        }
+    }
+}
+
+fn modified_time(file_path: &str) -> Option<SystemTime> {
+    std::fs::metadata(Path::new(file_path))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Renders the diagram as inline SVG, or the parse error if the file does not parse
+pub fn render_diagram(file_path: &str, options: &LayoutOptions) -> String {
+    let input = match read_lines(file_path) {
+        Ok(input) => input,
+        Err(e) => {
+            return render_error(&format!("Error reading file {}: {}", file_path, e));
+        }
+    };
+
+    // A panic in the layout must not take the server down, show it like a parse error instead
+    match std::panic::catch_unwind(|| dsl_to_svg(&input, options)) {
+        Ok(Ok(svg)) => svg,
+        Ok(Err(diagnostics)) => render_error(&diagnostics.to_string()),
+        Err(_) => render_error("Failed to lay out the diagram"),
+    }
+}
+
+fn render_page(file_path: &str, diagram: &str) -> String {
+    PAGE_TEMPLATE
+        .replace("{title}", &escape_html(file_path))
+        .replace("{diagram}", diagram)
+}
+
+fn render_error(message: &str) -> String {
+    format!(r#"<pre class="error">{}</pre>"#, escape_html(message))
+}
+
+fn escape_html(text: &str) -> String {
+    escape(text, true).into_owned()
+}
@@ -0,0 +1,96 @@
+use crate::{dsl_to_bpmn, layout, parse, to_bpmn_xml, Layering, LayoutOptions, Severity};
+
+#[test]
+fn test_api_parse_layout_and_export() {
+    let input = r#"
+# Start Event
+- Task
+. End Event
+"#;
+
+    let mut graph = parse(input).expect("Input should parse");
+    assert_eq!(graph.nodes().count(), 3);
+    assert!(graph.nodes().all(|node| node.x.is_none()), "Parsing should not place nodes");
+
+    layout(&mut graph, &LayoutOptions::default());
+    assert!(graph.nodes().all(|node| node.x.is_some() && node.y.is_some()));
+    assert!(graph.edges.iter().all(|edge| edge.bend_points.is_some()));
+
+    let xml = to_bpmn_xml(&graph);
+    assert_eq!(xml, dsl_to_bpmn(input, &LayoutOptions::default()).unwrap());
+}
+
+#[test]
+fn test_api_reports_diagnostics() {
+    let input = "# Start Event\n- Task\nG\n";
+
+    let diagnostics = parse(input).expect_err("Input should not parse");
+    let diagnostic = diagnostics.iter().next().unwrap();
+    assert_eq!(diagnostic.severity, Severity::Error);
+    assert_eq!(diagnostic.line, Some(3));
+    assert!(diagnostic.message.contains("'G'"));
+    assert!(diagnostic.snippet.is_some());
+}
+
+#[test]
+fn test_layout_options_from_config() {
+    let config = r#"
+# Compact diagrams for slides
+layering = "longest-path"
+layer_gap = 20
+node-gap = 10
+"#;
+
+    let options = LayoutOptions::from_config(config).expect("Config should parse");
+    assert_eq!(options.layering, Layering::LongestPath);
+    assert_eq!(options.layer_gap, 20.0);
+    assert_eq!(options.node_gap, 10.0);
+    assert_eq!(options.lane_padding, LayoutOptions::default().lane_padding);
+
+    let error = LayoutOptions::from_config("layer_gap = 20\nedge_spacing = 0").unwrap_err();
+    assert!(error.starts_with("Line 2:"), "{}", error);
+    assert!(LayoutOptions::from_config("spacing = 3").is_err());
+
+    // Only a `#` that starts a word and is not quoted starts a comment
+    let options = LayoutOptions::from_config("layering = \"longest-path\" # fast\n# node_gap = 5")
+        .expect("Comments should be skipped");
+    assert_eq!(options.layering, Layering::LongestPath);
+    assert_eq!(options.node_gap, LayoutOptions::default().node_gap);
+    let error = LayoutOptions::from_config("layering = longest-path#1").unwrap_err();
+    assert!(error.contains("longest-path#1"), "{}", error);
+    let error = LayoutOptions::from_config("layering = \"longest path #1\"").unwrap_err();
+    assert!(error.contains("longest path #1"), "{}", error);
+
+    assert!(LayoutOptions::has_option("layer-gap"));
+    assert!(LayoutOptions::has_option("grid_size"));
+    assert!(!LayoutOptions::has_option("help"));
+    let mut options = LayoutOptions::default();
+    assert_eq!(
+        options.set("help", "1"),
+        Err("Unknown layout option 'help'".to_string())
+    );
+    options.set("layer-gap", " 35 ").unwrap();
+    assert_eq!(options.layer_gap, 35.0);
+
+    // Configs written for the old name of the option still work
+    let options = LayoutOptions::from_config("grid_size = 15").expect("Old name should parse");
+    assert_eq!(options.edge_spacing, 15.0);
+}
+
+#[test]
+fn test_layer_gap_spaces_columns() {
+    let input = "# Start Event\n- Task\n. End Event\n";
+    let column_x = |options: &LayoutOptions| {
+        let mut graph = parse(input).expect("Input should parse");
+        layout(&mut graph, options);
+        let mut xs: Vec<f64> = graph.nodes().map(|node| node.x.unwrap()).collect();
+        xs.sort_by(f64::total_cmp);
+        xs
+    };
+
+    let mut roomy = LayoutOptions::default();
+    roomy.set("layer-gap", "120").unwrap();
+    let (normal, roomy) = (column_x(&LayoutOptions::default()), column_x(&roomy));
+    assert_eq!(roomy[1] - roomy[0], normal[1] - normal[0] + 70.0);
+    assert_eq!(roomy[2] - roomy[1], normal[2] - normal[1] + 70.0);
+}
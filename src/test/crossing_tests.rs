@@ -0,0 +1,56 @@
+use crate::layout::crossing_minimization::{count_crossings, order_layers, reduce_crossings};
+use crate::layout::solve_layer_assignment::solve_layer_assignment;
+use crate::{parse, LayoutOptions};
+
+#[test]
+fn test_crossed_edges_are_untangled() {
+    let layers = vec![vec![1, 2], vec![3, 4]];
+    let edges = [(1, 4), (2, 3)];
+    assert_eq!(count_crossings(&layers, &edges), 1);
+
+    let (ordered, counts) = order_layers(&layers, &edges);
+    assert_eq!(counts.before, 1);
+    assert_eq!(counts.after, 0);
+    assert_eq!(count_crossings(&ordered, &edges), 0);
+}
+
+#[test]
+fn test_long_edges_are_counted_in_every_layer() {
+    // 1 -> 5 passes the middle layer to the right of 3, so it crosses 2 -> 3
+    let layers = vec![vec![1, 2], vec![3], vec![4, 5]];
+    let edges = [(1, 5), (2, 3), (3, 4)];
+
+    let (ordered, counts) = order_layers(&layers, &edges);
+    assert_eq!(counts.before, 1);
+    assert_eq!(counts.after, 0);
+    for (layer, original) in ordered.iter().zip(&layers) {
+        let mut sorted = layer.clone();
+        sorted.sort();
+        assert_eq!(&sorted, original, "Every node should stay in its layer");
+    }
+}
+
+#[test]
+fn test_reduce_crossings_in_lanes() {
+    let input = r#"
+# Start Event
+X ->a "A" ->b "B" ->c "C"
+a:
+- Task A
+J done
+b:
+- Task B
+J done
+c:
+- Task C
+J done
+X <-done
+. End Event
+"#;
+
+    let mut graph = parse(input).expect("Input should parse");
+    solve_layer_assignment(&mut graph, &LayoutOptions::default());
+    let counts = reduce_crossings(&mut graph);
+    assert!(counts.after <= counts.before);
+    assert_eq!(counts.after, 0);
+}
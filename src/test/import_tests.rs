@@ -0,0 +1,155 @@
+use crate::{dsl_to_bpmn, from_bpmn_xml, BpmnEvent, LayoutOptions, Severity};
+
+#[test]
+fn test_import_reads_back_what_the_dsl_writes() {
+    let input = r#"
+= Shop
+== Sales
+# Order received
+X ->ok "In stock" ->no "Sold out"
+ok:
+- Ship order
+J done
+no:
+- Cancel order
+J done
+X <-done
+. Order handled
+== Operations
+=== Warehouse
+- Count stock
+"#;
+    let xml = dsl_to_bpmn(input, &LayoutOptions::default()).expect("Input should parse");
+
+    let (graph, warnings) = from_bpmn_xml(&xml).expect("Written XML should import");
+    assert!(warnings.is_empty(), "{}", warnings);
+    assert_eq!(graph.pools.len(), 1);
+    let lanes: Vec<(&str, Option<&str>)> = graph.pools[0]
+        .lanes
+        .iter()
+        .map(|lane| (lane.get_lane().as_str(), lane.parent.as_deref()))
+        .collect();
+    assert_eq!(
+        lanes,
+        [
+            ("Sales", None),
+            ("Operations", None),
+            ("Warehouse", Some("Operations"))
+        ]
+    );
+    assert_eq!(graph.nodes().count(), 7);
+    assert_eq!(graph.last_node_id, 7);
+    assert_eq!(graph.edges.len(), 6);
+    let mut texts: Vec<&str> = graph
+        .edges
+        .iter()
+        .filter_map(|e| e.text.as_deref())
+        .collect();
+    texts.sort();
+    assert_eq!(texts, ["In stock", "Sold out"]);
+    assert!(graph.edges.iter().all(|edge| edge.bend_points.is_some()));
+
+    // Positions come from the diagram, so writing the graph again gives the same shapes
+    let shapes = |xml: &str| -> Vec<String> {
+        xml.split("<dc:Bounds")
+            .skip(1)
+            .map(|bounds| bounds[..60].to_string())
+            .collect()
+    };
+    assert_eq!(shapes(&crate::to_bpmn_xml(&graph)), shapes(&xml));
+}
+
+#[test]
+fn test_import_warns_about_what_it_cannot_hold() {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<bpmn:definitions xmlns:bpmn="http://www.omg.org/spec/BPMN/20100524/MODEL" xmlns:bpmndi="http://www.omg.org/spec/BPMN/20100524/DI" xmlns:dc="http://www.omg.org/spec/DD/20100524/DC" xmlns:di="http://www.omg.org/spec/DD/20100524/DI" id="Definitions_1">
+  <bpmn:collaboration id="Collaboration_1">
+    <bpmn:participant id="Participant_Shop" name="Shop" processRef="Process_Shop" />
+    <bpmn:participant id="Participant_Bank" name="Bank" />
+    <bpmn:messageFlow id="MessageFlow_1" sourceRef="Task_Pay" targetRef="Participant_Bank" />
+  </bpmn:collaboration>
+  <bpmn:process id="Process_Shop">
+    <bpmn:laneSet id="LaneSet_1">
+      <bpmn:lane id="Lane_Front" name="Front">
+        <bpmn:flowNodeRef>Start_1</bpmn:flowNodeRef>
+        <bpmn:flowNodeRef>Task_Pay</bpmn:flowNodeRef>
+        <bpmn:flowNodeRef>Timer_1</bpmn:flowNodeRef>
+        <bpmn:flowNodeRef>End_1</bpmn:flowNodeRef>
+      </bpmn:lane>
+    </bpmn:laneSet>
+    <bpmn:startEvent id="Start_1" name="Order placed">
+      <bpmn:messageEventDefinition id="Message_1" />
+    </bpmn:startEvent>
+    <bpmn:sendTask id="Task_Pay" name="Request payment" />
+    <bpmn:boundaryEvent id="Timer_1" name="1 day" cancelActivity="false" attachedToRef="Task_Pay">
+      <bpmn:timerEventDefinition id="Timer_Definition_1" />
+    </bpmn:boundaryEvent>
+    <bpmn:complexGateway id="Complex_1" />
+    <bpmn:endEvent id="End_1" name="Paid" />
+    <bpmn:sequenceFlow id="Flow_1" sourceRef="Start_1" targetRef="Task_Pay" />
+    <bpmn:sequenceFlow id="Flow_2" name="done" sourceRef="Task_Pay" targetRef="End_1" />
+    <bpmn:sequenceFlow id="Flow_3" sourceRef="Task_Pay" targetRef="Complex_1" />
+  </bpmn:process>
+  <bpmndi:BPMNDiagram id="BPMNDiagram_1">
+    <bpmndi:BPMNPlane id="BPMNPlane_1" bpmnElement="Collaboration_1">
+      <bpmndi:BPMNShape id="Task_Pay_di" bpmnElement="Task_Pay">
+        <dc:Bounds x="300" y="128" width="100" height="80" />
+      </bpmndi:BPMNShape>
+      <bpmndi:BPMNEdge id="Flow_2_di" bpmnElement="Flow_2">
+        <di:waypoint x="400" y="168" />
+        <di:waypoint x="500" y="168" />
+        <bpmndi:BPMNLabel><dc:Bounds x="430" y="150" width="30" height="14" /></bpmndi:BPMNLabel>
+      </bpmndi:BPMNEdge>
+    </bpmndi:BPMNPlane>
+  </bpmndi:BPMNDiagram>
+</bpmn:definitions>"#;
+
+    let (graph, warnings) = from_bpmn_xml(xml).expect("Modeler XML should import");
+    let messages: Vec<&str> = warnings.iter().map(|w| w.message.as_str()).collect();
+    assert_eq!(
+        messages,
+        [
+            "The sendTask 'Task_Pay' was imported as a plain task",
+            "The complexGateway 'Complex_1' is not supported and was skipped",
+            "The sequenceFlow 'Flow_3' connects an element that was not imported, so it was skipped",
+        ]
+    );
+    assert!(warnings.iter().all(|w| w.severity == Severity::Warning));
+    assert_eq!(warnings.iter().next().unwrap().line, Some(20));
+
+    assert!(graph.pools[1].collapsed);
+    // The message to the collapsed pool ends at the pool itself
+    let flows: Vec<(usize, bool)> = graph
+        .pool_flows
+        .iter()
+        .map(|flow| (flow.pool, flow.to_pool))
+        .collect();
+    assert_eq!(flows, [(1, true)]);
+    let events: Vec<&BpmnEvent> = graph
+        .nodes()
+        .filter_map(|node| node.event.as_ref())
+        .collect();
+    assert_eq!(events.len(), 4);
+    assert!(events.contains(&&BpmnEvent::StartMessageEvent("Order placed".to_string())));
+    let task = graph
+        .nodes()
+        .find(|node| node.event == Some(BpmnEvent::ActivityTask("Request payment".to_string())))
+        .unwrap();
+    assert_eq!((task.x, task.y), (Some(300.0), Some(128.0)));
+    assert!(events.contains(&&BpmnEvent::BoundaryTimerEvent(
+        "1 day".to_string(),
+        task.id,
+        false
+    )));
+    assert!(graph
+        .nodes()
+        .all(|node| node.lane.as_deref() == Some("Front")));
+
+    let done = graph.edges.iter().find(|edge| edge.text.is_some()).unwrap();
+    assert_eq!(done.bend_points, Some(vec![(400.0, 168.0), (500.0, 168.0)]));
+    assert_eq!(done.label_bounds, Some((430.0, 150.0, 30.0, 14.0)));
+
+    let error = from_bpmn_xml("<bpmn:definitions>\n<unclosed>").unwrap_err();
+    assert!(error.has_errors());
+    assert!(from_bpmn_xml("<svg />").is_err());
+}
@@ -0,0 +1,267 @@
+use crate::layout::eliminate_back_edges::find_back_edges;
+use crate::layout::longest_path::longest_path_layers;
+use crate::layout::network_simplex::network_simplex_layers;
+use crate::{layout, parse, Layering, LayoutOptions};
+use std::collections::HashMap;
+
+fn total_edge_length(layers: &HashMap<usize, usize>, edges: &[(usize, usize)]) -> usize {
+    edges
+        .iter()
+        .map(|(from, to)| layers[to] - layers[from])
+        .sum()
+}
+
+#[test]
+fn test_network_simplex_shortens_edges() {
+    // Node 5 only leads into the end of the chain, so it belongs right before node 4
+    let nodes = [1, 2, 3, 4, 5];
+    let edges = [(1, 2), (2, 3), (3, 4), (5, 4)];
+
+    let longest_path = longest_path_layers(&nodes, &edges);
+    assert_eq!(longest_path[&5], 0);
+    assert_eq!(total_edge_length(&longest_path, &edges), 6);
+
+    let network_simplex = network_simplex_layers(&nodes, &edges);
+    assert_eq!(network_simplex[&1], 0);
+    assert_eq!(network_simplex[&4], 3);
+    assert_eq!(network_simplex[&5], 2);
+    assert_eq!(total_edge_length(&network_simplex, &edges), 4);
+}
+
+#[test]
+fn test_network_simplex_keeps_every_edge_forward() {
+    // Two branches of different length that meet again, plus a separate part
+    let nodes = [1, 2, 3, 4, 5, 6, 7, 8, 9];
+    let edges = [
+        (1, 2),
+        (2, 3),
+        (3, 4),
+        (4, 7),
+        (2, 5),
+        (5, 6),
+        (6, 7),
+        (1, 6),
+        (8, 9),
+    ];
+
+    let layers = network_simplex_layers(&nodes, &edges);
+    for (from, to) in edges {
+        assert!(
+            layers[&to] > layers[&from],
+            "Edge {} -> {} should point forward",
+            from,
+            to
+        );
+    }
+    assert_eq!(layers[&1], 0);
+    assert_eq!(layers[&8], 0, "Every separate part should start at layer 0");
+    assert!(
+        total_edge_length(&layers, &edges)
+            <= total_edge_length(&longest_path_layers(&nodes, &edges), &edges)
+    );
+}
+
+#[test]
+fn test_layering_ignores_cycles() {
+    let nodes = [1, 2, 3];
+    let edges = [(1, 2), (2, 3), (3, 1)];
+
+    for layers in [
+        longest_path_layers(&nodes, &edges),
+        network_simplex_layers(&nodes, &edges),
+    ] {
+        assert_eq!(layers[&1], 0);
+        assert_eq!(layers[&2], 1);
+        assert_eq!(layers[&3], 2);
+    }
+}
+
+#[test]
+fn test_layering_option_is_used() {
+    let input = r#"
+# Start Event
+- Task A
+X ->Short "Short" ->Long "Long"
+Short:
+- Task B
+J Done
+Long:
+- Task C
+J Done
+X <-Done
+. End Event
+"#;
+
+    let mut options = LayoutOptions::default();
+    assert_eq!(options.layering, Layering::NetworkSimplex);
+
+    for layering in [Layering::NetworkSimplex, Layering::LongestPath] {
+        options.layering = layering;
+        let mut graph = parse(input).expect("Input should parse");
+        layout(&mut graph, &options);
+
+        let layer_of = |id: usize| {
+            graph
+                .nodes()
+                .find(|node| node.id == id)
+                .unwrap()
+                .layer_id
+                .unwrap()
+        };
+        for edge in graph.edges.iter().filter(|edge| edge.from != edge.to) {
+            assert!(
+                layer_of(edge.to) > layer_of(edge.from),
+                "{:?}: edges should point forward",
+                layering
+            );
+        }
+    }
+}
+
+#[test]
+fn test_back_edges_break_every_cycle() {
+    // 2 -> 3 -> 4 -> 2 is a rework loop, 5 -> 5 loops on itself
+    let nodes = [1, 2, 3, 4, 5];
+    let edges = [(1, 2), (2, 3), (3, 4), (4, 2), (4, 5), (5, 5)];
+
+    assert_eq!(find_back_edges(&nodes, &edges), vec![3, 5]);
+}
+
+#[test]
+fn test_rework_loop_is_drawn_over_the_lane() {
+    let input = r#"
+= Pool
+== Team
+# Start
+G <-rework
+- Review
+- Fix
+G ->rework
+"#;
+
+    let mut graph = parse(input).expect("Input should parse");
+    layout(&mut graph, &LayoutOptions::default());
+
+    let back_edge = graph
+        .edges
+        .iter()
+        .find(|edge| edge.is_back_edge)
+        .expect("The jump back should be a back edge");
+    let review = graph.nodes().find(|node| node.id == back_edge.to).unwrap();
+    let fix = graph
+        .nodes()
+        .find(|node| node.id == back_edge.from)
+        .unwrap();
+    assert!(
+        fix.layer_id > review.layer_id,
+        "The back edge should keep its direction"
+    );
+
+    let points = back_edge.bend_points.as_ref().unwrap();
+    let lane = &graph.pools[0].lanes[0];
+    let loop_y = points[1].1;
+    assert!(
+        loop_y > lane.y.unwrap(),
+        "The loop should stay inside the lane"
+    );
+    assert!(
+        graph
+            .nodes()
+            .all(|node| loop_y < node.y.unwrap() + node.y_offset.unwrap()),
+        "The loop should pass over the nodes"
+    );
+}
+
+#[test]
+fn test_lanes_of_a_pool_share_layers() {
+    let input = r#"
+= Pool
+== Lane1
+# Start Event
+- Task
+G ->jump
+== Lane2
+G <-jump
+- Task 2
+. End Event
+"#;
+
+    for layer_across_pools in [false, true] {
+        let options = LayoutOptions {
+            layer_across_pools,
+            ..Default::default()
+        };
+        let mut graph = parse(input).expect("Input should parse");
+        layout(&mut graph, &options);
+
+        let node = |id: usize| graph.nodes().find(|node| node.id == id).unwrap();
+        for edge in graph.edges.iter().filter(|edge| !edge.is_back_edge) {
+            let (from, to) = (node(edge.from), node(edge.to));
+            assert!(
+                to.layer_id > from.layer_id,
+                "Edge {} -> {} should point forward across lanes",
+                edge.from,
+                edge.to
+            );
+            assert!(to.x.unwrap() > from.x.unwrap());
+        }
+    }
+}
+
+#[test]
+fn test_layer_hints_pin_nodes_and_warn_when_they_cannot() {
+    let input = r#"
+# Start
+- Task A
+@layer 4
+- Task B
+. End
+@layer 2
+"#;
+
+    for layering in [Layering::LongestPath, Layering::NetworkSimplex] {
+        let options = LayoutOptions {
+            layering,
+            ..Default::default()
+        };
+        let mut graph = parse(input).expect("Input should parse");
+        let warnings = layout(&mut graph, &options);
+
+        let layer_of = |name: &str| {
+            graph
+                .nodes()
+                .find(|node| node.event.as_ref().and_then(|event| event.label()) == Some(name))
+                .and_then(|node| node.layer_id)
+                .unwrap()
+        };
+        assert_eq!(
+            layer_of("Task A"),
+            3,
+            "Task A should be pinned to the fourth layer"
+        );
+        assert_eq!(layer_of("Task B"), 4, "Task B should follow Task A");
+        assert_eq!(
+            layer_of("End"),
+            5,
+            "End cannot go before the nodes leading to it"
+        );
+
+        let warnings: Vec<_> = warnings.iter().collect();
+        assert_eq!(warnings.len(), 1, "Only the hint on End should warn");
+        assert_eq!(warnings[0].line, Some(7));
+        assert!(warnings[0].message.contains("@layer 2"));
+    }
+
+    // A far away layer goes into the last one the nodes can fill instead of allocating all of them
+    let mut graph = parse("# Start\n- Task\n@layer 100000000000\n").expect("Input should parse");
+    let warnings = layout(&mut graph, &LayoutOptions::default());
+    let task = graph
+        .nodes()
+        .find(|node| node.event.as_ref().and_then(|event| event.label()) == Some("Task"))
+        .unwrap();
+    assert_eq!(task.layer_id, Some(1));
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings
+        .iter()
+        .all(|warning| warning.message.contains("only 2 nodes")));
+}
@@ -0,0 +1,160 @@
+use crate::lsp::analysis::{Analysis, LabelUse};
+use crate::lsp::run;
+use serde_json::{json, Value};
+
+const INPUT: &str = r#"= Pool
+== Lane1
+# Start Event
+X ->above"Go Here" ->below
+above:
+- Above
+J endjoin
+below:
+- Below
+J endjoin
+X <-endjoin
+- Task
+G ->jump
+== Lane2
+G <-jump
+. End Event
+"#;
+
+#[test]
+fn test_lsp_definitions_and_references() {
+    let analysis = Analysis::new(INPUT);
+    assert!(analysis.diagnostics.is_empty(), "Input should parse");
+
+    // `X ->above` goes to `above:`
+    let definitions = analysis.definitions(3, 5);
+    assert_eq!(definitions.len(), 1);
+    assert_eq!((definitions[0].line, definitions[0].start), (4, 0));
+
+    // `J endjoin` goes to the joining gateway
+    let definitions = analysis.definitions(6, 3);
+    assert_eq!(definitions.len(), 1);
+    assert_eq!(definitions[0].line, 10);
+
+    // `G <-jump` goes to where the jump starts
+    let definitions = analysis.definitions(14, 5);
+    assert_eq!(definitions.len(), 1);
+    assert_eq!(definitions[0].line, 12);
+
+    let references = analysis.references(10, 5);
+    assert_eq!(references.len(), 3, "Both joins and the gateway use 'endjoin'");
+}
+
+#[test]
+fn test_lsp_completions_and_diagnostics() {
+    let analysis = Analysis::new(INPUT);
+    assert_eq!(analysis.completions("X ->", 4), vec!["above", "below"]);
+    assert_eq!(analysis.completions("X <-", 4), vec!["endjoin"]);
+    assert_eq!(analysis.completions("G <-", 4), vec!["jump"]);
+    assert!(analysis.completions("- Task", 6).is_empty());
+
+    let broken = Analysis::new("# Start\nX\n- Task\n");
+    assert_eq!(broken.diagnostics.len(), 1);
+    assert_eq!(broken.diagnostics[0].span.line, 1);
+
+    let undefined = Analysis::new("# Start\nX ->nowhere\n");
+    assert_eq!(undefined.diagnostics.len(), 1);
+    assert!(undefined
+        .labels
+        .iter()
+        .any(|label| label.usage == LabelUse::Branch && label.name == "nowhere"));
+}
+
+fn frame(message: Value) -> String {
+    let body = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+fn read_responses(output: &[u8]) -> Vec<Value> {
+    let output = String::from_utf8(output.to_vec()).unwrap();
+    output
+        .split("Content-Length: ")
+        .filter(|part| !part.is_empty())
+        .map(|part| serde_json::from_str(part.split_once("\r\n\r\n").unwrap().1).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_lsp_scripted_session() {
+    let uri = "file:///process.txt";
+    let script = [
+        frame(json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} })),
+        frame(json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} })),
+        frame(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": uri, "languageId": "bpmn", "version": 1, "text": INPUT } }
+        })),
+        frame(json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "textDocument/definition",
+            "params": { "textDocument": { "uri": uri }, "position": { "line": 3, "character": 5 } }
+        })),
+        frame(json!({
+            "jsonrpc": "2.0",
+            "id": 3,
+            "method": "textDocument/documentSymbol",
+            "params": { "textDocument": { "uri": uri } }
+        })),
+        frame(json!({
+            "jsonrpc": "2.0",
+            "id": 4,
+            "method": "textDocument/hover",
+            "params": { "textDocument": { "uri": uri }, "position": { "line": 5, "character": 3 } }
+        })),
+        frame(json!({ "jsonrpc": "2.0", "id": 5, "method": "shutdown" })),
+        frame(json!({ "jsonrpc": "2.0", "method": "exit" })),
+    ]
+    .concat();
+
+    let mut output = Vec::new();
+    let exit_code = run(script.as_bytes(), &mut output).unwrap();
+    assert_eq!(exit_code, 0, "Exit after shutdown should succeed");
+
+    let responses = read_responses(&output);
+    assert_eq!(responses.len(), 6);
+    assert!(responses[0]["result"]["capabilities"]["definitionProvider"]
+        .as_bool()
+        .unwrap());
+    assert_eq!(responses[1]["method"], "textDocument/publishDiagnostics");
+    assert_eq!(responses[1]["params"]["diagnostics"], json!([]));
+    assert_eq!(responses[2]["result"][0]["range"]["start"]["line"], 4);
+
+    let symbols = responses[3]["result"].as_array().unwrap();
+    assert_eq!(symbols.len(), 1);
+    assert_eq!(symbols[0]["name"], "Pool");
+    assert_eq!(symbols[0]["children"].as_array().unwrap().len(), 2);
+    assert_eq!(symbols[0]["children"][0]["children"].as_array().unwrap().len(), 2);
+
+    assert!(responses[4]["result"]["contents"]["value"]
+        .as_str()
+        .unwrap()
+        .contains("Task"));
+}
+
+#[test]
+fn test_lsp_answers_a_broken_message_and_goes_on() {
+    let broken = "{ \"jsonrpc\": \"2.0\", \"id\": ";
+    let script = [
+        format!("Content-Length: {}\r\n\r\n{}", broken.len(), broken),
+        frame(json!({ "jsonrpc": "2.0", "id": 1, "method": "shutdown" })),
+        frame(json!({ "jsonrpc": "2.0", "method": "exit" })),
+    ]
+    .concat();
+
+    let mut output = Vec::new();
+    let exit_code = run(script.as_bytes(), &mut output).unwrap();
+    assert_eq!(exit_code, 0, "The server should still shut down cleanly");
+
+    let responses = read_responses(&output);
+    assert_eq!(responses.len(), 2);
+    assert_eq!(responses[0]["id"], Value::Null);
+    assert_eq!(responses[0]["error"]["code"], -32700);
+    assert_eq!(responses[1]["id"], 1);
+    assert_eq!(responses[1]["result"], Value::Null);
+}
@@ -1,2 +1,26 @@
 #[cfg(test)]
+pub mod api_tests;
+#[cfg(test)]
+pub mod crossing_tests;
+#[cfg(test)]
+pub mod import_tests;
+#[cfg(test)]
+pub mod layering_tests;
+#[cfg(test)]
+pub mod lsp_tests;
+#[cfg(test)]
+pub mod positioning_tests;
+#[cfg(test)]
+pub mod relayout_tests;
+#[cfg(test)]
+pub mod round_trip_tests;
+#[cfg(test)]
+pub mod svg_tests;
+#[cfg(test)]
 pub mod use_cases_tests;
+#[cfg(test)]
+pub mod validate_tests;
+#[cfg(all(test, feature = "wasm"))]
+pub mod wasm_tests;
+#[cfg(test)]
+pub mod xml_tests;
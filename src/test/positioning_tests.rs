@@ -0,0 +1,836 @@
+use crate::common::bpmn_event::{get_node_size, BpmnEvent, TASK_PADDING};
+use crate::common::text_metrics::{text_size, text_width, wrap_text};
+use crate::layout::brandes_koepf::brandes_koepf_positions;
+use crate::layout::node_positioning::{COLLAPSED_POOL_HEIGHT, MIN_LANE_HEIGHT};
+use crate::layout::place_labels::place_edge_labels;
+use crate::{layout, layout_incremental, parse, to_bpmn_xml, LayoutOptions, PreviousLayout};
+use std::collections::HashMap;
+
+#[test]
+fn test_branches_are_balanced_around_the_gateway() {
+    // 2 splits into 3, 4 and 5, which join again in 6
+    let layers = vec![vec![1], vec![2], vec![3, 4, 5], vec![6]];
+    let edges = [(1, 2), (2, 3), (2, 4), (2, 5), (3, 6), (4, 6), (5, 6)];
+    let sizes: HashMap<usize, (f64, f64)> = (1..=6).map(|id| (id, (40.0, 40.0))).collect();
+
+    let positions = brandes_koepf_positions(&layers, &edges, &sizes, 20.0);
+    assert_eq!(positions[&3], 40.0, "The first node should start at 0");
+    assert_eq!(positions[&4] - positions[&3], 100.0);
+    assert_eq!(positions[&5] - positions[&4], 100.0);
+    for id in [1, 2, 6] {
+        assert_eq!(
+            positions[&id], positions[&4],
+            "Node {} should line up with the middle branch",
+            id
+        );
+    }
+}
+
+#[test]
+fn test_nodes_of_a_layer_keep_their_gap() {
+    let layers = vec![vec![1, 2], vec![3, 4, 5]];
+    let edges = [(1, 3), (1, 4), (2, 5)];
+    // A label below a node makes it reach further after its center than before
+    let sizes = HashMap::from([
+        (1, (100.0, 100.0)),
+        (2, (18.0, 50.0)),
+        (3, (40.0, 40.0)),
+        (4, (25.0, 60.0)),
+        (5, (40.0, 40.0)),
+    ]);
+
+    let positions = brandes_koepf_positions(&layers, &edges, &sizes, 20.0);
+    for layer in &layers {
+        for pair in layer.windows(2) {
+            let space =
+                positions[&pair[1]] - sizes[&pair[1]].0 - (positions[&pair[0]] + sizes[&pair[0]].1);
+            assert!(
+                space >= 20.0 - 1e-9,
+                "Nodes {:?} are only {} apart",
+                pair,
+                space
+            );
+        }
+    }
+}
+
+#[test]
+fn test_main_path_is_straight() {
+    let input = r#"
+= Pool
+== Lane
+# Start
+- Task A
+X ->yes "Yes" ->no "No"
+yes:
+- Task B
+J done
+no:
+- Task C
+J done
+X <-done
+- Task D
+. End
+"#;
+
+    let mut graph = parse(input).expect("Input should parse");
+    layout(&mut graph, &LayoutOptions::default());
+
+    // Nodes are numbered in the order they appear in the input
+    let center_y = |id: usize| {
+        let node = graph.nodes().find(|node| node.id == id).unwrap();
+        let (_, height) = get_node_size(node.event.as_ref().unwrap());
+        node.y.unwrap() + node.y_offset.unwrap() + height as f64 / 2.0
+    };
+    let line = center_y(1);
+    for id in [2, 3, 6, 7, 8] {
+        assert_eq!(center_y(id), line, "Node {} should be on the main line", id);
+    }
+    let (upper, lower) = (center_y(4), center_y(5));
+    assert!(upper < line && line < lower);
+    assert_eq!(
+        line - upper,
+        lower - line,
+        "The branches should be balanced around the main line"
+    );
+}
+
+#[test]
+fn test_top_to_bottom_layout() {
+    let input = r#"
+= Pool
+== Lane1
+# Start Event
+- Task
+G ->jump
+== Lane2
+G <-jump
+- Task 2
+. End Event
+"#;
+
+    let mut options = LayoutOptions::default();
+    options.set("orientation", "top-to-bottom").unwrap();
+    let mut graph = parse(input).expect("Input should parse");
+    layout(&mut graph, &options);
+
+    let node = |id: usize| graph.nodes().find(|node| node.id == id).unwrap();
+    for edge in &graph.edges {
+        let (from, to) = (node(edge.from), node(edge.to));
+        assert!(
+            to.y > from.y,
+            "Edge {} -> {} should point down",
+            edge.from,
+            edge.to
+        );
+        let points = edge.bend_points.as_ref().unwrap();
+        assert!(
+            points.last().unwrap().1 < to.y.unwrap() + to.y_offset.unwrap() + 1.0,
+            "Edge {} -> {} should enter from the top",
+            edge.from,
+            edge.to
+        );
+    }
+
+    let lanes = &graph.pools[0].lanes;
+    assert_eq!(lanes[0].y, lanes[1].y, "Lanes should stand side by side");
+    assert!(lanes[0].x.unwrap() + lanes[0].width.unwrap() <= lanes[1].x.unwrap());
+
+    let xml = to_bpmn_xml(&graph);
+    assert!(xml.contains(r#"isHorizontal="false""#));
+    assert!(!xml.contains(r#"isHorizontal="true""#));
+}
+
+#[test]
+fn test_text_is_measured_per_character() {
+    assert_eq!(text_width(""), 0.0);
+    assert!(text_width("iii") < text_width("WWW"));
+    assert_eq!(text_width("ab"), text_width("a") + text_width("b"));
+
+    let lines = wrap_text("Check the incoming invoice", 80.0);
+    assert!(lines.len() > 1);
+    assert!(lines.iter().all(|line| text_width(line) <= 80.0));
+    assert_eq!(wrap_text("Supercalifragilistic", 20.0).len(), 1);
+}
+
+#[test]
+fn test_tasks_grow_to_fit_their_label() {
+    assert_eq!(
+        get_node_size(&BpmnEvent::ActivityTask("Pay".to_string())),
+        (100, 80)
+    );
+
+    let label = "Collect all signed documents from the customer and archive them in the document \
+                 management system before the review meeting with the auditors takes place next week, \
+                 and let the team lead know once everything has been filed";
+    let (width, height) = get_node_size(&BpmnEvent::ActivityTask(label.to_string()));
+    assert!(
+        width > 100 && height > 80,
+        "{}x{} is too small",
+        width,
+        height
+    );
+    let (text_width, text_height) = text_size(label, width as f64 - 2.0 * TASK_PADDING);
+    assert!(text_width + 2.0 * TASK_PADDING <= width as f64);
+    assert!(text_height + 2.0 * TASK_PADDING <= height as f64);
+}
+
+#[test]
+fn test_external_labels_do_not_overlap_nodes() {
+    let input = r#"
+= Pool
+== Lane
+# Start
+X ->a "A" ->b "B"
+a:
+# Waiting for the confirmation of the customer to arrive by mail
+J done
+b:
+- Task B
+J done
+X <-done
+. End
+"#;
+
+    let mut graph = parse(input).expect("Input should parse");
+    layout(&mut graph, &LayoutOptions::default());
+
+    let shape = |node: &crate::common::node::Node| {
+        let (width, height) = get_node_size(node.event.as_ref().unwrap());
+        let x = node.x.unwrap() + node.x_offset.unwrap();
+        let y = node.y.unwrap() + node.y_offset.unwrap();
+        (x, y, width as f64, height as f64)
+    };
+    let overlaps = |(ax, ay, aw, ah): (f64, f64, f64, f64),
+                    (bx, by, bw, bh): (f64, f64, f64, f64)| {
+        ax < bx + bw && bx < ax + aw && ay < by + bh && by < ay + ah
+    };
+    let mut labels = 0;
+    for node in graph.nodes() {
+        let Some(label) = node.label_bounds(graph.orientation) else {
+            continue;
+        };
+        labels += 1;
+        for other in graph.nodes() {
+            assert!(
+                !overlaps(label, shape(other)),
+                "The label of node {} overlaps node {}",
+                node.id,
+                other.id
+            );
+        }
+        let lane = &graph.pools[0].lanes[0];
+        assert!(label.1 + label.3 <= lane.y.unwrap() + lane.height.unwrap());
+    }
+    assert!(labels >= 3, "Start, middle and end events have labels");
+}
+
+#[test]
+fn test_edge_labels_are_placed_apart() {
+    let input = r#"
+= Pool
+== Lane
+# Start
+X ->a "Approved" ->b "Rejected" ->c "Needs more information"
+a:
+- Task A
+J done
+b:
+- Task B
+J done
+c:
+- Task C
+J done
+X <-done
+. End
+"#;
+
+    let mut graph = parse(input).expect("Input should parse");
+    layout(&mut graph, &LayoutOptions::default());
+
+    let overlaps = |(ax, ay, aw, ah): (f64, f64, f64, f64),
+                    (bx, by, bw, bh): (f64, f64, f64, f64)| {
+        ax < bx + bw && bx < ax + aw && ay < by + bh && by < ay + ah
+    };
+    let labels: Vec<_> = graph
+        .edges
+        .iter()
+        .filter(|edge| edge.text.is_some())
+        .map(|edge| edge.label_bounds.expect("Branch texts should be placed"))
+        .collect();
+    assert_eq!(labels.len(), 3);
+    for (index, label) in labels.iter().enumerate() {
+        for other in &labels[index + 1..] {
+            assert!(
+                !overlaps(*label, *other),
+                "{:?} overlaps {:?}",
+                label,
+                other
+            );
+        }
+        for node in graph.nodes() {
+            let (width, height) = get_node_size(node.event.as_ref().unwrap());
+            let x = node.x.unwrap() + node.x_offset.unwrap();
+            let y = node.y.unwrap() + node.y_offset.unwrap();
+            assert!(
+                !overlaps(*label, (x, y, width as f64, height as f64)),
+                "{:?} overlaps node {}",
+                label,
+                node.id
+            );
+        }
+    }
+
+    let xml = to_bpmn_xml(&graph);
+    assert!(xml.contains(r#"name="Needs more information""#));
+    assert_eq!(
+        xml.matches("<bpmndi:BPMNLabel>").count(),
+        5,
+        "Three branch texts and the start and end events should have label bounds"
+    );
+
+    // A route of one point repeated, e.g. from a previous file, gets no label instead of a panic
+    let edge = graph
+        .edges
+        .iter_mut()
+        .find(|edge| edge.text.is_some())
+        .unwrap();
+    edge.bend_points = Some(vec![(10.0, 10.0), (10.0, 10.0)]);
+    place_edge_labels(&mut graph, &LayoutOptions::default());
+    assert_eq!(
+        graph
+            .edges
+            .iter()
+            .filter(|edge| edge.label_bounds.is_some())
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn test_routes_are_orthogonal_and_kept_apart() {
+    let input = r#"
+= Pool
+== Lane1
+# Start
+- Task A
+G ->jump
+== Lane2
+# Second start
+- Task B
+G ->jump
+== Lane3
+G <-jump
+- Task C
+. End
+"#;
+
+    let mut graph = parse(input).expect("Input should parse");
+    layout(&mut graph, &LayoutOptions::default());
+
+    let shapes: Vec<(usize, f64, f64, f64, f64)> = graph
+        .nodes()
+        .map(|node| {
+            let (width, height) = get_node_size(node.event.as_ref().unwrap());
+            let x = node.x.unwrap() + node.x_offset.unwrap();
+            let y = node.y.unwrap() + node.y_offset.unwrap();
+            (node.id, x, y, x + width as f64, y + height as f64)
+        })
+        .collect();
+    let mut segments = Vec::new();
+    for edge in &graph.edges {
+        let points = edge
+            .bend_points
+            .as_ref()
+            .expect("Every edge should be routed");
+        assert!(points.len() <= 4, "{:?} has too many bends", points);
+        for pair in points.windows(2) {
+            let ((x1, y1), (x2, y2)) = (pair[0], pair[1]);
+            assert!(x1 == x2 || y1 == y2, "{:?} is not orthogonal", pair);
+            for &(id, left, top, right, bottom) in &shapes {
+                let passes = x1.min(x2) < right
+                    && x1.max(x2) > left
+                    && y1.min(y2) < bottom
+                    && y1.max(y2) > top;
+                assert!(
+                    !passes,
+                    "Edge {} -> {} runs through node {}",
+                    edge.from, edge.to, id
+                );
+            }
+            segments.push((edge.to, pair[0], pair[1]));
+        }
+    }
+
+    // The two edges into Task C enter it side by side and do not run on top of each other
+    let ends: Vec<(f64, f64)> = graph
+        .edges
+        .iter()
+        .filter(|edge| edge.to == 5)
+        .map(|edge| *edge.bend_points.as_ref().unwrap().last().unwrap())
+        .collect();
+    assert_eq!(ends.len(), 2);
+    assert_ne!(ends[0], ends[1]);
+    for (index, &(_, a1, a2)) in segments.iter().enumerate() {
+        for &(_, b1, b2) in &segments[index + 1..] {
+            let overlap = if a1.0 == a2.0 && b1.0 == b2.0 && a1.0 == b1.0 {
+                a1.1.max(a2.1).min(b1.1.max(b2.1)) - a1.1.min(a2.1).max(b1.1.min(b2.1))
+            } else if a1.1 == a2.1 && b1.1 == b2.1 && a1.1 == b1.1 {
+                a1.0.max(a2.0).min(b1.0.max(b2.0)) - a1.0.min(a2.0).max(b1.0.min(b2.0))
+            } else {
+                0.0
+            };
+            assert!(overlap <= 0.0, "{:?} and {:?} overlap", (a1, a2), (b1, b2));
+        }
+    }
+}
+
+#[test]
+fn test_gateway_edges_get_a_corner_each() {
+    let input = r#"
+= Pool
+== Lane
+# Start
+X ->a "A" ->b "B" ->c "C"
+a:
+- Task A
+J done
+b:
+- Task B
+J done
+c:
+- Task C
+J done
+X <-done
+. End
+"#;
+
+    let mut graph = parse(input).expect("Input should parse");
+    layout(&mut graph, &LayoutOptions::default());
+
+    let corners = |id: usize| {
+        let node = graph.nodes().find(|node| node.id == id).unwrap();
+        let (x, y) = (
+            node.x.unwrap() + node.x_offset.unwrap(),
+            node.y.unwrap() + node.y_offset.unwrap(),
+        );
+        // Top, right, bottom and left corner of the diamond
+        [
+            (x + 25.0, y),
+            (x + 50.0, y + 25.0),
+            (x + 25.0, y + 50.0),
+            (x, y + 25.0),
+        ]
+    };
+    // The split is node 2 and the join node 6, the branches are 3 to 5 from top to bottom
+    let (split, join) = (corners(2), corners(6));
+    let route = |from: usize, to: usize| {
+        let edge = graph
+            .edges
+            .iter()
+            .find(|edge| edge.from == from && edge.to == to)
+            .unwrap();
+        edge.bend_points.clone().unwrap()
+    };
+    for (branch, (start, end)) in [
+        (3, (split[0], join[0])),
+        (4, (split[1], join[3])),
+        (5, (split[2], join[2])),
+    ] {
+        assert_eq!(
+            route(2, branch).first(),
+            Some(&start),
+            "Branch {} should leave from its own corner",
+            branch
+        );
+        assert_eq!(
+            route(branch, 6).last(),
+            Some(&end),
+            "Branch {} should enter the join at its own corner",
+            branch
+        );
+    }
+}
+
+#[test]
+fn test_incremental_layout_keeps_unchanged_nodes() {
+    let input = r#"
+= Pool
+== Lane
+# Start
+- Task A
+X ->a "A" ->b "B"
+a:
+- Task B
+J done
+b:
+- Task C
+J done
+X <-done
+. End
+"#;
+    let edited = input.replace("- Task B\n", "- Task B\n- Task B2\n");
+    let options = LayoutOptions::default();
+    let mut graph = parse(input).expect("Input should parse");
+    layout(&mut graph, &options);
+    let previous = PreviousLayout::from_bpmn(&to_bpmn_xml(&graph)).expect("Output should be read");
+
+    let mut edited_graph = parse(&edited).expect("Edited input should parse");
+    layout_incremental(&mut edited_graph, &options, &previous);
+
+    let shapes = |graph: &crate::Graph| -> HashMap<String, (f64, f64, f64, f64)> {
+        graph
+            .nodes()
+            .filter_map(|node| {
+                let event = node.event.as_ref()?;
+                let (width, height) = get_node_size(event);
+                let x = node.x.unwrap() + node.x_offset.unwrap();
+                let y = node.y.unwrap() + node.y_offset.unwrap();
+                Some((
+                    event.label()?.to_string(),
+                    (x, y, width as f64, height as f64),
+                ))
+            })
+            .collect()
+    };
+    let (before, after) = (shapes(&graph), shapes(&edited_graph));
+    for name in ["Start", "Task A", "Task B", "Task C"] {
+        assert_eq!(
+            before[name], after[name],
+            "{} should stay where it was",
+            name
+        );
+    }
+    let new = after["Task B2"];
+    for (name, other) in &after {
+        let overlaps = new.0 < other.0 + other.2
+            && other.0 < new.0 + new.2
+            && new.1 < other.1 + other.3
+            && other.1 < new.1 + new.3;
+        assert!(name == "Task B2" || !overlaps, "Task B2 overlaps {}", name);
+    }
+
+    let route = |graph: &crate::Graph, from: &str| {
+        let edge = graph.edges.iter().find(|edge| {
+            graph
+                .get_node_by_id(edge.from)
+                .and_then(|node| node.event.as_ref()?.label())
+                == Some(from)
+        });
+        edge.and_then(|edge| edge.bend_points.clone())
+    };
+    assert_eq!(
+        route(&graph, "Start"),
+        route(&edited_graph, "Start"),
+        "Edges between unchanged nodes should keep their route"
+    );
+
+    // Routes in the file that stand still or end away from their nodes are routed again
+    let flow = graph.edges.iter().find(|edge| edge.text.is_some()).unwrap();
+    let element = format!(r#"bpmnElement="Flow_{}_{}""#, flow.from, flow.to);
+    let xml = to_bpmn_xml(&graph);
+    let start = xml.find(&element).unwrap();
+    let start = start + xml[start..].find('>').unwrap() + 1;
+    let end = start + xml[start..].find("<bpmndi:BPMNLabel>").unwrap();
+    for waypoints in [
+        r#"<di:waypoint x="10" y="10" /><di:waypoint x="10" y="10" />"#,
+        r#"<di:waypoint x="5000" y="10" /><di:waypoint x="5000" y="900" />"#,
+    ] {
+        let broken = format!("{}{}{}", &xml[..start], waypoints, &xml[end..]);
+        let previous = PreviousLayout::from_bpmn(&broken).expect("Broken file should be read");
+        let mut edited_graph = parse(&edited).expect("Edited input should parse");
+        layout_incremental(&mut edited_graph, &options, &previous);
+        let points = edited_graph
+            .edges
+            .iter()
+            .find(|edge| edge.text == flow.text)
+            .and_then(|edge| edge.bend_points.clone())
+            .unwrap();
+        assert!(points.iter().all(|&(x, _)| x < 5000.0));
+        assert!(points.iter().any(|&point| point != points[0]));
+    }
+}
+
+#[test]
+fn test_row_and_above_hints_move_nodes_across_the_flow() {
+    let input = r#"
+= Pool
+== Lane
+# Start
+X ->a ->b
+a:
+- Task A
+@below Task B
+J done
+b:
+- Task B
+J done
+X <-done
+- Task C
+@row Task A
+. End
+"#;
+
+    let mut graph = parse(input).expect("Input should parse");
+    let warnings = layout(&mut graph, &LayoutOptions::default());
+    assert!(warnings.is_empty(), "All hints can be kept: {}", warnings);
+
+    let center_y = |name: &str| {
+        let node = graph
+            .nodes()
+            .find(|node| node.event.as_ref().and_then(|event| event.label()) == Some(name))
+            .unwrap();
+        let (_, height) = get_node_size(node.event.as_ref().unwrap());
+        node.y.unwrap() + node.y_offset.unwrap() + height as f64 / 2.0
+    };
+    assert!(
+        center_y("Task A") > center_y("Task B"),
+        "Task A should be below Task B"
+    );
+    assert_eq!(
+        center_y("Task C"),
+        center_y("Task A"),
+        "Task C should share the row of Task A"
+    );
+}
+
+#[test]
+fn test_collapsed_pools_are_bands_without_a_process() {
+    let input = r#"
+= [Customer]
+= Shop
+== Sales
+# Order received
+- Ship order
+. Order shipped
+= [Bank]
+"#;
+
+    let mut graph = parse(input).expect("Input should parse");
+    layout(&mut graph, &LayoutOptions::default());
+    let bounds: Vec<_> = graph
+        .pools
+        .iter()
+        .map(|pool| {
+            let (y, height) = (pool.y.unwrap(), pool.height.unwrap());
+            (
+                pool.collapsed,
+                pool.x.unwrap(),
+                y,
+                pool.width.unwrap(),
+                height,
+            )
+        })
+        .collect();
+    assert_eq!(bounds.iter().filter(|pool| pool.0).count(), 2);
+    let shop = bounds.iter().find(|pool| !pool.0).unwrap();
+    for pool in bounds.iter().filter(|pool| pool.0) {
+        assert_eq!(pool.4, COLLAPSED_POOL_HEIGHT);
+        assert_eq!(
+            (pool.1, pool.3),
+            (shop.1, shop.3),
+            "Bands are as wide as the pool"
+        );
+        let apart = pool.2 + pool.4 <= shop.2 || shop.2 + shop.4 <= pool.2;
+        assert!(apart, "Bands do not overlap the pool");
+    }
+
+    let xml = to_bpmn_xml(&graph);
+    assert_eq!(xml.matches("<bpmn:process ").count(), 1);
+    assert_eq!(xml.matches("processRef=").count(), 1);
+    assert!(xml.contains(r#"name="Customer""#));
+
+    let error = parse("= [Bank]\n# Start\n").unwrap_err();
+    let diagnostic = error.iter().next().unwrap();
+    assert!(diagnostic.message.contains("Collapsed pool 'Bank'"));
+    assert_eq!(diagnostic.line, Some(2));
+}
+
+#[test]
+fn test_collapsed_pools_send_and_receive_messages() {
+    // The bank is written between the pools but drawn below both
+    let input = r#"
+= [Customer]
+G ->order "Order"
+= Shop
+G <-order
+# Order received
+. Invoice sent
+G ->pay "Invoice"
+= [Bank]
+G <-pay
+= Warehouse
+- Pick items
+"#;
+
+    let mut graph = parse(input).expect("Input should parse");
+    let flows: Vec<_> = graph
+        .pool_flows
+        .iter()
+        .map(|flow| (flow.node, flow.pool, flow.to_pool, flow.text.clone()))
+        .collect();
+    assert_eq!(
+        flows,
+        [
+            (1, 0, false, Some("Order".to_string())),
+            (2, 2, true, Some("Invoice".to_string()))
+        ]
+    );
+
+    layout(&mut graph, &LayoutOptions::default());
+    let top = |name: &str| {
+        let pool = graph.pools.iter().find(|pool| pool.get_pool_name() == name);
+        pool.unwrap().y.unwrap()
+    };
+    assert!(top("Customer") < top("Shop"));
+    assert!(top("Shop") < top("Warehouse"));
+    assert!(
+        top("Warehouse") < top("Bank"),
+        "Collapsed pools go around the others"
+    );
+    for flow in &graph.pool_flows {
+        let points = flow.bend_points.as_ref().expect("Messages are routed");
+        let pool = &graph.pools[flow.pool];
+        let band = if flow.to_pool {
+            points.last()
+        } else {
+            points.first()
+        };
+        let y = band.unwrap().1;
+        assert!(y == pool.y.unwrap() || y == pool.y.unwrap() + COLLAPSED_POOL_HEIGHT);
+    }
+
+    let xml = to_bpmn_xml(&graph);
+    assert_eq!(xml.matches("<bpmn:messageFlow ").count(), 2);
+    assert!(xml.contains(r#"sourceRef="Participant_Customer" targetRef="StartEvent_1""#));
+}
+
+#[test]
+fn test_nested_lanes_are_enclosed_by_their_parent() {
+    let input = r#"
+= Company
+== Sales
+# Order received
+- Check order
+== Operations
+=== Warehouse
+- Pick items
+=== Shipping
+- Ship order
+. Order shipped
+"#;
+
+    let mut graph = parse(input).expect("Input should parse");
+    let options = LayoutOptions::default();
+    layout(&mut graph, &options);
+    let pool = &graph.pools[0];
+    let lane = |name: &str| {
+        let lane = pool
+            .lanes
+            .iter()
+            .find(|lane| lane.get_lane() == name)
+            .unwrap();
+        let bounds = (
+            lane.x.unwrap(),
+            lane.y.unwrap(),
+            lane.width.unwrap(),
+            lane.height.unwrap(),
+        );
+        (bounds, lane.parent.clone())
+    };
+    let names: Vec<&String> = pool.lanes.iter().map(|lane| lane.get_lane()).collect();
+    assert_eq!(names, ["Sales", "Operations", "Warehouse", "Shipping"]);
+
+    let (sales, _) = lane("Sales");
+    let (operations, _) = lane("Operations");
+    let (warehouse, parent) = lane("Warehouse");
+    let (shipping, _) = lane("Shipping");
+    assert_eq!(parent.as_deref(), Some("Operations"));
+    assert_eq!(operations.1, warehouse.1);
+    assert_eq!(operations.1 + operations.3, shipping.1 + shipping.3);
+    assert_eq!(warehouse.0, operations.0 + options.pool_header_width);
+    assert_eq!(warehouse.0 + warehouse.2, sales.0 + sales.2);
+    for node in pool.lanes.iter().flat_map(|lane| lane.get_layers()) {
+        assert!(node.x.unwrap() >= warehouse.0 + options.lane_padding);
+    }
+
+    let xml = to_bpmn_xml(&graph);
+    let child_lanes = xml
+        .find(r#"<bpmn:childLaneSet id="LaneSet_Operations">"#)
+        .unwrap();
+    assert!(xml.find(r#"<bpmn:lane id="Lane_Operations""#).unwrap() < child_lanes);
+    assert!(xml.find(r#"<bpmn:lane id="Lane_Shipping""#).unwrap() > child_lanes);
+
+    let error = parse("= Company\n== Sales\n- Check order\n=== Team\n").unwrap_err();
+    let diagnostic = error.iter().next().unwrap();
+    assert!(diagnostic.message.contains("which already has nodes"));
+    assert_eq!(diagnostic.line, Some(4));
+    assert!(parse("= Company\n=== Team\n- Check order\n").is_err());
+
+    // Going back to a lane after nesting lanes in it cannot add nodes to it either
+    let error = parse("= Company\n== Sales\n=== Team\n- A\n== Sales\n# Start\n").unwrap_err();
+    let diagnostic = error.iter().next().unwrap();
+    assert!(diagnostic
+        .message
+        .contains("Lane 'Sales' has lanes nested in it"));
+    assert_eq!(diagnostic.line, Some(6));
+    assert!(parse("= Company\n== Sales\n=== Team\n- A\n== Sales\n=== Team\n- B\n").is_ok());
+
+    // Lanes of the same name under different parents would share their nodes
+    let error =
+        parse("= Company\n== Sales\n=== Team\n- A\n== Support\n=== Team\n- B\n").unwrap_err();
+    let diagnostic = error.iter().next().unwrap();
+    assert!(diagnostic
+        .message
+        .contains("Lane 'Team' is already nested in lane 'Sales'"));
+    assert_eq!(diagnostic.line, Some(6));
+}
+
+#[test]
+fn test_pools_share_their_width_and_empty_lanes_are_kept() {
+    let input = r#"
+= Shop
+== Sales
+# Order received
+- Check order
+- Confirm order
+- Pack order
+. Order done
+== Legal
+= Courier
+== Drivers
+# Pickup
+. Delivered
+"#;
+
+    let mut graph = parse(input).expect("Input should parse");
+    layout(&mut graph, &LayoutOptions::default());
+    let (shop, courier) = (&graph.pools[0], &graph.pools[1]);
+    assert_eq!((shop.x, shop.width), (courier.x, courier.width));
+    for lane in shop.lanes.iter().chain(&courier.lanes) {
+        assert_eq!(
+            lane.x.unwrap() + lane.width.unwrap(),
+            shop.x.unwrap() + shop.width.unwrap()
+        );
+    }
+
+    let legal = shop
+        .lanes
+        .iter()
+        .find(|lane| lane.get_lane() == "Legal")
+        .unwrap();
+    assert!(legal.get_layers().is_empty());
+    assert_eq!(legal.height, Some(MIN_LANE_HEIGHT));
+    // Lanes with nodes reach as far as their nodes and the padding
+    let sales = &shop.lanes[0];
+    let bottom = sales
+        .get_layers()
+        .iter()
+        .filter_map(|node| node.label_bounds(graph.orientation))
+        .map(|(_, y, _, height)| y + height)
+        .fold(0.0, f64::max);
+    let lane_bottom = sales.y.unwrap() + sales.height.unwrap();
+    assert!(lane_bottom >= bottom + LayoutOptions::default().lane_padding);
+    assert!(lane_bottom <= bottom + LayoutOptions::default().lane_padding + 30.0);
+    assert!(to_bpmn_xml(&graph).contains(r#"<bpmn:lane id="Lane_Legal" name="Legal">"#));
+}
@@ -0,0 +1,281 @@
+use crate::common::bpmn_event::{get_node_size, BpmnEvent};
+use crate::{relayout_bpmn_xml, validate_bpmn_xml, LayoutOptions};
+use roxmltree::Document;
+
+// Everything but the diagram, which is what a relayout must leave alone
+fn without_diagram(xml: &str) -> String {
+    let start = xml.find("<bpmndi:BPMNDiagram").unwrap_or(xml.len());
+    let end = xml
+        .find("</bpmndi:BPMNDiagram>")
+        .map_or(start, |end| end + "</bpmndi:BPMNDiagram>".len());
+    format!("{}{}", &xml[..start].trim_end(), &xml[end..])
+}
+
+#[test]
+fn test_relayout_replaces_only_the_diagram() {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<bpmn:definitions xmlns:bpmn="http://www.omg.org/spec/BPMN/20100524/MODEL" xmlns:bpmndi="http://www.omg.org/spec/BPMN/20100524/DI" xmlns:dc="http://www.omg.org/spec/DD/20100524/DC" xmlns:di="http://www.omg.org/spec/DD/20100524/DI" xmlns:camunda="http://camunda.org/schema/1.0/bpmn" id="Definitions_1">
+  <bpmn:collaboration id="Collaboration_1">
+    <bpmn:participant id="Participant_Shop" name="Shop" processRef="Process_Shop" />
+  </bpmn:collaboration>
+  <bpmn:process id="Process_Shop" isExecutable="true" camunda:versionTag="2">
+    <bpmn:extensionElements>
+      <camunda:properties><camunda:property name="owner" value="sales &amp; ops" /></camunda:properties>
+    </bpmn:extensionElements>
+    <bpmn:laneSet id="LaneSet_1">
+      <bpmn:lane id="Lane_Sales" name="Sales">
+        <bpmn:flowNodeRef>Start_1</bpmn:flowNodeRef>
+        <bpmn:flowNodeRef>Task_Check</bpmn:flowNodeRef>
+        <bpmn:flowNodeRef>Timer_1</bpmn:flowNodeRef>
+        <bpmn:flowNodeRef>End_Late</bpmn:flowNodeRef>
+      </bpmn:lane>
+      <bpmn:lane id="Lane_Stock" name="Stock">
+        <bpmn:flowNodeRef>End_1</bpmn:flowNodeRef>
+      </bpmn:lane>
+    </bpmn:laneSet>
+    <bpmn:startEvent id="Start_1" name="Order received" />
+    <bpmn:userTask id="Task_Check" name="Check order" camunda:assignee="sales" />
+    <bpmn:boundaryEvent id="Timer_1" name="1 day" attachedToRef="Task_Check">
+      <bpmn:timerEventDefinition id="TimerDefinition_1" />
+    </bpmn:boundaryEvent>
+    <bpmn:endEvent id="End_1" name="Checked" />
+    <bpmn:endEvent id="End_Late" name="Too late" />
+    <bpmn:sequenceFlow id="Flow_1" sourceRef="Start_1" targetRef="Task_Check" />
+    <bpmn:sequenceFlow id="Flow_2" name="ok" sourceRef="Task_Check" targetRef="End_1" />
+    <bpmn:sequenceFlow id="Flow_3" sourceRef="Timer_1" targetRef="End_Late" />
+  </bpmn:process>
+  <bpmndi:BPMNDiagram id="BPMNDiagram_7">
+    <bpmndi:BPMNPlane id="BPMNPlane_7" bpmnElement="Collaboration_1">
+      <bpmndi:BPMNShape id="Task_Check_di" bpmnElement="Task_Check">
+        <dc:Bounds x="0" y="0" width="0" height="0" />
+      </bpmndi:BPMNShape>
+      <bpmndi:BPMNShape id="Gone_di" bpmnElement="Gone">
+        <dc:Bounds x="10" y="10" width="36" height="36" />
+      </bpmndi:BPMNShape>
+    </bpmndi:BPMNPlane>
+  </bpmndi:BPMNDiagram>
+</bpmn:definitions>
+"#;
+
+    let (result, warnings) =
+        relayout_bpmn_xml(xml, &LayoutOptions::default()).expect("XML should lay out");
+    assert!(warnings.is_empty(), "{}", warnings);
+    assert_eq!(without_diagram(&result), without_diagram(xml));
+    // The file lists no incoming and outgoing flows, which is kept, but the diagram is fixed
+    let problems = |xml: &str| -> Vec<String> {
+        validate_bpmn_xml(xml)
+            .iter()
+            .map(|problem| problem.message.clone())
+            .collect()
+    };
+    let before = problems(xml);
+    assert!(before
+        .iter()
+        .any(|problem| problem.contains("in the diagram")));
+    let after = problems(&result);
+    assert!(after
+        .iter()
+        .all(|problem| problem.contains("does not list")));
+    assert!(after.iter().all(|problem| before.contains(problem)));
+
+    let document = Document::parse(&result).expect("Result should be XML");
+    let diagram = document
+        .descendants()
+        .find(|node| node.has_tag_name("BPMNDiagram"))
+        .unwrap();
+    assert_eq!(diagram.attribute("id"), Some("BPMNDiagram_7"));
+    let mut drawn: Vec<&str> = diagram
+        .descendants()
+        .filter_map(|node| node.attribute("bpmnElement"))
+        .collect();
+    drawn.sort();
+    assert_eq!(
+        drawn,
+        [
+            "Collaboration_1",
+            "End_1",
+            "End_Late",
+            "Flow_1",
+            "Flow_2",
+            "Flow_3",
+            "Lane_Sales",
+            "Lane_Stock",
+            "Participant_Shop",
+            "Start_1",
+            "Task_Check",
+            "Timer_1"
+        ]
+    );
+
+    // The boundary event sits on the bottom border of its task
+    let bounds = |id: &str| -> Vec<f64> {
+        let shape = diagram
+            .descendants()
+            .find(|node| node.attribute("bpmnElement") == Some(id))
+            .unwrap();
+        let bounds = shape.first_element_child().unwrap();
+        ["x", "y", "width", "height"]
+            .iter()
+            .map(|name| bounds.attribute(*name).unwrap().parse().unwrap())
+            .collect()
+    };
+    let (task, timer) = (bounds("Task_Check"), bounds("Timer_1"));
+    assert_eq!(timer[1] + timer[3] / 2.0, task[1] + task[3]);
+    assert!(timer[0] > task[0] && timer[0] + timer[2] < task[0] + task[2]);
+    let flow = diagram
+        .descendants()
+        .find(|node| node.attribute("bpmnElement") == Some("Flow_3"))
+        .unwrap();
+    assert!(
+        flow.children()
+            .filter(|node| node.has_tag_name("waypoint"))
+            .count()
+            >= 2
+    );
+}
+
+#[test]
+fn test_relayout_adds_a_diagram_where_there_is_none() {
+    let xml = r#"<definitions xmlns="http://www.omg.org/spec/BPMN/20100524/MODEL" id="Definitions_1"><process id="Process_1"><startEvent id="Start" /><task id="Task" name="Work" /><sequenceFlow id="Flow" sourceRef="Start" targetRef="Task" /></process></definitions>"#;
+
+    let (result, _) = relayout_bpmn_xml(xml, &LayoutOptions::default()).unwrap();
+    assert!(result.starts_with(&xml[..xml.len() - "</definitions>".len()]));
+    let document = Document::parse(&result).expect("Result should be XML");
+    let plane = document
+        .descendants()
+        .find(|node| node.has_tag_name("BPMNPlane"))
+        .expect("A diagram should be added");
+    assert_eq!(plane.attribute("bpmnElement"), Some("Process_1"));
+    assert_eq!(plane.children().filter(|node| node.is_element()).count(), 3);
+}
+
+#[test]
+fn test_relayout_draws_message_flows_between_pools() {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<bpmn:definitions xmlns:bpmn="http://www.omg.org/spec/BPMN/20100524/MODEL" xmlns:bpmndi="http://www.omg.org/spec/BPMN/20100524/DI" xmlns:dc="http://www.omg.org/spec/DD/20100524/DC" xmlns:di="http://www.omg.org/spec/DD/20100524/DI" id="Definitions_1">
+  <bpmn:collaboration id="Collaboration_1">
+    <bpmn:participant id="Participant_Shop" name="Shop" processRef="Process_Shop" />
+    <bpmn:participant id="Participant_Bank" name="Bank" processRef="Process_Bank" />
+    <bpmn:messageFlow id="Message_Pay" name="Invoice" sourceRef="Task_Ship" targetRef="Task_Charge" />
+  </bpmn:collaboration>
+  <bpmn:process id="Process_Shop">
+    <bpmn:startEvent id="Start_1">
+      <bpmn:outgoing>Flow_1</bpmn:outgoing>
+    </bpmn:startEvent>
+    <bpmn:task id="Task_Ship" name="Ship">
+      <bpmn:incoming>Flow_1</bpmn:incoming>
+    </bpmn:task>
+    <bpmn:sequenceFlow id="Flow_1" sourceRef="Start_1" targetRef="Task_Ship" />
+  </bpmn:process>
+  <bpmn:process id="Process_Bank">
+    <bpmn:task id="Task_Charge" name="Charge">
+      <bpmn:outgoing>Flow_2</bpmn:outgoing>
+    </bpmn:task>
+    <bpmn:endEvent id="End_1">
+      <bpmn:incoming>Flow_2</bpmn:incoming>
+    </bpmn:endEvent>
+    <bpmn:sequenceFlow id="Flow_2" sourceRef="Task_Charge" targetRef="End_1" />
+  </bpmn:process>
+</bpmn:definitions>
+"#;
+
+    let (result, warnings) =
+        relayout_bpmn_xml(xml, &LayoutOptions::default()).expect("XML should lay out");
+    assert!(warnings.is_empty(), "{}", warnings);
+    let problems = validate_bpmn_xml(&result);
+    assert!(problems.is_empty(), "{}", problems);
+
+    let document = Document::parse(&result).unwrap();
+    let waypoints: Vec<(f64, f64)> = document
+        .descendants()
+        .find(|node| node.attribute("bpmnElement") == Some("Message_Pay"))
+        .expect("The message flow should be drawn")
+        .children()
+        .filter(|node| node.has_tag_name("waypoint"))
+        .map(|node| {
+            let number = |name| node.attribute(name).unwrap().parse::<f64>().unwrap();
+            (number("x"), number("y"))
+        })
+        .collect();
+    assert!(waypoints.len() >= 2);
+    assert!(document
+        .descendants()
+        .any(|node| node.has_tag_name("BPMNLabel")
+            && node.parent().and_then(|edge| edge.attribute("bpmnElement"))
+                == Some("Message_Pay")));
+}
+
+#[test]
+fn test_relayout_keeps_the_planes_of_collapsed_sub_processes() {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<bpmn:definitions xmlns:bpmn="http://www.omg.org/spec/BPMN/20100524/MODEL" xmlns:bpmndi="http://www.omg.org/spec/BPMN/20100524/DI" xmlns:dc="http://www.omg.org/spec/DD/20100524/DC" xmlns:di="http://www.omg.org/spec/DD/20100524/DI" id="Definitions_1">
+  <bpmn:process id="Process_1">
+    <bpmn:startEvent id="Start_1">
+      <bpmn:outgoing>Flow_1</bpmn:outgoing>
+    </bpmn:startEvent>
+    <bpmn:subProcess id="Sub_1" name="Pack order">
+      <bpmn:incoming>Flow_1</bpmn:incoming>
+      <bpmn:startEvent id="Sub_Start">
+        <bpmn:outgoing>Sub_Flow</bpmn:outgoing>
+      </bpmn:startEvent>
+      <bpmn:endEvent id="Sub_End">
+        <bpmn:incoming>Sub_Flow</bpmn:incoming>
+      </bpmn:endEvent>
+      <bpmn:sequenceFlow id="Sub_Flow" sourceRef="Sub_Start" targetRef="Sub_End" />
+    </bpmn:subProcess>
+    <bpmn:sequenceFlow id="Flow_1" sourceRef="Start_1" targetRef="Sub_1" />
+  </bpmn:process>
+  <bpmndi:BPMNDiagram id="BPMNDiagram_1">
+    <bpmndi:BPMNPlane id="BPMNPlane_1" bpmnElement="Process_1">
+      <bpmndi:BPMNShape id="Start_1_di" bpmnElement="Start_1">
+        <dc:Bounds x="0" y="0" width="36" height="36" />
+      </bpmndi:BPMNShape>
+    </bpmndi:BPMNPlane>
+  </bpmndi:BPMNDiagram>
+  <bpmndi:BPMNDiagram id="BPMNDiagram_Sub">
+    <bpmndi:BPMNPlane id="BPMNPlane_Sub" bpmnElement="Sub_1">
+      <bpmndi:BPMNShape id="Sub_Start_di" bpmnElement="Sub_Start">
+        <dc:Bounds x="180" y="160" width="36" height="36" />
+      </bpmndi:BPMNShape>
+      <bpmndi:BPMNShape id="Sub_End_di" bpmnElement="Sub_End">
+        <dc:Bounds x="280" y="160" width="36" height="36" />
+      </bpmndi:BPMNShape>
+      <bpmndi:BPMNEdge id="Sub_Flow_di" bpmnElement="Sub_Flow">
+        <di:waypoint x="216" y="178" />
+        <di:waypoint x="280" y="178" />
+      </bpmndi:BPMNEdge>
+    </bpmndi:BPMNPlane>
+  </bpmndi:BPMNDiagram>
+</bpmn:definitions>
+"#;
+
+    let (result, _) =
+        relayout_bpmn_xml(xml, &LayoutOptions::default()).expect("XML should lay out");
+    let problems = validate_bpmn_xml(&result);
+    assert!(problems.is_empty(), "{}\n{}", problems, result);
+
+    // The drill-down plane of the sub-process is kept as it was
+    let start = xml
+        .find(r#"  <bpmndi:BPMNDiagram id="BPMNDiagram_Sub">"#)
+        .unwrap();
+    let end = xml.rfind("</bpmndi:BPMNDiagram>").unwrap();
+    assert!(result.contains(&xml[start..end]));
+
+    // The sub-process is drawn collapsed, as large as a task
+    let document = Document::parse(&result).unwrap();
+    let shape = document
+        .descendants()
+        .find(|node| node.attribute("bpmnElement") == Some("Sub_1"))
+        .expect("The sub-process should be drawn");
+    assert_eq!(shape.attribute("isExpanded"), Some("false"));
+    let bounds = shape.first_element_child().unwrap();
+    let size = get_node_size(&BpmnEvent::ActivityTask("Pack order".to_string()));
+    assert_eq!(
+        bounds.attribute("width"),
+        Some(format!("{:.2}", size.0 as f64).as_str())
+    );
+    assert_eq!(
+        bounds.attribute("height"),
+        Some(format!("{:.2}", size.1 as f64).as_str())
+    );
+}
@@ -0,0 +1,228 @@
+use crate::{bpmn_to_dsl, dsl_to_bpmn, parse, to_dsl, Graph, LayoutOptions};
+
+/// Describes every node by its element, lane, pool and neighbours, so two graphs can be compared
+/// without depending on ids or order
+pub(crate) fn signature(graph: &Graph) -> Vec<String> {
+    let name = |id: usize| {
+        let node = graph.get_node_by_id(id).unwrap();
+        format!("{:?} in {:?}", node.event.as_ref().unwrap(), node.lane)
+    };
+    let mut lines: Vec<String> = graph
+        .nodes()
+        .map(|node| {
+            let mut incoming: Vec<String> = graph
+                .edges
+                .iter()
+                .filter(|edge| edge.to == node.id)
+                .map(|edge| format!("{} {:?}", name(edge.from), edge.text))
+                .collect();
+            incoming.sort();
+            let mut outgoing: Vec<String> = graph
+                .edges
+                .iter()
+                .filter(|edge| edge.from == node.id)
+                .map(|edge| format!("{} {:?}", name(edge.to), edge.text))
+                .collect();
+            outgoing.sort();
+            format!(
+                "{} of {:?} from {:?} to {:?}",
+                name(node.id),
+                node.pool,
+                incoming,
+                outgoing
+            )
+        })
+        .collect();
+    lines.sort();
+    for pool in &graph.pools {
+        let lanes: Vec<(&String, Option<&String>)> = pool
+            .lanes
+            .iter()
+            .map(|lane| (lane.get_lane(), lane.parent.as_ref()))
+            .collect();
+        lines.push(format!(
+            "{} {} {:?}",
+            pool.get_pool_name(),
+            pool.collapsed,
+            lanes
+        ));
+    }
+    lines
+}
+
+fn assert_round_trip(input: &str) {
+    let graph = parse(input).expect("Input should parse");
+    let (dsl, warnings) = to_dsl(&graph);
+    assert!(warnings.is_empty(), "{}", warnings);
+    let again = parse(&dsl).unwrap_or_else(|e| panic!("{}\n{}", e, dsl));
+    assert_eq!(signature(&again), signature(&graph), "\n{}", dsl);
+    assert_eq!(
+        to_dsl(&again).0,
+        dsl,
+        "Writing again should give the same text"
+    );
+}
+
+#[test]
+fn test_round_trip_keeps_branches_and_joins() {
+    assert_round_trip(
+        r#"
+# Start
+X ->yes "Approved" ->no "Rejected"
+yes:
+- Ship order
+J done
+no:
+- Notify customer
+J done
+X <-done
+. End
+"#,
+    );
+    assert_round_trip(
+        r#"
+# Start
++ ->a ->b
+a:
+- Pack
+X ->big "Big" ->small "Small"
+big:
+- Use a truck @above(Pack)
+J parcel
+small:
+- Use a bike
+J parcel
+X <-parcel
+J packed
+b:
+- Write invoice
+J packed
++ <-packed
+. Done
+"#,
+    );
+}
+
+#[test]
+fn test_round_trip_keeps_lanes_and_jumps() {
+    assert_round_trip(
+        r#"
+= Pool
+== Lane1
+# Start Event
+- Task
+G ->jump
+== Lane2
+G <-jump
+- Task
+. End Event
+"#,
+    );
+    assert_round_trip(
+        r#"
+= Shop
+== Sales
+# Order received
+G <-again
+- Check order
+X ->ok "Complete" ->no "Incomplete"
+no:
+- Ask customer
+G ->again
+J end
+ok:
+- Ship order
+. Shipped
+J end
+"#,
+    );
+}
+
+#[test]
+fn test_to_dsl_writes_pools_and_nested_lanes() {
+    let input = r#"
+= Shop
+== Operations
+=== Front
+# Order placed
+- Take payment
+=== Back
+- Pack order
+. Sent
+= [Bank]
+"#;
+    let (dsl, warnings) = to_dsl(&parse(input).unwrap());
+    assert!(warnings.is_empty());
+    assert_eq!(dsl, input.trim_start());
+}
+
+#[test]
+fn test_to_dsl_writes_messages_of_collapsed_pools() {
+    let input = r#"
+= [Customer]
+G ->order_received "Order"
+= Shop
+G <-order_received
+# Order received
+- Ship
+. Invoice sent
+G ->bank "Invoice"
+= [Bank]
+G <-bank
+"#;
+    let (dsl, warnings) = to_dsl(&parse(input).unwrap());
+    assert!(warnings.is_empty());
+    assert_eq!(dsl, input.trim_start());
+
+    let xml = dsl_to_bpmn(input, &LayoutOptions::default()).unwrap();
+    let (dsl, _) = bpmn_to_dsl(&xml).expect("Output should import");
+    assert_eq!(dsl, input.trim_start());
+}
+
+#[test]
+fn test_bpmn_to_dsl_warns_about_what_it_changes() {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<bpmn:definitions xmlns:bpmn="http://www.omg.org/spec/BPMN/20100524/MODEL" id="Definitions_1">
+  <bpmn:process id="Process_1">
+    <bpmn:startEvent id="Start_1" name="Order #12" />
+    <bpmn:userTask id="Task_1" name="Check order" />
+    <bpmn:boundaryEvent id="Timer_1" name="1 day" attachedToRef="Task_1">
+      <bpmn:timerEventDefinition id="Timer_Definition_1" />
+    </bpmn:boundaryEvent>
+    <bpmn:endEvent id="End_1" name="Checked" />
+    <bpmn:endEvent id="End_2" name="Late" />
+    <bpmn:sequenceFlow id="Flow_1" sourceRef="Start_1" targetRef="Task_1" />
+    <bpmn:sequenceFlow id="Flow_2" sourceRef="Task_1" targetRef="End_1" />
+    <bpmn:sequenceFlow id="Flow_3" sourceRef="Timer_1" targetRef="End_2" />
+  </bpmn:process>
+</bpmn:definitions>"#;
+
+    let (dsl, warnings) = bpmn_to_dsl(xml).expect("XML should import");
+    assert_eq!(dsl, "# Order ＃12\n- Check order\n. Checked\n=\n. Late\n");
+    let messages: Vec<&str> = warnings.iter().map(|w| w.message.as_str()).collect();
+    assert_eq!(
+        messages,
+        [
+            "The user task 'Check order' was written as a plain task",
+            "The boundary event '1 day' cannot be written in the DSL and was left out with its flows",
+            "The name 'Order #12' was written as 'Order ＃12', the DSL cannot hold '-', '.', '#' or '\"' in names",
+        ]
+    );
+
+    // Elements without a name are called by their id
+    let unnamed = xml.replace(r#" name="1 day""#, "");
+    let (_, warnings) = bpmn_to_dsl(&unnamed).expect("XML should import");
+    assert!(warnings.iter().any(|warning| warning.message
+        == "The boundary event 'Timer_1' cannot be written in the DSL and was left out with its flows"));
+}
+
+#[test]
+fn test_bpmn_to_dsl_keeps_jumps_between_pools() {
+    let input = "= Shop\n# Order\n- Ship\nG ->pay\n= Bank\nG <-pay\n- Charge\n. Charged\n";
+    let xml = dsl_to_bpmn(input, &LayoutOptions::default()).unwrap();
+    let (dsl, warnings) = bpmn_to_dsl(&xml).expect("Output should import");
+    assert!(warnings.is_empty(), "{}", warnings);
+    let graph = parse(input).unwrap();
+    let again = parse(&dsl).unwrap_or_else(|e| panic!("{}\n{}", e, dsl));
+    assert_eq!(signature(&again), signature(&graph), "\n{}", dsl);
+}
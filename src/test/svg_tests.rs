@@ -0,0 +1,32 @@
+use crate::{dsl_to_svg, LayoutOptions};
+
+#[test]
+fn test_svg_renders_nodes_and_pools() {
+    let input = r#"
+= Pool
+== Lane
+# Start Event
+- Review order
+. End Event
+"#;
+
+    let svg = dsl_to_svg(input, &LayoutOptions::default()).expect("Input should render");
+
+    assert!(svg.starts_with("<svg"), "Output should be an SVG document");
+    assert!(svg.contains("Review"), "Task label should be rendered");
+    assert_eq!(svg.matches("<polyline").count(), 2, "Both edges should be drawn");
+}
+
+#[test]
+fn test_svg_returns_parse_error() {
+    let input = r#"
+# Start Event
+X
+- Task
+"#;
+
+    let diagnostics = dsl_to_svg(input, &LayoutOptions::default())
+        .expect_err("Unparseable input should not render");
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics.iter().next().unwrap().line, Some(3));
+}
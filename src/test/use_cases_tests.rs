@@ -1,7 +1,9 @@
 use crate::common::bpmn_event::BpmnEvent;
 use crate::lexer::Lexer;
 use crate::parser::Parser;
+use crate::test::validate_tests::assert_valid_bpmn;
 use crate::to_xml::generate_bpmn;
+use crate::{layout, LayoutOptions};
 
 #[test]
 fn test_uc1_define_bpmn_elements() {
@@ -38,15 +40,14 @@ X<-JoinPoint
     let graph = parse_result.unwrap();
 
     // Verify that BPMN elements have been defined and added to the graph
-    assert!(!graph.nodes.is_empty(), "Graph should contain nodes");
+    assert!(graph.nodes().next().is_some(), "Graph should contain nodes");
     assert!(!graph.edges.is_empty(), "Graph should contain edges");
 
     // Additional checks for specific nodes and connections
 
     // Find the start event node
     let start_event = graph
-        .nodes
-        .iter()
+        .nodes()
         .find(|n| matches!(n.event, Some(BpmnEvent::Start(_))))
         .expect("There should be a Start Event");
 
@@ -59,8 +60,7 @@ X<-JoinPoint
 
     // Similarly for the end event
     let end_event = graph
-        .nodes
-        .iter()
+        .nodes()
         .find(|n| matches!(n.event, Some(BpmnEvent::End(_))))
         .expect("There should be an End Event");
 
@@ -72,8 +72,7 @@ X<-JoinPoint
 
     // Checks for other types of events
     let gateway_event = graph
-        .nodes
-        .iter()
+        .nodes()
         .find(|n| matches!(n.event, Some(BpmnEvent::GatewayExclusive)))
         .expect("There should be an Exclusive Gateway");
 
@@ -84,14 +83,16 @@ X<-JoinPoint
     );
 
     let tasks = graph
-        .nodes
-        .iter()
+        .nodes()
         .filter(|n| matches!(n.event, Some(BpmnEvent::ActivityTask(_))))
         .collect::<Vec<_>>();
     assert!(
         !tasks.is_empty(),
         "There should be Activity Tasks in the graph"
     );
+
+    // The BPMN written for it has to be valid
+    assert_valid_bpmn(&generate_bpmn(&graph));
 }
 
 #[test]
@@ -138,4 +139,77 @@ X<-JoinGateway
         !bpmn_xml.is_empty(),
         "BPMN XML should be generated based on the input"
     );
+    assert_valid_bpmn(&bpmn_xml);
+}
+
+#[test]
+fn test_uc3_model_collaboration() {
+    // UC3: Model several participants that exchange messages
+
+    // Pools with nested lanes, a jump between two pools and collapsed pools that send and receive
+    let input = r#"
+= [Customer]
+G ->order "Order"
+= Shop
+== Sales
+G <-order
+# Order received
+- Check order
+G ->pick
+== Operations
+=== Warehouse
+G <-pick
+- Pick items
+=== Shipping
+- Ship order
+G ->pick_up "Parcel"
+= Carrier
+G <-pick_up
+# Parcel handed over
+- Deliver parcel
+. Parcel delivered
+G ->pay "Invoice"
+= [Bank]
+G <-pay
+"#;
+
+    // Initialize the lexer and parser
+    let lexer = Lexer::new(input);
+    let mut parser = Parser::new(lexer).expect("Failed to create parser");
+
+    // Parse the input
+    let parse_result = parser.parse();
+    assert!(
+        parse_result.is_ok(),
+        "Parser should successfully process the UC3 input"
+    );
+    let mut graph = parse_result.unwrap();
+
+    // Verify the participants, lanes and messages
+    assert_eq!(graph.pools.len(), 4, "There should be four pools");
+    assert_eq!(
+        graph.pools.iter().filter(|pool| pool.collapsed).count(),
+        2,
+        "Customer and Bank should be collapsed"
+    );
+    let shop = &graph.pools[1];
+    let warehouse = shop
+        .lanes
+        .iter()
+        .find(|lane| lane.get_lane() == "Warehouse")
+        .expect("There should be a Warehouse lane");
+    assert_eq!(warehouse.parent.as_deref(), Some("Operations"));
+    assert_eq!(
+        graph.pool_flows.len(),
+        2,
+        "The collapsed pools should send one message and receive one"
+    );
+
+    // Lay out and generate BPMN, which has to be valid
+    layout(&mut graph, &LayoutOptions::default());
+    let bpmn_xml = generate_bpmn(&graph);
+    assert_eq!(bpmn_xml.matches("<bpmn:participant ").count(), 4);
+    // The jump from the Shop to the Carrier is a message flow as well
+    assert_eq!(bpmn_xml.matches("<bpmn:messageFlow ").count(), 3);
+    assert_valid_bpmn(&bpmn_xml);
 }
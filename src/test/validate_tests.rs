@@ -0,0 +1,99 @@
+use crate::{dsl_to_bpmn, validate_bpmn_xml, LayoutOptions};
+
+/// Fails with every problem the validator finds in the XML
+pub(crate) fn assert_valid_bpmn(xml: &str) {
+    let problems = validate_bpmn_xml(xml);
+    assert!(problems.is_empty(), "Invalid BPMN:\n{}", problems);
+}
+
+const INPUT: &str = r#"
+= [Customer]
+= Shop
+== Sales
+# Order received
+- Check order
+X ->stock "In stock" ->none "Sold out"
+stock:
+- Ship order
+J done
+none:
+- Refund
+J done
+X <-done
+. Order closed
+== Stock
+- Count items
+= [Bank]
+"#;
+
+#[test]
+fn test_generated_bpmn_is_valid() {
+    let options = LayoutOptions::default();
+    assert_valid_bpmn(&dsl_to_bpmn(INPUT, &options).unwrap());
+    assert_valid_bpmn(&dsl_to_bpmn("# Start\n- Task\n# Wait\n. End", &options).unwrap());
+
+    // Jumps between pools are message flows, which flow nodes do not list as incoming or outgoing
+    let jump = "= Shop\n# Order\n- Ship\nG ->pay\n= Bank\nG <-pay\n- Charge\n. Charged\n";
+    let xml = dsl_to_bpmn(jump, &options).unwrap();
+    assert_eq!(xml.matches("<bpmn:messageFlow ").count(), 1);
+    assert_valid_bpmn(&xml);
+
+    // So are the jumps of collapsed pools, which start or end at the participant
+    let pools = "= [Customer]\nG ->order\n= Shop\nG <-order\n# Order\n. Billed\nG ->pay\n= [Bank]\nG <-pay\n";
+    let xml = dsl_to_bpmn(pools, &options).unwrap();
+    assert_eq!(xml.matches("<bpmn:messageFlow ").count(), 2);
+    assert_valid_bpmn(&xml);
+}
+
+#[test]
+fn test_validation_finds_every_kind_of_problem() {
+    let xml = dsl_to_bpmn(INPUT, &LayoutOptions::default()).unwrap();
+    let problems = |broken: String| -> Vec<String> {
+        validate_bpmn_xml(&broken)
+            .iter()
+            .map(|problem| problem.message.clone())
+            .collect()
+    };
+
+    let duplicate = xml.replace(r#"id="Lane_Stock""#, r#"id="Lane_Sales""#);
+    assert!(problems(duplicate).contains(&"The id 'Lane_Sales' is used more than once".to_string()));
+
+    let invalid = xml.replace("Participant_Bank", "Participant:Bank");
+    assert!(
+        problems(invalid).contains(&"The id 'Participant:Bank' is not a valid NCName".to_string())
+    );
+
+    let unresolved = xml.replace(r#"targetRef="Activity_2""#, r#"targetRef="Activity_99""#);
+    let found = problems(unresolved);
+    assert!(
+        found.contains(&"The targetRef 'Activity_99' of 'sequenceFlow' does not exist".to_string())
+    );
+    assert!(found.contains(
+        &"'Activity_2' lists 'Flow_1_2' as incoming, but it is not a sequence flow with targetRef 'Activity_2'".to_string()
+    ));
+
+    let unlisted = xml.replacen("<bpmn:outgoing>Flow_1_2</bpmn:outgoing>", "", 1);
+    assert_eq!(
+        problems(unlisted),
+        ["'StartEvent_1' does not list the sequence flow 'Flow_1_2' as outgoing"]
+    );
+
+    // A flow moved into a process of its own leaves the nodes it connects behind
+    let split = xml.replacen(
+        "<bpmn:sequenceFlow id=\"Flow_1_2\"",
+        "</bpmn:process>\n  <bpmn:process id=\"Process_Other\">\n    <bpmn:sequenceFlow id=\"Flow_1_2\"",
+        1,
+    );
+    let found = problems(split);
+    assert!(found
+        .iter()
+        .any(|problem| problem.starts_with("The sequence flow 'Flow_1_2' leaves its process")));
+
+    let undrawn = xml.replace(r#"bpmnElement="Lane_Stock""#, r#"bpmnElement="Lane_Sales""#);
+    assert!(problems(undrawn)
+        .contains(&"The lane 'Lane_Stock' has no shape or edge in the diagram".to_string()));
+
+    let broken = validate_bpmn_xml("<bpmn:definitions>");
+    assert_eq!(broken.len(), 1);
+    assert!(broken.iter().all(|problem| problem.line.is_some()));
+}
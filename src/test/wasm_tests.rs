@@ -0,0 +1,51 @@
+use crate::wasm::diagnostics_to_json;
+use crate::{parse, Diagnostic, Diagnostics};
+use serde_json::json;
+
+#[test]
+fn test_diagnostics_are_objects_with_severity_and_position() {
+    let diagnostics: Diagnostics = [
+        Diagnostic {
+            line: Some(3),
+            column: Some(7),
+            ..Diagnostic::warning("Layout hint '@layer 2' cannot be kept")
+        },
+        Diagnostic::error("Something broke"),
+    ]
+    .into_iter()
+    .collect();
+
+    assert_eq!(
+        diagnostics_to_json(&diagnostics),
+        json!([
+            {
+                "severity": "warning",
+                "message": "Layout hint '@layer 2' cannot be kept",
+                "line": 3,
+                "column": 7
+            },
+            {
+                "severity": "error",
+                "message": "Something broke",
+                "line": null,
+                "column": null
+            }
+        ])
+    );
+    assert_eq!(diagnostics_to_json(&Diagnostics::default()), json!([]));
+}
+
+#[test]
+fn test_parse_errors_keep_their_line_in_json() {
+    let diagnostics = parse("# Start\n- Task\nJ end\n").unwrap_err();
+    let json = diagnostics_to_json(&diagnostics);
+    let problems = json.as_array().expect("Diagnostics are an array");
+    assert!(!problems.is_empty());
+    for (problem, diagnostic) in problems.iter().zip(&diagnostics) {
+        assert_eq!(problem["severity"], "error");
+        assert_eq!(problem["message"], diagnostic.message.as_str());
+        assert_eq!(problem["line"], json!(diagnostic.line));
+        assert_eq!(problem["column"], json!(diagnostic.column));
+    }
+    assert!(problems[0]["line"].is_u64(), "Parse errors have a line");
+}
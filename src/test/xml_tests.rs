@@ -0,0 +1,140 @@
+use super::round_trip_tests::signature;
+use super::validate_tests::assert_valid_bpmn;
+use crate::xml_writer::XmlWriter;
+use crate::{from_bpmn_xml, layout, parse, to_bpmn_xml, BpmnEvent, LayoutOptions};
+use roxmltree::Document;
+
+#[test]
+fn test_xml_holds_every_element_and_any_name() {
+    let input = r#"
+= Shop & Co
+== Sales team
+# Order received
+- Check amount
++ ->a ->b
+a:
+- Pack
+# Wait for pickup
+J packed
+b:
+X ->big "Amount > 100" ->small "Amount <= 100"
+big:
+- Ask manager
+J approved
+small:
+- Approve
+J approved
+X <-approved
+J packed
++ <-packed
+. Order done
+== Warehouse
+- Count stock
+"#;
+    let mut graph = parse(input).expect("Input should parse");
+    let task = graph.pools[0].lanes[0]
+        .get_layers_mut()
+        .iter_mut()
+        .find(|node| node.event == Some(BpmnEvent::ActivityTask("Check amount".to_string())))
+        .unwrap();
+    let name = "Check <amount> & \"limits\"\n";
+    task.event = Some(BpmnEvent::ActivityTask(name.to_string()));
+    layout(&mut graph, &LayoutOptions::default());
+
+    let xml = to_bpmn_xml(&graph);
+    assert_valid_bpmn(&xml);
+    let document = Document::parse(&xml).expect("Output should be XML");
+    let ids: Vec<&str> = document
+        .descendants()
+        .filter_map(|node| node.attribute("id"))
+        .collect();
+    assert!(ids.contains(&"Participant_Shop___Co"));
+    assert!(ids.contains(&"Lane_Sales_team"));
+    let mut unique = ids.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(unique.len(), ids.len(), "Ids should be unique");
+    // Only lanes from the DSL are written, not the one of nodes outside of any lane
+    assert!(!xml.contains("default_lane"));
+    // Every flow is drawn once, in the diagram and not in the process
+    let process = document
+        .descendants()
+        .find(|node| node.has_tag_name("process"))
+        .unwrap();
+    assert!(!process
+        .descendants()
+        .any(|node| node.has_tag_name("BPMNEdge")));
+    assert_eq!(
+        document
+            .descendants()
+            .filter(|node| node.has_tag_name("BPMNEdge"))
+            .count(),
+        graph.edges.len()
+    );
+
+    let (imported, warnings) = from_bpmn_xml(&xml).expect("Output should import");
+    assert!(warnings.is_empty(), "{}", warnings);
+    assert_eq!(signature(&imported), signature(&graph));
+    assert!(imported
+        .nodes()
+        .any(|node| node.event == Some(BpmnEvent::ActivityTask(name.to_string()))));
+    assert_eq!(
+        xml,
+        to_bpmn_xml(&graph),
+        "Output should not depend on hashing"
+    );
+}
+
+#[test]
+fn test_xml_writer_indents_escapes_and_checks_prefixes() {
+    let mut xml = XmlWriter::new(Vec::new());
+    xml.declaration().unwrap();
+    xml.element("a:root")
+        .namespace("a", "urn:a")
+        .attribute("note", "1 < 2 & \"3\"\tfour")
+        .start()
+        .unwrap();
+    xml.element("a:empty").start().unwrap();
+    xml.end().unwrap();
+    xml.element("a:text").text("x > y\u{7}").unwrap();
+    xml.element("b:child")
+        .attribute("id", 1)
+        .empty()
+        .unwrap_err();
+    xml.element("b:child")
+        .namespace("b", "urn:b")
+        .optional_attribute("id", None::<&str>)
+        .empty()
+        .unwrap();
+    xml.element("b:child").empty().unwrap_err();
+    xml.end().unwrap();
+    let out = String::from_utf8(xml.finish().unwrap()).unwrap();
+    assert_eq!(
+        out,
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<a:root xmlns:a="urn:a" note="1 &lt; 2 &amp; &quot;3&quot;&#9;four">
+  <a:empty></a:empty>
+  <a:text>x &gt; y</a:text>
+  <b:child xmlns:b="urn:b" />
+</a:root>
+"#
+    );
+
+    let mut xml = XmlWriter::new(Vec::new());
+    xml.element("root").start().unwrap();
+    assert!(xml.finish().is_err(), "Open elements should be an error");
+
+    // A fragment is indented to where it goes and knows the prefixes declared around it
+    let mut xml = XmlWriter::fragment(Vec::new(), "    ", &["a"]);
+    xml.element("a:outer").start().unwrap();
+    xml.element("a:inner")
+        .attribute("name", "<a>")
+        .empty()
+        .unwrap();
+    xml.end().unwrap();
+    let out = String::from_utf8(xml.finish().unwrap()).unwrap();
+    assert_eq!(
+        out,
+        "    <a:outer>\n      <a:inner name=\"&lt;a&gt;\" />\n    </a:outer>\n"
+    );
+}
@@ -0,0 +1,913 @@
+// to_dsl.rs
+use crate::common::bpmn_event::BpmnEvent;
+use crate::common::graph::Graph;
+use crate::common::node::Node;
+use crate::diagnostics::Diagnostic;
+use crate::to_xml::get_node_bpmn_id;
+use std::collections::{HashMap, HashSet};
+
+// Characters the lexer ends a name at, with look-alikes that keep the name readable
+const RESERVED: &[(char, char)] = &[('-', '‐'), ('.', '․'), ('#', '＃'), ('"', '\'')];
+
+/// Writes a graph as DSL text. Chains of nodes become lines, splitting gateways get a labeled block
+/// for every branch that ends in a `J` to the gateway joining them, and flows the DSL has no other
+/// way to write, like ones between lanes or back to an earlier node, become `G` jumps.
+/// Nodes the DSL cannot hold are written as the closest symbol or left out, with a warning.
+pub fn write_dsl(graph: &Graph) -> (String, Vec<Diagnostic>) {
+    write_dsl_with_ids(graph, &HashMap::new())
+}
+
+/// Writes a graph like [`write_dsl`], with the XML ids of its nodes to name the ones without a
+/// name in warnings
+pub(crate) fn write_dsl_with_ids(
+    graph: &Graph,
+    ids: &HashMap<usize, String>,
+) -> (String, Vec<Diagnostic>) {
+    let mut writer = Writer::new(graph, ids);
+    writer.write();
+    (writer.render(), writer.warnings)
+}
+
+/// What is written, nodes are kept apart so a `G <-` can still be put in front of them once a
+/// jump to them is found further on
+enum Item {
+    Line(String),
+    Node(usize, String),
+}
+
+/// A label block being written for a branch of a splitting gateway
+#[derive(Clone, Copy)]
+struct Branch {
+    owner: usize,         // Gateway the branch leaves from
+    merge: Option<usize>, // Gateway the branches of the owner join again at
+}
+
+/// What the parser will know at the current line of the main flow
+#[derive(Default)]
+struct Flow {
+    pool: Option<usize>, // Index of the current pool
+    path: Vec<String>,   // Current lane and the lanes it is nested in, outermost first
+    last: Option<usize>, // Node the next node is connected from
+    go: bool,            // Whether a `G ->` waits for a `G <-` before the next node
+    seen_start: bool,    // Whether the next `#` is a middle event
+}
+
+struct Writer<'a> {
+    graph: &'a Graph,
+    place: HashMap<usize, (usize, Option<String>)>, // Pool index and lane of every node that can be written
+    outgoing: HashMap<usize, Vec<usize>>, // Edges leaving each node, as indices into the edges of the graph
+    incoming: HashMap<usize, Vec<usize>>, // Edges entering each node
+    items: Vec<Item>,
+    written: HashSet<usize>, // Nodes already written
+    claimed: HashSet<usize>, // Nodes that start a label block still to be written
+    done: HashSet<usize>,    // Edges already written
+    pending: Vec<(usize, Option<String>, String, usize, Branch)>, // Label blocks waiting for their lane
+    jumps: HashMap<usize, String>, // Label of the `G <-` in front of every node that is jumped to
+    messages: HashMap<usize, Vec<usize>>, // Pool flows each node sends
+    sent: HashSet<usize>,          // Messages to collapsed pools already written
+    inboxes: HashMap<usize, String>, // Label of the `G <-` in each pool
+    joins: HashMap<usize, String>, // Label of every joining gateway
+    joining: HashSet<usize>,       // Gateways with one edge in and one out that are written joining
+    unused: Option<String>,        // Join label of branches that do not join anywhere
+    labels: HashSet<String>,       // All labels given out
+    flow: Flow,
+    warnings: Vec<Diagnostic>,
+}
+
+impl<'a> Writer<'a> {
+    fn new(graph: &'a Graph, ids: &HashMap<usize, String>) -> Self {
+        let mut warnings = Vec::new();
+        let mut place = HashMap::new();
+        for (index, pool) in graph.pools.iter().enumerate() {
+            for node in pool.lanes.iter().flat_map(|lane| lane.get_layers()) {
+                let Some(event) = &node.event else { continue };
+                match symbol(event) {
+                    Some((_, None)) => {}
+                    Some((_, Some(plain))) => warnings.push(Diagnostic::warning(format!(
+                        "The {} '{}' was written as {}",
+                        kind(event),
+                        name_or_id(node, ids),
+                        plain
+                    ))),
+                    None => {
+                        warnings.push(Diagnostic::warning(format!(
+                            "The {} '{}' cannot be written in the DSL and was left out with its flows",
+                            kind(event),
+                            name_or_id(node, ids)
+                        )));
+                        continue;
+                    }
+                }
+                place.insert(node.id, (index, node.lane.clone()));
+            }
+        }
+        let mut outgoing: HashMap<usize, Vec<usize>> = HashMap::new();
+        let mut incoming: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (index, edge) in graph.edges.iter().enumerate() {
+            if place.contains_key(&edge.from) && place.contains_key(&edge.to) {
+                outgoing.entry(edge.from).or_default().push(index);
+                incoming.entry(edge.to).or_default().push(index);
+            }
+        }
+        let mut messages: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (index, flow) in graph.pool_flows.iter().enumerate() {
+            if flow.to_pool && place.contains_key(&flow.node) {
+                messages.entry(flow.node).or_default().push(index);
+            }
+        }
+        // Names with characters the lexer stops at are written with look-alikes
+        let names = graph
+            .pools
+            .iter()
+            .flat_map(|pool| {
+                let lanes = pool.lanes.iter().map(|lane| lane.get_lane().as_str());
+                std::iter::once(pool.get_pool_name()).chain(lanes.map(str::to_string))
+            })
+            .chain(place.keys().filter_map(|id| {
+                let node = graph.get_node_by_id(*id)?;
+                Some(node.event.as_ref()?.label()?.to_string())
+            }))
+            .chain(
+                outgoing
+                    .values()
+                    .flatten()
+                    .filter_map(|&index| graph.edges[index].text.clone()),
+            )
+            .chain(
+                graph
+                    .pool_flows
+                    .iter()
+                    .filter(|flow| place.contains_key(&flow.node))
+                    .filter_map(|flow| flow.text.clone()),
+            );
+        let mut seen = HashSet::new();
+        let mut renamed: Vec<(String, String)> = names
+            .filter(|name| seen.insert(name.clone()))
+            .filter_map(|name| {
+                let cleaned = clean(&name);
+                let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+                (cleaned != name).then_some((name, cleaned))
+            })
+            .collect();
+        renamed.sort();
+        for (name, cleaned) in renamed {
+            warnings.push(Diagnostic::warning(format!(
+                "The name '{}' was written as '{}', the DSL cannot hold '-', '.', '#' or '\"' in names",
+                name, cleaned
+            )));
+        }
+        // Edges come out of the parser in no particular order, going by the target keeps the output stable
+        for edges in outgoing.values_mut().chain(incoming.values_mut()) {
+            edges.sort_by_key(|&index| (graph.edges[index].to, graph.edges[index].from, index));
+        }
+
+        Writer {
+            graph,
+            place,
+            outgoing,
+            incoming,
+            items: Vec::new(),
+            written: HashSet::new(),
+            claimed: HashSet::new(),
+            done: HashSet::new(),
+            pending: Vec::new(),
+            jumps: HashMap::new(),
+            messages,
+            sent: HashSet::new(),
+            inboxes: HashMap::new(),
+            joins: HashMap::new(),
+            joining: HashSet::new(),
+            unused: None,
+            labels: HashSet::new(),
+            flow: Flow::default(),
+            warnings,
+        }
+    }
+
+    /// Writes the pools and lanes in order with what starts in them, then goes back to the lanes
+    /// that still have nodes waiting for nodes written after them
+    fn write(&mut self) {
+        for (index, pool) in self.graph.pools.iter().enumerate() {
+            self.pool_header(index);
+            if pool.collapsed {
+                self.write_messages(index);
+                continue;
+            }
+            let parents = pool.parent_lanes();
+            for lane in &pool.lanes {
+                // Nodes outside of any lane are kept in a lane of this name
+                let lane = Some(lane.get_lane().clone()).filter(|lane| lane != "default_lane");
+                self.enter(index, lane.as_deref());
+                if !lane.as_ref().is_some_and(|lane| parents.contains(lane)) {
+                    self.write_lane(index, lane, false);
+                }
+            }
+        }
+        loop {
+            let next = self
+                .pending
+                .first()
+                .map(|(pool, lane, ..)| (*pool, lane.clone()))
+                .or_else(|| {
+                    let node = *self.waiting(|_| true).first()?;
+                    self.place.get(&node).cloned()
+                });
+            let Some((pool, lane)) = next else { break };
+            self.enter(pool, lane.as_deref());
+            self.write_lane(pool, lane, true);
+        }
+    }
+
+    /// Writes the label blocks waiting for a lane and the nodes that can start in it. Nodes are
+    /// taken when nothing flows into them, or when all that flows into them is written already.
+    /// With `force` the first node that is left is taken when there is no such node.
+    fn write_lane(&mut self, pool: usize, lane: Option<String>, force: bool) {
+        loop {
+            if let Some(index) = self
+                .pending
+                .iter()
+                .position(|(p, l, ..)| *p == pool && *l == lane)
+            {
+                let (_, _, label, node, branch) = self.pending.remove(index);
+                self.claimed.remove(&node);
+                self.write_label(&label, node, branch);
+                continue;
+            }
+            let here = |place: &(usize, Option<String>)| place.0 == pool && place.1 == lane;
+            let candidates = self.waiting(here);
+            let starts = |node: &&usize| !self.incoming.contains_key(*node);
+            let ready = |node: &&usize| {
+                self.incoming[*node].iter().all(|&edge| {
+                    let from = self.graph.edges[edge].from;
+                    from == **node || self.written.contains(&from)
+                })
+            };
+            let root = candidates
+                .iter()
+                .find(starts)
+                .or_else(|| candidates.iter().find(ready))
+                .or_else(|| candidates.first().filter(|_| force))
+                .copied();
+            match root {
+                Some(node) => self.write_root(node),
+                None => break,
+            }
+        }
+    }
+
+    /// Nodes not written or claimed by a label block yet, in the order of their ids
+    fn waiting(&self, filter: impl Fn(&(usize, Option<String>)) -> bool) -> Vec<usize> {
+        let mut nodes: Vec<usize> = self
+            .place
+            .iter()
+            .filter(|(node, place)| {
+                !self.written.contains(node) && !self.claimed.contains(node) && filter(place)
+            })
+            .map(|(node, _)| *node)
+            .collect();
+        nodes.sort();
+        nodes
+    }
+
+    /// Starts a new chain in the main flow, on a fresh line of the lane if the parser would
+    /// otherwise connect it to what came before
+    fn write_root(&mut self, node: usize) {
+        let start = self.symbol(node) == "#" && !matches!(self.event(node), BpmnEvent::Middle(_));
+        if self.flow.go
+            || (!self.is_join(node) && self.flow.last.is_some())
+            || (start && self.flow.seen_start)
+        {
+            self.break_lane();
+        }
+        self.write_chain(node, None);
+    }
+
+    /// Writes a node and the nodes it leads on to for as long as a plain line is enough
+    fn write_chain(&mut self, mut node: usize, branch: Option<Branch>) {
+        loop {
+            let next = if self.event(node).is_gateway() && !self.is_join(node) {
+                self.write_split(node, branch)
+            } else {
+                let line = if self.event(node).is_gateway() {
+                    format!("{} <-{}", self.symbol(node), self.join_label(node))
+                } else {
+                    format!("{} {}", self.symbol(node), self.name(node))
+                };
+                self.write_node(node, line, branch);
+                self.next_in_chain(node, branch)
+            };
+            match next {
+                Some(next) => node = next,
+                None => break,
+            }
+        }
+        self.finish(node, branch);
+    }
+
+    fn write_node(&mut self, node: usize, line: String, branch: Option<Branch>) {
+        self.items.push(Item::Node(node, line));
+        self.written.insert(node);
+        if branch.is_none() || self.event(node).is_gateway() {
+            self.flow.last = Some(node);
+        }
+        if branch.is_none() && self.jumps.contains_key(&node) {
+            self.flow.go = false;
+        }
+        match self.symbol(node) {
+            "#" => self.flow.seen_start = true,
+            "." => self.flow.seen_start = false,
+            _ => {}
+        }
+        let hints: Vec<String> = self
+            .graph
+            .hints
+            .iter()
+            .filter(|hint| hint.node == node)
+            .map(|hint| hint.describe(|id| self.name(id)))
+            .collect();
+        for hint in hints {
+            self.items.push(Item::Line(hint));
+        }
+    }
+
+    /// The node a plain line after `node` would be connected from it, if its only edge goes there
+    fn next_in_chain(&mut self, node: usize, branch: Option<Branch>) -> Option<usize> {
+        let [edge] = self.outgoing.get(&node)?.as_slice() else {
+            return None;
+        };
+        // The `G` line of a message to a collapsed pool ends the chain
+        if self.messages.contains_key(&node) {
+            return None;
+        }
+        let edge = *edge;
+        let next = self.graph.edges[edge].to;
+        // A gateway with one edge in and one out is the joining one when a branch leads to it
+        if branch.is_some_and(|branch| {
+            branch.merge == Some(next) && self.symbol(next) == self.symbol(branch.owner)
+        }) {
+            self.joining.insert(next);
+            return None;
+        }
+        let chained = !self.done.contains(&edge)
+            && self.graph.edges[edge].text.is_none()
+            && !self.written.contains(&next)
+            && !self.claimed.contains(&next)
+            && self.place.get(&next) == self.place.get(&node)
+            && !self.is_join(next)
+            && (!self.event(next).is_gateway() || self.has_branch(next));
+        if !chained {
+            return None;
+        }
+        self.done.insert(edge);
+        Some(next)
+    }
+
+    /// Writes a splitting gateway with its branches. Branches in the same lane are written right
+    /// below it, the others wait for their lane. Returns the gateway joining the branches again
+    /// when the flow can go on from there.
+    fn write_split(&mut self, gateway: usize, branch: Option<Branch>) -> Option<usize> {
+        if !self.has_branch(gateway) {
+            let line = format!("{} <-{}", self.symbol(gateway), self.join_label(gateway));
+            self.write_node(gateway, line, branch);
+            return None;
+        }
+        let mut branches = Vec::new();
+        let mut jumps = Vec::new();
+        for edge in self.outgoing.get(&gateway).cloned().unwrap_or_default() {
+            if self.done.contains(&edge) {
+                continue;
+            }
+            let target = self.graph.edges[edge].to;
+            if self.can_branch_to(target) {
+                let text = self.graph.edges[edge].text.clone();
+                let base = text.clone().unwrap_or_else(|| self.name(target));
+                let label = self.new_label(&base, "branch");
+                self.claimed.insert(target);
+                self.done.insert(edge);
+                branches.push((label, target, text));
+            } else {
+                jumps.push(edge);
+            }
+        }
+
+        let arrows: Vec<String> = branches
+            .iter()
+            .map(|(label, _, text)| format!("->{}{}", label, self.quoted(text)))
+            .collect();
+        let line = format!("{} {}", self.symbol(gateway), arrows.join(" "));
+        self.write_node(gateway, line, branch);
+        let jumped: Vec<usize> = jumps
+            .iter()
+            .map(|&edge| self.graph.edges[edge].to)
+            .collect();
+        self.write_jumps(gateway, jumps, branch);
+
+        let merge = self.merge_of(gateway);
+        let own = Branch {
+            owner: gateway,
+            merge,
+        };
+        let lane = self.place[&gateway].clone();
+        for (label, target, _) in branches {
+            if self.place[&target] == lane {
+                self.claimed.remove(&target);
+                self.write_label(&label, target, own);
+            } else {
+                let (pool, lane) = self.place[&target].clone();
+                self.pending.push((pool, lane, label, target, own));
+            }
+        }
+
+        // A jump waiting for its `G <-` would stop the gateway that joins the branches again
+        merge.filter(|merge| {
+            !self.written.contains(merge)
+                && self.is_join(*merge)
+                && self.place.get(merge) == Some(&lane)
+                && branch.is_none_or(|branch| branch.merge != Some(*merge))
+                && (jumped.is_empty() || jumped.contains(merge))
+        })
+    }
+
+    fn write_label(&mut self, label: &str, node: usize, branch: Branch) {
+        self.items.push(Item::Line(format!("{}:", label)));
+        self.write_chain(node, Some(branch));
+    }
+
+    /// Writes what is left of the edges of the last node of a chain. In a label block one of them
+    /// can go to the joining gateway with `J`, the block needs a `J` either way.
+    fn finish(&mut self, node: usize, branch: Option<Branch>) {
+        let left: Vec<usize> = self
+            .outgoing
+            .get(&node)
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|edge| !self.done.contains(edge))
+            .collect();
+        let join = branch.and_then(|branch| {
+            let joins = |edge: &&usize| {
+                let to = self.graph.edges[**edge].to;
+                self.is_join(to) && self.symbol(to) == self.symbol(branch.owner)
+            };
+            let merge = |edge: &&usize| Some(self.graph.edges[**edge].to) == branch.merge;
+            left.iter()
+                .filter(joins)
+                .find(merge)
+                .or_else(|| left.iter().find(joins))
+                .copied()
+        });
+        let jumps = left
+            .into_iter()
+            .filter(|edge| Some(*edge) != join)
+            .collect();
+        self.write_jumps(node, jumps, branch);
+        if branch.is_some() {
+            let line = match join {
+                Some(edge) => {
+                    self.done.insert(edge);
+                    let to = self.graph.edges[edge].to;
+                    let text = self.graph.edges[edge].text.clone();
+                    format!("J {}{}", self.join_label(to), self.quoted(&text))
+                }
+                None => format!("J {}", self.unused_label()),
+            };
+            self.items.push(Item::Line(line));
+        }
+    }
+
+    /// Writes edges and messages to collapsed pools from the node just written as a `G ->` line,
+    /// a `G <-` goes in front of their targets. Parallel, inclusive and event gateways cannot
+    /// follow a `G <-`.
+    fn write_jumps(&mut self, node: usize, edges: Vec<usize>, branch: Option<Branch>) {
+        let mut arrows = Vec::new();
+        for edge in edges {
+            self.done.insert(edge);
+            let to = self.graph.edges[edge].to;
+            if matches!(self.symbol(to), "+" | "O" | "*") {
+                let from = self.graph.edges[edge].from;
+                self.warnings.push(Diagnostic::warning(format!(
+                    "The flow from '{}' to '{}' cannot be written in the DSL and was left out",
+                    self.title(from),
+                    self.title(to)
+                )));
+                continue;
+            }
+            let label = self.jump_label(to);
+            let text = self.graph.edges[edge].text.clone();
+            arrows.push(format!("->{}{}", label, self.quoted(&text)));
+        }
+        for message in self.messages.get(&node).cloned().unwrap_or_default() {
+            if !self.sent.insert(message) {
+                continue;
+            }
+            let flow = &self.graph.pool_flows[message];
+            let text = flow.text.clone();
+            let label = self.inbox(flow.pool);
+            arrows.push(format!("->{}{}", label, self.quoted(&text)));
+        }
+        if !arrows.is_empty() {
+            self.items
+                .push(Item::Line(format!("G {}", arrows.join(" "))));
+            if branch.is_none() {
+                self.flow.go = true;
+            }
+        }
+    }
+
+    /// The joining gateway that every branch of a split that joins at all reaches first
+    fn merge_of(&self, gateway: usize) -> Option<usize> {
+        let paths: Vec<Vec<usize>> = self
+            .outgoing
+            .get(&gateway)
+            .into_iter()
+            .flatten()
+            .map(|&edge| self.joins_after(self.graph.edges[edge].to))
+            .filter(|joins| !joins.is_empty())
+            .collect();
+        let (first, rest) = paths.split_first()?;
+        first
+            .iter()
+            .copied()
+            .find(|join| rest.iter().all(|joins| joins.contains(join)))
+    }
+
+    /// Joining gateways on the way on from a node, taking the first edge wherever the flow splits
+    fn joins_after(&self, mut node: usize) -> Vec<usize> {
+        let mut seen = HashSet::new();
+        let mut joins = Vec::new();
+        while seen.insert(node) {
+            if self.can_join(node) {
+                joins.push(node);
+            }
+            match self.outgoing.get(&node).and_then(|edges| edges.first()) {
+                Some(&edge) => node = self.graph.edges[edge].to,
+                None => break,
+            }
+        }
+        joins
+    }
+
+    /// Gateways are written joining, as `X <-label`, when several edges come in or none go out.
+    /// The parser connects nothing to a joining gateway, so the edges come from `J` and `G`.
+    fn is_join(&self, node: usize) -> bool {
+        let count = |edges: &HashMap<usize, Vec<usize>>| edges.get(&node).map_or(0, Vec::len);
+        self.event(node).is_gateway()
+            && (count(&self.incoming) >= 2
+                || count(&self.outgoing) == 0
+                || self.joining.contains(&node))
+    }
+
+    /// Whether a gateway could be written joining, which one with one edge in and one out can be
+    fn can_join(&self, node: usize) -> bool {
+        let count = |edges: &HashMap<usize, Vec<usize>>| edges.get(&node).map_or(0, Vec::len);
+        self.event(node).is_gateway() && (count(&self.incoming) >= 2 || count(&self.outgoing) <= 1)
+    }
+
+    /// Whether a splitting gateway has a branch left to write as a label block
+    fn has_branch(&self, gateway: usize) -> bool {
+        self.outgoing
+            .get(&gateway)
+            .into_iter()
+            .flatten()
+            .any(|edge| !self.done.contains(edge) && self.can_branch_to(self.graph.edges[*edge].to))
+    }
+
+    fn can_branch_to(&self, node: usize) -> bool {
+        !self.written.contains(&node) && !self.claimed.contains(&node) && !self.is_join(node)
+    }
+
+    /// Writes the headers needed to get to a lane, or to the part of a pool outside of its lanes
+    fn enter(&mut self, pool: usize, lane: Option<&str>) {
+        let here = self.flow.pool == Some(pool)
+            && self.flow.path.last().map(String::as_str) == lane
+            && self.flow.last.is_none()
+            && !self.flow.go;
+        if here {
+            return;
+        }
+        let Some(lane) = lane else {
+            self.pool_header(pool);
+            return;
+        };
+        if self.flow.pool != Some(pool) {
+            self.pool_header(pool);
+        }
+        let lanes = &self.graph.pools[pool].lanes;
+        let parent_of = |name: &str| {
+            lanes
+                .iter()
+                .find(|other| other.get_lane() == name)
+                .and_then(|other| other.parent.clone())
+        };
+        let mut path = vec![lane.to_string()];
+        while let Some(parent) = parent_of(&path[0]) {
+            path.insert(0, parent);
+        }
+        let shared = path[..path.len() - 1]
+            .iter()
+            .zip(&self.flow.path)
+            .take_while(|(a, b)| a == b)
+            .count();
+        for (depth, name) in path.iter().enumerate().skip(shared) {
+            let line = format!("{} {}", "=".repeat(depth + 2), clean(name));
+            self.items.push(Item::Line(line));
+        }
+        self.flow = Flow {
+            pool: Some(pool),
+            path,
+            ..Flow::default()
+        };
+    }
+
+    /// Writes the header of the current lane again, so the parser starts a new chain
+    fn break_lane(&mut self) {
+        let pool = self.flow.pool.unwrap_or_default();
+        let lane = self.flow.path.last().cloned();
+        self.flow.last = None;
+        self.flow.go = false;
+        match lane {
+            Some(lane) => {
+                self.flow.path.pop();
+                self.enter(pool, Some(&lane));
+            }
+            None => self.pool_header(pool),
+        }
+    }
+
+    fn pool_header(&mut self, index: usize) {
+        let pool = &self.graph.pools[index];
+        let name = clean(&pool.get_pool_name());
+        // Nodes before the first pool of the DSL end up in a pool without a name
+        if pool.collapsed {
+            self.items.push(Item::Line(format!("= [{}]", name)));
+        } else if !name.is_empty() || !self.items.is_empty() {
+            self.items
+                .push(Item::Line(format!("= {}", name).trim_end().to_string()));
+        }
+        self.flow = Flow {
+            pool: Some(index),
+            ..Flow::default()
+        };
+    }
+
+    /// Writes the jumps of the messages a collapsed pool sends and receives. The nodes it sends to
+    /// get a `G <-` in front of them, the nodes it receives from a `G ->` to its own `G <-`.
+    fn write_messages(&mut self, pool: usize) {
+        let flows: Vec<(usize, bool, Option<String>)> = self
+            .graph
+            .pool_flows
+            .iter()
+            .filter(|flow| flow.pool == pool && self.place.contains_key(&flow.node))
+            .map(|flow| (flow.node, flow.to_pool, flow.text.clone()))
+            .collect();
+        if flows.iter().any(|(_, to_pool, _)| *to_pool) {
+            let label = self.inbox(pool);
+            self.items.push(Item::Line(format!("G <-{}", label)));
+        }
+        let mut arrows = Vec::new();
+        for (node, _, text) in flows.into_iter().filter(|(_, to_pool, _)| !to_pool) {
+            if matches!(self.symbol(node), "+" | "O" | "*") {
+                self.warnings.push(Diagnostic::warning(format!(
+                    "The message from '{}' to '{}' cannot be written in the DSL and was left out",
+                    self.graph.pools[pool].get_pool_name(),
+                    self.title(node)
+                )));
+                continue;
+            }
+            let label = self.jump_label(node);
+            arrows.push(format!("->{}{}", label, self.quoted(&text)));
+        }
+        if !arrows.is_empty() {
+            self.items
+                .push(Item::Line(format!("G {}", arrows.join(" "))));
+        }
+    }
+
+    /// The label of the `G <-` in front of a node that is jumped to
+    fn jump_label(&mut self, node: usize) -> String {
+        if let Some(label) = self.jumps.get(&node) {
+            return label.clone();
+        }
+        let label = self.new_label(&self.name(node), "jump");
+        self.jumps.insert(node, label.clone());
+        label
+    }
+
+    /// The label of the `G <-` messages to a collapsed pool jump to
+    fn inbox(&mut self, pool: usize) -> String {
+        if let Some(label) = self.inboxes.get(&pool) {
+            return label.clone();
+        }
+        let label = self.new_label(&self.graph.pools[pool].get_pool_name(), "pool");
+        self.inboxes.insert(pool, label.clone());
+        label
+    }
+
+    fn join_label(&mut self, gateway: usize) -> String {
+        if let Some(label) = self.joins.get(&gateway) {
+            return label.clone();
+        }
+        let label = self.new_label("done", "done");
+        self.joins.insert(gateway, label.clone());
+        label
+    }
+
+    fn unused_label(&mut self) -> String {
+        if self.unused.is_none() {
+            self.unused = Some(self.new_label("end", "end"));
+        }
+        self.unused.clone().unwrap_or_default()
+    }
+
+    /// A label that is not used yet, made of lower case letters, digits and underscores so the
+    /// lexer cannot take it for a symbol
+    fn new_label(&mut self, base: &str, fallback: &str) -> String {
+        let words: Vec<String> = base
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(str::to_string)
+            .collect();
+        let base = if words.is_empty() {
+            fallback.to_string()
+        } else {
+            words.join("_")
+        };
+        let mut label = base.clone();
+        let mut count = 1;
+        while self.labels.contains(&label) {
+            count += 1;
+            label = format!("{}_{}", base, count);
+        }
+        self.labels.insert(label.clone());
+        label
+    }
+
+    fn quoted(&self, text: &Option<String>) -> String {
+        match text {
+            Some(text) => format!(" \"{}\"", clean(text)),
+            None => String::new(),
+        }
+    }
+
+    fn event(&self, node: usize) -> &BpmnEvent {
+        self.node(node)
+            .event
+            .as_ref()
+            .expect("Only nodes with an event are written")
+    }
+
+    fn node(&self, id: usize) -> &Node {
+        self.graph.get_node_by_id(id).expect("Edges point at nodes")
+    }
+
+    fn symbol(&self, node: usize) -> &'static str {
+        symbol(self.event(node)).map_or("-", |(symbol, _)| symbol)
+    }
+
+    /// The name as it is written after the symbol of the node
+    fn name(&self, node: usize) -> String {
+        clean(self.event(node).label().unwrap_or_default())
+    }
+
+    /// The node as it is called in warnings
+    fn title(&self, node: usize) -> String {
+        match self.event(node).label().filter(|label| !label.is_empty()) {
+            Some(label) => label.to_string(),
+            None => kind(self.event(node)).to_string(),
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut dsl = String::new();
+        for item in &self.items {
+            match item {
+                Item::Line(line) => dsl.push_str(line),
+                Item::Node(node, line) => {
+                    if let Some(label) = self.jumps.get(node) {
+                        dsl.push_str(&format!("G <-{}\n", label));
+                    }
+                    dsl.push_str(line);
+                }
+            }
+            dsl.push('\n');
+        }
+        dsl
+    }
+}
+
+/// The name of a node, or its XML id when it has none
+fn name_or_id(node: &Node, ids: &HashMap<usize, String>) -> String {
+    let label = node.event.as_ref().and_then(BpmnEvent::label);
+    match label.filter(|label| !label.is_empty()) {
+        Some(label) => label.to_string(),
+        None => ids
+            .get(&node.id)
+            .cloned()
+            .unwrap_or_else(|| get_node_bpmn_id(node)),
+    }
+}
+
+/// The DSL symbol of an event, with what it is written as when the symbol does not say all of it
+fn symbol(event: &BpmnEvent) -> Option<(&'static str, Option<&'static str>)> {
+    Some(match event {
+        BpmnEvent::Start(_) | BpmnEvent::Middle(_) => ("#", None),
+        BpmnEvent::End(_) => (".", None),
+        BpmnEvent::ActivityTask(_) => ("-", None),
+        BpmnEvent::GatewayExclusive => ("X", None),
+        BpmnEvent::GatewayInclusive => ("O", None),
+        BpmnEvent::GatewayParallel => ("+", None),
+        BpmnEvent::GatewayEvent => ("*", None),
+        BpmnEvent::GatewayJoin(_) => ("X", Some("an exclusive gateway")),
+        BpmnEvent::StartTimerEvent(_)
+        | BpmnEvent::StartSignalEvent(_)
+        | BpmnEvent::StartMessageEvent(_)
+        | BpmnEvent::StartConditionalEvent(_) => ("#", Some("a plain start event")),
+        BpmnEvent::EndErrorEvent(_)
+        | BpmnEvent::EndCancelEvent(_)
+        | BpmnEvent::EndSignalEvent(_)
+        | BpmnEvent::EndMessageEvent(_)
+        | BpmnEvent::EndTerminateEvent(_)
+        | BpmnEvent::EndEscalationEvent(_)
+        | BpmnEvent::EndCompensationEvent(_) => (".", Some("a plain end event")),
+        BpmnEvent::TaskUser(_)
+        | BpmnEvent::TaskService(_)
+        | BpmnEvent::TaskBusinessRule(_)
+        | BpmnEvent::TaskScript(_)
+        | BpmnEvent::ActivitySubprocess(_)
+        | BpmnEvent::ActivityCallActivity(_)
+        | BpmnEvent::ActivityEventSubprocess(_)
+        | BpmnEvent::ActivityTransaction(_) => ("-", Some("a plain task")),
+        BpmnEvent::BoundaryEvent(..)
+        | BpmnEvent::BoundaryErrorEvent(..)
+        | BpmnEvent::BoundaryTimerEvent(..)
+        | BpmnEvent::BoundaryCancelEvent(..)
+        | BpmnEvent::BoundarySignalEvent(..)
+        | BpmnEvent::BoundaryMessageEvent(..)
+        | BpmnEvent::BoundaryEscalationEvent(..)
+        | BpmnEvent::BoundaryConditionalEvent(..)
+        | BpmnEvent::BoundaryCompensationEvent(..)
+        | BpmnEvent::DataStoreReference(_)
+        | BpmnEvent::DataObjectReference(_) => return None,
+    })
+}
+
+/// What an event is called in warnings
+fn kind(event: &BpmnEvent) -> &'static str {
+    match event {
+        BpmnEvent::Start(_) => "start event",
+        BpmnEvent::Middle(_) => "intermediate event",
+        BpmnEvent::End(_) => "end event",
+        BpmnEvent::ActivityTask(_) => "task",
+        BpmnEvent::GatewayExclusive => "exclusive gateway",
+        BpmnEvent::GatewayInclusive => "inclusive gateway",
+        BpmnEvent::GatewayParallel => "parallel gateway",
+        BpmnEvent::GatewayEvent => "event-based gateway",
+        BpmnEvent::GatewayJoin(_) => "joining gateway",
+        BpmnEvent::StartTimerEvent(_) => "timer start event",
+        BpmnEvent::StartSignalEvent(_) => "signal start event",
+        BpmnEvent::StartMessageEvent(_) => "message start event",
+        BpmnEvent::StartConditionalEvent(_) => "conditional start event",
+        BpmnEvent::EndErrorEvent(_) => "error end event",
+        BpmnEvent::EndCancelEvent(_) => "cancel end event",
+        BpmnEvent::EndSignalEvent(_) => "signal end event",
+        BpmnEvent::EndMessageEvent(_) => "message end event",
+        BpmnEvent::EndTerminateEvent(_) => "terminate end event",
+        BpmnEvent::EndEscalationEvent(_) => "escalation end event",
+        BpmnEvent::EndCompensationEvent(_) => "compensation end event",
+        BpmnEvent::TaskUser(_) => "user task",
+        BpmnEvent::TaskService(_) => "service task",
+        BpmnEvent::TaskBusinessRule(_) => "business rule task",
+        BpmnEvent::TaskScript(_) => "script task",
+        BpmnEvent::ActivitySubprocess(_) => "subprocess",
+        BpmnEvent::ActivityCallActivity(_) => "call activity",
+        BpmnEvent::ActivityEventSubprocess(_) => "event subprocess",
+        BpmnEvent::ActivityTransaction(_) => "transaction",
+        BpmnEvent::BoundaryEvent(..)
+        | BpmnEvent::BoundaryErrorEvent(..)
+        | BpmnEvent::BoundaryTimerEvent(..)
+        | BpmnEvent::BoundaryCancelEvent(..)
+        | BpmnEvent::BoundarySignalEvent(..)
+        | BpmnEvent::BoundaryMessageEvent(..)
+        | BpmnEvent::BoundaryEscalationEvent(..)
+        | BpmnEvent::BoundaryConditionalEvent(..)
+        | BpmnEvent::BoundaryCompensationEvent(..) => "boundary event",
+        BpmnEvent::DataStoreReference(_) => "data store",
+        BpmnEvent::DataObjectReference(_) => "data object",
+    }
+}
+
+/// A name on one line, with the characters the lexer would stop at replaced by look-alikes
+fn clean(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .map(|c| {
+            RESERVED
+                .iter()
+                .find(|(reserved, _)| *reserved == c)
+                .map_or(c, |(_, replacement)| *replacement)
+        })
+        .collect()
+}
@@ -0,0 +1,444 @@
+// to_svg.rs
+
+use crate::common::bpmn_event::{get_node_size, BpmnEvent, EXTERNAL_LABEL_WIDTH, TASK_PADDING};
+use crate::common::graph::Graph;
+use crate::common::node::Node;
+use crate::common::text_metrics::{wrap_text, FONT_SIZE, LINE_HEIGHT};
+use crate::layout::Orientation;
+use svg::node::element::{
+    Circle, Definitions, Group, Marker, Path, Polygon, Polyline, Rectangle, Text, TSpan,
+};
+use svg::Document;
+
+const MARGIN: f64 = 20.0;
+
+/// Renders a laid out graph as an SVG document
+pub fn generate_svg(graph: &Graph) -> String {
+    let (min_x, min_y, max_x, max_y) = find_bounds(graph);
+
+    let mut document = Document::new()
+        .set(
+            "viewBox",
+            (
+                min_x - MARGIN,
+                min_y - MARGIN,
+                max_x - min_x + 2.0 * MARGIN,
+                max_y - min_y + 2.0 * MARGIN,
+            ),
+        )
+        .set("width", max_x - min_x + 2.0 * MARGIN)
+        .set("height", max_y - min_y + 2.0 * MARGIN)
+        .set("font-family", "Arial, sans-serif")
+        .set("font-size", FONT_SIZE)
+        .add(arrow_marker());
+
+    // Pools and lanes are drawn first so that nodes and edges end up on top
+    for pool in graph.get_pools() {
+        let (x, y) = (pool.x.unwrap_or(0.0), pool.y.unwrap_or(0.0));
+        let (width, height) = (pool.width.unwrap_or(0.0), pool.height.unwrap_or(0.0));
+        let pool_name = pool.get_pool_name();
+        if pool.collapsed {
+            // A black box has no header, its name is centered in the band
+            document = document
+                .add(outlined(Rectangle::new())
+                    .set("x", x)
+                    .set("y", y)
+                    .set("width", width)
+                    .set("height", height))
+                .add(Text::new(pool_name.as_str())
+                    .set("x", x + width / 2.0)
+                    .set("y", y + height / 2.0)
+                    .set("text-anchor", "middle")
+                    .set("dominant-baseline", "middle"));
+            continue;
+        }
+        if !pool_name.is_empty() {
+            document = document
+                .add(outlined(Rectangle::new())
+                    .set("x", x)
+                    .set("y", y)
+                    .set("width", width)
+                    .set("height", height))
+                .add(header_label(&pool_name, (x, y, width, height), graph.orientation));
+        }
+
+        for lane in pool.get_lanes() {
+            if lane.get_lane() == "default_lane" {
+                continue;
+            }
+            let (x, y) = (lane.x.unwrap_or(0.0), lane.y.unwrap_or(0.0));
+            let (width, height) = (lane.width.unwrap_or(0.0), lane.height.unwrap_or(0.0));
+            document = document
+                .add(outlined(Rectangle::new())
+                    .set("x", x)
+                    .set("y", y)
+                    .set("width", width)
+                    .set("height", height))
+                .add(header_label(lane.get_lane(), (x, y, width, height), graph.orientation));
+        }
+    }
+
+    for edge in &graph.edges {
+        if let Some(points) = &edge.bend_points {
+            let points_attr = points
+                .iter()
+                .map(|(x, y)| format!("{},{}", x, y))
+                .collect::<Vec<_>>()
+                .join(" ");
+            document = document.add(
+                Polyline::new()
+                    .set("points", points_attr)
+                    .set("fill", "none")
+                    .set("stroke", "black")
+                    .set("stroke-width", 1.5)
+                    .set("marker-end", "url(#arrow)"),
+            );
+
+            // Branch texts are written where the layout placed them, next to the start of the edge
+            if let (Some(text), Some((x, y, width, _))) = (&edge.text, edge.label_bounds) {
+                document = document.add(wrapped_text(
+                    text,
+                    x + width / 2.0,
+                    y + FONT_SIZE,
+                    EXTERNAL_LABEL_WIDTH,
+                ));
+            }
+        }
+    }
+
+    // Messages to and from collapsed pools are dashed, like message flows in BPMN
+    for flow in &graph.pool_flows {
+        if let Some(points) = &flow.bend_points {
+            let points_attr = points
+                .iter()
+                .map(|(x, y)| format!("{},{}", x, y))
+                .collect::<Vec<_>>()
+                .join(" ");
+            document = document.add(
+                Polyline::new()
+                    .set("points", points_attr)
+                    .set("fill", "none")
+                    .set("stroke", "black")
+                    .set("stroke-width", 1.5)
+                    .set("stroke-dasharray", "6,4")
+                    .set("marker-end", "url(#arrow)"),
+            );
+            if let (Some(text), Some((x, y, width, _))) = (&flow.text, flow.label_bounds) {
+                document = document.add(wrapped_text(
+                    text,
+                    x + width / 2.0,
+                    y + FONT_SIZE,
+                    EXTERNAL_LABEL_WIDTH,
+                ));
+            }
+        }
+    }
+
+    for pool in graph.get_pools() {
+        for lane in pool.get_lanes() {
+            for node in lane.get_layers() {
+                document = document.add(render_node(node, graph.orientation));
+            }
+        }
+    }
+
+    document.to_string()
+}
+
+/// Finds the bounding box of everything that will be drawn
+fn find_bounds(graph: &Graph) -> (f64, f64, f64, f64) {
+    let mut min_x = f64::MAX;
+    let mut min_y = f64::MAX;
+    let mut max_x = f64::MIN;
+    let mut max_y = f64::MIN;
+
+    let mut include = |x: f64, y: f64, width: f64, height: f64| {
+        min_x = min_x.min(x);
+        min_y = min_y.min(y);
+        max_x = max_x.max(x + width);
+        max_y = max_y.max(y + height);
+    };
+
+    for pool in graph.get_pools() {
+        include(
+            pool.x.unwrap_or(0.0),
+            pool.y.unwrap_or(0.0),
+            pool.width.unwrap_or(0.0),
+            pool.height.unwrap_or(0.0),
+        );
+        for lane in pool.get_lanes() {
+            for node in lane.get_layers() {
+                let (x, y, width, height) = node_bounds(node);
+                include(x, y, width, height);
+            }
+        }
+    }
+    for edge in &graph.edges {
+        for (x, y) in edge.bend_points.iter().flatten() {
+            include(*x, *y, 0.0, 0.0);
+        }
+    }
+
+    if min_x > max_x {
+        return (0.0, 0.0, 0.0, 0.0);
+    }
+    (min_x, min_y, max_x, max_y)
+}
+
+/// Returns the x, y, width and height of the shape drawn for a node
+fn node_bounds(node: &Node) -> (f64, f64, f64, f64) {
+    let (width, height) = node
+        .event
+        .as_ref()
+        .map(get_node_size)
+        .unwrap_or((100, 80));
+    (
+        node.x.unwrap_or(0.0) + node.x_offset.unwrap_or(0.0),
+        node.y.unwrap_or(0.0) + node.y_offset.unwrap_or(0.0),
+        width as f64,
+        height as f64,
+    )
+}
+
+fn render_node(node: &Node, orientation: Orientation) -> Group {
+    let (x, y, width, height) = node_bounds(node);
+    let center_x = x + width / 2.0;
+    let center_y = y + height / 2.0;
+    let label = node.event.as_ref().and_then(|e| e.label()).unwrap_or("");
+    let group = Group::new().set("id", format!("Node_{}", node.id));
+
+    match node.event.as_ref() {
+        // Events are circles with their label outside
+        Some(
+            event @ (BpmnEvent::Start(_)
+            | BpmnEvent::Middle(_)
+            | BpmnEvent::End(_)
+            | BpmnEvent::StartTimerEvent(_)
+            | BpmnEvent::StartSignalEvent(_)
+            | BpmnEvent::StartMessageEvent(_)
+            | BpmnEvent::StartConditionalEvent(_)
+            | BpmnEvent::EndErrorEvent(_)
+            | BpmnEvent::EndCancelEvent(_)
+            | BpmnEvent::EndSignalEvent(_)
+            | BpmnEvent::EndMessageEvent(_)
+            | BpmnEvent::EndTerminateEvent(_)
+            | BpmnEvent::EndEscalationEvent(_)
+            | BpmnEvent::EndCompensationEvent(_)
+            | BpmnEvent::BoundaryEvent(_, _, _)
+            | BpmnEvent::BoundaryErrorEvent(_, _, _)
+            | BpmnEvent::BoundaryTimerEvent(_, _, _)
+            | BpmnEvent::BoundaryCancelEvent(_, _, _)
+            | BpmnEvent::BoundarySignalEvent(_, _, _)
+            | BpmnEvent::BoundaryMessageEvent(_, _, _)
+            | BpmnEvent::BoundaryEscalationEvent(_, _, _)
+            | BpmnEvent::BoundaryConditionalEvent(_, _, _)
+            | BpmnEvent::BoundaryCompensationEvent(_, _)),
+        ) => {
+            let stroke_width = if is_end_event(event) { 4 } else { 2 };
+            let mut group = group.add(
+                outlined(Circle::new())
+                    .set("cx", center_x)
+                    .set("cy", center_y)
+                    .set("r", width / 2.0)
+                    .set("stroke-width", stroke_width),
+            );
+            if matches!(event, BpmnEvent::Middle(_)) {
+                group = group.add(
+                    outlined(Circle::new())
+                        .set("cx", center_x)
+                        .set("cy", center_y)
+                        .set("r", width / 2.0 - 3.0),
+                );
+            }
+            add_external_label(group, node, label, orientation)
+        }
+
+        // Gateways are diamonds with a marker for the gateway type
+        Some(
+            event @ (BpmnEvent::GatewayExclusive
+            | BpmnEvent::GatewayInclusive
+            | BpmnEvent::GatewayParallel
+            | BpmnEvent::GatewayEvent
+            | BpmnEvent::GatewayJoin(_)),
+        ) => {
+            let diamond = format!(
+                "{},{} {},{} {},{} {},{}",
+                center_x,
+                y,
+                x + width,
+                center_y,
+                center_x,
+                y + height,
+                x,
+                center_y
+            );
+            let group = group.add(outlined(Polygon::new()).set("points", diamond));
+            let group = match event {
+                BpmnEvent::GatewayExclusive => group.add(
+                    Path::new()
+                        .set(
+                            "d",
+                            format!(
+                                "M {} {} L {} {} M {} {} L {} {}",
+                                center_x - 8.0,
+                                center_y - 8.0,
+                                center_x + 8.0,
+                                center_y + 8.0,
+                                center_x + 8.0,
+                                center_y - 8.0,
+                                center_x - 8.0,
+                                center_y + 8.0
+                            ),
+                        )
+                        .set("stroke", "black")
+                        .set("stroke-width", 3),
+                ),
+                BpmnEvent::GatewayInclusive => group.add(
+                    outlined(Circle::new())
+                        .set("cx", center_x)
+                        .set("cy", center_y)
+                        .set("r", 10)
+                        .set("stroke-width", 2.5),
+                ),
+                BpmnEvent::GatewayEvent => group
+                    .add(
+                        outlined(Circle::new())
+                            .set("cx", center_x)
+                            .set("cy", center_y)
+                            .set("r", 11),
+                    )
+                    .add(
+                        outlined(Circle::new())
+                            .set("cx", center_x)
+                            .set("cy", center_y)
+                            .set("r", 8),
+                    ),
+                _ => group.add(
+                    Path::new()
+                        .set(
+                            "d",
+                            format!(
+                                "M {} {} L {} {} M {} {} L {} {}",
+                                center_x,
+                                center_y - 10.0,
+                                center_x,
+                                center_y + 10.0,
+                                center_x - 10.0,
+                                center_y,
+                                center_x + 10.0,
+                                center_y
+                            ),
+                        )
+                        .set("stroke", "black")
+                        .set("stroke-width", 3),
+                ),
+            };
+            add_external_label(group, node, label, orientation)
+        }
+
+        // Everything else is drawn as an activity with its label inside
+        _ => {
+            let max_width = width - 2.0 * TASK_PADDING;
+            let lines = wrap_text(label, max_width);
+            let text_y = center_y - (lines.len() as f64 - 1.0) * LINE_HEIGHT / 2.0 + 4.0;
+            group
+                .add(
+                    outlined(Rectangle::new())
+                        .set("x", x)
+                        .set("y", y)
+                        .set("width", width)
+                        .set("height", height)
+                        .set("rx", 10)
+                        .set("stroke-width", 2),
+                )
+                .add(wrapped_text(label, center_x, text_y, max_width))
+        }
+    }
+}
+
+fn is_end_event(event: &BpmnEvent) -> bool {
+    matches!(
+        event,
+        BpmnEvent::End(_)
+            | BpmnEvent::EndErrorEvent(_)
+            | BpmnEvent::EndCancelEvent(_)
+            | BpmnEvent::EndSignalEvent(_)
+            | BpmnEvent::EndMessageEvent(_)
+            | BpmnEvent::EndTerminateEvent(_)
+            | BpmnEvent::EndEscalationEvent(_)
+            | BpmnEvent::EndCompensationEvent(_)
+    )
+}
+
+/// Gives a shape the default white fill and black outline
+fn outlined<T: svg::Node>(mut shape: T) -> T {
+    shape.assign("fill", "white");
+    shape.assign("stroke", "black");
+    shape
+}
+
+/// Pool and lane names go in the header band at the start of the flow,
+/// written bottom to top when the band is on the left
+fn header_label(
+    label: &str,
+    (x, y, width, height): (f64, f64, f64, f64),
+    orientation: Orientation,
+) -> Text {
+    match orientation {
+        Orientation::LeftToRight => vertical_label(label, x + 15.0, y + height / 2.0),
+        Orientation::TopToBottom => Text::new(label)
+            .set("x", x + width / 2.0)
+            .set("y", y + 15.0)
+            .set("text-anchor", "middle")
+            .set("dominant-baseline", "middle"),
+    }
+}
+
+fn vertical_label(label: &str, x: f64, y: f64) -> Text {
+    Text::new(label)
+        .set("x", x)
+        .set("y", y)
+        .set("text-anchor", "middle")
+        .set("dominant-baseline", "middle")
+        .set("transform", format!("rotate(-90 {} {})", x, y))
+}
+
+/// Adds the label of an event or gateway in the box the layout reserved for it
+fn add_external_label(group: Group, node: &Node, label: &str, orientation: Orientation) -> Group {
+    match node.label_bounds(orientation) {
+        Some((x, y, width, _)) => group.add(wrapped_text(
+            label,
+            x + width / 2.0,
+            y + FONT_SIZE,
+            EXTERNAL_LABEL_WIDTH,
+        )),
+        None => group,
+    }
+}
+
+/// Writes a label wrapped to `max_width` centered at `x`, starting with its first line at `y`
+fn wrapped_text(label: &str, x: f64, y: f64, max_width: f64) -> Text {
+    let mut text = Text::new("")
+        .set("x", x)
+        .set("y", y)
+        .set("text-anchor", "middle");
+    for (index, line) in wrap_text(label, max_width).into_iter().enumerate() {
+        let dy = if index == 0 { 0.0 } else { LINE_HEIGHT };
+        text = text.add(TSpan::new(line).set("x", x).set("dy", dy));
+    }
+    text
+}
+
+fn arrow_marker() -> Definitions {
+    Definitions::new().add(
+        Marker::new()
+            .set("id", "arrow")
+            .set("viewBox", (0, 0, 10, 10))
+            .set("refX", 10)
+            .set("refY", 5)
+            .set("markerWidth", 8)
+            .set("markerHeight", 8)
+            .set("orient", "auto")
+            .add(Path::new().set("d", "M 0 0 L 10 5 L 0 10 z")),
+    )
+}
@@ -1,395 +1,575 @@
 // to_xml.rs
 
-use crate::common::bpmn_event::BpmnEvent;
+use crate::common::bpmn_event::{get_node_size, BpmnEvent};
+use crate::common::edge::Edge;
 use crate::common::graph::Graph;
 use crate::common::node::Node;
-use std::collections::HashSet;
-use std::fs::File;
-use std::io::Write;
+use crate::common::pool::Pool;
+use crate::layout::Orientation;
+use crate::xml_writer::XmlWriter;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+
+const NAMESPACES: &[(&str, &str)] = &[
+    ("xsi", "http://www.w3.org/2001/XMLSchema-instance"),
+    ("bpmn", "http://www.omg.org/spec/BPMN/20100524/MODEL"),
+    ("bpmndi", "http://www.omg.org/spec/BPMN/20100524/DI"),
+    ("dc", "http://www.omg.org/spec/DD/20100524/DC"),
+    ("di", "http://www.omg.org/spec/DD/20100524/DI"),
+    ("modeler", "http://camunda.org/schema/modeler/1.0"),
+];
+
+// Lanes of nodes outside of any lane, they are not written as lanes
+const DEFAULT_LANE: &str = "default_lane";
 
 pub fn generate_bpmn(graph: &Graph) -> String {
-    let mut bpmn = String::from(
-        r#"<?xml version="1.0" encoding="UTF-8"?>
-<bpmn:definitions xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"
-xmlns:bpmn="http://www.omg.org/spec/BPMN/20100524/MODEL"
-xmlns:bpmndi="http://www.omg.org/spec/BPMN/20100524/DI"
-xmlns:dc="http://www.omg.org/spec/DD/20100524/DC"
-xmlns:di="http://www.omg.org/spec/DD/20100524/DI"
-xmlns:modeler="http://camunda.org/schema/modeler/1.0" id="Definitions_1"
-targetNamespace="http://bpmn.io/schema/bpmn" exporter="Camunda Modeler"
-exporterVersion="5.17.0">
-"#,
-    );
-
-    // Begin collaboration
-    bpmn.push_str(r#"  <bpmn:collaboration id="Collaboration_1">"#);
-
-    // Collect unique pool IDs from nodes
-    let pool_ids: HashSet<String> = graph
-        .pools
-        .iter()
-        .map(|pool| pool.get_pool_name())
-        .collect();
-
-    for pool_id in &pool_ids {
-        bpmn.push_str(&format!(
-            r#"<bpmn:participant id="Participant_{}" name="{}" processRef="Process_{}" />"#,
-            pool_id, pool_id, pool_id
-        ));
+    let mut bpmn = Vec::new();
+    // Writing to memory cannot fail and the writer only writes UTF-8
+    write_bpmn(graph, &mut bpmn).expect("Writing to memory should not fail");
+    String::from_utf8(bpmn).expect("The XML should be UTF-8")
+}
+
+/// Writes a laid out graph as BPMN 2.0 XML to any writer
+pub fn write_bpmn<W: Write>(graph: &Graph, out: W) -> io::Result<()> {
+    let ids = Ids::new(graph);
+    let mut xml = XmlWriter::new(out);
+    xml.declaration()?;
+    let mut definitions = xml.element("bpmn:definitions");
+    for (prefix, uri) in NAMESPACES {
+        definitions = definitions.namespace(prefix, uri);
+    }
+    definitions
+        .attribute("id", "Definitions_1")
+        .attribute("targetNamespace", "http://bpmn.io/schema/bpmn")
+        .attribute("exporter", "Camunda Modeler")
+        .attribute("exporterVersion", "5.17.0")
+        .start()?;
+
+    // Pools are participants of the collaboration, collapsed ones are black boxes without a process
+    if !ids.bare {
+        xml.element("bpmn:collaboration")
+            .attribute("id", "Collaboration_1")
+            .start()?;
+        for (index, pool) in graph.pools.iter().enumerate() {
+            let name = pool.get_pool_name();
+            xml.element("bpmn:participant")
+                .attribute("id", format!("Participant_{}", ids.pools[index]))
+                .optional_attribute("name", Some(name).filter(|name| !name.is_empty()))
+                .optional_attribute(
+                    "processRef",
+                    (!pool.collapsed).then(|| format!("Process_{}", ids.pools[index])),
+                )
+                .empty()?;
+        }
+        // Flows between pools are messages, sequence flows stay inside of a process
+        for (index, edge) in graph.edges.iter().enumerate() {
+            if ids.pool_of(edge.from) != ids.pool_of(edge.to) {
+                write_flow(&mut xml, "bpmn:messageFlow", &ids, index, edge)?;
+            }
+        }
+        // Collapsed pools have no nodes, their messages start or end at the participant
+        for (index, flow) in graph.pool_flows.iter().enumerate() {
+            let Some(node) = ids.node(flow.node) else {
+                continue;
+            };
+            let participant = format!("Participant_{}", ids.pools[flow.pool]);
+            let (from, to) = if flow.to_pool {
+                (node, participant.as_str())
+            } else {
+                (participant.as_str(), node)
+            };
+            xml.element("bpmn:messageFlow")
+                .attribute("id", &ids.pool_flows[index])
+                .optional_attribute("name", flow.text.as_ref())
+                .attribute("sourceRef", from)
+                .attribute("targetRef", to)
+                .empty()?;
+        }
+        xml.end()?;
     }
 
-    bpmn.push_str(r#"  </bpmn:collaboration>"#);
+    for (index, pool) in graph.pools.iter().enumerate() {
+        if !pool.collapsed {
+            write_process(&mut xml, graph, &ids, index, pool)?;
+        }
+    }
 
-    // Generate processes for each pool
-    for pool_id in &pool_ids {
-        bpmn.push_str(&format!(
-            r#"<bpmn:process id="Process_{}" isExecutable="true">"#,
-            pool_id
-        ));
+    write_diagram(&mut xml, graph, &ids)?;
+    xml.end()?;
+    xml.finish()?;
+    Ok(())
+}
 
-        // Get nodes in this pool
-        let pool_nodes: Vec<&Node> = graph.get_nodes_by_pool_name(pool_id);
+/// XML ids of pools, lanes, nodes and flows. Pool and lane names go into them with everything but
+/// letters, digits, `_`, `-` and `.` replaced, and names that end up the same get a number.
+struct Ids {
+    bare: bool, // A single pool without a name is written as a process without a collaboration
+    pools: Vec<String>,
+    lanes: HashMap<(usize, String), String>, // By pool index and lane name
+    nodes: HashMap<usize, (String, usize)>,  // Id and pool index of every node
+    flows: Vec<String>,
+    pool_flows: Vec<String>,
+}
 
-        // Collect unique lane IDs within this pool
-        let lane_ids: HashSet<String> = pool_nodes
-            .iter()
-            .filter_map(|node| node.lane.clone())
-            .collect();
-
-        // Generate laneSet if there are lanes
-        if !lane_ids.is_empty() {
-            bpmn.push_str(&format!(r#"<bpmn:laneSet id="LaneSet_{}">"#, pool_id));
-
-            for lane_id in &lane_ids {
-                bpmn.push_str(&format!(
-                    r#"<bpmn:lane id="Lane_{}" name="{}">"#,
-                    lane_id, lane_id
-                ));
-
-                // Get nodes in this lane
-                let lane_nodes: Vec<&Node> = pool_nodes
-                    .iter()
-                    .filter(|node| node.lane.as_deref() == Some(lane_id.as_str()))
-                    .cloned()
-                    .collect();
-
-                // Add flowNodeRefs
-                for node in &lane_nodes {
-                    let node_id = get_node_bpmn_id(node);
-                    bpmn.push_str(&format!(
-                        r#"<bpmn:flowNodeRef>{}</bpmn:flowNodeRef>"#,
-                        node_id
-                    ));
+impl Ids {
+    fn new(graph: &Graph) -> Self {
+        let mut ids = Ids {
+            bare: matches!(graph.pools.as_slice(), [pool] if pool.get_pool_name().is_empty() && !pool.collapsed),
+            pools: Vec::new(),
+            lanes: HashMap::new(),
+            nodes: HashMap::new(),
+            flows: Vec::new(),
+            pool_flows: Vec::new(),
+        };
+        let (mut pools, mut lanes, mut flows) = (HashSet::new(), HashSet::new(), HashSet::new());
+        for (index, pool) in graph.pools.iter().enumerate() {
+            // The pool of nodes before the first pool of the DSL has no name
+            let name = Some(clean(&pool.get_pool_name()))
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| (index + 1).to_string());
+            ids.pools.push(unique(&mut pools, name));
+            for lane in pool.get_lanes() {
+                let id = unique(&mut lanes, clean(lane.get_lane()));
+                ids.lanes.insert((index, lane.get_lane().clone()), id);
+                for node in lane.get_layers() {
+                    ids.nodes.insert(node.id, (get_node_bpmn_id(node), index));
                 }
-
-                bpmn.push_str(r#"</bpmn:lane>"#);
             }
-
-            bpmn.push_str(r#"</bpmn:laneSet>"#);
         }
-
-        // Generate flow nodes (events, tasks, etc.)
-        for node in &pool_nodes {
-            generate_flow_node(&mut bpmn, node, graph);
+        for edge in &graph.edges {
+            let id = unique(&mut flows, format!("Flow_{}_{}", edge.from, edge.to));
+            ids.flows.push(id);
         }
+        for flow in &graph.pool_flows {
+            let base = format!("Flow_{}_{}", flow.node, ids.pools[flow.pool]);
+            ids.pool_flows.push(unique(&mut flows, base));
+        }
+        ids
+    }
 
-        // Generate sequence flows
-        generate_sequence_flows(&mut bpmn, &graph, &pool_nodes);
+    fn node(&self, node: usize) -> Option<&str> {
+        self.nodes.get(&node).map(|(id, _)| id.as_str())
+    }
 
-        bpmn.push_str(r#"</bpmn:process>"#);
+    fn pool_of(&self, node: usize) -> Option<usize> {
+        self.nodes.get(&node).map(|(_, pool)| *pool)
     }
 
-    // Add BPMN diagram elements
-    bpmn.push_str(
-        r#"<bpmndi:BPMNDiagram id="BPMNDiagram_1">
-  <bpmndi:BPMNPlane id="BPMNPlane_1" bpmnElement="Collaboration_1">
-"#,
-    );
-
-    // Add BPMN shapes for participants (pools)
-    for pool in graph.get_pools() {
-        let pool_id = pool.get_pool_name();
-        bpmn.push_str(&format!(
-            r#"<bpmndi:BPMNShape id="Participant_{}_di" bpmnElement="Participant_{}" isHorizontal="true">
-    <dc:Bounds x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" />
-  </bpmndi:BPMNShape>"#,
-  pool_id,
-  pool_id,
-            /* x */ pool.x.unwrap_or(0.0),
-            /* y */ pool.y.unwrap_or(0.0),
-            /* width */ pool.width.unwrap_or(0.0),
-            /* height */ pool.height.unwrap_or(0.0),
-        ));
-
-        for lane in pool.get_lanes() {
-            let lane_id = lane.get_lane();
-            bpmn.push_str(&format!(
-                r#"<bpmndi:BPMNShape id="Lane_{}_di" bpmnElement="Lane_{}" isHorizontal="true">
-    <dc:Bounds x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" />
-  </bpmndi:BPMNShape>"#,
-                lane_id,
-                lane_id,
-                /* x */ lane.x.unwrap_or(0.0),
-                /* y */ lane.y.unwrap_or(0.0),
-                /* width */ lane.width.unwrap_or(0.0),
-                /* height */ lane.height.unwrap_or(0.0),
-            ));
-        }
+    fn lane(&self, pool: usize, lane: &str) -> &str {
+        self.lanes
+            .get(&(pool, lane.to_string()))
+            .map_or("", String::as_str)
     }
+}
 
-    // Add BPMN shapes for flow nodes
-    for pool in &graph.pools {
-        for lane in pool.get_lanes() {
-            for node in lane.get_layers() {
-                let (width, height) = if let Some(event) = &node.event {
-                    get_node_size(event)
-                } else {
-                    (100, 80)
-                };
-
-                let x = node.x.unwrap_or(0.0) + node.x_offset.unwrap_or(0.0);
-                let y = node.y.unwrap_or(0.0) + node.y_offset.unwrap_or(0.0);
-
-                bpmn.push_str(&format!(
-                    r#"<bpmndi:BPMNShape id="{}_di" bpmnElement="{}">
-                <dc:Bounds x="{:.2}" y="{:.2}" width="{}" height="{}" />
-                </bpmndi:BPMNShape>"#,
-                    get_node_bpmn_id(node),
-                    get_node_bpmn_id(node),
-                    x,
-                    y,
-                    width,
-                    height
-                ));
-            }
-        }
+/// `base`, or `base` with the first number that makes it unused
+fn unique(used: &mut HashSet<String>, base: String) -> String {
+    let mut id = base.clone();
+    let mut number = 2;
+    while !used.insert(id.clone()) {
+        id = format!("{}_{}", base, number);
+        number += 1;
     }
+    id
+}
 
-    // Add BPMN edges for sequence flows
-    for edge in &graph.edges {
-        bpmn.push_str(&format!(
-            r#"<bpmndi:BPMNEdge id="Flow_{}_{}_di" bpmnElement="Flow_{}_{}">"#,
-            edge.from, edge.to, edge.from, edge.to
-        ));
-
-        // Lisa waypoints adjusted_points-st
-        if let Some(points) = &edge.bend_points {
-            for (x, y) in points {
-                bpmn.push_str(&format!(r#"<di:waypoint x="{:.2}" y="{:.2}" />"#, x, y));
+/// A name as it can go into an XML id
+fn clean(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '_' | '-' | '.') {
+                c
+            } else {
+                '_'
             }
-        } else {
-            eprintln!(
-                "Warning: Edge {} -> {} has no adjusted_points.",
-                edge.from, edge.to
-            );
-        }
+        })
+        .collect()
+}
 
-        bpmn.push_str(r#"</bpmndi:BPMNEdge>"#);
+fn write_process<W: Write>(
+    xml: &mut XmlWriter<W>,
+    graph: &Graph,
+    ids: &Ids,
+    index: usize,
+    pool: &Pool,
+) -> io::Result<()> {
+    xml.element("bpmn:process")
+        .attribute("id", format!("Process_{}", ids.pools[index]))
+        .attribute("isExecutable", "true")
+        .start()?;
+
+    if pool
+        .get_lanes()
+        .iter()
+        .any(|lane| lane.get_lane() != DEFAULT_LANE)
+    {
+        xml.element("bpmn:laneSet")
+            .attribute("id", format!("LaneSet_{}", ids.pools[index]))
+            .start()?;
+        write_lanes(xml, ids, index, pool, None)?;
+        xml.end()?;
     }
 
-    // Sulge BPMNPlane, BPMNDiagram ja definitions lõpus
-    bpmn.push_str(
-        r#"  </bpmndi:BPMNPlane>
-</bpmndi:BPMNDiagram>
-</bpmn:definitions>
-"#,
-    );
+    for node in pool.get_lanes().iter().flat_map(|lane| lane.get_layers()) {
+        write_flow_node(xml, graph, ids, node)?;
+    }
+    for (flow, edge) in graph.edges.iter().enumerate() {
+        if ids.pool_of(edge.from) == Some(index) && ids.pool_of(edge.to) == Some(index) {
+            write_flow(xml, "bpmn:sequenceFlow", ids, flow, edge)?;
+        }
+    }
 
-    bpmn
+    xml.end()
 }
 
-fn generate_flow_node(bpmn: &mut String, node: &Node, graph: &Graph) {
-    if let Some(event) = &node.event {
-        match event {
-            // Start Events
-            BpmnEvent::Start(label)
-            | BpmnEvent::StartTimerEvent(label)
-            | BpmnEvent::StartSignalEvent(label)
-            | BpmnEvent::StartMessageEvent(label)
-            | BpmnEvent::StartConditionalEvent(label) => {
-                bpmn.push_str(&format!(
-                    r#"<bpmn:startEvent id="{}" name="{}">"#,
-                    get_node_bpmn_id(node),
-                    label
-                ));
-
-                // Add outgoing flows
-                for edge in graph.edges.iter().filter(|e| e.from == node.id) {
-                    bpmn.push_str(&format!(
-                        r#"<bpmn:outgoing>Flow_{}_{}"</bpmn:outgoing>"#,
-                        edge.from, edge.to
-                    ));
-                }
-
-                bpmn.push_str(r#"</bpmn:startEvent>"#);
-            }
-
-            // End Events
-            BpmnEvent::End(label)
-            | BpmnEvent::EndErrorEvent(label)
-            | BpmnEvent::EndCancelEvent(label)
-            | BpmnEvent::EndSignalEvent(label)
-            | BpmnEvent::EndMessageEvent(label)
-            | BpmnEvent::EndTerminateEvent(label)
-            | BpmnEvent::EndEscalationEvent(label)
-            | BpmnEvent::EndCompensationEvent(label) => {
-                bpmn.push_str(&format!(
-                    r#"<bpmn:endEvent id="{}" name="{}">"#,
-                    get_node_bpmn_id(node),
-                    label
-                ));
-
-                // Add incoming flows
-                for edge in graph.edges.iter().filter(|e| e.to == node.id) {
-                    bpmn.push_str(&format!(
-                        r#"<bpmn:incoming>Flow_{}_{}"</bpmn:incoming>"#,
-                        edge.from, edge.to
-                    ));
-                }
-
-                bpmn.push_str(r#"</bpmn:endEvent>"#);
-            }
+/// Writes the lanes nested in `parent`, or the lanes directly in the pool if there is none.
+/// A lane refers to the nodes of the lanes nested in it as well.
+fn write_lanes<W: Write>(
+    xml: &mut XmlWriter<W>,
+    ids: &Ids,
+    index: usize,
+    pool: &Pool,
+    parent: Option<&String>,
+) -> io::Result<()> {
+    for lane in pool
+        .get_lanes()
+        .iter()
+        .filter(|lane| lane.parent.as_ref() == parent && lane.get_lane() != DEFAULT_LANE)
+    {
+        let lane_id = ids.lane(index, lane.get_lane());
+        let mut lane_node_ids: Vec<String> =
+            lane.get_layers().iter().map(get_node_bpmn_id).collect();
+        lane_node_ids.extend(nested_node_ids(pool, lane.get_lane()));
+        let has_children = pool
+            .get_lanes()
+            .iter()
+            .any(|child| child.parent.as_ref() == Some(lane.get_lane()));
+
+        xml.element("bpmn:lane")
+            .attribute("id", format!("Lane_{}", lane_id))
+            .attribute("name", lane.get_lane())
+            .start()?;
+        for node_id in &lane_node_ids {
+            xml.element("bpmn:flowNodeRef").text(node_id)?;
+        }
+        if has_children {
+            xml.element("bpmn:childLaneSet")
+                .attribute("id", format!("LaneSet_{}", lane_id))
+                .start()?;
+            write_lanes(xml, ids, index, pool, Some(lane.get_lane()))?;
+            xml.end()?;
+        }
+        xml.end()?;
+    }
+    Ok(())
+}
 
-            // Tasks and Activities
-            BpmnEvent::ActivityTask(label)
-            | BpmnEvent::ActivitySubprocess(label)
-            | BpmnEvent::ActivityCallActivity(label)
-            | BpmnEvent::ActivityEventSubprocess(label)
-            | BpmnEvent::ActivityTransaction(label)
-            | BpmnEvent::TaskUser(label)
-            | BpmnEvent::TaskService(label)
-            | BpmnEvent::TaskBusinessRule(label)
-            | BpmnEvent::TaskScript(label) => {
-                let element_type = match event {
-                    BpmnEvent::ActivityTask(_) => "task",
-                    BpmnEvent::TaskUser(_) => "userTask",
-                    BpmnEvent::TaskService(_) => "serviceTask",
-                    BpmnEvent::TaskBusinessRule(_) => "businessRuleTask",
-                    BpmnEvent::TaskScript(_) => "scriptTask",
-                    BpmnEvent::ActivitySubprocess(_) => "subProcess",
-                    BpmnEvent::ActivityCallActivity(_) => "callActivity",
-                    BpmnEvent::ActivityEventSubprocess(_) => "subProcess triggeredByEvent=\"true\"",
-                    BpmnEvent::ActivityTransaction(_) => "transaction",
-                    _ => "task",
-                };
-
-                bpmn.push_str(&format!(
-                    r#"<bpmn:{} id="{}" name="{}">"#,
-                    element_type,
-                    get_node_bpmn_id(node),
-                    label
-                ));
-
-                // Add incoming flows
-                for edge in graph.edges.iter().filter(|e| e.to == node.id) {
-                    bpmn.push_str(&format!(
-                        r#"<bpmn:incoming>Flow_{}_{}"</bpmn:incoming>"#,
-                        edge.from, edge.to
-                    ));
-                }
+/// Ids of the nodes in the lanes nested in a lane, however deep
+fn nested_node_ids(pool: &Pool, lane: &String) -> Vec<String> {
+    pool.get_lanes()
+        .iter()
+        .filter(|child| child.parent.as_ref() == Some(lane))
+        .flat_map(|child| {
+            let mut ids: Vec<String> = child.get_layers().iter().map(get_node_bpmn_id).collect();
+            ids.extend(nested_node_ids(pool, child.get_lane()));
+            ids
+        })
+        .collect()
+}
 
-                // Add outgoing flows
-                for edge in graph.edges.iter().filter(|e| e.from == node.id) {
-                    bpmn.push_str(&format!(
-                        r#"<bpmn:outgoing>Flow_{}_{}"</bpmn:outgoing>"#,
-                        edge.from, edge.to
-                    ));
-                }
+fn write_flow_node<W: Write>(
+    xml: &mut XmlWriter<W>,
+    graph: &Graph,
+    ids: &Ids,
+    node: &Node,
+) -> io::Result<()> {
+    let Some(event) = &node.event else {
+        return Ok(());
+    };
+    let (element, definition) = element_of(event);
+    let id = get_node_bpmn_id(node);
+    let mut start = xml
+        .element(&format!("bpmn:{}", element))
+        .attribute("id", &id)
+        .optional_attribute("name", event.label());
+    match event {
+        BpmnEvent::ActivityEventSubprocess(_) => {
+            start = start.attribute("triggeredByEvent", "true")
+        }
+        BpmnEvent::BoundaryEvent(_, activity, cancel)
+        | BpmnEvent::BoundaryErrorEvent(_, activity, cancel)
+        | BpmnEvent::BoundaryTimerEvent(_, activity, cancel)
+        | BpmnEvent::BoundaryCancelEvent(_, activity, cancel)
+        | BpmnEvent::BoundarySignalEvent(_, activity, cancel)
+        | BpmnEvent::BoundaryMessageEvent(_, activity, cancel)
+        | BpmnEvent::BoundaryEscalationEvent(_, activity, cancel)
+        | BpmnEvent::BoundaryConditionalEvent(_, activity, cancel) => {
+            start = start
+                .optional_attribute("cancelActivity", (!cancel).then_some("false"))
+                .optional_attribute("attachedToRef", attached_id(graph, *activity));
+        }
+        BpmnEvent::BoundaryCompensationEvent(_, activity) => {
+            start = start.optional_attribute("attachedToRef", attached_id(graph, *activity));
+        }
+        _ => {}
+    }
 
-                bpmn.push_str(&format!(r#"</bpmn:{}>"#, element_type));
-            }
+    // Data references take no part in the flow
+    if matches!(
+        event,
+        BpmnEvent::DataStoreReference(_) | BpmnEvent::DataObjectReference(_)
+    ) {
+        return start.empty();
+    }
+    start.start()?;
+    // Only sequence flows are listed, message flows to other pools are not
+    let sequence_flows = || {
+        graph
+            .edges
+            .iter()
+            .enumerate()
+            .filter(|(_, edge)| ids.pool_of(edge.from) == ids.pool_of(edge.to))
+    };
+    for (index, edge) in sequence_flows() {
+        if edge.to == node.id {
+            xml.element("bpmn:incoming").text(&ids.flows[index])?;
+        }
+    }
+    for (index, edge) in sequence_flows() {
+        if edge.from == node.id {
+            xml.element("bpmn:outgoing").text(&ids.flows[index])?;
+        }
+    }
+    if let Some(definition) = definition {
+        xml.element(&format!("bpmn:{}", definition))
+            .attribute("id", format!("EventDefinition_{}", node.id))
+            .empty()?;
+    }
+    xml.end()
+}
 
-            // Gateways
-            BpmnEvent::GatewayExclusive
-            | BpmnEvent::GatewayInclusive
-            | BpmnEvent::GatewayJoin(_) => {
-                let element_type = match event {
-                    BpmnEvent::GatewayExclusive => "exclusiveGateway",
-                    BpmnEvent::GatewayInclusive => "inclusiveGateway",
-                    BpmnEvent::GatewayJoin(_) => "parallelGateway",
-                    _ => "exclusiveGateway",
-                };
-
-                bpmn.push_str(&format!(
-                    r#"<bpmn:{} id="{}">"#,
-                    element_type,
-                    get_node_bpmn_id(node),
-                ));
-
-                // Add incoming flows
-                for edge in graph.edges.iter().filter(|e| e.to == node.id) {
-                    bpmn.push_str(&format!(
-                        r#"<bpmn:incoming>Flow_{}_{}"</bpmn:incoming>"#,
-                        edge.from, edge.to
-                    ));
-                }
+fn attached_id(graph: &Graph, activity: usize) -> Option<String> {
+    graph.get_node_by_id(activity).map(get_node_bpmn_id)
+}
 
-                // Add outgoing flows
-                for edge in graph.edges.iter().filter(|e| e.from == node.id) {
-                    bpmn.push_str(&format!(
-                        r#"<bpmn:outgoing>Flow_{}_{}"</bpmn:outgoing>"#,
-                        edge.from, edge.to
-                    ));
-                }
+/// The BPMN element of a node and the event definition in it
+fn element_of(event: &BpmnEvent) -> (&'static str, Option<&'static str>) {
+    match event {
+        BpmnEvent::Start(_) => ("startEvent", None),
+        BpmnEvent::StartTimerEvent(_) => ("startEvent", Some("timerEventDefinition")),
+        BpmnEvent::StartSignalEvent(_) => ("startEvent", Some("signalEventDefinition")),
+        BpmnEvent::StartMessageEvent(_) => ("startEvent", Some("messageEventDefinition")),
+        BpmnEvent::StartConditionalEvent(_) => ("startEvent", Some("conditionalEventDefinition")),
+        BpmnEvent::Middle(_) => ("intermediateThrowEvent", None),
+        BpmnEvent::End(_) => ("endEvent", None),
+        BpmnEvent::EndErrorEvent(_) => ("endEvent", Some("errorEventDefinition")),
+        BpmnEvent::EndCancelEvent(_) => ("endEvent", Some("cancelEventDefinition")),
+        BpmnEvent::EndSignalEvent(_) => ("endEvent", Some("signalEventDefinition")),
+        BpmnEvent::EndMessageEvent(_) => ("endEvent", Some("messageEventDefinition")),
+        BpmnEvent::EndTerminateEvent(_) => ("endEvent", Some("terminateEventDefinition")),
+        BpmnEvent::EndEscalationEvent(_) => ("endEvent", Some("escalationEventDefinition")),
+        BpmnEvent::EndCompensationEvent(_) => ("endEvent", Some("compensateEventDefinition")),
+        BpmnEvent::BoundaryEvent(..) => ("boundaryEvent", None),
+        BpmnEvent::BoundaryErrorEvent(..) => ("boundaryEvent", Some("errorEventDefinition")),
+        BpmnEvent::BoundaryTimerEvent(..) => ("boundaryEvent", Some("timerEventDefinition")),
+        BpmnEvent::BoundaryCancelEvent(..) => ("boundaryEvent", Some("cancelEventDefinition")),
+        BpmnEvent::BoundarySignalEvent(..) => ("boundaryEvent", Some("signalEventDefinition")),
+        BpmnEvent::BoundaryMessageEvent(..) => ("boundaryEvent", Some("messageEventDefinition")),
+        BpmnEvent::BoundaryEscalationEvent(..) => {
+            ("boundaryEvent", Some("escalationEventDefinition"))
+        }
+        BpmnEvent::BoundaryConditionalEvent(..) => {
+            ("boundaryEvent", Some("conditionalEventDefinition"))
+        }
+        BpmnEvent::BoundaryCompensationEvent(..) => {
+            ("boundaryEvent", Some("compensateEventDefinition"))
+        }
+        BpmnEvent::ActivityTask(_) => ("task", None),
+        BpmnEvent::TaskUser(_) => ("userTask", None),
+        BpmnEvent::TaskService(_) => ("serviceTask", None),
+        BpmnEvent::TaskBusinessRule(_) => ("businessRuleTask", None),
+        BpmnEvent::TaskScript(_) => ("scriptTask", None),
+        BpmnEvent::ActivitySubprocess(_) | BpmnEvent::ActivityEventSubprocess(_) => {
+            ("subProcess", None)
+        }
+        BpmnEvent::ActivityCallActivity(_) => ("callActivity", None),
+        BpmnEvent::ActivityTransaction(_) => ("transaction", None),
+        // A join gateway on its own merges whichever branch arrives
+        BpmnEvent::GatewayExclusive | BpmnEvent::GatewayJoin(_) => ("exclusiveGateway", None),
+        BpmnEvent::GatewayInclusive => ("inclusiveGateway", None),
+        BpmnEvent::GatewayParallel => ("parallelGateway", None),
+        BpmnEvent::GatewayEvent => ("eventBasedGateway", None),
+        BpmnEvent::DataStoreReference(_) => ("dataStoreReference", None),
+        BpmnEvent::DataObjectReference(_) => ("dataObjectReference", None),
+    }
+}
 
-                bpmn.push_str(&format!(r#"</bpmn:{}>"#, element_type));
-            }
+fn write_flow<W: Write>(
+    xml: &mut XmlWriter<W>,
+    element: &str,
+    ids: &Ids,
+    index: usize,
+    edge: &Edge,
+) -> io::Result<()> {
+    let (Some(from), Some(to)) = (ids.node(edge.from), ids.node(edge.to)) else {
+        return Ok(());
+    };
+    // Branch texts become the name of the flow
+    xml.element(element)
+        .attribute("id", &ids.flows[index])
+        .optional_attribute("name", edge.text.as_ref())
+        .attribute("sourceRef", from)
+        .attribute("targetRef", to)
+        .empty()
+}
 
-            // Data Objects
-            BpmnEvent::DataStoreReference(label) => {
-                bpmn.push_str(&format!(
-                    r#"<bpmn:dataStoreReference id="{}" name="{}" />"#,
-                    get_node_bpmn_id(node),
-                    label
-                ));
-            }
-            BpmnEvent::DataObjectReference(label) => {
-                bpmn.push_str(&format!(
-                    r#"<bpmn:dataObjectReference id="{}" name="{}" />"#,
-                    get_node_bpmn_id(node),
-                    label
-                ));
-            }
-            _ => {}
+fn write_diagram<W: Write>(xml: &mut XmlWriter<W>, graph: &Graph, ids: &Ids) -> io::Result<()> {
+    xml.element("bpmndi:BPMNDiagram")
+        .attribute("id", "BPMNDiagram_1")
+        .start()?;
+    let plane = if ids.bare {
+        format!("Process_{}", ids.pools[0])
+    } else {
+        "Collaboration_1".to_string()
+    };
+    xml.element("bpmndi:BPMNPlane")
+        .attribute("id", "BPMNPlane_1")
+        .attribute("bpmnElement", plane)
+        .start()?;
+
+    // Pools and lanes are horizontal bands unless the flow goes top to bottom
+    let is_horizontal = graph.orientation != Orientation::TopToBottom;
+    for (index, pool) in graph.pools.iter().enumerate() {
+        if !ids.bare {
+            let participant = format!("Participant_{}", ids.pools[index]);
+            write_shape(
+                xml,
+                &participant,
+                Some(is_horizontal),
+                bounds(pool.x, pool.y, pool.width, pool.height),
+                None,
+            )?;
+        }
+        for lane in pool
+            .get_lanes()
+            .iter()
+            .filter(|lane| lane.get_lane() != DEFAULT_LANE)
+        {
+            let lane_id = format!("Lane_{}", ids.lane(index, lane.get_lane()));
+            write_shape(
+                xml,
+                &lane_id,
+                Some(is_horizontal),
+                bounds(lane.x, lane.y, lane.width, lane.height),
+                None,
+            )?;
         }
     }
-}
 
-fn generate_sequence_flows(bpmn: &mut String, graph: &Graph, pool_nodes: &Vec<&Node>) {
-    let node_ids: HashSet<usize> = pool_nodes.iter().map(|node| node.id).collect();
+    for node in graph.nodes() {
+        let (width, height) = node.event.as_ref().map_or((100, 80), get_node_size);
+        let x = node.x.unwrap_or(0.0) + node.x_offset.unwrap_or(0.0);
+        let y = node.y.unwrap_or(0.0) + node.y_offset.unwrap_or(0.0);
+        write_shape(
+            xml,
+            &get_node_bpmn_id(node),
+            None,
+            (x, y, width as f64, height as f64),
+            node.label_bounds(graph.orientation),
+        )?;
+    }
 
-    for edge in &graph.edges {
-        if node_ids.contains(&edge.from) && node_ids.contains(&edge.to) {
-            let from_node = graph.get_node_by_id(edge.from).unwrap();
-            let to_node = graph.get_node_by_id(edge.to).unwrap();
+    for (edge, flow) in graph.edges.iter().zip(&ids.flows) {
+        write_edge(xml, flow, &edge.bend_points, edge.label_bounds)?;
+    }
+    for (pool_flow, flow) in graph.pool_flows.iter().zip(&ids.pool_flows) {
+        write_edge(xml, flow, &pool_flow.bend_points, pool_flow.label_bounds)?;
+    }
 
-            let source_ref = get_node_bpmn_id(from_node);
-            let target_ref = get_node_bpmn_id(to_node);
+    xml.end()?;
+    xml.end()
+}
 
-            // Lisa sequenceFlow element
-            bpmn.push_str(&format!(
-                r#"<bpmn:sequenceFlow id="Flow_{}_{}" sourceRef="{}" targetRef="{}" />"#,
-                edge.from, edge.to, source_ref, target_ref
-            ));
+fn write_edge<W: Write>(
+    xml: &mut XmlWriter<W>,
+    flow: &str,
+    points: &Option<Vec<(f64, f64)>>,
+    label: Option<(f64, f64, f64, f64)>,
+) -> io::Result<()> {
+    xml.element("bpmndi:BPMNEdge")
+        .attribute("id", format!("{}_di", flow))
+        .attribute("bpmnElement", flow)
+        .start()?;
+    for (x, y) in points.iter().flatten() {
+        xml.element("di:waypoint")
+            .attribute("x", number(*x))
+            .attribute("y", number(*y))
+            .empty()?;
+    }
+    write_label(xml, label)?;
+    xml.end()
+}
 
-            // Lisa BPMNEdge element ja waypoints
-            bpmn.push_str(&format!(
-                r#"<bpmndi:BPMNEdge id="Flow_{}_{}_di" bpmnElement="Flow_{}_{}">"#,
-                edge.from, edge.to, edge.from, edge.to
-            ));
+fn write_shape<W: Write>(
+    xml: &mut XmlWriter<W>,
+    element: &str,
+    is_horizontal: Option<bool>,
+    (x, y, width, height): (f64, f64, f64, f64),
+    label: Option<(f64, f64, f64, f64)>,
+) -> io::Result<()> {
+    xml.element("bpmndi:BPMNShape")
+        .attribute("id", format!("{}_di", element))
+        .attribute("bpmnElement", element)
+        .optional_attribute("isHorizontal", is_horizontal)
+        .start()?;
+    xml.element("dc:Bounds")
+        .attribute("x", number(x))
+        .attribute("y", number(y))
+        .attribute("width", number(width))
+        .attribute("height", number(height))
+        .empty()?;
+    write_label(xml, label)?;
+    xml.end()
+}
 
-            if let Some(points) = &edge.bend_points {
-                for (x, y) in points {
-                    bpmn.push_str(&format!(r#"<di:waypoint x="{:.2}" y="{:.2}" />"#, x, y));
-                }
-            }
+/// Label element of a shape or edge, nothing when there is no label to place
+fn write_label<W: Write>(
+    xml: &mut XmlWriter<W>,
+    bounds: Option<(f64, f64, f64, f64)>,
+) -> io::Result<()> {
+    let Some((x, y, width, height)) = bounds else {
+        return Ok(());
+    };
+    xml.element("bpmndi:BPMNLabel").start()?;
+    xml.element("dc:Bounds")
+        .attribute("x", number(x))
+        .attribute("y", number(y))
+        .attribute("width", number(width))
+        .attribute("height", number(height))
+        .empty()?;
+    xml.end()
+}
 
-            bpmn.push_str(r#"</bpmndi:BPMNEdge>"#);
-        }
-    }
+fn bounds(
+    x: Option<f64>,
+    y: Option<f64>,
+    width: Option<f64>,
+    height: Option<f64>,
+) -> (f64, f64, f64, f64) {
+    (
+        x.unwrap_or(0.0),
+        y.unwrap_or(0.0),
+        width.unwrap_or(0.0),
+        height.unwrap_or(0.0),
+    )
 }
 
-fn get_node_bpmn_id(node: &Node) -> String {
+pub(crate) fn number(value: f64) -> String {
+    format!("{:.2}", value)
+}
+
+pub(crate) fn get_node_bpmn_id(node: &Node) -> String {
     if let Some(event) = &node.event {
         match event {
             BpmnEvent::Start(_)
@@ -398,6 +578,8 @@ fn get_node_bpmn_id(node: &Node) -> String {
             | BpmnEvent::StartMessageEvent(_)
             | BpmnEvent::StartConditionalEvent(_) => format!("StartEvent_{}", node.id),
 
+            BpmnEvent::Middle(_) => format!("Event_{}", node.id),
+
             BpmnEvent::End(_)
             | BpmnEvent::EndErrorEvent(_)
             | BpmnEvent::EndCancelEvent(_)
@@ -409,6 +591,16 @@ fn get_node_bpmn_id(node: &Node) -> String {
                 format!("EndEvent_{}", node.id)
             }
 
+            BpmnEvent::BoundaryEvent(..)
+            | BpmnEvent::BoundaryErrorEvent(..)
+            | BpmnEvent::BoundaryTimerEvent(..)
+            | BpmnEvent::BoundaryCancelEvent(..)
+            | BpmnEvent::BoundarySignalEvent(..)
+            | BpmnEvent::BoundaryMessageEvent(..)
+            | BpmnEvent::BoundaryEscalationEvent(..)
+            | BpmnEvent::BoundaryConditionalEvent(..)
+            | BpmnEvent::BoundaryCompensationEvent(..) => format!("BoundaryEvent_{}", node.id),
+
             BpmnEvent::ActivityTask(_)
             | BpmnEvent::TaskUser(_)
             | BpmnEvent::TaskService(_)
@@ -422,71 +614,14 @@ fn get_node_bpmn_id(node: &Node) -> String {
 
             BpmnEvent::GatewayExclusive
             | BpmnEvent::GatewayInclusive
+            | BpmnEvent::GatewayParallel
+            | BpmnEvent::GatewayEvent
             | BpmnEvent::GatewayJoin(_) => format!("Gateway_{}", node.id),
 
             BpmnEvent::DataStoreReference(_) => format!("DataStoreReference_{}", node.id),
             BpmnEvent::DataObjectReference(_) => format!("DataObjectReference_{}", node.id),
-
-            // Add other event types as needed
-            _ => format!("Node_{}", node.id),
         }
     } else {
         format!("Node_{}", node.id)
     }
 }
-
-pub fn get_node_size(event: &BpmnEvent) -> (usize, usize) {
-    match event {
-        // Start Events
-        BpmnEvent::Start(_)
-        | BpmnEvent::StartTimerEvent(_)
-        | BpmnEvent::StartSignalEvent(_)
-        | BpmnEvent::StartMessageEvent(_)
-        | BpmnEvent::StartConditionalEvent(_) => (36, 36),
-
-        // End Events
-        BpmnEvent::End(_)
-        | BpmnEvent::EndErrorEvent(_)
-        | BpmnEvent::EndCancelEvent(_)
-        | BpmnEvent::EndSignalEvent(_)
-        | BpmnEvent::EndMessageEvent(_)
-        | BpmnEvent::EndTerminateEvent(_)
-        | BpmnEvent::EndEscalationEvent(_)
-        | BpmnEvent::EndCompensationEvent(_) => (36, 36),
-
-        // Gateways
-        BpmnEvent::GatewayExclusive | BpmnEvent::GatewayInclusive | BpmnEvent::GatewayJoin(_) => {
-            (50, 50)
-        }
-
-        // Activities
-        BpmnEvent::ActivityTask(_)
-        | BpmnEvent::ActivityCallActivity(_)
-        | BpmnEvent::TaskUser(_)
-        | BpmnEvent::TaskService(_)
-        | BpmnEvent::TaskBusinessRule(_)
-        | BpmnEvent::TaskScript(_) => (100, 80),
-
-        // Subprocesses and Transactions (expanded)
-        BpmnEvent::ActivitySubprocess(_)
-        | BpmnEvent::ActivityEventSubprocess(_)
-        | BpmnEvent::ActivityTransaction(_) => (350, 200),
-
-        // Data Objects
-        BpmnEvent::DataStoreReference(_) => (50, 50),
-        BpmnEvent::DataObjectReference(_) => (36, 50),
-
-        // Default case
-        _ => (100, 80),
-    }
-}
-
-pub fn export_to_xml(bpmn: &String) {
-    // Write BPMN to file
-    let file_path = "generated_bpmn.bpmn";
-    let mut file = File::create(file_path).expect("Unable to create file");
-    file.write_all(bpmn.as_bytes())
-        .expect("Unable to write data");
-
-    println!("BPMN file generated at: {}", file_path);
-}
@@ -0,0 +1,256 @@
+// validate.rs
+use crate::diagnostics::Diagnostic;
+use crate::from_xml::is;
+use roxmltree::{Document, Node as XmlNode};
+use std::collections::{HashMap, HashSet};
+
+const MODEL_NAMESPACE: &str = "http://www.omg.org/spec/BPMN/20100524/MODEL";
+const DI_NAMESPACE: &str = "http://www.omg.org/spec/BPMN/20100524/DI";
+
+// Attributes that hold the id of another element
+const REFERENCE_ATTRIBUTES: &[&str] = &[
+    "sourceRef",
+    "targetRef",
+    "attachedToRef",
+    "processRef",
+    "default",
+    "dataObjectRef",
+    "dataStoreRef",
+    "bpmnElement",
+    "sourceElement",
+    "targetElement",
+];
+
+// Elements whose text is the id of another element
+const REFERENCE_ELEMENTS: &[&str] = &[
+    "incoming",
+    "outgoing",
+    "flowNodeRef",
+    "sourceRef",
+    "targetRef",
+];
+
+// Model elements that need a shape or an edge in the diagram, next to the flow nodes
+const DRAWN: &[&str] = &[
+    "participant",
+    "lane",
+    "sequenceFlow",
+    "messageFlow",
+    "association",
+    "dataInputAssociation",
+    "dataOutputAssociation",
+    "textAnnotation",
+    "dataObjectReference",
+    "dataStoreReference",
+];
+
+/// Checks BPMN 2.0 XML against the rules modelers rely on to open it: ids are unique NCNames,
+/// every reference resolves, flow nodes list exactly the sequence flows that start and end at
+/// them, sequence flows stay inside one process or sub-process, and everything in the model has
+/// a shape or an edge in the diagram. Returns one error for every problem, none if it is valid.
+pub fn validate_bpmn(xml: &str) -> Vec<Diagnostic> {
+    let document = match Document::parse(xml) {
+        Ok(document) => document,
+        Err(error) => {
+            let position = error.pos();
+            return vec![Diagnostic {
+                line: Some(position.row as usize),
+                column: Some(position.col as usize),
+                ..Diagnostic::error(format!("Invalid XML: {}", error))
+            }];
+        }
+    };
+    let mut validator = Validator {
+        document: &document,
+        problems: Vec::new(),
+    };
+    let ids = validator.check_ids();
+    validator.check_references(&ids);
+    validator.check_flows(&ids);
+    validator.check_diagram();
+    validator.problems
+}
+
+struct Validator<'a, 'input> {
+    document: &'a Document<'input>,
+    problems: Vec<Diagnostic>,
+}
+
+impl<'a, 'input> Validator<'a, 'input> {
+    /// Checks that ids are unique and valid NCNames, and returns the elements by id
+    fn check_ids(&mut self) -> HashMap<&'a str, XmlNode<'a, 'input>> {
+        let mut ids = HashMap::new();
+        for element in self.document.descendants().filter(|node| node.is_element()) {
+            let Some(id) = element.attribute("id") else {
+                continue;
+            };
+            if !is_ncname(id) {
+                self.problem(element, format!("The id '{}' is not a valid NCName", id));
+            }
+            if ids.insert(id, element).is_some() {
+                self.problem(element, format!("The id '{}' is used more than once", id));
+            }
+        }
+        ids
+    }
+
+    fn check_references(&mut self, ids: &HashMap<&str, XmlNode>) {
+        for element in self.document.descendants().filter(|node| node.is_element()) {
+            for attribute in REFERENCE_ATTRIBUTES {
+                if let Some(id) = element.attribute(*attribute) {
+                    if !ids.contains_key(id) {
+                        self.problem(
+                            element,
+                            format!(
+                                "The {} '{}' of '{}' does not exist",
+                                attribute,
+                                id,
+                                element.tag_name().name()
+                            ),
+                        );
+                    }
+                }
+            }
+            let name = element.tag_name().name();
+            if is_model(&element) && REFERENCE_ELEMENTS.contains(&name) {
+                let id = element.text().unwrap_or_default().trim();
+                if !ids.contains_key(id) {
+                    self.problem(element, format!("The {} '{}' does not exist", name, id));
+                }
+            }
+        }
+    }
+
+    /// Checks that sequence flows and the incoming and outgoing lists of flow nodes agree, and
+    /// that both ends of a sequence flow are in the same process or sub-process as the flow
+    fn check_flows(&mut self, ids: &HashMap<&str, XmlNode>) {
+        let flows = self
+            .document
+            .descendants()
+            .filter(|node| is_model(node) && is(node, "sequenceFlow"));
+        for flow in flows {
+            let id = flow.attribute("id").unwrap_or_default();
+            for (end, list) in [("sourceRef", "outgoing"), ("targetRef", "incoming")] {
+                let Some(node) = flow.attribute(end).and_then(|other| ids.get(other)) else {
+                    continue;
+                };
+                if node.parent() != flow.parent() {
+                    self.problem(
+                        flow,
+                        format!(
+                            "The sequence flow '{}' leaves its process, its {} '{}' is in another one",
+                            id,
+                            end,
+                            node.attribute("id").unwrap_or_default()
+                        ),
+                    );
+                }
+                if !listed(node, list).any(|other| other == id) {
+                    self.problem(
+                        *node,
+                        format!(
+                            "'{}' does not list the sequence flow '{}' as {}",
+                            node.attribute("id").unwrap_or_default(),
+                            id,
+                            list
+                        ),
+                    );
+                }
+            }
+        }
+
+        // The other way round, every listed flow has to start or end here
+        let nodes = self
+            .document
+            .descendants()
+            .filter(|node| is_model(node) && is_flow_node(node));
+        for node in nodes {
+            let id = node.attribute("id").unwrap_or_default();
+            for (list, end) in [("incoming", "targetRef"), ("outgoing", "sourceRef")] {
+                for flow_id in listed(&node, list) {
+                    let Some(flow) = ids.get(flow_id) else {
+                        continue;
+                    };
+                    if !is(flow, "sequenceFlow") || flow.attribute(end) != Some(id) {
+                        self.problem(
+                            node,
+                            format!(
+                                "'{}' lists '{}' as {}, but it is not a sequence flow with {} '{}'",
+                                id, flow_id, list, end, id
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Checks that every element that is drawn in modelers has a shape or an edge
+    fn check_diagram(&mut self) {
+        let drawn: HashSet<&str> = self
+            .document
+            .descendants()
+            .filter(|node| {
+                node.tag_name().namespace() == Some(DI_NAMESPACE)
+                    && (is(node, "BPMNShape") || is(node, "BPMNEdge"))
+            })
+            .filter_map(|node| node.attribute("bpmnElement"))
+            .collect();
+        let elements = self.document.descendants().filter(|node| {
+            is_model(node) && (is_flow_node(node) || DRAWN.contains(&node.tag_name().name()))
+        });
+        for element in elements {
+            let id = element.attribute("id").unwrap_or_default();
+            if !drawn.contains(id) {
+                self.problem(
+                    element,
+                    format!(
+                        "The {} '{}' has no shape or edge in the diagram",
+                        element.tag_name().name(),
+                        id
+                    ),
+                );
+            }
+        }
+    }
+
+    fn problem(&mut self, element: XmlNode, message: String) {
+        let position = self.document.text_pos_at(element.range().start);
+        self.problems.push(Diagnostic {
+            line: Some(position.row as usize),
+            column: Some(position.col as usize),
+            ..Diagnostic::error(message)
+        });
+    }
+}
+
+/// Ids in the `incoming` or `outgoing` elements of a flow node
+fn listed<'a>(node: &XmlNode<'a, '_>, list: &'a str) -> impl Iterator<Item = &'a str> {
+    node.children()
+        .filter(move |child| is_model(child) && is(child, list))
+        .map(|child| child.text().unwrap_or_default().trim())
+}
+
+/// Events, activities and gateways, the elements sequence flows connect
+fn is_flow_node(node: &XmlNode) -> bool {
+    let name = node.tag_name().name();
+    name.ends_with("Event")
+        || name.ends_with("Task")
+        || name.ends_with("Gateway")
+        || ["task", "subProcess", "callActivity", "transaction"].contains(&name)
+}
+
+fn is_model(node: &XmlNode) -> bool {
+    node.is_element() && node.tag_name().namespace() == Some(MODEL_NAMESPACE)
+}
+
+/// Whether the id is an XML name without a colon, letters, digits and `_`, `-` and `.` that does
+/// not start with a digit, `-` or `.`
+fn is_ncname(id: &str) -> bool {
+    let mut chars = id.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+    (first.is_alphabetic() || first == '_')
+        && chars.all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '\u{B7}'))
+}
@@ -0,0 +1,47 @@
+// wasm.rs
+
+use crate::{Diagnostics, LayoutOptions, Severity};
+use serde_json::{json, Value};
+use wasm_bindgen::prelude::*;
+
+/// Converts DSL input to BPMN 2.0 XML, throws the diagnostics text if it does not parse
+#[wasm_bindgen(js_name = dslToBpmn)]
+pub fn dsl_to_bpmn(input: &str) -> Result<String, JsError> {
+    crate::dsl_to_bpmn(input, &LayoutOptions::default()).map_err(|d| JsError::new(&d.to_string()))
+}
+
+/// Converts DSL input to an SVG image, throws the diagnostics text if it does not parse
+#[wasm_bindgen(js_name = dslToSvg)]
+pub fn dsl_to_svg(input: &str) -> Result<String, JsError> {
+    crate::dsl_to_svg(input, &LayoutOptions::default()).map_err(|d| JsError::new(&d.to_string()))
+}
+
+/// Returns the problems in the input as an array of
+/// `{ severity, message, line, column }` objects: the errors when it does not parse,
+/// otherwise warnings for layout hints that cannot be kept
+#[wasm_bindgen]
+pub fn diagnostics(input: &str) -> JsValue {
+    let diagnostics = match crate::parse(input) {
+        Ok(mut graph) => crate::layout(&mut graph, &LayoutOptions::default()),
+        Err(diagnostics) => diagnostics,
+    };
+    let json = diagnostics_to_json(&diagnostics).to_string();
+    js_sys::JSON::parse(&json).unwrap_or(JsValue::NULL)
+}
+
+pub(crate) fn diagnostics_to_json(diagnostics: &Diagnostics) -> Value {
+    diagnostics
+        .iter()
+        .map(|diagnostic| {
+            json!({
+                "severity": match diagnostic.severity {
+                    Severity::Error => "error",
+                    Severity::Warning => "warning",
+                },
+                "message": diagnostic.message,
+                "line": diagnostic.line,
+                "column": diagnostic.column
+            })
+        })
+        .collect()
+}
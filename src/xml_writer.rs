@@ -0,0 +1,257 @@
+// xml_writer.rs
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::io::{self, Write};
+
+const INDENT: &str = "  ";
+
+/// Writes XML element by element to any writer, without holding the document in memory.
+/// Text and attribute values are escaped, every element goes on a line of its own indented by its
+/// depth, and prefixed names must belong to a namespace declared on the element or around it.
+pub struct XmlWriter<W: Write> {
+    out: W,
+    open: Vec<Open>,
+    prefixes: Vec<String>, // Prefixes declared on the open elements, innermost last
+    empty: bool,           // Nothing is written yet
+    margin: String,        // Indentation of the whole output, for a fragment of a document
+}
+
+struct Open {
+    name: String,
+    prefixes: usize, // Number of prefixes declared on this element
+    children: bool,
+}
+
+impl<W: Write> XmlWriter<W> {
+    pub fn new(out: W) -> Self {
+        XmlWriter {
+            out,
+            open: Vec::new(),
+            prefixes: Vec::new(),
+            empty: true,
+            margin: String::new(),
+        }
+    }
+
+    /// Writes a fragment that goes into a document at a line indented by `margin`, inside
+    /// elements that declare `prefixes` already
+    pub fn fragment(out: W, margin: &str, prefixes: &[&str]) -> Self {
+        XmlWriter {
+            out,
+            open: Vec::new(),
+            prefixes: prefixes.iter().map(|prefix| prefix.to_string()).collect(),
+            empty: true,
+            margin: margin.to_string(),
+        }
+    }
+
+    /// Writes the XML declaration, it has to come first
+    pub fn declaration(&mut self) -> io::Result<()> {
+        if !self.empty {
+            return Err(invalid("The XML declaration has to come first".to_string()));
+        }
+        self.empty = false;
+        self.out
+            .write_all(br#"<?xml version="1.0" encoding="UTF-8"?>"#)
+    }
+
+    /// Starts an element, it is written once [`Element::start`], [`Element::empty`] or
+    /// [`Element::text`] is called on it
+    pub fn element(&mut self, name: &str) -> Element<'_, W> {
+        Element {
+            writer: self,
+            name: name.to_string(),
+            attributes: Vec::new(),
+            namespaces: Vec::new(),
+        }
+    }
+
+    /// Closes the element started last
+    pub fn end(&mut self) -> io::Result<()> {
+        let open = self
+            .open
+            .pop()
+            .ok_or_else(|| invalid("There is no element to end".to_string()))?;
+        self.prefixes.truncate(self.prefixes.len() - open.prefixes);
+        if open.children {
+            self.new_line(self.open.len())?;
+        }
+        write!(self.out, "</{}>", open.name)
+    }
+
+    /// Ends the document with a line break and gives the writer back. Every element has to be
+    /// closed by then.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(open) = self.open.last() {
+            return Err(invalid(format!(
+                "The element '{}' is not closed",
+                open.name
+            )));
+        }
+        self.out.write_all(b"\n")?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn new_line(&mut self, depth: usize) -> io::Result<()> {
+        if self.empty {
+            self.empty = false;
+            return self.out.write_all(self.margin.as_bytes());
+        }
+        self.out.write_all(b"\n")?;
+        self.out.write_all(self.margin.as_bytes())?;
+        for _ in 0..depth {
+            self.out.write_all(INDENT.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the prefix of a name is declared, `xml` and `xmlns` are always known
+    fn check_prefix(&self, name: &str, declared: &[(String, String)]) -> io::Result<()> {
+        let Some((prefix, _)) = name.split_once(':') else {
+            return Ok(());
+        };
+        let known = prefix == "xml"
+            || prefix == "xmlns"
+            || self.prefixes.iter().any(|other| other == prefix)
+            || declared.iter().any(|(other, _)| other == prefix);
+        if known {
+            Ok(())
+        } else {
+            Err(invalid(format!(
+                "The prefix '{}' of '{}' is not declared",
+                prefix, name
+            )))
+        }
+    }
+}
+
+/// An element with its attributes, before it is written
+pub struct Element<'a, W: Write> {
+    writer: &'a mut XmlWriter<W>,
+    name: String,
+    attributes: Vec<(String, String)>,
+    namespaces: Vec<(String, String)>, // Prefix and URI
+}
+
+impl<'a, W: Write> Element<'a, W> {
+    pub fn attribute(mut self, name: &str, value: impl Display) -> Self {
+        self.attributes.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Adds the attribute only if there is a value
+    pub fn optional_attribute(self, name: &str, value: Option<impl Display>) -> Self {
+        match value {
+            Some(value) => self.attribute(name, value),
+            None => self,
+        }
+    }
+
+    /// Declares a namespace prefix for this element and everything in it, an empty prefix
+    /// declares the default namespace
+    pub fn namespace(mut self, prefix: &str, uri: &str) -> Self {
+        self.namespaces.push((prefix.to_string(), uri.to_string()));
+        self
+    }
+
+    /// Writes the start tag, the element stays open until [`XmlWriter::end`]
+    pub fn start(self) -> io::Result<()> {
+        let (writer, name, prefixes) = self.write_open_tag()?;
+        writer.out.write_all(b">")?;
+        writer.open.push(Open {
+            name,
+            prefixes,
+            children: false,
+        });
+        Ok(())
+    }
+
+    /// Writes the element without content as `<name />`
+    pub fn empty(self) -> io::Result<()> {
+        let (writer, _, prefixes) = self.write_open_tag()?;
+        writer.prefixes.truncate(writer.prefixes.len() - prefixes);
+        writer.out.write_all(b" />")
+    }
+
+    /// Writes the element with only text in it, on one line
+    pub fn text(self, text: &str) -> io::Result<()> {
+        let (writer, name, prefixes) = self.write_open_tag()?;
+        writer.prefixes.truncate(writer.prefixes.len() - prefixes);
+        write!(writer.out, ">{}</{}>", escape(text, false), name)
+    }
+
+    /// Writes `<name` with the namespaces and attributes, and keeps the prefixes declared here
+    /// in scope. Returns the writer, the name and the number of prefixes declared.
+    fn write_open_tag(self) -> io::Result<(&'a mut XmlWriter<W>, String, usize)> {
+        let Element {
+            writer,
+            name,
+            attributes,
+            namespaces,
+        } = self;
+        writer.check_prefix(&name, &namespaces)?;
+        for (attribute, _) in &attributes {
+            writer.check_prefix(attribute, &namespaces)?;
+        }
+        if let Some(parent) = writer.open.last_mut() {
+            parent.children = true;
+        }
+        writer.new_line(writer.open.len())?;
+        write!(writer.out, "<{}", name)?;
+        for (prefix, uri) in &namespaces {
+            let separator = if prefix.is_empty() { "" } else { ":" };
+            write!(
+                writer.out,
+                r#" xmlns{}{}="{}""#,
+                separator,
+                prefix,
+                escape(uri, true)
+            )?;
+        }
+        for (attribute, value) in &attributes {
+            write!(writer.out, r#" {}="{}""#, attribute, escape(value, true))?;
+        }
+        writer
+            .prefixes
+            .extend(namespaces.iter().map(|(prefix, _)| prefix.clone()));
+        Ok((writer, name, namespaces.len()))
+    }
+}
+
+/// Escapes the characters that would end text or an attribute value early. In attributes line
+/// breaks and tabs are written as character references so they survive attribute normalization.
+/// Control characters XML cannot hold at all are left out.
+pub fn escape(value: &str, attribute: bool) -> Cow<'_, str> {
+    let special = |c: char| {
+        matches!(c, '&' | '<' | '>' | '\r')
+            || (attribute && matches!(c, '"' | '\n' | '\t'))
+            || is_forbidden(c)
+    };
+    if !value.contains(special) {
+        return Cow::Borrowed(value);
+    }
+    let mut escaped = String::with_capacity(value.len() + 16);
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\r' => escaped.push_str("&#13;"),
+            '"' if attribute => escaped.push_str("&quot;"),
+            '\n' if attribute => escaped.push_str("&#10;"),
+            '\t' if attribute => escaped.push_str("&#9;"),
+            c if is_forbidden(c) => {}
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+fn is_forbidden(c: char) -> bool {
+    (c < ' ' && !matches!(c, '\t' | '\n' | '\r')) || matches!(c, '\u{FFFE}' | '\u{FFFF}')
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}